@@ -1,4 +1,5 @@
 use futures::future::FutureResult;
+use std::collections::BTreeSet;
 
 use super::*;
 
@@ -32,6 +33,7 @@ fn create_subgraph(
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        features: BTreeSet::new(),
     };
 
     let deployment = SubgraphDeploymentEntity::new(&manifest, false, start_block);
@@ -2,11 +2,12 @@ use async_trait::async_trait;
 use futures01::sync::mpsc::Sender;
 use lazy_static::lazy_static;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 use std::str::FromStr;
 
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
+use graph::data::subgraph::SubgraphFeature;
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
 use web3::types::{Log, H256};
 
@@ -22,6 +23,9 @@ pub struct SubgraphInstance<T: RuntimeHostBuilder> {
     network: String,
     host_builder: T,
 
+    /// The features the subgraph opted into in its manifest
+    features: BTreeSet<SubgraphFeature>,
+
     /// Runtime hosts, one for each data source mapping.
     ///
     /// The runtime hosts are created and added in the same order the
@@ -51,6 +55,7 @@ where
             host_builder,
             subgraph_id,
             network,
+            features: manifest.features,
             hosts: Vec::new(),
             module_cache: HashMap::new(),
         };
@@ -115,11 +120,24 @@ where
                 self.subgraph_id.clone(),
                 data_source,
                 top_level_templates,
+                self.features.clone(),
                 mapping_request_sender,
                 host_metrics,
             )
             .compat_err()
     }
+
+    /// The number of runtime hosts, including those for dynamic data sources
+    pub(crate) fn host_count(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Remove the runtime hosts for dynamic data sources that were added
+    /// after the instance had `count` hosts. Since hosts are only ever
+    /// appended, this undoes the creation of these data sources
+    pub(crate) fn revert_data_sources(&mut self, count: usize) {
+        self.hosts.truncate(count);
+    }
 }
 
 #[async_trait]
@@ -3,17 +3,18 @@ use futures01::sync::mpsc::{channel, Receiver, Sender};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use graph::components::ethereum::{triggers_in_block, EthereumNetworks};
 use graph::components::store::ModificationsAndCache;
 use graph::components::subgraph::{MappingError, ProofOfIndexing, SharedProofOfIndexing};
 use graph::data::store::scalar::Bytes;
 use graph::data::subgraph::schema::{
-    DynamicEthereumContractDataSourceEntity, SubgraphError, POI_OBJECT,
+    DynamicEthereumContractDataSourceEntity, SubgraphDeploymentAssignmentEntity, SubgraphError,
+    POI_OBJECT,
 };
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
-use graph::util::lfu_cache::LfuCache;
+use graph::util::{backoff::ExponentialBackoff, lfu_cache::LfuCache};
 
 use super::SubgraphInstance;
 
@@ -25,8 +26,22 @@ lazy_static! {
             .unwrap_or("10000".into())
             .parse::<usize>()
             .expect("invalid GRAPH_ENTITY_CACHE_SIZE");
+
+    /// The maximum delay between restarts of a subgraph that failed with a
+    /// non-deterministic error.
+    static ref SUBGRAPH_ERROR_RETRY_CEIL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_SUBGRAPH_ERROR_RETRY_CEIL_SECS")
+            .unwrap_or("1800".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_SUBGRAPH_ERROR_RETRY_CEIL_SECS")
+    );
 }
 
+/// The delay before the first restart of a subgraph that failed with a
+/// non-deterministic error; subsequent delays double until they reach
+/// `SUBGRAPH_ERROR_RETRY_CEIL`
+const SUBGRAPH_ERROR_RETRY_BASE: Duration = Duration::from_secs(30);
+
 type SharedInstanceKeepAliveMap = Arc<RwLock<HashMap<SubgraphDeploymentId, CancelGuard>>>;
 
 struct IndexingInputs<B, S> {
@@ -249,13 +264,35 @@ impl SubgraphInstanceManager {
                             "data_sources" => manifest.data_sources.len()
                         );
                         let network = manifest.network_name();
+                        let store = stores.get(&network).cloned();
+
+                        // The deployment might have been paused while we
+                        // were resolving it; make sure we do not index it
+                        if let Some(store) = &store {
+                            match is_paused(store.as_ref(), &manifest.id) {
+                                Ok(false) => (),
+                                Ok(true) => {
+                                    info!(logger, "Subgraph is paused, not starting it");
+                                    continue;
+                                }
+                                Err(err) => {
+                                    error!(
+                                        logger,
+                                        "Failed to start subgraph";
+                                        "error" => format!("{}", err),
+                                        "code" => LogCode::SubgraphStartFailure
+                                    );
+                                    continue;
+                                }
+                            }
+                        }
 
                         match Self::start_subgraph(
                             logger.clone(),
                             instances.clone(),
                             host_builder.clone(),
                             block_stream_builder.clone(),
-                            stores.get(&network).cloned(),
+                            store,
                             &eth_networks,
                             manifest,
                             metrics_registry_for_subgraph.clone(),
@@ -362,6 +399,13 @@ impl SubgraphInstanceManager {
         let instance =
             SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
 
+        // Anybody waiting for the deployment to stop, like a copy of the
+        // deployment that is about to be switched over, waits until the
+        // indexer records that it stopped
+        store.set_running(&deployment_id, true)?;
+        let store_for_stop = store.cheap_clone();
+        let logger_for_stop = logger.clone();
+
         // The subgraph state tracks the state of the subgraph instance over time
         let ctx = IndexingContext {
             inputs: IndexingInputs {
@@ -399,6 +443,10 @@ impl SubgraphInstanceManager {
         graph::spawn_blocking(async move {
             let res = run_subgraph(ctx).await;
             subgraph_metrics_unregister.unregister(registry);
+            if let Err(e) = store_for_stop.set_running(&deployment_id, false) {
+                error!(logger_for_stop, "Failed to record that the subgraph stopped";
+                       "error" => e.to_string());
+            }
             res
         });
 
@@ -412,6 +460,14 @@ impl SubgraphInstanceManager {
     }
 }
 
+/// Check whether the assignment for the deployment `id` has been paused
+fn is_paused<S: Store>(store: &S, id: &SubgraphDeploymentId) -> Result<bool, Error> {
+    let assignment = store
+        .get(SubgraphDeploymentAssignmentEntity::key(id.clone()))
+        .map_err(|e| format_err!("Failed to get subgraph assignment entity: {}", e))?;
+    Ok(assignment.and_then(|entity| entity.get("paused").cloned()) == Some(Value::Bool(true)))
+}
+
 impl EventConsumer<SubgraphAssignmentProviderEvent> for SubgraphInstanceManager {
     /// Get the wrapped event sink.
     fn event_sink(
@@ -436,6 +492,13 @@ where
     let logger = ctx.state.logger.cheap_clone();
     let id_for_err = ctx.inputs.deployment_id.clone();
 
+    // Non-deterministic failures are retried with an increasing delay.
+    // After such a failure, the deployment is marked healthy again once a
+    // block was processed successfully
+    let mut backoff =
+        ExponentialBackoff::new(SUBGRAPH_ERROR_RETRY_BASE, *SUBGRAPH_ERROR_RETRY_CEIL);
+    let mut should_try_unfail = false;
+
     loop {
         debug!(logger, "Starting or restarting subgraph");
 
@@ -500,11 +563,12 @@ where
             }
 
             let start = Instant::now();
+            let host_count = ctx.state.instance.host_count();
 
             let res = process_block(
                 &logger,
                 ctx.inputs.eth_adapter.cheap_clone(),
-                ctx,
+                &mut ctx,
                 block_stream_cancel_handle.clone(),
                 block,
             )
@@ -514,8 +578,22 @@ where
             subgraph_metrics.block_processing_duration.observe(elapsed);
 
             match res {
-                Ok((c, needs_restart)) => {
-                    ctx = c;
+                Ok(needs_restart) => {
+                    if should_try_unfail {
+                        match store_for_err.unfail(&id_for_err) {
+                            Ok(()) => {
+                                info!(&logger, "Subgraph recovered from non-deterministic error");
+                                should_try_unfail = false;
+                                backoff.reset();
+                            }
+                            Err(e) => error!(
+                                &logger,
+                                "Failed to clear subgraph error: {}", e;
+                                "id" => id_for_err.to_string(),
+                            ),
+                        }
+                    }
+
                     if needs_restart {
                         // Cancel the stream for real
                         ctx.state
@@ -536,7 +614,86 @@ where
                     );
                     return Err(());
                 }
-                // Handle unexpected stream errors by marking the subgraph as failed.
+                // Errors that might go away on their own, like timeouts
+                // when talking to an Ethereum node, mark the subgraph as
+                // failed and restart it after a delay
+                Err(CancelableError::Error(e)) if !e.is_deterministic() => {
+                    let delay = backoff.next_attempt();
+                    error!(
+                        &logger,
+                        "Subgraph instance failed with a non-deterministic error: {}", e;
+                        "id" => id_for_err.to_string(),
+                        "attempt" => backoff.attempt,
+                        "retry_delay_s" => delay.as_secs(),
+                        "code" => LogCode::SubgraphSyncingFailure
+                    );
+
+                    let error = SubgraphError {
+                        subgraph_id: id_for_err.clone(),
+                        message: e.to_string(),
+                        block_ptr: Some(block_ptr),
+                        handler: None,
+                        deterministic: false,
+                    };
+                    let next_retry_at = (SystemTime::now() + delay)
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs();
+
+                    let mut status_ops =
+                        SubgraphDeploymentEntity::fail_operations(&id_for_err, error);
+                    status_ops.extend(SubgraphDeploymentEntity::retry_operations(
+                        &id_for_err,
+                        backoff.attempt as i32,
+                        next_retry_at,
+                    ));
+                    if let Err(e) = store_for_err.apply_metadata_operations(&id_for_err, status_ops)
+                    {
+                        error!(
+                            &logger,
+                            "Failed to set subgraph status to Failed: {}", e;
+                            "id" => id_for_err.to_string(),
+                            "code" => LogCode::SubgraphSyncingFailureNotRecorded
+                        );
+                    }
+
+                    // Forget the data sources that the failed block created;
+                    // they are created again when the block is retried. The
+                    // filters might still include them, but that only leads
+                    // to triggers that no host handles
+                    ctx.state.instance.revert_data_sources(host_count);
+                    ctx.state.entity_lfu_cache = LfuCache::new();
+
+                    // Stop waiting as soon as the subgraph is unassigned or
+                    // removed, since the delay can be long
+                    let _ = tokio::time::delay_for(delay)
+                        .map(Ok::<(), ()>)
+                        .boxed()
+                        .compat()
+                        .cancelable(&block_stream_cancel_handle, || ())
+                        .compat()
+                        .await;
+
+                    // The subgraph might have been stopped while we waited
+                    if block_stream_cancel_handle.is_canceled() {
+                        debug!(
+                            &logger,
+                            "Subgraph was stopped while waiting to be restarted";
+                            "id" => id_for_err.to_string(),
+                        );
+                        return Err(());
+                    }
+                    should_try_unfail = true;
+
+                    // Cancel the stream for real and restart the subgraph
+                    ctx.state
+                        .instances
+                        .write()
+                        .unwrap()
+                        .remove(&ctx.inputs.deployment_id);
+                    break;
+                }
+                // Handle deterministic errors by marking the subgraph as failed.
                 Err(CancelableError::Error(e)) => {
                     error!(
                         &logger,
@@ -550,7 +707,7 @@ where
                         message: e.to_string(),
                         block_ptr: Some(block_ptr),
                         handler: None,
-                        deterministic: e.is_deterministic(),
+                        deterministic: true,
                     };
 
                     // Set subgraph status to Failed
@@ -595,15 +752,15 @@ impl From<failure::Error> for BlockProcessingError {
     }
 }
 
-/// Processes a block and returns the updated context and a boolean flag indicating
+/// Processes a block, updating the context, and returns a boolean flag indicating
 /// whether new dynamic data sources have been added to the subgraph.
 async fn process_block<B: BlockStreamBuilder, T: RuntimeHostBuilder, S>(
     logger: &Logger,
     eth_adapter: Arc<dyn EthereumAdapter>,
-    mut ctx: IndexingContext<B, T, S>,
+    ctx: &mut IndexingContext<B, T, S>,
     block_stream_cancel_handle: CancelHandle,
     block: EthereumBlockWithTriggers,
-) -> Result<(IndexingContext<B, T, S>, bool), CancelableError<BlockProcessingError>>
+) -> Result<bool, CancelableError<BlockProcessingError>>
 where
     S: ChainStore + Store + EthereumCallCache + SubgraphDeploymentStore,
 {
@@ -685,7 +842,7 @@ where
             // Losing the cache is a bit annoying but not an issue for correctness.
             //
             // See also b21fa73b-6453-4340-99fb-1a78ec62efb1.
-            return Ok((ctx, true));
+            return Ok(true);
         }
     };
 
@@ -706,7 +863,7 @@ where
         // Instantiate dynamic data sources, removing them from the block state.
         let (data_sources, runtime_hosts) = create_dynamic_data_sources(
             logger.clone(),
-            &mut ctx,
+            ctx,
             host_metrics.clone(),
             block_state.created_data_sources.drain(..),
         )
@@ -744,7 +901,7 @@ where
         // and add runtimes for the data sources to the subgraph instance.
         persist_dynamic_data_sources(
             logger.clone(),
-            &mut ctx,
+            ctx,
             &mut block_state.entity_cache,
             data_sources,
             block_ptr_for_new_data_sources,
@@ -796,6 +953,14 @@ where
         .await?;
     }
 
+    let deterministic_errors = std::mem::take(&mut block_state.deterministic_errors);
+    for error in &deterministic_errors {
+        warn!(&logger, "Skipped handler that failed deterministically";
+            "handler" => error.handler.as_deref().unwrap_or(""),
+            "error" => &error.message,
+        );
+    }
+
     let section = ctx.host_metrics.stopwatch.start_section("as_modifications");
     let ModificationsAndCache {
         modifications: mods,
@@ -833,11 +998,13 @@ where
     let stopwatch = ctx.host_metrics.stopwatch.clone();
     let start = Instant::now();
 
-    match ctx
-        .inputs
-        .store
-        .transact_block_operations(subgraph_id, block_ptr_after, mods, stopwatch)
-    {
+    match ctx.inputs.store.transact_block_operations(
+        subgraph_id,
+        block_ptr_after,
+        mods,
+        stopwatch,
+        deterministic_errors,
+    ) {
         Ok(should_migrate) => {
             let elapsed = start.elapsed().as_secs_f64();
             metrics.block_ops_transaction_duration.observe(elapsed);
@@ -848,7 +1015,7 @@ where
                     &block_ptr_after,
                 );
             }
-            Ok((ctx, needs_restart))
+            Ok(needs_restart)
         }
         Err(e) => {
             Err(format_err!("Error while processing block stream for a subgraph: {}", e).into())
@@ -192,7 +192,7 @@ where
             let query_result = self
                 .graphql_runner
                 .cheap_clone()
-                .query_metadata(query)
+                .query_deployment_metadata(deployment_id.clone(), query)
                 .await?;
             let unresolved_data_sources =
                 self.parse_data_sources(&deployment_id, query_result.as_ref().clone())?;
@@ -34,6 +34,7 @@ pub struct SubgraphRegistrar<L, P, S, CS> {
     ethereum_networks: EthereumNetworks,
     node_id: NodeId,
     version_switching_mode: SubgraphVersionSwitchingMode,
+    placer: Arc<dyn DeploymentPlacer>,
     assignment_event_stream_cancel_guard: CancelGuard, // cancels on drop
 }
 
@@ -53,6 +54,7 @@ where
         ethereum_networks: EthereumNetworks,
         node_id: NodeId,
         version_switching_mode: SubgraphVersionSwitchingMode,
+        placer: Arc<dyn DeploymentPlacer>,
     ) -> Self {
         let logger = logger_factory.component_logger("SubgraphRegistrar", None);
         let logger_factory = logger_factory.with_parent(logger.clone());
@@ -73,6 +75,7 @@ where
             ethereum_networks,
             node_id,
             version_switching_mode,
+            placer,
             assignment_event_stream_cancel_guard: CancelGuard::new(),
         }
     }
@@ -192,8 +195,11 @@ where
                                 .map(
                                     |entity_opt| -> Box<dyn Stream<Item = _, Error = _> + Send> {
                                         if let Some(entity) = entity_opt {
+                                            let paused =
+                                                entity.get("paused") == Some(&Value::Bool(true));
                                             if entity.get("nodeId")
                                                 == Some(&node_id.to_string().into())
+                                                && !paused
                                             {
                                                 // Start subgraph on this node
                                                 Box::new(stream::once(Ok(AssignmentEvent::Add {
@@ -201,7 +207,8 @@ where
                                                     node_id: node_id.clone(),
                                                 })))
                                             } else {
-                                                // Ensure it is removed from this node
+                                                // Ensure it is removed from this node; paused
+                                                // deployments stay assigned but are not indexed
                                                 Box::new(stream::once(Ok(
                                                     AssignmentEvent::Remove {
                                                         subgraph_id: subgraph_hash,
@@ -236,9 +243,13 @@ where
         let provider = self.provider.clone();
         let logger = self.logger.clone();
 
-        // Create a query to find all assignments with this node ID
-        let assignment_query = SubgraphDeploymentAssignmentEntity::query()
-            .filter(EntityFilter::new_equal("nodeId", self.node_id.to_string()));
+        // Create a query to find all assignments with this node ID that
+        // are not paused
+        let assignment_query =
+            SubgraphDeploymentAssignmentEntity::query().filter(EntityFilter::And(vec![
+                EntityFilter::new_equal("nodeId", self.node_id.to_string()),
+                EntityFilter::new_equal("paused", false),
+            ]));
 
         future::result(self.store.find(assignment_query))
             .map_err(|e| format_err!("Error querying subgraph assignments: {}", e))
@@ -277,6 +288,56 @@ where
                 })
             })
     }
+
+    /// Decide which node should index the deployment `name` on `network`.
+    /// If a deployment rule matches, we use the indexer from the rule's
+    /// list that currently has the fewest deployments assigned to it, and
+    /// fall back to `node_id` if no rule applies
+    fn choose_node(
+        &self,
+        name: &SubgraphName,
+        network: &str,
+        node_id: NodeId,
+    ) -> Result<NodeId, SubgraphRegistrarError> {
+        let indexers = match self
+            .placer
+            .place(name.as_str(), network)
+            .map_err(|e| SubgraphRegistrarError::Unknown(format_err!("{}", e)))?
+        {
+            Some((_, indexers)) if !indexers.is_empty() => indexers,
+            _ => return Ok(node_id),
+        };
+
+        let assignment_query =
+            SubgraphDeploymentAssignmentEntity::query().filter(EntityFilter::In(
+                "nodeId".to_owned(),
+                indexers
+                    .iter()
+                    .map(|node| node.to_string().into())
+                    .collect(),
+            ));
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for assignment in self.store.find(assignment_query)? {
+            if let Some(Value::String(node)) = assignment.get("nodeId") {
+                *counts.entry(node.clone()).or_default() += 1;
+            }
+        }
+
+        Ok(least_loaded(indexers, &counts))
+    }
+}
+
+/// Pick the node from `indexers` that has the fewest deployments according
+/// to `counts`; nodes without an entry in `counts` have no deployments.
+/// `indexers` must not be empty
+fn least_loaded(indexers: Vec<NodeId>, counts: &HashMap<String, usize>) -> NodeId {
+    // `min_by_key` returns the first of several equally loaded
+    // indexers, which keeps the choice stable in the order in which
+    // the rule lists them
+    indexers
+        .into_iter()
+        .min_by_key(|node| counts.get(node.as_str()).cloned().unwrap_or(0))
+        .expect("the list of indexers is not empty")
 }
 
 #[async_trait]
@@ -320,6 +381,8 @@ where
 
         let network_name = manifest.network_name();
 
+        let node_id = self.choose_node(&name, &network_name, node_id)?;
+
         let chain_store = self.chain_stores.get(&network_name).ok_or(
             SubgraphRegistrarError::NetworkNotSupported(network_name.clone()),
         )?;
@@ -372,7 +435,7 @@ where
     /// Reassign a subgraph deployment to a different node.
     ///
     /// Reassigning to a nodeId that does not match any reachable graph-nodes will effectively pause the
-    /// subgraph syncing process.
+    /// subgraph syncing process. Use `pause_subgraph` to stop syncing without losing the assignment.
     async fn reassign_subgraph(
         &self,
         id: SubgraphDeploymentId,
@@ -382,6 +445,55 @@ where
 
         Ok(())
     }
+
+    /// Stop syncing a subgraph deployment. The deployment stays assigned
+    /// to its node and can still be queried.
+    async fn pause_subgraph(&self, id: SubgraphDeploymentId) -> Result<(), SubgraphRegistrarError> {
+        self.store.pause_subgraph(&id)?;
+
+        Ok(())
+    }
+
+    /// Resume syncing a paused subgraph deployment on the node it is
+    /// assigned to.
+    async fn resume_subgraph(
+        &self,
+        id: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError> {
+        self.store.resume_subgraph(&id)?;
+
+        Ok(())
+    }
+
+    /// Rewind a subgraph deployment to an earlier block. The block must be
+    /// on the chain that the deployment indexes.
+    ///
+    /// The deployment must be paused or unassigned while it is rewound.
+    async fn rewind_subgraph(
+        &self,
+        id: SubgraphDeploymentId,
+        block_ptr: EthereumBlockPointer,
+    ) -> Result<(), SubgraphRegistrarError> {
+        match self.store.block_number(&id, block_ptr.hash)? {
+            Some(number) if number as u64 == block_ptr.number => (),
+            Some(number) => {
+                return Err(SubgraphRegistrarError::BlockNotFound(format!(
+                    "block {:x} has number {} and not {}",
+                    block_ptr.hash, number, block_ptr.number
+                )))
+            }
+            None => {
+                return Err(SubgraphRegistrarError::BlockNotFound(format!(
+                    "{:x}",
+                    block_ptr.hash
+                )))
+            }
+        }
+
+        self.store.rewind(id, block_ptr)?;
+
+        Ok(())
+    }
 }
 
 async fn handle_assignment_event(
@@ -583,3 +695,52 @@ fn create_subgraph_version(
             })
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(names: &[&str]) -> Vec<NodeId> {
+        names
+            .iter()
+            .map(|name| NodeId::new(*name).unwrap())
+            .collect()
+    }
+
+    fn counts(entries: &[(&str, usize)]) -> HashMap<String, usize> {
+        entries
+            .iter()
+            .map(|(node, count)| (node.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn least_loaded_picks_node_with_fewest_deployments() {
+        let indexers = nodes(&["index_0", "index_1", "index_2"]);
+
+        let chosen = least_loaded(
+            indexers.clone(),
+            &counts(&[("index_0", 3), ("index_1", 1), ("index_2", 2)]),
+        );
+        assert_eq!("index_1", chosen.as_str());
+
+        // Nodes without any deployments are not in `counts`
+        let chosen = least_loaded(indexers, &counts(&[("index_0", 3), ("index_2", 2)]));
+        assert_eq!("index_1", chosen.as_str());
+    }
+
+    #[test]
+    fn least_loaded_breaks_ties_in_rule_order() {
+        let chosen = least_loaded(
+            nodes(&["index_2", "index_0", "index_1"]),
+            &counts(&[("index_0", 1), ("index_1", 1), ("index_2", 1)]),
+        );
+        assert_eq!("index_2", chosen.as_str());
+
+        let chosen = least_loaded(
+            nodes(&["index_0", "index_1", "index_2"]),
+            &counts(&[("index_0", 2), ("index_1", 1), ("index_2", 1)]),
+        );
+        assert_eq!("index_1", chosen.as_str());
+    }
+}
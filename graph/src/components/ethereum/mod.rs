@@ -4,6 +4,11 @@ mod network;
 mod stream;
 mod types;
 
+use lazy_static::lazy_static;
+use std::env;
+
+use crate::components::store::BlockNumber;
+
 pub use self::adapter::{
     blocks_with_triggers, triggers_in_block, BlockStreamMetrics, EthGetLogsFilter, EthereumAdapter,
     EthereumAdapterError, EthereumBlockFilter, EthereumCallFilter, EthereumContractCall,
@@ -20,3 +25,17 @@ pub use self::types::{
     EthereumCallData, EthereumEventData, EthereumTransactionData, EthereumTrigger,
     LightEthereumBlock, LightEthereumBlockExt,
 };
+
+lazy_static! {
+    /// The maximum number of blocks that a reorg can revert. Defaults to
+    /// 50 blocks
+    pub static ref REORG_THRESHOLD: BlockNumber = env::var("ETHEREUM_REORG_THRESHOLD")
+        .ok()
+        .map(|s| {
+            s.parse::<BlockNumber>()
+                .ok()
+                .filter(|threshold| *threshold >= 0)
+                .unwrap_or_else(|| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD"))
+        })
+        .unwrap_or(50);
+}
@@ -2,7 +2,7 @@ use futures::prelude::*;
 
 use crate::data::graphql::effort::LoadManager;
 use crate::data::query::{CacheStatus, Query, QueryResult};
-use crate::data::subgraph::DeploymentState;
+use crate::data::subgraph::{DeploymentState, SubgraphDeploymentId};
 use crate::data::subscription::{Subscription, SubscriptionError, SubscriptionResult};
 
 use async_trait::async_trait;
@@ -51,20 +51,37 @@ pub trait GraphQlRunner: Send + Sync + 'static {
             .run_query_with_complexity(query, state, None, None, None, None, false)
             .await;
 
-        // Metadata queries are not cached.
-        Arc::try_unwrap(result)
-            .unwrap()
-            .to_result()
-            .map_err(|errors| format_err!("Failed to query metadata: {:?}", errors))
-            .and_then(|data| {
-                data.map(|data| Ok(Arc::new(data)))
-                    .unwrap_or_else(|| Err(format_err!("No metadata found")))
-            })
+        metadata_result(result)
+    }
+
+    /// Run a metadata query that only looks at the metadata of
+    /// `deployment`. Implementations that keep metadata for different
+    /// deployments in different places should override this so that the
+    /// query is sent to wherever the metadata for `deployment` lives
+    async fn query_deployment_metadata(
+        self: Arc<Self>,
+        _deployment: SubgraphDeploymentId,
+        query: Query,
+    ) -> Result<Arc<q::Value>, Error> {
+        self.query_metadata(query).await
     }
 
     fn load_manager(&self) -> Arc<LoadManager>;
 }
 
+/// Turn the result of a metadata query into its data
+pub fn metadata_result(result: Arc<QueryResult>) -> Result<Arc<q::Value>, Error> {
+    // Metadata queries are not cached.
+    Arc::try_unwrap(result)
+        .unwrap()
+        .to_result()
+        .map_err(|errors| format_err!("Failed to query metadata: {:?}", errors))
+        .and_then(|data| {
+            data.map(|data| Ok(Arc::new(data)))
+                .unwrap_or_else(|| Err(format_err!("No metadata found")))
+        })
+}
+
 #[async_trait]
 pub trait QueryLoadManager: Send + Sync {
     async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit;
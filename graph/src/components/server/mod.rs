@@ -1,6 +1,9 @@
 /// Component for running GraphQL queries over HTTP.
 pub mod query;
 
+/// Persisted queries shared by the GraphQL servers.
+pub mod persisted;
+
 /// Component for running GraphQL subscriptions over WebSockets.
 pub mod subscription;
 
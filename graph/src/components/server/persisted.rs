@@ -0,0 +1,305 @@
+//! Apollo-style automatic persisted queries.
+//!
+//! Instead of the query text, clients can send the SHA-256 hash of the
+//! query in `extensions.persistedQuery.sha256Hash`. If the server does not
+//! know the hash, it responds with a `PersistedQueryNotFound` error, and the
+//! client retries with both the hash and the query text. The parsed query
+//! is then kept in a bounded cache so that later requests only need to
+//! send the hash.
+//!
+//! Deployments can also be restricted to an allow-list of queries; for such
+//! deployments, only the registered queries can be run, both over HTTP and
+//! as subscriptions.
+use graphql_parser::query as q;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::Mutex;
+
+use crate::components::server::query::GraphQLServerError;
+use crate::prelude::*;
+use crate::util::lfu_cache::LfuCache;
+
+/// A parsed query document together with its shape hash
+#[derive(Clone, Debug)]
+pub struct PersistedQuery {
+    pub document: Arc<q::Document>,
+    pub shape_hash: u64,
+    /// The size of the query text, which serves as an estimate of the
+    /// memory used by the parsed document
+    weight: usize,
+}
+
+impl PersistedQuery {
+    fn parse(text: &str) -> Result<Self, GraphQLServerError> {
+        let document = graphql_parser::parse_query(text).map_err(|e| {
+            GraphQLServerError::from(QueryError::ParseError(Arc::new(e.compat().into())))
+        })?;
+        Ok(PersistedQuery {
+            shape_hash: shape_hash(&document),
+            document: Arc::new(document),
+            weight: text.len(),
+        })
+    }
+}
+
+impl Default for PersistedQuery {
+    fn default() -> Self {
+        PersistedQuery {
+            document: Arc::new(q::Document {
+                definitions: vec![],
+            }),
+            shape_hash: 0,
+            weight: 0,
+        }
+    }
+}
+
+impl CacheWeight for PersistedQuery {
+    fn indirect_weight(&self) -> usize {
+        self.weight
+    }
+}
+
+/// The only version of the `persistedQuery` extension that is supported
+const PERSISTED_QUERY_VERSION: u64 = 1;
+
+/// Return the hash from the `persistedQuery` entry of a request's
+/// `extensions`, if there is one
+pub fn persisted_query_hash(
+    extensions: Option<&serde_json::Value>,
+) -> Result<Option<&str>, GraphQLServerError> {
+    let persisted_query = match extensions.and_then(|extensions| extensions.get("persistedQuery")) {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(persisted_query) => persisted_query,
+    };
+
+    let version = persisted_query.get("version").and_then(|v| v.as_u64());
+    if version != Some(PERSISTED_QUERY_VERSION) {
+        return Err(GraphQLServerError::ClientError(String::from(
+            "Unsupported persisted query version",
+        )));
+    }
+
+    persisted_query
+        .get("sha256Hash")
+        .and_then(|hash| hash.as_str())
+        .map(Some)
+        .ok_or_else(|| {
+            GraphQLServerError::ClientError(String::from(
+                "The \"sha256Hash\" field of the persisted query is not a string",
+            ))
+        })
+}
+
+/// Return the hex-encoded SHA-256 hash of `text`
+pub fn query_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+/// The persisted queries known to the server
+pub struct PersistedQueries {
+    cache: Mutex<LfuCache<String, PersistedQuery>>,
+    max_weight: usize,
+    allowlist: HashMap<SubgraphDeploymentId, HashMap<String, PersistedQuery>>,
+}
+
+impl PersistedQueries {
+    /// Create a store for persisted queries whose cache holds queries with
+    /// a total text size of at most `max_weight` bytes
+    pub fn new(max_weight: usize) -> Self {
+        PersistedQueries {
+            cache: Mutex::new(LfuCache::new()),
+            max_weight,
+            allowlist: HashMap::new(),
+        }
+    }
+
+    /// Configure persisted queries from the environment:
+    ///
+    /// - `GRAPH_PERSISTED_QUERY_CACHE_MAX_MEM`: the size of the cache, in MB
+    /// - `GRAPH_PERSISTED_QUERIES_ALLOWLIST`: the path of a JSON file that
+    ///   maps deployment ids to the list of queries that may be run against
+    ///   them
+    pub fn from_env(logger: &Logger) -> Self {
+        let max_mem = env::var("GRAPH_PERSISTED_QUERY_CACHE_MAX_MEM")
+            .unwrap_or("100".to_string())
+            .parse::<usize>()
+            .expect("Invalid value for GRAPH_PERSISTED_QUERY_CACHE_MAX_MEM environment variable");
+        let mut queries = Self::new(1_000_000 * max_mem);
+
+        if let Ok(path) = env::var("GRAPH_PERSISTED_QUERIES_ALLOWLIST") {
+            queries.load_allowlist(&path).unwrap_or_else(|e| {
+                panic!(
+                    "failed to load persisted query allow-list from `{}`: {}",
+                    path, e
+                )
+            });
+            info!(
+                logger,
+                "Loaded persisted query allow-list";
+                "path" => &path,
+                "deployments" => queries.allowlist.len(),
+            );
+        }
+        queries
+    }
+
+    fn load_allowlist(&mut self, path: &str) -> Result<(), Error> {
+        let text = fs::read_to_string(path)?;
+        let allowlist: HashMap<String, Vec<String>> = serde_json::from_str(&text)?;
+        for (deployment, queries) in allowlist {
+            let deployment = SubgraphDeploymentId::new(deployment)
+                .map_err(|id| format_err!("invalid deployment id `{}`", id))?;
+            for query in queries {
+                self.allow(deployment.clone(), &query)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add `text` to the queries that may be run against `deployment` and
+    /// return its hash. Once a deployment has an allow-list, no other
+    /// queries can be run against it.
+    pub fn allow(&mut self, deployment: SubgraphDeploymentId, text: &str) -> Result<String, Error> {
+        let query = PersistedQuery::parse(text)
+            .map_err(|e| format_err!("invalid query for deployment `{}`: {}", deployment, e))?;
+        let hash = query_hash(text);
+        self.allowlist
+            .entry(deployment)
+            .or_default()
+            .insert(hash.clone(), query);
+        Ok(hash)
+    }
+
+    /// Determine the query that a request against `deployment` should run.
+    /// The `hash` is the hash from the request's `persistedQuery` extension
+    /// and `text` the query text, if the request contained them. At least
+    /// one of them must be present.
+    pub fn resolve(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        hash: Option<&str>,
+        text: Option<&str>,
+    ) -> Result<PersistedQuery, GraphQLServerError> {
+        let hash = hash.map(|hash| hash.to_lowercase());
+
+        if let Some(allowed) = self.allowlist.get(deployment) {
+            let hash = hash.ok_or_else(|| {
+                GraphQLServerError::ClientError(format!(
+                    "Deployment `{}` only accepts persisted queries",
+                    deployment
+                ))
+            })?;
+            return allowed.get(&hash).cloned().ok_or_else(|| {
+                GraphQLServerError::ClientError(format!(
+                    "The persisted query `{}` is not allowed for deployment `{}`",
+                    hash, deployment
+                ))
+            });
+        }
+
+        match (hash, text) {
+            (None, Some(text)) => PersistedQuery::parse(text),
+            (Some(hash), None) => self
+                .cache
+                .lock()
+                .unwrap()
+                .get(&hash)
+                .cloned()
+                .ok_or_else(|| QueryError::PersistedQueryNotFound.into()),
+            (Some(hash), Some(text)) => {
+                if hash != query_hash(text) {
+                    return Err(GraphQLServerError::ClientError(String::from(
+                        "The \"sha256Hash\" of the persisted query does not match the query",
+                    )));
+                }
+                if let Some(query) = self.cache.lock().unwrap().get(&hash) {
+                    return Ok(query.clone());
+                }
+                let query = PersistedQuery::parse(text)?;
+                let mut cache = self.cache.lock().unwrap();
+                cache.evict(self.max_weight);
+                cache.insert(hash, query.clone());
+                Ok(query)
+            }
+            (None, None) => Err(GraphQLServerError::ClientError(String::from(
+                "The \"query\" field is missing in request data",
+            ))),
+        }
+    }
+}
+
+impl Default for PersistedQueries {
+    fn default() -> Self {
+        Self::new(100_000_000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::components::server::query::GraphQLServerError;
+    use crate::prelude::*;
+
+    use super::{query_hash, PersistedQueries};
+
+    const QUERY: &str = "{ users { name } }";
+
+    #[test]
+    fn requires_query_text_for_unknown_hashes() {
+        let queries = PersistedQueries::default();
+        let deployment = SubgraphDeploymentId::new("test").unwrap();
+        let hash = query_hash(QUERY);
+
+        match queries.resolve(&deployment, Some(&hash), None) {
+            Err(GraphQLServerError::QueryError(QueryError::PersistedQueryNotFound)) => (),
+            _ => panic!("Unknown hashes should not resolve"),
+        }
+
+        queries
+            .resolve(&deployment, Some(&hash), Some(QUERY))
+            .expect("Query with matching hash resolves");
+        let query = queries
+            .resolve(&deployment, Some(&hash), None)
+            .expect("Known hashes resolve");
+        assert_eq!(*query.document, graphql_parser::parse_query(QUERY).unwrap());
+        assert_eq!(query.shape_hash, shape_hash(&query.document));
+    }
+
+    #[test]
+    fn rejects_mismatched_hashes() {
+        let queries = PersistedQueries::default();
+        let deployment = SubgraphDeploymentId::new("test").unwrap();
+        let hash = query_hash("{ other }");
+
+        queries
+            .resolve(&deployment, Some(&hash), Some(QUERY))
+            .expect_err("Hash must match the query");
+        queries
+            .resolve(&deployment, Some(&hash), None)
+            .expect_err("Mismatched queries are not stored");
+    }
+
+    #[test]
+    fn restricts_deployments_to_allowlist() {
+        let mut queries = PersistedQueries::default();
+        let restricted = SubgraphDeploymentId::new("restricted").unwrap();
+        let open = SubgraphDeploymentId::new("open").unwrap();
+        let hash = queries.allow(restricted.clone(), QUERY).unwrap();
+
+        queries
+            .resolve(&restricted, Some(&hash), None)
+            .expect("Allowed queries resolve by hash");
+        queries
+            .resolve(&restricted, None, Some(QUERY))
+            .expect_err("Restricted deployments require a hash");
+        let other = "{ users { id } }";
+        queries
+            .resolve(&restricted, Some(&query_hash(other)), Some(other))
+            .expect_err("Queries that are not on the allow-list are rejected");
+        queries
+            .resolve(&open, None, Some(other))
+            .expect("Other deployments are not restricted");
+    }
+}
@@ -6,7 +6,7 @@ use mockall::predicate::*;
 use mockall::*;
 use serde::{Deserialize, Serialize};
 use stable_hash::prelude::*;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::fmt;
 use std::str::FromStr;
@@ -77,6 +77,24 @@ pub enum EntityFilter {
     NotStartsWith(Attribute, Value),
     EndsWith(Attribute, Value),
     NotEndsWith(Attribute, Value),
+    /// Match entities for which at least one of the entities they
+    /// refer to through an attribute matches a filter
+    Child(Child),
+    /// Match entity versions that were written in the given block or
+    /// later, i.e., entities that changed since that block
+    ChangeBlockGte(BlockNumber),
+}
+
+/// A filter on the entities that an entity refers to. When `derived` is
+/// `false`, `attr` is an attribute of the parent entity that holds the
+/// ids of child entities. Otherwise, `attr` is an attribute of the child
+/// entities that holds the id of the parent, as for `@derivedFrom` fields
+#[derive(Clone, Debug, PartialEq)]
+pub struct Child {
+    pub attr: Attribute,
+    pub entity_type: String,
+    pub filter: Box<EntityFilter>,
+    pub derived: bool,
 }
 
 // Define some convenience methods
@@ -121,6 +139,16 @@ impl EntityFilter {
     }
 }
 
+/// Order by `child_attr` of the entity of type `entity_type` whose id is
+/// stored in the parent's `attr`. Entities without such a child sort as if
+/// `child_attr` was null
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChildOrder {
+    pub attr: Attribute,
+    pub entity_type: String,
+    pub child_attr: Attribute,
+}
+
 /// The order in which entities should be restored from a store.
 #[derive(Clone, Debug, PartialEq)]
 pub enum EntityOrder {
@@ -128,6 +156,12 @@ pub enum EntityOrder {
     Ascending(String, ValueType),
     /// Order descending by the given attribute. Use `id` as a tie-breaker
     Descending(String, ValueType),
+    /// Order ascending by an attribute of the entity that the parent
+    /// refers to. Use the `id` of the parent as a tie-breaker
+    ChildAscending(ChildOrder, ValueType),
+    /// Order descending by an attribute of the entity that the parent
+    /// refers to. Use the `id` of the parent as a tie-breaker
+    ChildDescending(ChildOrder, ValueType),
     /// Order by the `id` of the entities
     Default,
     /// Do not order at all. This speeds up queries where we know that
@@ -143,6 +177,15 @@ pub struct EntityRange {
 
     /// How many entities to skip.
     pub skip: u32,
+
+    /// Only return entities that come after this cursor in the order of
+    /// the query. Unlike `skip`, the store can find where to start with an
+    /// index lookup instead of reading and discarding rows
+    pub after: Option<EntityCursor>,
+
+    /// Only return entities that come before this cursor in the order of
+    /// the query
+    pub before: Option<EntityCursor>,
 }
 
 impl EntityRange {
@@ -151,10 +194,57 @@ impl EntityRange {
         Self {
             first: Some(n),
             skip: 0,
+            after: None,
+            before: None,
         }
     }
 }
 
+/// A position in a sorted collection: the value of the sort key of an
+/// entity together with its id. Clients only ever see the encoded form of
+/// a cursor and have to treat it as opaque
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EntityCursor {
+    /// The `orderBy` of the query the cursor was taken from; `id` if the
+    /// query did not have one
+    pub order_by: String,
+    /// The value of the sort key for the entity
+    pub value: Value,
+    /// The id of the entity
+    pub id: String,
+}
+
+impl EntityCursor {
+    pub fn new(order_by: &str, value: Value, id: &str) -> Self {
+        EntityCursor {
+            order_by: order_by.to_owned(),
+            value,
+            id: id.to_owned(),
+        }
+    }
+
+    /// The URL-safe base64 encoding of the JSON for the cursor
+    pub fn encode(&self) -> String {
+        encode_cursor(self)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, QueryExecutionError> {
+        decode_cursor(cursor)
+    }
+}
+
+fn encode_cursor<T: Serialize>(position: &T) -> String {
+    let json = serde_json::to_vec(position).expect("cursors can always be serialized");
+    base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_cursor<T: serde::de::DeserializeOwned>(cursor: &str) -> Result<T, QueryExecutionError> {
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| QueryExecutionError::InvalidCursor(cursor.to_owned()))
+}
+
 /// The attribute we want to window by in an `EntityWindow`. We have to
 /// distinguish between scalar and list attributes since we need to use
 /// different queries for them, and the JSONB storage scheme can not
@@ -331,6 +421,16 @@ impl EntityQuery {
         self
     }
 
+    pub fn after(mut self, cursor: EntityCursor) -> Self {
+        self.range.after = Some(cursor);
+        self
+    }
+
+    pub fn before(mut self, cursor: EntityCursor) -> Self {
+        self.range.before = Some(cursor);
+        self
+    }
+
     pub fn simplify(mut self) -> Self {
         // If there is one window, with one id, in a direct relation to the
         // entities, we can simplify the query by changing the filter and
@@ -359,6 +459,102 @@ impl EntityQuery {
     }
 }
 
+/// A function that summarizes the values of an attribute across all
+/// entities matched by an `AggregateQuery`. Only attributes of type `Int`,
+/// `BigInt` and `BigDecimal` can be aggregated
+#[derive(Clone, Debug, PartialEq)]
+pub enum Aggregate {
+    /// The number of entities
+    Count,
+    Sum(Attribute),
+    Min(Attribute),
+    Max(Attribute),
+    Avg(Attribute),
+}
+
+/// A query for aggregate values over the entities of one type. The result
+/// contains one value for each entry in `aggregates`, in the same order.
+/// Aggregates over an empty set of entities are `Value::Null`, except for
+/// `Count`, which is zero
+#[derive(Clone, Debug)]
+pub struct AggregateQuery {
+    /// ID of the subgraph.
+    pub subgraph_id: SubgraphDeploymentId,
+
+    /// The block height at which to execute the query, with the same
+    /// meaning as for `EntityQuery`
+    pub block: BlockNumber,
+
+    /// The entity type whose entities are aggregated
+    pub entity_type: String,
+
+    /// Only aggregate entities that match this filter
+    pub filter: Option<EntityFilter>,
+
+    /// The aggregates to compute
+    pub aggregates: Vec<Aggregate>,
+}
+
+/// An entity that was removed from the store. Entities that were removed
+/// and then created again are not deletions
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EntityDeletion {
+    pub entity_type: String,
+    pub entity_id: String,
+    /// The block in which the entity was removed
+    pub block: BlockNumber,
+}
+
+impl EntityDeletion {
+    /// The opaque cursor that clients pass back to get the deletions that
+    /// come after this one
+    pub fn cursor(&self) -> String {
+        encode_cursor(self)
+    }
+
+    pub fn from_cursor(cursor: &str) -> Result<Self, QueryExecutionError> {
+        decode_cursor(cursor)
+    }
+}
+
+/// A query for the entities that were removed in blocks from `since` up
+/// to and including `block`. Deletions are ordered by block, entity type
+/// and id, and only the ones that come after `after` in that order are
+/// returned
+#[derive(Clone, Debug)]
+pub struct EntityDeletionsQuery {
+    pub subgraph_id: SubgraphDeploymentId,
+    pub since: BlockNumber,
+    pub block: BlockNumber,
+    /// The maximum number of deletions to return
+    pub first: u32,
+    pub after: Option<EntityDeletion>,
+}
+
+/// A query for the versions of one entity that the store keeps. Only
+/// versions that were current at some block between `from_block` and
+/// `to_block`, both inclusive, are returned
+#[derive(Clone, Debug)]
+pub struct EntityHistoryQuery {
+    pub subgraph_id: SubgraphDeploymentId,
+    pub entity_type: String,
+    pub entity_id: String,
+    pub from_block: BlockNumber,
+    pub to_block: BlockNumber,
+    /// The maximum number of versions to return
+    pub first: u32,
+}
+
+/// One version of an entity together with the blocks for which it was
+/// current. `block_to` is exclusive, and `None` if the version is still
+/// current
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityVersion {
+    pub block_from: BlockNumber,
+    pub block_to: Option<BlockNumber>,
+    pub data: BTreeMap<String, graphql_parser::query::Value>,
+}
+
 /// Operation types that lead to entity changes.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
@@ -835,6 +1031,10 @@ pub trait Store: Send + Sync + 'static {
     ///
     /// `block_ptr_to` must point to a child block of the current subgraph block pointer.
     ///
+    /// The `deterministic_errors` are the errors of handlers whose changes
+    /// were skipped while processing the block; they are recorded as
+    /// non-fatal errors of the subgraph.
+    ///
     /// Return `true` if the subgraph mentioned in `history_event` should have
     /// its schema migrated at `block_ptr_to`
     fn transact_block_operations(
@@ -843,6 +1043,7 @@ pub trait Store: Send + Sync + 'static {
         block_ptr_to: EthereumBlockPointer,
         mods: Vec<EntityModification>,
         stopwatch: StopwatchMetrics,
+        deterministic_errors: Vec<SubgraphError>,
     ) -> Result<bool, StoreError>;
 
     /// Apply the specified metadata operations which only concern metadata
@@ -865,6 +1066,16 @@ pub trait Store: Send + Sync + 'static {
         block_ptr_to: EthereumBlockPointer,
     ) -> Result<(), StoreError>;
 
+    /// Revert the deployment `subgraph_id` to the state it was in at
+    /// `block_ptr_to`, undoing the changes of all later blocks in a single
+    /// transaction. `block_ptr_to` must be before the current block pointer
+    /// of the deployment, and the deployment must not be indexing.
+    fn rewind(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError>;
+
     /// Subscribe to changes for specific subgraphs and entities.
     ///
     /// Returns a stream of store events that match the input arguments.
@@ -945,6 +1156,18 @@ pub trait Store: Send + Sync + 'static {
         node_id: &NodeId,
     ) -> Result<(), StoreError>;
 
+    /// Stop indexing the subgraph with `id` while keeping its assignment.
+    /// If there is no assignment for the given deployment, report an error.
+    fn pause_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError>;
+
+    /// Resume indexing a subgraph that was paused with `pause_subgraph`
+    fn resume_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError>;
+
+    /// Record that an indexer for the deployment `id` started or stopped
+    /// running. Stopping a deployment, for example by pausing it, only
+    /// takes effect once its indexer has recorded that it stopped
+    fn set_running(&self, id: &SubgraphDeploymentId, running: bool) -> Result<(), StoreError>;
+
     /// Start an existing subgraph deployment. This will reset the state of
     /// the subgraph to a known good state. `ops` needs to contain all the
     /// operations on the subgraph of subgraphs to reset the metadata of the
@@ -955,6 +1178,10 @@ pub trait Store: Send + Sync + 'static {
         subgraph_id: &SubgraphDeploymentId,
     ) -> Result<(), StoreError>;
 
+    /// Clear the fatal error of a subgraph that failed with a
+    /// non-deterministic error and has since made progress again
+    fn unfail(&self, subgraph_id: &SubgraphDeploymentId) -> Result<(), StoreError>;
+
     /// Try to perform a pending migration for a subgraph schema. Even if a
     /// subgraph has a pending schema migration, this method might not actually
     /// perform the migration because of limits on the total number of
@@ -977,11 +1204,17 @@ pub trait Store: Send + Sync + 'static {
         block_hash: H256,
     ) -> Result<Option<BlockNumber>, StoreError>;
 
-    /// Get a new `QueryStore`. A `QueryStore` is tied to a DB replica, so if Graph Node is
-    /// configured to use secondary DB servers the queries will be distributed between servers.
+    /// Get a new `QueryStore` for queries against the deployment `id`. A
+    /// `QueryStore` is tied to a DB replica of the shard that holds `id`,
+    /// so if Graph Node is configured to use secondary DB servers the
+    /// queries will be distributed between servers.
     ///
     /// If `for_subscription` is true, the main replica will always be used.
-    fn query_store(self: Arc<Self>, for_subscription: bool) -> Arc<dyn QueryStore + Send + Sync>;
+    fn query_store(
+        self: Arc<Self>,
+        id: &SubgraphDeploymentId,
+        for_subscription: bool,
+    ) -> Result<Arc<dyn QueryStore + Send + Sync>, StoreError>;
 
     fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError>;
 }
@@ -993,6 +1226,8 @@ mock! {
             _subgraph_id: &SubgraphDeploymentId,
             _ids_for_type: BTreeMap<&'a str, Vec<&'a str>>,
         ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError>;
+
+        fn find_mock(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError>;
     }
 }
 
@@ -1037,8 +1272,8 @@ impl Store for MockStore {
         self.get_many_mock(subgraph_id, ids_for_type)
     }
 
-    fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
-        unimplemented!()
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        self.find_mock(query)
     }
 
     fn find_one(&self, _query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
@@ -1055,6 +1290,7 @@ impl Store for MockStore {
         _block_ptr_to: EthereumBlockPointer,
         _mods: Vec<EntityModification>,
         _stopwatch: StopwatchMetrics,
+        _deterministic_errors: Vec<SubgraphError>,
     ) -> Result<bool, StoreError> {
         unimplemented!()
     }
@@ -1076,6 +1312,14 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn rewind(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
     fn subscribe(&self, _entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
         unimplemented!()
     }
@@ -1114,6 +1358,18 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn pause_subgraph(&self, _: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn resume_subgraph(&self, _: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn set_running(&self, _: &SubgraphDeploymentId, _: bool) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
     fn start_subgraph_deployment(
         &self,
         _logger: &Logger,
@@ -1122,6 +1378,10 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn unfail(&self, _: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
     fn migrate_subgraph_deployment(
         &self,
         _logger: &Logger,
@@ -1139,7 +1399,11 @@ impl Store for MockStore {
         unimplemented!()
     }
 
-    fn query_store(self: Arc<Self>, _: bool) -> Arc<dyn QueryStore + Send + Sync> {
+    fn query_store(
+        self: Arc<Self>,
+        _: &SubgraphDeploymentId,
+        _: bool,
+    ) -> Result<Arc<dyn QueryStore + Send + Sync>, StoreError> {
         unimplemented!()
     }
 
@@ -1152,6 +1416,17 @@ impl Store for MockStore {
     }
 }
 
+/// Decide where a new deployment should be stored and which nodes are
+/// allowed to index it
+pub trait DeploymentPlacer: Send + Sync {
+    /// Return the name of the shard that should hold the data for a new
+    /// deployment of subgraph `name` that indexes `network`, together with
+    /// the nodes that may index it. If the list of nodes is empty, the
+    /// caller should decide which node indexes the deployment. Return
+    /// `None` if no placement rule applies
+    fn place(&self, name: &str, network: &str) -> Result<Option<(String, Vec<NodeId>)>, String>;
+}
+
 #[automock]
 pub trait SubgraphDeploymentStore: Send + Sync + 'static {
     /// Return the GraphQL schema supplied by the user
@@ -1270,6 +1545,25 @@ pub trait QueryStore: Send + Sync {
         query: EntityQuery,
     ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError>;
 
+    /// Compute the aggregates in `query` with a single query against the
+    /// store
+    fn aggregate(&self, query: AggregateQuery) -> Result<Vec<Value>, QueryExecutionError>;
+
+    /// Return the versions of an entity, ordered by the block at which
+    /// they became current
+    fn entity_history(
+        &self,
+        query: EntityHistoryQuery,
+    ) -> Result<Vec<EntityVersion>, QueryExecutionError>;
+
+    /// List up to `query.first` of the entities that were removed in the
+    /// blocks that `query` asks for, ordered by the block in which they
+    /// were removed, then by entity type and id
+    fn deletions(
+        &self,
+        query: EntityDeletionsQuery,
+    ) -> Result<Vec<EntityDeletion>, QueryExecutionError>;
+
     fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox;
 
     fn is_deployment_synced(&self, id: SubgraphDeploymentId) -> Result<bool, Error>;
@@ -1285,6 +1579,14 @@ pub trait QueryStore: Send + Sync {
         block_hash: H256,
     ) -> Result<Option<BlockNumber>, StoreError>;
 
+    /// The earliest block for which the deployment still has its complete
+    /// history. Queries for earlier blocks can not be answered since the
+    /// entity versions they would need have been pruned
+    fn history_horizon(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<BlockNumber, StoreError>;
+
     fn wait_stats(&self) -> &PoolWaitStats;
 }
 
@@ -1339,6 +1641,21 @@ pub struct EntityCache {
     /// means that the entity should be deleted
     updates: BTreeMap<EntityKey, Option<Entity>>,
 
+    /// The changes made by the handler that is currently running. They are
+    /// kept apart from `updates` so that they can be discarded if the
+    /// handler fails
+    handler_updates: BTreeMap<EntityKey, Option<Entity>>,
+
+    /// Whether changes are currently being made by a handler
+    in_handler: bool,
+
+    /// When each entity in `updates` and `handler_updates` was first
+    /// written, so that changes can be applied in the order in which the
+    /// mappings made them
+    first_write: HashMap<EntityKey, u64>,
+    handler_first_write: HashMap<EntityKey, u64>,
+    writes: u64,
+
     pub store: Arc<dyn Store>,
 }
 
@@ -1347,6 +1664,7 @@ impl Debug for EntityCache {
         f.debug_struct("EntityCache")
             .field("current", &self.current)
             .field("updates", &self.updates)
+            .field("handler_updates", &self.handler_updates)
             .finish()
     }
 }
@@ -1361,6 +1679,11 @@ impl EntityCache {
         Self {
             current: LfuCache::new(),
             updates: BTreeMap::new(),
+            handler_updates: BTreeMap::new(),
+            in_handler: false,
+            first_write: HashMap::new(),
+            handler_first_write: HashMap::new(),
+            writes: 0,
             store,
         }
     }
@@ -1372,60 +1695,287 @@ impl EntityCache {
         EntityCache {
             current,
             updates: BTreeMap::new(),
+            handler_updates: BTreeMap::new(),
+            in_handler: false,
+            first_write: HashMap::new(),
+            handler_first_write: HashMap::new(),
+            writes: 0,
             store,
         }
     }
 
-    pub fn get(&mut self, key: &EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
-        let current = self.current.get_entity(&*self.store, &key)?;
-        let updates = self.updates.get(&key).cloned();
-        match (current, updates) {
+    /// Start collecting the changes of a handler separately from the
+    /// changes made so far
+    pub fn enter_handler(&mut self) {
+        assert!(!self.in_handler);
+        self.in_handler = true;
+    }
+
+    /// Keep the changes made by the current handler
+    pub fn exit_handler(&mut self) -> Result<(), QueryExecutionError> {
+        assert!(self.in_handler);
+        self.in_handler = false;
+
+        for (key, write) in std::mem::take(&mut self.handler_first_write) {
+            self.first_write.entry(key).or_insert(write);
+        }
+        let handler_updates = std::mem::take(&mut self.handler_updates);
+        for (key, update) in handler_updates {
+            match update {
+                Some(update) => self.set(key, update)?,
+                None => self.remove(key),
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget all changes made by the current handler
+    pub fn exit_handler_and_discard_changes(&mut self) {
+        assert!(self.in_handler);
+        self.in_handler = false;
+        self.handler_updates.clear();
+        self.handler_first_write.clear();
+    }
+
+    /// Apply the accumulated changes `update` for an entity to its
+    /// state `entity` before those changes
+    fn apply_update(entity: Option<Entity>, update: Option<Option<Entity>>) -> Option<Entity> {
+        match (entity, update) {
             // Entity is unchanged
-            (current, None) => Ok(current),
+            (entity, None) => entity,
             // Entity was deleted
-            (_, Some(None)) => Ok(None),
+            (_, Some(None)) => None,
             // Entity created
-            (None, Some(updates)) => Ok(updates),
+            (None, Some(update)) => update,
             // Entity updated
-            (Some(current), Some(Some(updates))) => {
-                let mut current = current;
-                current.merge_remove_null_fields(updates);
-                Ok(Some(current))
+            (Some(mut entity), Some(Some(update))) => {
+                entity.merge_remove_null_fields(update);
+                Some(entity)
+            }
+        }
+    }
+
+    /// Get an entity, ignoring changes made by the current handler
+    fn get_outside_handler(
+        &mut self,
+        key: &EntityKey,
+    ) -> Result<Option<Entity>, QueryExecutionError> {
+        let current = self.current.get_entity(&*self.store, &key)?;
+        let update = self.updates.get(&key).cloned();
+        Ok(Self::apply_update(current, update))
+    }
+
+    pub fn get(&mut self, key: &EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        let entity = self.get_outside_handler(key)?;
+        let update = self.handler_updates.get(&key).cloned();
+        Ok(Self::apply_update(entity, update))
+    }
+
+    /// Get an entity that was written in the current block, including by
+    /// the current handler, without ever looking it up in the store. Since
+    /// writes only carry the attributes they set, the entity is only
+    /// returned if the cache also knows its state before this block, i.e.,
+    /// if it was loaded or created in this block. Returns `None` if that is
+    /// not the case, if the entity was not written in this block, or if it
+    /// was removed
+    pub fn get_in_block(&self, key: &EntityKey) -> Option<Entity> {
+        let update = self.updates.get(key).cloned();
+        let handler_update = self.handler_updates.get(key).cloned();
+        if update.is_none() && handler_update.is_none() {
+            return None;
+        }
+
+        let current = self.current.peek(key)?.clone();
+        let entity = Self::apply_update(current, update);
+        Self::apply_update(entity, handler_update).map(|updates| {
+            let mut entity = Entity::new();
+            entity.merge_remove_null_fields(updates);
+            entity
+        })
+    }
+
+    /// Whether `get` can return the entity for `key` without loading it
+    /// from the store
+    pub fn is_cached(&self, key: &EntityKey) -> bool {
+        self.current.contains_key(key)
+    }
+
+    /// Get all entities of one of `entity_types` whose attribute `attr`
+    /// refers to the entity with id `id`, ordered by their id. This is
+    /// what a `@derivedFrom` field of that entity resolves to, taking the
+    /// changes made so far, including those of the current handler, into
+    /// account
+    pub fn load_related(
+        &mut self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: Vec<String>,
+        attr: &WindowAttribute,
+        id: &str,
+    ) -> Result<Vec<Entity>, QueryExecutionError> {
+        fn refers_to(entity: &Entity, attr: &WindowAttribute, id: &str) -> bool {
+            match (attr, entity.get(attr.name())) {
+                (WindowAttribute::Scalar(_), Some(value)) => value.as_str() == Some(id),
+                (WindowAttribute::List(_), Some(Value::List(values))) => {
+                    values.iter().any(|value| value.as_str() == Some(id))
+                }
+                _ => false,
+            }
+        }
+
+        let filter = match attr {
+            WindowAttribute::Scalar(name) => EntityFilter::new_equal(name.as_str(), id),
+            WindowAttribute::List(name) => EntityFilter::Contains(
+                name.clone(),
+                Value::List(vec![Value::String(id.to_owned())]),
+            ),
+        };
+        let query = EntityQuery::new(
+            subgraph_id.clone(),
+            BLOCK_NUMBER_MAX,
+            EntityCollection::All(entity_types.clone()),
+        )
+        .filter(filter)
+        .range(EntityRange {
+            first: None,
+            skip: 0,
+            after: None,
+            before: None,
+        });
+
+        // Entities that refer to `id` in the store, or that might refer to
+        // it because of the changes made to them
+        let mut keys = Vec::new();
+        for mut entity in self.store.find(query)? {
+            let entity_type = entity
+                .remove("__typename")
+                .and_then(|value| value.as_string())
+                .expect("the store sets `__typename` for queries");
+            let key = EntityKey {
+                subgraph_id: subgraph_id.clone(),
+                entity_type,
+                entity_id: entity.id().unwrap(),
+            };
+            if !self.current.contains_key(&key) {
+                self.current.insert(key.clone(), Some(entity));
             }
+            keys.push(key);
+        }
+        keys.extend(
+            self.updates
+                .iter()
+                .chain(self.handler_updates.iter())
+                .filter(|(key, update)| {
+                    &key.subgraph_id == subgraph_id
+                        && entity_types.contains(&key.entity_type)
+                        && update
+                            .as_ref()
+                            .map_or(false, |update| update.contains_key(attr.name()))
+                })
+                .map(|(key, _)| key.clone()),
+        );
+        keys.sort_by(|a, b| {
+            a.entity_id
+                .cmp(&b.entity_id)
+                .then_with(|| a.entity_type.cmp(&b.entity_type))
+        });
+        keys.dedup();
+
+        // Entities created in this block are not in `current` yet; look
+        // them up with one query rather than one for each of them
+        let mut missing: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for key in keys.iter().filter(|key| !self.current.contains_key(key)) {
+            missing
+                .entry(&key.entity_type)
+                .or_default()
+                .push(&key.entity_id);
+        }
+        if !missing.is_empty() {
+            let mut found = BTreeMap::new();
+            for (entity_type, entities) in self.store.get_many(subgraph_id, missing)? {
+                for mut entity in entities {
+                    entity.remove("__typename");
+                    found.insert((entity_type.clone(), entity.id().unwrap()), entity);
+                }
+            }
+            for key in keys.iter().filter(|key| !self.current.contains_key(key)) {
+                let entity = found.remove(&(key.entity_type.clone(), key.entity_id.clone()));
+                self.current.insert(key.clone(), entity);
+            }
+        }
+
+        let mut related = Vec::new();
+        for key in keys {
+            if let Some(entity) = self.get(&key)? {
+                if refers_to(&entity, attr, id) {
+                    related.push(entity);
+                }
+            }
+        }
+        Ok(related)
+    }
+
+    /// The changes that `set` and `remove` should modify
+    fn updates_mut(&mut self) -> &mut BTreeMap<EntityKey, Option<Entity>> {
+        if self.in_handler {
+            &mut self.handler_updates
+        } else {
+            &mut self.updates
+        }
+    }
+
+    /// Remember when `key` was first written if this is the first write
+    fn record_write(&mut self, key: &EntityKey) {
+        let first_write = if self.in_handler {
+            &mut self.handler_first_write
+        } else {
+            &mut self.first_write
+        };
+        if !first_write.contains_key(key) {
+            first_write.insert(key.clone(), self.writes);
+            self.writes += 1;
         }
     }
 
     pub fn remove(&mut self, key: EntityKey) {
-        self.updates.insert(key, None);
+        self.record_write(&key);
+        self.updates_mut().insert(key, None);
     }
 
     pub fn set(&mut self, key: EntityKey, mut entity: Entity) -> Result<(), QueryExecutionError> {
         use std::collections::btree_map::Entry;
 
-        let update = self.updates.entry(key.clone());
+        self.record_write(&key);
 
-        match update {
+        // Previous change was a removal, clear fields in the entity as it
+        // was before that removal
+        if self.updates_mut().get(&key) == Some(&None) {
+            let prior = if self.in_handler {
+                self.get_outside_handler(&key)?
+            } else {
+                self.current.get_entity(&*self.store, &key)?
+            };
+            if let Some(prior) = prior {
+                // Entity was removed so the fields not updated need to be unset.
+                for field in prior.keys().cloned() {
+                    entity.entry(field).or_insert(Value::Null);
+                }
+            }
+            self.updates_mut().insert(key, Some(entity));
+            return Ok(());
+        }
+
+        match self.updates_mut().entry(key) {
             // First change.
             Entry::Vacant(entry) => {
                 entry.insert(Some(entity));
             }
 
             // Previously changed.
-            Entry::Occupied(mut entry) => match entry.get_mut() {
-                Some(prev_update) => prev_update.merge(entity),
-
-                // Previous change was a removal, clear fields in `current`.
-                None => {
-                    if let Some(current) = self.current.get_entity(&*self.store, &key)? {
-                        // Entity was removed so the fields not updated need to be unset.
-                        for field in current.keys().cloned() {
-                            entity.entry(field).or_insert(Value::Null);
-                        }
-                    }
-
-                    entry.insert(Some(entity));
+            Entry::Occupied(mut entry) => {
+                if let Some(prev_update) = entry.get_mut() {
+                    prev_update.merge(entity);
                 }
-            },
+            }
         }
         Ok(())
     }
@@ -1446,7 +1996,9 @@ impl EntityCache {
 
     pub fn extend(&mut self, other: EntityCache) -> Result<(), QueryExecutionError> {
         self.current.extend(other.current);
-        for (key, update) in other.updates {
+        let mut updates: Vec<_> = other.updates.into_iter().collect();
+        updates.sort_by_key(|(key, _)| other.first_write.get(key).cloned());
+        for (key, update) in updates {
             match update {
                 Some(update) => self.set(key, update)?,
                 None => self.remove(key),
@@ -1457,13 +2009,16 @@ impl EntityCache {
 
     /// Return the changes that have been made via `set` and `remove` as
     /// `EntityModification`, making sure to only produce one when a change
-    /// to the current state is actually needed.
+    /// to the current state is actually needed. The modifications are in
+    /// the order in which their entities were first written.
     ///
     /// Also returns the updated `LfuCache`.
     pub fn as_modifications(
         mut self,
         store: &(impl Store + ?Sized),
     ) -> Result<ModificationsAndCache, QueryExecutionError> {
+        assert!(!self.in_handler);
+
         // The first step is to make sure all entities being set are in `self.current`.
         // For each subgraph, we need a map of entity type to missing entity ids.
         let missing = self
@@ -1494,8 +2049,12 @@ impl EntityCache {
             }
         }
 
+        let mut updates: Vec<_> = self.updates.into_iter().collect();
+        let first_write = &self.first_write;
+        updates.sort_by_key(|(key, _)| first_write.get(key).cloned());
+
         let mut mods = Vec::new();
-        for (key, update) in self.updates {
+        for (key, update) in updates {
             use EntityModification::*;
             let current = self.current.remove(&key).and_then(|entity| entity);
             let modification = match (current, update) {
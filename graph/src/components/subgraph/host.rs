@@ -1,4 +1,5 @@
 use std::cmp::PartialEq;
+use std::collections::BTreeSet;
 use std::fmt;
 use std::sync::Arc;
 
@@ -8,6 +9,7 @@ use futures::sync::mpsc;
 
 use crate::components::metrics::HistogramVec;
 use crate::components::subgraph::SharedProofOfIndexing;
+use crate::data::subgraph::SubgraphFeature;
 use crate::prelude::*;
 use web3::types::{Log, Transaction};
 
@@ -93,6 +95,7 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
 pub struct HostMetrics {
     handler_execution_time: Box<HistogramVec>,
     host_fn_execution_time: Box<HistogramVec>,
+    store_get_lookups: Box<CounterVec>,
     pub stopwatch: StopwatchMetrics,
 }
 
@@ -127,9 +130,19 @@ impl HostMetrics {
                 vec![0.025, 0.05, 0.2, 2.0, 8.0, 20.0],
             )
             .expect("failed to create `deployment_host_fn_execution_time` histogram");
+        let store_get_lookups = registry
+            .new_deployment_counter_vec(
+                "deployment_store_get_lookups",
+                "Counts the entities that `store.get` found in the entity cache \
+                 and those it had to load from the database",
+                subgraph,
+                vec![String::from("source")],
+            )
+            .expect("failed to create `deployment_store_get_lookups` counter");
         Self {
             handler_execution_time,
             host_fn_execution_time,
+            store_get_lookups,
             stopwatch,
         }
     }
@@ -145,6 +158,14 @@ impl HostMetrics {
             .with_label_values(vec![fn_name].as_slice())
             .observe(duration);
     }
+
+    /// Count a `store.get` call by where the entity came from, either
+    /// `cache` or `database`
+    pub fn observe_store_get(&self, source: &str) {
+        self.store_get_lookups
+            .with_label_values(vec![source].as_slice())
+            .inc();
+    }
 }
 
 pub trait RuntimeHostBuilder: Clone + Send + Sync + 'static {
@@ -158,6 +179,7 @@ pub trait RuntimeHostBuilder: Clone + Send + Sync + 'static {
         subgraph_id: SubgraphDeploymentId,
         data_source: DataSource,
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
+        features: BTreeSet<SubgraphFeature>,
         mapping_request_sender: mpsc::Sender<Self::Req>,
         metrics: Arc<HostMetrics>,
     ) -> Result<Self::Host, Error>;
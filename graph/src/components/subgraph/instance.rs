@@ -2,6 +2,7 @@ use async_trait::async_trait;
 use web3::types::Log;
 
 use crate::components::subgraph::{MappingError, SharedProofOfIndexing};
+use crate::data::subgraph::schema::SubgraphError;
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
 
@@ -17,6 +18,14 @@ pub struct DataSourceTemplateInfo {
 pub struct BlockState {
     pub entity_cache: EntityCache,
     pub created_data_sources: Vec<DataSourceTemplateInfo>,
+
+    /// Data sources created by the handler that is currently running
+    handler_created_data_sources: Vec<DataSourceTemplateInfo>,
+    in_handler: bool,
+
+    /// Errors of handlers that failed deterministically and whose changes
+    /// were discarded
+    pub deterministic_errors: Vec<SubgraphError>,
 }
 
 impl BlockState {
@@ -24,8 +33,53 @@ impl BlockState {
         BlockState {
             entity_cache: EntityCache::with_current(store, lfu_cache),
             created_data_sources: Vec::new(),
+            handler_created_data_sources: Vec::new(),
+            in_handler: false,
+            deterministic_errors: Vec::new(),
         }
     }
+
+    pub fn enter_handler(&mut self) {
+        assert!(!self.in_handler);
+        self.in_handler = true;
+        self.entity_cache.enter_handler();
+    }
+
+    /// Keep the entity changes and data sources of the current handler
+    pub fn exit_handler(&mut self) -> Result<(), QueryExecutionError> {
+        assert!(self.in_handler);
+        self.in_handler = false;
+        self.created_data_sources
+            .extend(self.handler_created_data_sources.drain(..));
+        self.entity_cache.exit_handler()
+    }
+
+    /// Throw away everything the current handler did
+    pub fn exit_handler_and_discard_changes(&mut self) {
+        assert!(self.in_handler);
+        self.in_handler = false;
+        self.handler_created_data_sources.clear();
+        self.entity_cache.exit_handler_and_discard_changes();
+    }
+
+    /// Throw away everything the current handler did and remember that it
+    /// failed with `error`
+    pub fn exit_handler_and_discard_changes_due_to_error(&mut self, error: SubgraphError) {
+        self.exit_handler_and_discard_changes();
+        self.deterministic_errors.push(error);
+    }
+
+    pub fn push_created_data_source(&mut self, ds: DataSourceTemplateInfo) {
+        if self.in_handler {
+            self.handler_created_data_sources.push(ds);
+        } else {
+            self.created_data_sources.push(ds);
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.deterministic_errors.is_empty()
+    }
 }
 
 /// Represents a loaded instance of a subgraph.
@@ -11,7 +11,10 @@ use std::sync::Arc;
 
 use crate::data::graphql::SerializableValue;
 use crate::data::subgraph::*;
-use crate::{components::store::StoreError, prelude::CacheWeight};
+use crate::{
+    components::store::{BlockNumber, StoreError},
+    prelude::CacheWeight,
+};
 
 #[derive(Debug)]
 pub struct CloneableFailureError(Arc<failure::Error>);
@@ -78,6 +81,8 @@ pub enum QueryExecutionError {
     EventStreamError,
     FulltextQueryRequiresFilter,
     DeploymentReverted,
+    InvalidCursor(String),
+    ImportedDeploymentBehind(SubgraphDeploymentId, Option<BlockNumber>, BlockNumber),
 }
 
 impl Error for QueryExecutionError {
@@ -218,6 +223,9 @@ impl fmt::Display for QueryExecutionError {
             TooExpensive => write!(f, "query is too expensive"),
             Throttled=> write!(f, "service is overloaded and can not run the query right now. Please try again in a few minutes"),
             DeploymentReverted => write!(f, "the chain was reorganized while executing the query"),
+            InvalidCursor(cursor) => write!(f, "`{}` is not a valid cursor for this query", cursor),
+            ImportedDeploymentBehind(id, Some(latest), block) => write!(f, "the imported deployment `{}` has only indexed up to block number {} and data for block number {} is therefore not yet available", id, latest, block),
+            ImportedDeploymentBehind(id, None, block) => write!(f, "the imported deployment `{}` has not started indexing and data for block number {} is therefore not yet available", id, block),
         }
     }
 }
@@ -258,6 +266,12 @@ pub enum QueryError {
     EncodingError(FromUtf8Error),
     ParseError(Arc<anyhow::Error>),
     ExecutionError(QueryExecutionError),
+    /// The deployment that was queried skipped some handlers because they
+    /// failed, and the data in the response might therefore be incomplete
+    IndexingError,
+    /// The client sent only the hash of a persisted query, and the server
+    /// does not know the query for that hash
+    PersistedQueryNotFound,
 }
 
 impl From<FromUtf8Error> for QueryError {
@@ -292,6 +306,9 @@ impl fmt::Display for QueryError {
             QueryError::EncodingError(ref e) => write!(f, "{}", e),
             QueryError::ExecutionError(ref e) => write!(f, "{}", e),
             QueryError::ParseError(ref e) => write!(f, "{}", e),
+            QueryError::IndexingError => write!(f, "indexing_error"),
+            // Clients recognize this error by its exact message
+            QueryError::PersistedQueryNotFound => write!(f, "PersistedQueryNotFound"),
         }
     }
 }
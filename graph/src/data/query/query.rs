@@ -108,7 +108,7 @@ impl serde::ser::Serialize for QueryVariables {
 #[derive(Clone, Debug)]
 pub struct Query {
     pub schema: Arc<ApiSchema>,
-    pub document: q::Document,
+    pub document: Arc<q::Document>,
     pub variables: Option<QueryVariables>,
     pub shape_hash: u64,
     pub network: Option<String>,
@@ -126,7 +126,18 @@ impl Query {
         network: Option<String>,
     ) -> Self {
         let shape_hash = shape_hash(&document);
+        Self::with_shape_hash(schema, Arc::new(document), shape_hash, variables, network)
+    }
 
+    /// Like `new`, but for documents whose `shape_hash` is already known,
+    /// for example because they were kept in a cache after parsing them
+    pub fn with_shape_hash(
+        schema: Arc<ApiSchema>,
+        document: Arc<q::Document>,
+        shape_hash: u64,
+        variables: Option<QueryVariables>,
+        network: Option<String>,
+    ) -> Self {
         let (query_text, variables_text) = if *crate::log::LOG_GQL_TIMING {
             (
                 document
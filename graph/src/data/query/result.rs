@@ -75,6 +75,12 @@ impl QueryResult {
         return !self.errors.is_empty();
     }
 
+    /// Mark the result as coming from a deployment that has encountered
+    /// errors while indexing
+    pub fn add_indexing_error(&mut self) {
+        self.errors.push(QueryError::IndexingError);
+    }
+
     pub fn append(&mut self, other: QueryResult) {
         // Currently we don't used extensions, the desired behaviour for merging them is tbd.
         assert!(self.extensions.is_none());
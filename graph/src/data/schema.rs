@@ -25,9 +25,41 @@ use std::sync::Arc;
 
 pub const SCHEMA_TYPE_NAME: &str = "_Schema_";
 
+/// The directive that declares an entity type as a rollup of another one
+pub const ROLLUP_DIRECTIVE: &str = "rollup";
+
 pub const META_FIELD_TYPE: &str = "_Meta_";
 pub const META_FIELD_NAME: &str = "_meta";
 
+pub const DELETION_TYPE: &str = "_Deletion_";
+pub const DELETIONS_FIELD_NAME: &str = "_deletions";
+
+pub const PAGE_INFO_TYPE: &str = "_PageInfo_";
+
+/// The suffix of the types that hold the result of aggregation queries;
+/// aggregates over `Thing` are returned as a `Thing_aggregate`
+pub const AGGREGATE_TYPE_SUFFIX: &str = "_aggregate";
+
+/// The suffix of the types that hold the versions returned by history
+/// queries; the versions of a `Thing` are returned as `Thing_version`
+pub const VERSION_TYPE_SUFFIX: &str = "_version";
+
+/// The suffix of the types that hold one page of a collection; pages of
+/// `Thing` are returned as a `Thing_page`
+pub const PAGE_TYPE_SUFFIX: &str = "_page";
+
+/// The directive that marks a generated `<Thing>_aggregate` type; its `type`
+/// argument is the name of the type that is aggregated
+pub const AGGREGATE_OF_DIRECTIVE: &str = "aggregateOf";
+
+/// The directive that marks a generated `<Thing>_version` type; its `type`
+/// argument is the name of the type whose versions it holds
+pub const VERSION_OF_DIRECTIVE: &str = "versionOf";
+
+/// The directive that marks a generated `<Thing>_page` type; its `type`
+/// argument is the name of the type whose entities are on the page
+pub const PAGE_OF_DIRECTIVE: &str = "pageOf";
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Strings(Vec<String>);
 
@@ -112,6 +144,8 @@ pub enum SchemaValidationError {
     FulltextIncludedFieldMissingRequiredProperty,
     #[fail(display = "Fulltext entity field, {}, not found or not a string", _0)]
     FulltextIncludedFieldInvalid(String),
+    #[fail(display = "Rollup `{}` is invalid: {}", _0, _1)]
+    RollupInvalid(String, String), // (type, reason)
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -265,14 +299,145 @@ impl From<&Directive> for FulltextDefinition {
         }
     }
 }
+/// The length of the time buckets of a rollup
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RollupInterval {
+    Hour,
+    Day,
+}
+
+impl RollupInterval {
+    pub fn seconds(&self) -> i32 {
+        match self {
+            RollupInterval::Hour => 3600,
+            RollupInterval::Day => 86400,
+        }
+    }
+}
+
+impl TryFrom<&String> for RollupInterval {
+    type Error = String;
+    fn try_from(interval: &String) -> Result<Self, Self::Error> {
+        match &interval[..] {
+            "hour" => Ok(RollupInterval::Hour),
+            "day" => Ok(RollupInterval::Day),
+            invalid => Err(format!(
+                "The interval {} is invalid. It must be one of: hour, day",
+                invalid
+            )),
+        }
+    }
+}
+
+/// How the value of a field of a rollup is computed from the source
+/// entities in a bucket
+#[derive(Clone, Debug, PartialEq)]
+pub enum RollupValue {
+    /// `@count`: the number of source entities
+    Count,
+    /// `@sum(field: ..)`: the sum of a field of the source entities; the
+    /// `ValueType` is the type of the sum, either `BigInt` or `BigDecimal`
+    Sum(String, ValueType),
+    /// `@last(field: ..)`: the value of a field of the source entity that
+    /// was added to the bucket last
+    Last(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RollupField {
+    pub name: String,
+    pub value: RollupValue,
+}
+
+/// A rollup declared with
+/// `@rollup(source: "Swap", interval: "hour", timestamp: "timestamp", dimensions: ["pair"])`
+/// on the entity type `entity_type`. Each entity of the rollup summarizes
+/// the `source` entities whose `timestamp` falls into the same bucket of
+/// length `interval` and that have the same values for the `dimensions`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RollupDefinition {
+    pub entity_type: String,
+    pub source: String,
+    pub interval: RollupInterval,
+    pub timestamp: String,
+    pub dimensions: Vec<String>,
+    pub fields: Vec<RollupField>,
+}
+
+impl RollupDefinition {
+    // Assumes that the `@rollup` directive on `rollup` has already been
+    // validated and unwraps liberally
+    fn new(rollup: &ObjectType) -> Self {
+        let directive = rollup.find_directive(ROLLUP_DIRECTIVE.to_owned()).unwrap();
+        let string = |name: &str| directive.argument(name).unwrap().as_string().unwrap();
+        let dimensions = directive
+            .argument("dimensions")
+            .and_then(|dimensions| dimensions.as_list())
+            .map(|dimensions| {
+                dimensions
+                    .iter()
+                    .map(|dimension| dimension.as_string().unwrap().clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let fields = rollup
+            .fields
+            .iter()
+            .filter_map(|field| {
+                let value = if field.find_directive("count".to_owned()).is_some() {
+                    RollupValue::Count
+                } else if let Some(sum) = field.find_directive("sum".to_owned()) {
+                    let value_type = ValueType::from_str(field.field_type.get_base_type()).unwrap();
+                    RollupValue::Sum(
+                        sum.argument("field").unwrap().as_string().unwrap().clone(),
+                        value_type,
+                    )
+                } else if let Some(last) = field.find_directive("last".to_owned()) {
+                    RollupValue::Last(last.argument("field").unwrap().as_string().unwrap().clone())
+                } else {
+                    return None;
+                };
+                Some(RollupField {
+                    name: field.name.clone(),
+                    value,
+                })
+            })
+            .collect();
+
+        RollupDefinition {
+            entity_type: rollup.name.clone(),
+            source: string("source").clone(),
+            interval: RollupInterval::try_from(string("interval")).unwrap(),
+            timestamp: string("timestamp").clone(),
+            dimensions,
+            fields,
+        }
+    }
+}
+
 #[derive(Debug, Fail, PartialEq, Eq, Clone)]
 pub enum SchemaImportError {
     #[fail(display = "Schema for imported subgraph `{}` was not found", _0)]
     ImportedSchemaNotFound(SchemaReference),
     #[fail(display = "Subgraph for imported schema `{}` is not deployed", _0)]
     ImportedSubgraphNotFound(SchemaReference),
+    #[fail(
+        display = "Imported type `{}` does not exist in the `{}` schema",
+        _0, _1
+    )]
+    ImportedTypeNotFound(String, SchemaReference),
 }
 
+/// The name of the directive that records the name an imported type has
+/// in the deployment it is imported from when it is imported under a
+/// different name
+pub const ORIGINAL_NAME_DIRECTIVE: &str = "originalName";
+
+/// How many levels of imports of imports we follow to find the definition
+/// of an imported type
+const MAX_IMPORT_DEPTH: usize = 10;
+
 /// The representation of a single type from an import statement. This
 /// corresponds either to a string `"Thing"` or an object
 /// `{name: "Thing", as: "Stuff"}`. The first form is equivalent to
@@ -298,6 +463,16 @@ impl fmt::Display for ImportedType {
 }
 
 impl ImportedType {
+    /// The name of the type in the schema it is imported from
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the type in the importing schema
+    pub fn alias(&self) -> &str {
+        &self.alias
+    }
+
     fn parse(type_import: &Value) -> Option<Self> {
         match type_import {
             Value::String(type_name) => Some(ImportedType {
@@ -306,10 +481,13 @@ impl ImportedType {
                 explicit: false,
             }),
             Value::Object(type_name_as) => {
-                match (type_name_as.get("name"), type_name_as.get("as")) {
+                match (
+                    type_name_as.get("name").and_then(|name| name.as_string()),
+                    type_name_as.get("as").and_then(|az| az.as_string()),
+                ) {
                     (Some(name), Some(az)) => Some(ImportedType {
-                        name: name.to_string(),
-                        alias: az.to_string(),
+                        name: name.to_owned(),
+                        alias: az.to_owned(),
                         explicit: true,
                     }),
                     _ => None,
@@ -320,6 +498,14 @@ impl ImportedType {
     }
 }
 
+/// Replace the named type at the bottom of `field_type` with `name`
+fn rename_base_type(field_type: &mut Type, name: Name) {
+    match field_type {
+        Type::NamedType(base) => *base = name,
+        Type::NonNullType(inner) | Type::ListType(inner) => rename_base_type(inner, name),
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct SchemaReference {
     subgraph: SubgraphDeploymentId,
@@ -336,6 +522,11 @@ impl SchemaReference {
         SchemaReference { subgraph }
     }
 
+    /// The deployment whose schema this refers to
+    pub fn subgraph(&self) -> &SubgraphDeploymentId {
+        &self.subgraph
+    }
+
     pub fn resolve<S: Store + SubgraphDeploymentStore>(
         &self,
         store: Arc<S>,
@@ -550,7 +741,9 @@ impl Schema {
         Ok(schema)
     }
 
-    fn imported_types(&self) -> HashMap<ImportedType, SchemaReference> {
+    /// Return the types this schema imports with the schemas they are
+    /// imported from
+    pub fn imported_types(&self) -> HashMap<ImportedType, SchemaReference> {
         fn parse_types(import: &Directive) -> Vec<ImportedType> {
             import
                 .argument("types")
@@ -593,6 +786,168 @@ impl Schema {
         })
     }
 
+    /// Find the definition of the type `name` that `schema_ref` exports,
+    /// following imports of imports. Return the definition together with
+    /// the schema that defines it
+    fn find_imported_type<'a>(
+        name: &str,
+        schema_ref: &SchemaReference,
+        schemas: &'a HashMap<SchemaReference, Arc<Schema>>,
+        depth: usize,
+    ) -> Result<(&'a ObjectType, &'a Schema), SchemaImportError> {
+        let schema = schemas
+            .get(schema_ref)
+            .ok_or_else(|| SchemaImportError::ImportedSchemaNotFound(schema_ref.clone()))?;
+        if let Some(object_type) = schema.document.get_object_type_definition(name) {
+            return Ok((object_type, schema.as_ref()));
+        }
+        match schema
+            .imported_types()
+            .into_iter()
+            .find(|(imported_type, _)| imported_type.alias == name)
+        {
+            Some((imported_type, next_ref)) if depth < MAX_IMPORT_DEPTH => {
+                Self::find_imported_type(&imported_type.name, &next_ref, schemas, depth + 1)
+            }
+            _ => Err(SchemaImportError::ImportedTypeNotFound(
+                name.to_owned(),
+                schema_ref.clone(),
+            )),
+        }
+    }
+
+    /// Find the definition of the type that this schema imports under the
+    /// name `alias`. `schemas` must contain the schemas of all deployments
+    /// this schema imports from, directly or indirectly
+    pub fn imported_object_type<'a>(
+        &self,
+        alias: &str,
+        schemas: &'a HashMap<SchemaReference, Arc<Schema>>,
+    ) -> Result<&'a ObjectType, SchemaImportError> {
+        let (imported_type, schema_ref) = self
+            .imported_types()
+            .into_iter()
+            .find(|(imported_type, _)| imported_type.alias == alias)
+            .ok_or_else(|| {
+                SchemaImportError::ImportedTypeNotFound(
+                    alias.to_owned(),
+                    SchemaReference::new(self.id.clone()),
+                )
+            })?;
+        Self::find_imported_type(&imported_type.name, &schema_ref, schemas, 0)
+            .map(|(object_type, _)| object_type)
+    }
+
+    /// Add the definitions of the types this schema imports to its
+    /// document so that queries can use them. This is only meant for
+    /// building API schemas; the input schema of a deployment never
+    /// contains imported types. `schemas` must contain the schemas of all
+    /// deployments this schema imports from, directly or indirectly.
+    ///
+    /// Imported types keep the `@subgraphId` directive that points to the
+    /// deployment that stores them. Types that are imported under a
+    /// different name get an `@originalName` directive with their name in
+    /// that deployment. Fields of imported types that refer to object types
+    /// that this schema does not import are left out, and so are the
+    /// interfaces that imported types implement
+    pub fn add_imported_types(
+        &mut self,
+        schemas: &HashMap<SchemaReference, Arc<Schema>>,
+    ) -> Result<(), SchemaImportError> {
+        let mut imports = self.imported_types().into_iter().collect::<Vec<_>>();
+        imports.sort_by(|(a, _), (b, _)| a.alias.cmp(&b.alias));
+
+        // The imported types with the deployment that stores them
+        let mut objects = Vec::new();
+        let mut enums: BTreeMap<Name, EnumType> = BTreeMap::new();
+        // Maps (deployment, name in deployment) to the name in this schema
+        let mut aliases = HashMap::new();
+        for (imported_type, schema_ref) in imports {
+            let (object_type, source) =
+                Self::find_imported_type(&imported_type.name, &schema_ref, schemas, 0)?;
+            aliases.insert(
+                (source.id.clone(), object_type.name.clone()),
+                imported_type.alias.clone(),
+            );
+            for field in &object_type.fields {
+                let base_type = field.field_type.get_base_type();
+                if let Some(enum_type) = source
+                    .document
+                    .get_enum_definitions()
+                    .into_iter()
+                    .find(|enum_type| &enum_type.name == base_type)
+                {
+                    enums
+                        .entry(enum_type.name.clone())
+                        .or_insert_with(|| enum_type.clone());
+                }
+            }
+            objects.push((imported_type.alias, object_type.clone(), source.id.clone()));
+        }
+
+        let local_enums = self
+            .document
+            .get_enum_definitions()
+            .into_iter()
+            .map(|enum_type| enum_type.name.clone())
+            .collect::<HashSet<_>>();
+        let enums = enums
+            .into_iter()
+            .filter(|(name, _)| !local_enums.contains(name))
+            .map(|(_, enum_type)| enum_type)
+            .collect::<Vec<_>>();
+        let known_enums = local_enums
+            .into_iter()
+            .chain(enums.iter().map(|enum_type| enum_type.name.clone()))
+            .collect::<HashSet<_>>();
+
+        for (alias, mut object_type, source) in objects {
+            if object_type.name != alias {
+                object_type.directives.push(Directive {
+                    name: ORIGINAL_NAME_DIRECTIVE.to_owned(),
+                    position: Pos::default(),
+                    arguments: vec![("name".to_owned(), Value::String(object_type.name.clone()))],
+                });
+                object_type.name = alias;
+            }
+            object_type.implements_interfaces = vec![];
+            object_type.fields = object_type
+                .fields
+                .into_iter()
+                .filter_map(|mut field| {
+                    let base_type = field.field_type.get_base_type().to_owned();
+                    if ValueType::is_scalar(&base_type) || known_enums.contains(&base_type) {
+                        Some(field)
+                    } else {
+                        // The field might refer to a type that the source
+                        // itself imports from yet another deployment
+                        let source = SchemaReference::new(source.clone());
+                        Self::find_imported_type(&base_type, &source, schemas, 0)
+                            .ok()
+                            .and_then(|(target, schema)| {
+                                aliases.get(&(schema.id.clone(), target.name.clone()))
+                            })
+                            .map(|alias| {
+                                rename_base_type(&mut field.field_type, alias.clone());
+                                field
+                            })
+                    }
+                })
+                .collect();
+            self.document
+                .definitions
+                .push(Definition::TypeDefinition(TypeDefinition::Object(
+                    object_type,
+                )));
+        }
+        for enum_type in enums {
+            self.document
+                .definitions
+                .push(Definition::TypeDefinition(TypeDefinition::Enum(enum_type)));
+        }
+        Ok(())
+    }
+
     pub fn name_argument_value_from_directive(directive: &Directive) -> Value {
         directive
             .argument("name")
@@ -672,6 +1027,7 @@ impl Schema {
         errors.append(&mut self.validate_fields());
         errors.append(&mut self.validate_import_directives());
         errors.append(&mut self.validate_fulltext_directives());
+        errors.append(&mut self.validate_rollup_directives());
         errors.append(&mut self.validate_imported_types(schemas));
         if errors.is_empty() {
             Ok(())
@@ -988,6 +1344,203 @@ impl Schema {
         return vec![];
     }
 
+    fn validate_rollup_directives(&self) -> Vec<SchemaValidationError> {
+        let local_types = self.document.get_object_type_definitions();
+        local_types
+            .iter()
+            .filter_map(|rollup| {
+                rollup
+                    .find_directive(ROLLUP_DIRECTIVE.to_owned())
+                    .map(|directive| (rollup, directive))
+            })
+            .filter_map(|(rollup, directive)| {
+                self.validate_rollup_directive(&local_types, rollup, directive)
+                    .err()
+                    .map(|reason| SchemaValidationError::RollupInvalid(rollup.name.clone(), reason))
+            })
+            .collect()
+    }
+
+    fn validate_rollup_directive(
+        &self,
+        local_types: &Vec<&ObjectType>,
+        rollup: &ObjectType,
+        directive: &Directive,
+    ) -> Result<(), String> {
+        fn is_list(field_type: &Type) -> bool {
+            match field_type {
+                Type::NamedType(_) => false,
+                Type::ListType(_) => true,
+                Type::NonNullType(inner) => is_list(inner),
+            }
+        }
+
+        // Look up a non-list field on `typ` and return its base type
+        fn scalar_field<'a>(typ: &'a ObjectType, name: &str) -> Result<&'a Name, String> {
+            match typ.fields.iter().find(|field| field.name == name) {
+                None => Err(format!("type `{}` has no field `{}`", typ.name, name)),
+                Some(field) if is_list(&field.field_type) => Err(format!(
+                    "the field `{}` of type `{}` must not be a list",
+                    name, typ.name
+                )),
+                Some(field) => Ok(field.field_type.get_base_type()),
+            }
+        }
+
+        let string = |name: &str| {
+            directive
+                .argument(name)
+                .and_then(|value| value.as_string())
+                .ok_or_else(|| format!("the argument `{}` must be a string", name))
+        };
+
+        let source = string("source")?;
+        let source = local_types
+            .iter()
+            .find(|typ| &typ.name == source)
+            .ok_or_else(|| format!("the source type `{}` is not a local entity type", source))?;
+        if source.find_directive(ROLLUP_DIRECTIVE.to_owned()).is_some() {
+            return Err(format!(
+                "the source type `{}` must not be a rollup itself",
+                source.name
+            ));
+        }
+
+        RollupInterval::try_from(string("interval")?)?;
+
+        match scalar_field(rollup, "id").map(|id| ValueType::from_str(id)) {
+            Ok(Ok(ValueType::String)) => (),
+            _ => return Err("the `id` field must be of type `ID` or `String`".to_owned()),
+        }
+
+        let timestamp = string("timestamp")?;
+        let timestamp_type = scalar_field(source, timestamp)?;
+        match ValueType::from_str(timestamp_type) {
+            Ok(ValueType::Int) | Ok(ValueType::BigInt) => (),
+            _ => {
+                return Err(format!(
+                    "the timestamp field `{}` must be of type `Int` or `BigInt`",
+                    timestamp
+                ))
+            }
+        }
+        match source.fields.iter().find(|field| &field.name == timestamp) {
+            Some(Field {
+                field_type: Type::NonNullType(_),
+                ..
+            }) => (),
+            _ => {
+                return Err(format!(
+                    "the timestamp field `{}` of type `{}` must be non-null",
+                    timestamp, source.name
+                ))
+            }
+        }
+
+        let dimensions = match directive.argument("dimensions") {
+            None => vec![],
+            Some(dimensions) => dimensions
+                .as_list()
+                .and_then(|dimensions| {
+                    dimensions
+                        .iter()
+                        .map(|dimension| dimension.as_string())
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| "the argument `dimensions` must be a list of strings".to_owned())?,
+        };
+
+        // The timestamp and the dimensions are copied from the source
+        for name in std::iter::once(timestamp).chain(dimensions.iter().cloned()) {
+            let source_type = scalar_field(source, name)?;
+            if scalar_field(rollup, name)? != source_type {
+                return Err(format!(
+                    "the field `{}` must have the same type `{}` as on the source type",
+                    name, source_type
+                ));
+            }
+        }
+
+        for field in &rollup.fields {
+            let copied =
+                field.name == "id" || field.name == timestamp || dimensions.contains(&&field.name);
+            let derived = field.find_directive("derivedFrom".to_owned()).is_some();
+            let values: Vec<_> = ["count", "sum", "last"]
+                .iter()
+                .filter_map(|name| field.find_directive(name.to_string()))
+                .collect();
+
+            if copied || derived {
+                if !values.is_empty() {
+                    return Err(format!(
+                        "the field `{}` must not have a `@{}` directive",
+                        field.name, values[0].name
+                    ));
+                }
+                continue;
+            }
+
+            let value = match values.as_slice() {
+                [value] => value,
+                _ => {
+                    return Err(format!(
+                        "the field `{}` must have exactly one of `@count`, `@sum` or `@last`",
+                        field.name
+                    ))
+                }
+            };
+            let base_type = scalar_field(rollup, &field.name)?;
+            if value.name == "count" {
+                match ValueType::from_str(base_type) {
+                    Ok(ValueType::Int) => continue,
+                    _ => {
+                        return Err(format!(
+                            "the `@count` field `{}` must be of type `Int`",
+                            field.name
+                        ))
+                    }
+                }
+            }
+
+            let source_field = value
+                .argument("field")
+                .and_then(|value| value.as_string())
+                .ok_or_else(|| {
+                    format!(
+                        "the `@{}` directive on `{}` needs a `field` argument",
+                        value.name, field.name
+                    )
+                })?;
+            let source_type = scalar_field(source, source_field)?;
+            if value.name == "sum" {
+                // Sums of `Int` fields are kept as `BigInt` so that they
+                // can not overflow
+                let sum_type = match ValueType::from_str(source_type) {
+                    Ok(ValueType::Int) | Ok(ValueType::BigInt) => "BigInt",
+                    Ok(ValueType::BigDecimal) => "BigDecimal",
+                    _ => {
+                        return Err(format!(
+                            "the `@sum` field `{}` must add up a field of type `Int`, `BigInt` or `BigDecimal`",
+                            field.name
+                        ))
+                    }
+                };
+                if base_type != sum_type {
+                    return Err(format!(
+                        "the `@sum` field `{}` must be of type `{}` to hold the sum of `{}.{}`",
+                        field.name, sum_type, source.name, source_field
+                    ));
+                }
+            } else if source_type != base_type {
+                return Err(format!(
+                    "the field `{}` must have the same type as `{}.{}`",
+                    field.name, source.name, source_field
+                ));
+            }
+        }
+        Ok(())
+    }
+
     fn validate_import_directives(&self) -> Vec<SchemaValidationError> {
         self.subgraph_schema_object_type()
             .map_or(vec![], |subgraph_schema_type| {
@@ -1306,6 +1859,17 @@ impl Schema {
             .map(|directive| FulltextDefinition::from(directive))
             .collect()
     }
+
+    /// The rollups declared with `@rollup` in this schema. The schema must
+    /// have been validated
+    pub fn rollup_definitions(&self) -> Vec<RollupDefinition> {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter(|typ| typ.find_directive(ROLLUP_DIRECTIVE.to_owned()).is_some())
+            .map(RollupDefinition::new)
+            .collect()
+    }
 }
 
 #[test]
@@ -1548,6 +2112,89 @@ type T @entity { id: ID! }
     }
 }
 
+#[test]
+fn test_add_imported_types() {
+    const ROOT_SCHEMA: &str = r#"
+type _Schema_ @import(types: [{name: "Token", as: "Coin"}, "Owner"], from: { id: "c1id" })
+type Transfer @entity { id: ID!, coin: Coin! }"#;
+    const CHILD_1_SCHEMA: &str = r#"
+type _Schema_ @import(types: ["Owner"], from: { id: "c2id" })
+interface Thing { id: ID! }
+enum Kind { Fungible, Other }
+type Token implements Thing @entity {
+  id: ID!
+  kind: Kind!
+  owner: Owner
+  holders: [Holder!]!
+}
+type Holder @entity { id: ID! }"#;
+    const CHILD_2_SCHEMA: &str = r#"
+type Owner @entity { id: ID!, name: String! }
+"#;
+
+    let parse = |id: &str, text: &str| {
+        let id = SubgraphDeploymentId::new(id).unwrap();
+        Schema::parse(text, id).expect("Failed to parse schema")
+    };
+    let mut root_schema = parse("rid", ROOT_SCHEMA);
+    let child_1_schema = parse("c1id", CHILD_1_SCHEMA);
+    let child_2_schema = parse("c2id", CHILD_2_SCHEMA);
+
+    let mut schemas = HashMap::new();
+    for schema in vec![child_1_schema, child_2_schema] {
+        schemas.insert(SchemaReference::new(schema.id.clone()), Arc::new(schema));
+    }
+
+    root_schema
+        .add_imported_types(&schemas)
+        .expect("Failed to add imported types");
+
+    let coin = root_schema
+        .document
+        .get_object_type_definition("Coin")
+        .expect("Coin was added");
+    assert!(coin.implements_interfaces.is_empty());
+    assert_eq!(
+        vec!["id", "kind", "owner"],
+        coin.fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect::<Vec<_>>()
+    );
+    let original_name = coin
+        .find_directive(ORIGINAL_NAME_DIRECTIVE.to_owned())
+        .and_then(|directive| directive.argument("name"))
+        .and_then(|name| name.as_string())
+        .map(String::as_str);
+    assert_eq!(Some("Token"), original_name);
+    assert_eq!(
+        Some(Value::String("c1id".to_owned())),
+        coin.find_directive("subgraphId".to_owned())
+            .and_then(|directive| directive.argument("id"))
+            .cloned()
+    );
+    assert!(root_schema
+        .document
+        .get_enum_definitions()
+        .iter()
+        .any(|enum_type| enum_type.name == "Kind"));
+
+    let owner = root_schema
+        .document
+        .get_object_type_definition("Owner")
+        .expect("Owner was added");
+    assert!(owner
+        .find_directive(ORIGINAL_NAME_DIRECTIVE.to_owned())
+        .is_none());
+    assert_eq!(
+        Some(Value::String("c2id".to_owned())),
+        owner
+            .find_directive("subgraphId".to_owned())
+            .and_then(|directive| directive.argument("id"))
+            .cloned()
+    );
+}
+
 #[test]
 fn test_fulltext_directive_validation() {
     const SCHEMA: &str = r#"
@@ -1577,3 +2224,95 @@ type Gravatar @entity {
 
     assert_eq!(schema.validate_fulltext_directives(), vec![]);
 }
+
+#[test]
+fn test_rollup_directive_validation() {
+    const SCHEMA: &str = r#"
+type Swap @entity {
+  id: ID!
+  timestamp: Int!
+  token: String!
+  amount: BigDecimal!
+  price: BigDecimal!
+}
+
+type TokenHourData @entity @rollup(source: "Swap", interval: "hour", timestamp: "timestamp", dimensions: ["token"]) {
+  id: ID!
+  timestamp: Int!
+  token: String!
+  swapCount: Int! @count
+  volume: BigDecimal! @sum(field: "amount")
+  lastPrice: BigDecimal @last(field: "price")
+}"#;
+
+    let document = graphql_parser::parse_schema(SCHEMA).expect("Failed to parse schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id1").unwrap(), document);
+    assert_eq!(schema.validate_rollup_directives(), vec![]);
+
+    let rollups = schema.rollup_definitions();
+    assert_eq!(1, rollups.len());
+    assert_eq!("TokenHourData", rollups[0].entity_type);
+    assert_eq!(RollupInterval::Hour, rollups[0].interval);
+    assert_eq!(vec!["token".to_owned()], rollups[0].dimensions);
+    assert_eq!(
+        vec![
+            RollupValue::Count,
+            RollupValue::Sum("amount".to_owned(), ValueType::BigDecimal),
+            RollupValue::Last("price".to_owned())
+        ],
+        rollups[0]
+            .fields
+            .iter()
+            .map(|field| field.value.clone())
+            .collect::<Vec<_>>()
+    );
+
+    let invalid = SCHEMA.replace("\"hour\"", "\"week\"");
+    let document = graphql_parser::parse_schema(&invalid).expect("Failed to parse schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id1").unwrap(), document);
+    assert_eq!(
+        schema.validate_rollup_directives(),
+        vec![SchemaValidationError::RollupInvalid(
+            "TokenHourData".to_owned(),
+            "The interval week is invalid. It must be one of: hour, day".to_owned()
+        )]
+    );
+
+    let invalid = SCHEMA.replace("BigDecimal! @sum", "Int! @sum");
+    let document = graphql_parser::parse_schema(&invalid).expect("Failed to parse schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id1").unwrap(), document);
+    assert_eq!(
+        schema.validate_rollup_directives(),
+        vec![SchemaValidationError::RollupInvalid(
+            "TokenHourData".to_owned(),
+            "the `@sum` field `volume` must be of type `BigDecimal` to hold the sum of `Swap.amount`"
+                .to_owned()
+        )]
+    );
+
+    let invalid = SCHEMA.replace(
+        "lastPrice: BigDecimal @last(field: \"price\")",
+        "count: Int! @sum(field: \"timestamp\")",
+    );
+    let document = graphql_parser::parse_schema(&invalid).expect("Failed to parse schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id1").unwrap(), document);
+    assert_eq!(
+        schema.validate_rollup_directives(),
+        vec![SchemaValidationError::RollupInvalid(
+            "TokenHourData".to_owned(),
+            "the `@sum` field `count` must be of type `BigInt` to hold the sum of `Swap.timestamp`"
+                .to_owned()
+        )]
+    );
+
+    let invalid = SCHEMA.replacen("timestamp: Int!", "timestamp: Int", 1);
+    let document = graphql_parser::parse_schema(&invalid).expect("Failed to parse schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id1").unwrap(), document);
+    assert_eq!(
+        schema.validate_rollup_directives(),
+        vec![SchemaValidationError::RollupInvalid(
+            "TokenHourData".to_owned(),
+            "the timestamp field `timestamp` of type `Swap` must be non-null".to_owned()
+        )]
+    );
+}
@@ -14,7 +14,7 @@ use stable_hash::{
 use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Display, Formatter};
 use std::io::Write;
-use std::ops::{Add, Deref, Div, Mul, Rem, Sub};
+use std::ops::{Add, BitAnd, BitOr, Deref, Div, Mul, Rem, Shl, Shr, Sub};
 use std::str::FromStr;
 
 pub use num_bigint::Sign as BigIntSign;
@@ -82,6 +82,51 @@ impl BigDecimal {
 
         BigDecimal(bigdecimal::BigDecimal::new(int_val.into(), scale))
     }
+
+    /// Drop all digits after the first `decimals` digits after the decimal
+    /// point, rounding toward zero.
+    pub fn truncated(&self, decimals: i64) -> BigDecimal {
+        self.with_decimals(decimals, |_, _| false)
+    }
+
+    /// Round to `decimals` digits after the decimal point. Halfway values are
+    /// rounded away from zero.
+    pub fn rounded(&self, decimals: i64) -> BigDecimal {
+        self.with_decimals(decimals, |remainder, divisor| {
+            remainder.abs() * 2 >= *divisor
+        })
+    }
+
+    /// Reduce the number of digits after the decimal point to `decimals`.
+    /// The digits that are dropped are `remainder / divisor`, and the result
+    /// is moved away from zero by one unit iff `round_up` returns `true`
+    fn with_decimals<F>(&self, decimals: i64, round_up: F) -> BigDecimal
+    where
+        F: Fn(&num_bigint::BigInt, &num_bigint::BigInt) -> bool,
+    {
+        use num_traits::{Pow, Signed};
+
+        let (digits, scale) = self.as_bigint_and_exponent();
+        if decimals >= scale {
+            return self.clone();
+        }
+        // Since values are normalized, `self.digits()` is small, and checking
+        // this avoids computing huge powers of ten
+        let dropped = scale.saturating_sub(decimals);
+        if dropped > self.digits() as i64 {
+            return BigDecimal::zero();
+        }
+
+        let divisor = num_bigint::BigInt::from(10u8).pow(dropped as u64);
+        let quotient = &digits / &divisor;
+        let remainder = &digits % &divisor;
+        let quotient = if round_up(&remainder, &divisor) {
+            quotient + remainder.signum()
+        } else {
+            quotient
+        };
+        BigDecimal::from(bigdecimal::BigDecimal::new(quotient, decimals))
+    }
 }
 
 impl Display for BigDecimal {
@@ -323,6 +368,15 @@ impl BigInt {
     pub fn bits(&self) -> usize {
         self.0.bits()
     }
+
+    /// The value of `self` as a Solidity `int256`, i.e., the lowest 256 bits
+    /// of its two's complement representation read as a signed number.
+    pub fn wrapping_int256(self) -> Self {
+        let fill = if self < BigInt::from(0) { 255 } else { 0 };
+        let mut bytes = self.to_signed_bytes_le();
+        bytes.resize(32, fill);
+        BigInt::from_signed_bytes_le(&bytes)
+    }
 }
 
 impl Display for BigInt {
@@ -446,6 +500,42 @@ impl Rem for BigInt {
     }
 }
 
+/// Bitwise operations treat negative numbers as if they were in two's
+/// complement with an infinite number of leading ones.
+impl BitOr for BigInt {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self::from(self.0.bitor(other.0))
+    }
+}
+
+impl BitAnd for BigInt {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self::from(self.0.bitand(other.0))
+    }
+}
+
+impl Shl<u8> for BigInt {
+    type Output = Self;
+
+    fn shl(self, bits: u8) -> Self {
+        Self::from(self.0.shl(bits.into()))
+    }
+}
+
+/// This is an arithmetic shift which rounds toward negative infinity, like
+/// `>>` for signed integers in Solidity.
+impl Shr<u8> for BigInt {
+    type Output = Self;
+
+    fn shr(self, bits: u8) -> Self {
+        Self::from(self.0.shr(bits.into()))
+    }
+}
+
 /// A byte array that's serialized as a hex string prefixed by `0x`.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Bytes(Box<[u8]>);
@@ -612,4 +702,58 @@ mod test {
             assert_eq!(normalized.to_string(), string);
         }
     }
+
+    #[test]
+    fn big_int_bitwise_operations() {
+        let n = |s: &str| BigInt::from_str(s).unwrap();
+
+        assert_eq!(n("12") | n("10"), n("14"));
+        assert_eq!(n("12") & n("10"), n("8"));
+        assert_eq!(n("-1") & n("255"), n("255"));
+        assert_eq!(n("-256") | n("15"), n("-241"));
+
+        assert_eq!(n("1") << 255, n("2").pow(255));
+        assert_eq!(n("-3") << 2, n("-12"));
+        assert_eq!(n("7") >> 1, n("3"));
+        // Like Solidity, shifting negative numbers rounds down
+        assert_eq!(n("-7") >> 1, n("-4"));
+        assert_eq!(n("-1") >> 200, n("-1"));
+
+        // Values outside of the range of `int256` wrap around
+        let max = n("2").pow(255) - n("1");
+        let min = n("0") - n("2").pow(255);
+        assert_eq!(n("-12").wrapping_int256(), n("-12"));
+        assert_eq!(max.clone().wrapping_int256(), max);
+        assert_eq!(min.clone().wrapping_int256(), min);
+        assert_eq!((max.clone() + n("1")).wrapping_int256(), min);
+        assert_eq!((min - n("1")).wrapping_int256(), max);
+        assert_eq!(n("2").pow(256).wrapping_int256(), n("0"));
+    }
+
+    #[test]
+    fn big_decimal_rounding_and_truncation() {
+        let d = |s: &str| BigDecimal::from_str(s).unwrap();
+        let cases = vec![
+            ("1.2345", 2, "1.23", "1.23"),
+            ("1.235", 2, "1.23", "1.24"),
+            ("-1.235", 2, "-1.23", "-1.24"),
+            ("-1.234", 2, "-1.23", "-1.23"),
+            ("0.5", 0, "0", "1"),
+            ("0.05", 0, "0", "0"),
+            ("-0.5", 0, "0", "-1"),
+            ("123.45", 5, "123.45", "123.45"),
+            ("1900", 0, "1900", "1900"),
+            ("99.99", 1, "99.9", "100"),
+        ];
+
+        for (value, decimals, truncated, rounded) in cases {
+            assert_eq!(d(value).truncated(decimals), d(truncated), "{}", value);
+            assert_eq!(d(value).rounded(decimals), d(rounded), "{}", value);
+        }
+        assert_eq!(
+            d("0.000001").truncated(i64::min_value()),
+            BigDecimal::zero()
+        );
+        assert_eq!(d("0.000001").rounded(i64::max_value()), d("0.000001"));
+    }
 }
@@ -36,6 +36,7 @@ use crate::util::ethereum::string_to_h256;
 use graphql_parser::query as q;
 
 use crate::components::ethereum::NodeCapabilities;
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
@@ -292,6 +293,8 @@ pub enum SubgraphRegistrarError {
     DeploymentNotFound(String),
     #[fail(display = "deployment assignment unchanged: {}", _0)]
     DeploymentAssignmentUnchanged(String),
+    #[fail(display = "block not found: {}", _0)]
+    BlockNotFound(String),
     #[fail(display = "subgraph registrar internal query error: {}", _0)]
     QueryExecutionError(QueryExecutionError),
     #[fail(display = "subgraph registrar error with store: {}", _0)]
@@ -947,6 +950,16 @@ impl Graft {
     }
 }
 
+/// Optional behavior that a subgraph opts into by listing it under
+/// `features` in its manifest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SubgraphFeature {
+    /// When a handler fails deterministically, discard the changes it made,
+    /// record the error as non-fatal and keep indexing
+    NonFatalErrors,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BaseSubgraphManifest<S, D, T> {
@@ -960,6 +973,8 @@ pub struct BaseSubgraphManifest<S, D, T> {
     pub graft: Option<Graft>,
     #[serde(default)]
     pub templates: Vec<T>,
+    #[serde(default)]
+    pub features: BTreeSet<SubgraphFeature>,
 }
 
 /// Consider two subgraphs to be equal if they come from the same IPLD link.
@@ -1212,6 +1227,7 @@ impl UnresolvedSubgraphManifest {
             data_sources,
             graft,
             templates,
+            features,
         } = self;
 
         match semver::Version::parse(&spec_version) {
@@ -1256,6 +1272,7 @@ impl UnresolvedSubgraphManifest {
             data_sources,
             graft,
             templates,
+            features,
         })
     }
 }
@@ -1278,6 +1295,9 @@ pub struct DeploymentState {
     pub max_reorg_depth: u32,
     /// The number of the last block that the subgraph has processed
     pub latest_ethereum_block_number: BlockNumber,
+    /// Whether handlers were skipped because they failed while indexing
+    /// the subgraph, which makes its data incomplete
+    pub has_non_fatal_errors: bool,
 }
 
 impl DeploymentState {
@@ -1289,6 +1309,7 @@ impl DeploymentState {
             reorg_count: 0,
             max_reorg_depth: 0,
             latest_ethereum_block_number: BLOCK_NUMBER_MAX,
+            has_non_fatal_errors: false,
         }
     }
 }
@@ -93,6 +93,8 @@ pub trait TypedEntity {
         let range = EntityRange {
             first: None,
             skip: 0,
+            after: None,
+            before: None,
         };
         EntityQuery::new(
             SUBGRAPHS_ID.clone(),
@@ -307,6 +309,10 @@ pub struct SubgraphDeploymentEntity {
     reorg_count: i32,
     current_reorg_depth: i32,
     max_reorg_depth: i32,
+    retry_count: i32,
+    next_retry_at: Option<u64>,
+    history_blocks: Option<i32>,
+    history_horizon: i32,
 }
 
 impl TypedEntity for SubgraphDeploymentEntity {
@@ -337,6 +343,10 @@ impl SubgraphDeploymentEntity {
             reorg_count: 0,
             current_reorg_depth: 0,
             max_reorg_depth: 0,
+            retry_count: 0,
+            next_retry_at: None,
+            history_blocks: None,
+            history_horizon: 0,
         }
     }
 
@@ -351,6 +361,18 @@ impl SubgraphDeploymentEntity {
         self
     }
 
+    /// The network that the first data source of this deployment indexes
+    pub fn network(&self) -> Option<&str> {
+        self.manifest
+            .data_sources
+            .first()
+            .and_then(|ds| ds.network.as_deref())
+    }
+
+    pub fn graft_base(&self) -> Option<&SubgraphDeploymentId> {
+        self.graft_base.as_ref()
+    }
+
     pub fn create_operations(self, id: &SubgraphDeploymentId) -> Vec<MetadataOperation> {
         let mut ops = vec![];
 
@@ -371,6 +393,10 @@ impl SubgraphDeploymentEntity {
             reorg_count: _,
             current_reorg_depth: _,
             max_reorg_depth: _,
+            retry_count: _,
+            next_retry_at: _,
+            history_blocks: _,
+            history_horizon: _,
         } = self;
 
         // A fresh subgraph will not have any errors.
@@ -411,7 +437,7 @@ impl SubgraphDeploymentEntity {
         id: &SubgraphDeploymentId,
         error: SubgraphError,
     ) -> Vec<MetadataOperation> {
-        let error_id = hex::encode(&stable_hash::<SetHasher, _>(&error));
+        let error_id = error.id();
 
         let mut entity = Entity::new();
         entity.set("failed", true);
@@ -423,12 +449,32 @@ impl SubgraphDeploymentEntity {
             update_metadata_operation(Self::TYPENAME, id.as_str(), entity),
         ]
     }
+
+    /// Record that indexing of a deployment that failed with a
+    /// non-deterministic error will be retried for the `retry_count`-th
+    /// time at `next_retry_at`, given in seconds since the Unix epoch
+    pub fn retry_operations(
+        id: &SubgraphDeploymentId,
+        retry_count: i32,
+        next_retry_at: u64,
+    ) -> Vec<MetadataOperation> {
+        let entity = entity! {
+            retryCount: retry_count,
+            nextRetryAt: next_retry_at,
+        };
+        vec![update_metadata_operation(
+            Self::TYPENAME,
+            id.as_str(),
+            entity,
+        )]
+    }
 }
 
 #[derive(Debug)]
 pub struct SubgraphDeploymentAssignmentEntity {
     node_id: NodeId,
     cost: u64,
+    paused: bool,
 }
 
 impl TypedEntity for SubgraphDeploymentAssignmentEntity {
@@ -438,7 +484,11 @@ impl TypedEntity for SubgraphDeploymentAssignmentEntity {
 
 impl SubgraphDeploymentAssignmentEntity {
     pub fn new(node_id: NodeId) -> Self {
-        Self { node_id, cost: 1 }
+        Self {
+            node_id,
+            cost: 1,
+            paused: false,
+        }
     }
 
     pub fn write_operations(self, id: &SubgraphDeploymentId) -> Vec<MetadataOperation> {
@@ -446,6 +496,7 @@ impl SubgraphDeploymentAssignmentEntity {
             id: id.to_string(),
             nodeId: self.node_id.to_string(),
             cost: self.cost,
+            paused: self.paused,
         };
         vec![set_metadata_operation(Self::TYPENAME, id.as_str(), entity)]
     }
@@ -1317,6 +1368,11 @@ impl TypedEntity for SubgraphError {
 }
 
 impl SubgraphError {
+    /// The id under which this error is stored, derived from its contents
+    pub fn id(&self) -> String {
+        hex::encode(&stable_hash::<SetHasher, _>(self))
+    }
+
     fn create_operation(self, id: String) -> MetadataOperation {
         let mut entity = Entity::from(self);
         entity.set("id", id.clone());
@@ -25,23 +25,27 @@ impl<S: SubgraphDeploymentStore + Store, Q: GraphQlRunner> LazyMetadata<S, Q> {
         let value = self
             .graphql_runner
             .cheap_clone()
-            .query_metadata(Query::new(
-                self.store.api_schema(&SUBGRAPHS_ID).unwrap(),
-                parse_query(
-                    r#"
+            .query_deployment_metadata(
+                self.id.clone(),
+                Query::new(
+                    self.store.api_schema(&SUBGRAPHS_ID).unwrap(),
+                    parse_query(
+                        r#"
                         query deployment($id: ID!) {
                             subgraphDeployment(id: $id) {
                                 health
                             }
                         }
                     "#,
-                )
-                .unwrap(),
-                Some(QueryVariables::new(HashMap::from_iter(
-                    vec![(String::from("id"), q::Value::String(self.id.to_string()))].into_iter(),
-                ))),
-                None,
-            ))
+                    )
+                    .unwrap(),
+                    Some(QueryVariables::new(HashMap::from_iter(
+                        vec![(String::from("id"), q::Value::String(self.id.to_string()))]
+                            .into_iter(),
+                    ))),
+                    None,
+                ),
+            )
             .await?;
 
         let deployment = match value.as_ref() {
@@ -59,10 +63,12 @@ impl<S: SubgraphDeploymentStore + Store, Q: GraphQlRunner> LazyMetadata<S, Q> {
         let value = self
             .graphql_runner
             .cheap_clone()
-            .query_metadata(Query::new(
-                self.store.api_schema(&SUBGRAPHS_ID).unwrap(),
-                parse_query(
-                    r#"
+            .query_deployment_metadata(
+                self.id.clone(),
+                Query::new(
+                    self.store.api_schema(&SUBGRAPHS_ID).unwrap(),
+                    parse_query(
+                        r#"
                         query deployment($id: ID!) {
                             subgraphDeployment(id: $id) {
                                 nonFatalErrors(limit: 1) {
@@ -71,13 +77,15 @@ impl<S: SubgraphDeploymentStore + Store, Q: GraphQlRunner> LazyMetadata<S, Q> {
                             }
                         }
                     "#,
-                )
-                .unwrap(),
-                Some(QueryVariables::new(HashMap::from_iter(
-                    vec![(String::from("id"), q::Value::String(self.id.to_string()))].into_iter(),
-                ))),
-                None,
-            ))
+                    )
+                    .unwrap(),
+                    Some(QueryVariables::new(HashMap::from_iter(
+                        vec![(String::from("id"), q::Value::String(self.id.to_string()))]
+                            .into_iter(),
+                    ))),
+                    None,
+                ),
+            )
             .await?;
 
         let deployment = match value.as_ref() {
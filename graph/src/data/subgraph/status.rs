@@ -88,6 +88,20 @@ pub struct Info {
 
     /// ID of the Graph Node that the subgraph is indexed by.
     pub node: Option<String>,
+
+    /// Whether indexing of the subgraph has been paused.
+    pub paused: bool,
+
+    /// How often indexing has been retried automatically after a
+    /// non-deterministic failure, and when the next retry will happen, in
+    /// seconds since the Unix epoch.
+    pub retry_count: i32,
+    pub next_retry_at: Option<u64>,
+
+    /// How many blocks of history the deployment keeps, and the earliest
+    /// block that can still be queried.
+    pub history_blocks: Option<i32>,
+    pub history_horizon: i32,
 }
 
 impl IntoValue for Info {
@@ -100,6 +114,11 @@ impl IntoValue for Info {
             health,
             node,
             non_fatal_errors,
+            paused,
+            retry_count,
+            next_retry_at,
+            history_blocks,
+            history_horizon,
             synced,
         } = self;
 
@@ -142,6 +161,11 @@ impl IntoValue for Info {
             chains: chains.into_iter().map(|chain| chain.into_value()).collect::<Vec<_>>(),
             entityCount: format!("{}", entity_count),
             node: node,
+            paused: paused,
+            retryCount: retry_count,
+            nextRetryAt: next_retry_at.map(|at| format!("{}", at)),
+            historyBlocks: history_blocks,
+            historyHorizon: history_horizon,
         }
     }
 }
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+/// Compute delays between retries of an operation. The delay starts at
+/// `base` and doubles with each attempt until it reaches `ceiling`
+#[derive(Clone, Debug)]
+pub struct ExponentialBackoff {
+    /// The number of attempts that have been made so far
+    pub attempt: u32,
+    base: Duration,
+    ceiling: Duration,
+}
+
+impl ExponentialBackoff {
+    pub fn new(base: Duration, ceiling: Duration) -> Self {
+        ExponentialBackoff {
+            attempt: 0,
+            base,
+            ceiling,
+        }
+    }
+
+    /// The delay to wait before the next attempt
+    pub fn delay(&self) -> Duration {
+        // Avoid overflowing the shift; the ceiling is reached long before
+        2u32.checked_pow(self.attempt)
+            .and_then(|factor| self.base.checked_mul(factor))
+            .map(|delay| delay.min(self.ceiling))
+            .unwrap_or(self.ceiling)
+    }
+
+    /// Record an attempt and return how long to wait before making it
+    pub fn next_attempt(&mut self) -> Duration {
+        let delay = self.delay();
+        self.attempt += 1;
+        delay
+    }
+
+    /// Forget all previous attempts so that the next delay is `base` again
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExponentialBackoff;
+    use std::time::Duration;
+
+    #[test]
+    fn delays_double_up_to_ceiling() {
+        let mut backoff = ExponentialBackoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_attempt().as_secs()).collect();
+        assert_eq!(vec![1, 2, 4, 5, 5], delays);
+        assert_eq!(5, backoff.attempt);
+
+        backoff.attempt = 200;
+        assert_eq!(Duration::from_secs(5), backoff.delay());
+
+        backoff.reset();
+        assert_eq!(Duration::from_secs(1), backoff.next_attempt());
+    }
+}
@@ -134,6 +134,13 @@ impl<K: Clone + Ord + Eq + Hash + Debug + CacheWeight, V: CacheWeight + Default>
         self.get_mut(key.clone()).map(|x| &x.value)
     }
 
+    /// Like `get`, but without counting as an access to the entry
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.queue
+            .get(&CacheEntry::cache_key(key.clone()))
+            .map(|(entry, _)| &entry.value)
+    }
+
     pub fn remove(&mut self, key: &K) -> Option<V> {
         // `PriorityQueue` doesn't have a remove method, so emulate that by setting the priority to
         // the absolute minimum and popping.
@@ -4,7 +4,9 @@ use std::sync::Arc;
 use graph::mock::MockStore;
 use graph::prelude::{
     Entity, EntityCache, EntityKey, EntityModification, SubgraphDeploymentId, Value,
+    WindowAttribute,
 };
+use graph::util::lfu_cache::LfuCache;
 
 fn make_band(id: &'static str, data: Vec<(&str, Value)>) -> (EntityKey, Entity) {
     let subgraph_id = SubgraphDeploymentId::new("entity_cache").unwrap();
@@ -207,3 +209,244 @@ fn consecutive_modifications() {
         },])
     );
 }
+
+#[test]
+fn modifications_are_in_write_order() {
+    let mut store = MockStore::new();
+
+    store
+        .expect_get_many_mock()
+        .returning(|_, _| Ok(BTreeMap::new()));
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+
+    // Written in the reverse order of their keys, partly in a handler
+    let (tx9_key, tx9_data) = make_band("tx-9", vec![("id", "tx-9".into())]);
+    cache.set(tx9_key.clone(), tx9_data.clone()).unwrap();
+    cache.enter_handler();
+    let (tx10_key, tx10_data) = make_band("tx-10", vec![("id", "tx-10".into())]);
+    cache.set(tx10_key.clone(), tx10_data.clone()).unwrap();
+    cache.set(tx9_key.clone(), tx9_data.clone()).unwrap();
+    cache.exit_handler().unwrap();
+
+    let result = cache.as_modifications(&*store);
+    assert_eq!(
+        result.unwrap().modifications,
+        vec![
+            EntityModification::Insert {
+                key: tx9_key,
+                data: tx9_data,
+            },
+            EntityModification::Insert {
+                key: tx10_key,
+                data: tx10_data,
+            },
+        ]
+    );
+}
+
+#[test]
+fn discarded_handler_modifications() {
+    let mut store = MockStore::new();
+
+    store
+        .expect_get_many_mock()
+        .returning(|_, _| Ok(BTreeMap::new()));
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
+
+    // A handler that fails; none of its changes should be kept
+    cache.enter_handler();
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    cache.set(sigurros_key, sigurros_data).unwrap();
+    cache.remove(mogwai_key.clone());
+    cache.exit_handler_and_discard_changes();
+
+    // A handler that succeeds
+    cache.enter_handler();
+    let (update_key, update_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("founded", 1995.into())],
+    );
+    cache.set(update_key, update_data).unwrap();
+    cache.exit_handler().unwrap();
+
+    let result = cache.as_modifications(&*store);
+    assert_eq!(
+        result.unwrap().modifications,
+        vec![EntityModification::Insert {
+            key: mogwai_key,
+            data: Entity::from(vec![
+                ("id", "mogwai".into()),
+                ("name", "Mogwai".into()),
+                ("founded", 1995.into()),
+            ]),
+        }]
+    );
+}
+
+fn make_member(id: &'static str, band: &'static str) -> (EntityKey, Entity) {
+    let subgraph_id = SubgraphDeploymentId::new("entity_cache").unwrap();
+
+    (
+        EntityKey {
+            subgraph_id: subgraph_id.clone(),
+            entity_type: "Member".into(),
+            entity_id: id.into(),
+        },
+        Entity::from(vec![("id", id.into()), ("band", band.into())]),
+    )
+}
+
+#[test]
+fn load_related_with_pending_changes() {
+    let mut store = MockStore::new();
+
+    // The store knows about two members of Mogwai
+    store.expect_find_mock().returning(|_| {
+        Ok(vec!["stuart", "dominic"]
+            .into_iter()
+            .map(|id| {
+                let mut member = make_member(id, "mogwai").1;
+                member.insert("__typename".into(), "Member".into());
+                member
+            })
+            .collect())
+    });
+    store
+        .expect_get_many_mock()
+        .returning(|_, _| Ok(BTreeMap::new()));
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+    let subgraph_id = SubgraphDeploymentId::new("entity_cache").unwrap();
+    let attr = WindowAttribute::Scalar("band".into());
+
+    let load_related = |cache: &mut EntityCache| -> Vec<String> {
+        cache
+            .load_related(&subgraph_id, vec!["Member".into()], &attr, "mogwai")
+            .unwrap()
+            .into_iter()
+            .map(|member| member.id().unwrap())
+            .collect()
+    };
+
+    // Move one member to another band and add a new one
+    let (key, member) = make_member("dominic", "sigurros");
+    cache.set(key, member).unwrap();
+    let (key, member) = make_member("barry", "mogwai");
+    cache.set(key, member).unwrap();
+    assert_eq!(vec!["barry", "stuart"], load_related(&mut cache));
+
+    // Changes made by the current handler are visible, too
+    cache.enter_handler();
+    let (key, _) = make_member("stuart", "mogwai");
+    cache.remove(key);
+    let (key, member) = make_member("martin", "mogwai");
+    cache.set(key, member).unwrap();
+    assert_eq!(vec!["barry", "martin"], load_related(&mut cache));
+    cache.exit_handler_and_discard_changes();
+
+    assert_eq!(vec!["barry", "stuart"], load_related(&mut cache));
+}
+
+#[test]
+fn get_in_block_ignores_store() {
+    // `get_in_block` must never touch the store; a `MockStore` without
+    // expectations panics if it is used
+    let store = Arc::new(MockStore::new());
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    // An earlier lookup found that there is no `mogwai` in the store
+    let mut current = LfuCache::new();
+    current.insert(mogwai_key.clone(), None);
+    let mut cache = EntityCache::with_current(store.clone(), current);
+    assert_eq!(None, cache.get_in_block(&mogwai_key));
+
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
+    assert_eq!(Some(mogwai_data), cache.get_in_block(&mogwai_key));
+    assert_eq!(None, cache.get_in_block(&sigurros_key));
+
+    // Without knowing what is in the store, a write might only have set
+    // some of the attributes of an existing entity
+    cache.set(sigurros_key.clone(), sigurros_data).unwrap();
+    assert_eq!(None, cache.get_in_block(&sigurros_key));
+
+    // Changes of the current handler are visible
+    cache.enter_handler();
+    let (update_key, update_data) = make_band(
+        "mogwai",
+        vec![
+            ("id", "mogwai".into()),
+            ("name", Value::Null),
+            ("founded", 1995.into()),
+        ],
+    );
+    cache.set(update_key, update_data).unwrap();
+    assert_eq!(
+        Some(Entity::from(vec![
+            ("id", "mogwai".into()),
+            ("founded", 1995.into()),
+        ])),
+        cache.get_in_block(&mogwai_key)
+    );
+
+    cache.remove(mogwai_key.clone());
+    assert_eq!(None, cache.get_in_block(&mogwai_key));
+    cache.exit_handler_and_discard_changes();
+}
+
+#[test]
+fn get_in_block_applies_changes_to_cached_entities() {
+    let store = Arc::new(MockStore::new());
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![
+            ("id", "mogwai".into()),
+            ("name", "Mogwai".into()),
+            ("founded", 1995.into()),
+        ],
+    );
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    let mut current = LfuCache::new();
+    current.insert(mogwai_key.clone(), Some(mogwai_data));
+    current.insert(sigurros_key.clone(), Some(sigurros_data));
+    let mut cache = EntityCache::with_current(store.clone(), current);
+
+    // Entities that were not written in this block are not returned, even
+    // if they are cached
+    assert_eq!(None, cache.get_in_block(&sigurros_key));
+
+    let (update_key, update_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", Value::Null)],
+    );
+    cache.set(update_key, update_data).unwrap();
+    assert_eq!(
+        Some(Entity::from(vec![
+            ("id", "mogwai".into()),
+            ("founded", 1995.into()),
+        ])),
+        cache.get_in_block(&mogwai_key)
+    );
+}
@@ -5,6 +5,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use graph::components::link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait};
+use graph::data::subgraph::SubgraphFeature;
 use graph::prelude::{
     Entity, Link, SubgraphDeploymentId, SubgraphManifest, SubgraphManifestValidationError,
     UnvalidatedSubgraphManifest,
@@ -95,6 +96,24 @@ specVersion: 0.0.1
 
     assert_eq!("Qmmanifest", manifest.id.as_str());
     assert!(manifest.graft.is_none());
+    assert!(manifest.features.is_empty());
+}
+
+#[tokio::test]
+async fn features_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+features:
+  - nonFatalErrors
+specVersion: 0.0.1
+";
+
+    let manifest = resolve_manifest(YAML).await;
+
+    assert!(manifest.features.contains(&SubgraphFeature::NonFatalErrors));
 }
 
 #[tokio::test]
@@ -10,7 +10,7 @@ use graph::data::graphql::{
     ObjectOrInterface,
 };
 use graph::data::query::{Query as GraphDataQuery, QueryVariables};
-use graph::data::schema::ApiSchema;
+use graph::data::schema::{ApiSchema, PAGE_OF_DIRECTIVE};
 use graph::data::subgraph::schema::SUBGRAPHS_ID;
 use graph::prelude::{info, o, BlockNumber, CheapClone, Logger, QueryExecutionError};
 
@@ -110,14 +110,14 @@ impl Query {
     ) -> Result<Arc<Self>, Vec<QueryExecutionError>> {
         let mut operation = None;
         let mut fragments = HashMap::new();
-        for defn in query.document.definitions.into_iter() {
+        for defn in query.document.definitions.iter() {
             match defn {
                 q::Definition::Operation(op) => match operation {
-                    None => operation = Some(op),
+                    None => operation = Some(op.clone()),
                     Some(_) => return Err(vec![QueryExecutionError::OperationNameRequired]),
                 },
                 q::Definition::Fragment(frag) => {
-                    fragments.insert(frag.name.clone(), frag);
+                    fragments.insert(frag.name.clone(), frag.clone());
                 }
             }
         }
@@ -412,6 +412,52 @@ impl Query {
             })
     }
 
+    /// The complexity of the `where` argument `filter`. Each branch of an
+    /// `and` or `or` and each filter on referenced entities counts as one
+    /// unit, together with the complexity of the filters nested in it
+    fn filter_complexity(&self, filter: &q::Value) -> Result<u64, ComplexityError> {
+        let filter = match filter {
+            q::Value::Variable(name) => match self.variables.get(name) {
+                Some(filter) => filter,
+                None => return Ok(0),
+            },
+            filter => filter,
+        };
+        let object = match filter {
+            q::Value::Object(object) => object,
+            _ => return Ok(0),
+        };
+        object.iter().try_fold(0u64, |total, (key, value)| {
+            let complexity = match key.as_str() {
+                "and" | "or" => {
+                    let value = match value {
+                        q::Value::Variable(name) => self.variables.get(name).unwrap_or(value),
+                        value => value,
+                    };
+                    match value {
+                        q::Value::List(filters) => {
+                            filters.iter().try_fold(0u64, |total, filter| {
+                                self.filter_complexity(filter)?
+                                    .checked_add(1)
+                                    .and_then(|complexity| total.checked_add(complexity))
+                                    .ok_or(ComplexityError::Overflow)
+                            })?
+                        }
+                        _ => 0,
+                    }
+                }
+                key if key.ends_with('_') => self
+                    .filter_complexity(value)?
+                    .checked_add(1)
+                    .ok_or(ComplexityError::Overflow)?,
+                _ => 0,
+            };
+            total
+                .checked_add(complexity)
+                .ok_or(ComplexityError::Overflow)
+        })
+    }
+
     fn complexity_inner(
         &self,
         ty: &s::TypeDefinition,
@@ -452,19 +498,39 @@ impl Query {
                         }
                         .ok_or(Invalid)?;
 
+                        let field_type = get_named_type(schema, s_field.field_type.get_base_type())
+                            .ok_or(Invalid)?;
                         let field_complexity = self.complexity_inner(
-                            &get_named_type(schema, s_field.field_type.get_base_type())
-                                .ok_or(Invalid)?,
+                            &field_type,
                             &field.selection_set,
                             max_depth,
                             depth + 1,
                         )?;
 
-                        // Non-collection queries pass through.
-                        if !sast::is_list_or_non_null_list_field(&s_field) {
+                        // Non-collection queries pass through. The entities on a
+                        // page count towards the field that returns the page, since
+                        // that is where `first` is
+                        let is_page = |ty: &s::TypeDefinition| match ty {
+                            s::TypeDefinition::Object(t) => {
+                                sast::get_object_type_directive(t, PAGE_OF_DIRECTIVE.to_owned())
+                                    .is_some()
+                            }
+                            _ => false,
+                        };
+                        if is_page(ty)
+                            || (!sast::is_list_or_non_null_list_field(&s_field)
+                                && !is_page(&field_type))
+                        {
                             return Ok(total_complexity + field_complexity);
                         }
 
+                        // Filters that combine other filters or look at
+                        // referenced entities cost extra for each entity
+                        let field_complexity = qast::get_argument_value(&field.arguments, "where")
+                            .map_or(Ok(0), |filter| self.filter_complexity(filter))?
+                            .checked_add(field_complexity)
+                            .ok_or(Overflow)?;
+
                         // For collection queries, check the `first` argument.
                         let max_entities = qast::get_argument_value(&field.arguments, "first")
                             .and_then(|arg| match arg {
@@ -6,12 +6,14 @@ use std::time::{Duration, Instant};
 use crate::prelude::{QueryExecutionOptions, StoreResolver, SubscriptionExecutionOptions};
 use crate::query::execute_query;
 use crate::subscription::execute_prepared_subscription;
+use graph::components::graphql::metadata_result;
 use graph::data::graphql::effort::LoadManager;
 use graph::prelude::{
-    async_trait, o, CheapClone, DeploymentState, GraphQlRunner as GraphQlRunnerTrait, Logger,
-    Query, QueryExecutionError, QueryResult, Store, SubgraphDeploymentStore, Subscription,
-    SubscriptionError, SubscriptionResult,
+    async_trait, o, CheapClone, DeploymentState, Error, GraphQlRunner as GraphQlRunnerTrait,
+    Logger, Query, QueryExecutionError, QueryResult, Store, SubgraphDeploymentId,
+    SubgraphDeploymentStore, Subscription, SubscriptionError, SubscriptionResult,
 };
+use graphql_parser::query as q;
 
 use lazy_static::lazy_static;
 
@@ -104,6 +106,7 @@ where
         &self,
         query: Query,
         state: DeploymentState,
+        target: &SubgraphDeploymentId,
         max_complexity: Option<u64>,
         max_depth: Option<u8>,
         max_first: Option<u32>,
@@ -119,7 +122,11 @@ where
         // while the query is running. `self.store` can not be used after this
         // point, and everything needs to go through the `store` we are
         // setting up here
-        let store = self.store.cheap_clone().query_store(false);
+        let store = self
+            .store
+            .cheap_clone()
+            .query_store(target, false)
+            .map_err(|e| QueryResult::from(QueryExecutionError::from(e)))?;
 
         let max_depth = max_depth.unwrap_or(*GRAPHQL_MAX_DEPTH);
         let query = crate::execution::Query::new(&self.logger, query, max_complexity, max_depth)?;
@@ -219,17 +226,32 @@ where
         max_skip: Option<u32>,
         nested_resolver: bool,
     ) -> Arc<QueryResult> {
-        self.execute(
-            query,
-            state,
-            max_complexity,
-            max_depth,
-            max_first,
-            max_skip,
-            nested_resolver,
-        )
-        .await
-        .unwrap_or_else(|e| Arc::new(e))
+        let target = state.id.clone();
+        let has_non_fatal_errors = state.has_non_fatal_errors;
+        let result = self
+            .execute(
+                query,
+                state,
+                &target,
+                max_complexity,
+                max_depth,
+                max_first,
+                max_skip,
+                nested_resolver,
+            )
+            .await
+            .unwrap_or_else(|e| Arc::new(e));
+
+        if has_non_fatal_errors {
+            // Let the client know that the data might be incomplete. The
+            // result might be shared with the query cache, so we can only
+            // modify a copy of it
+            let mut result = Arc::try_unwrap(result).unwrap_or_else(|result| (*result).clone());
+            result.add_indexing_error();
+            Arc::new(result)
+        } else {
+            result
+        }
     }
 
     async fn run_subscription(
@@ -243,7 +265,12 @@ where
             *GRAPHQL_MAX_DEPTH,
         )?;
 
-        let store = self.store.clone().query_store(true);
+        let deployment = query.schema.id().clone();
+        let store = self
+            .store
+            .clone()
+            .query_store(&deployment, true)
+            .map_err(|e| SubscriptionError::GraphQLError(vec![e.into()]))?;
         if let Err(err) = self
             .load_manager
             .decide(
@@ -256,7 +283,6 @@ where
             return Err(SubscriptionError::GraphQLError(vec![err]));
         }
 
-        let deployment = query.schema.id().clone();
         execute_prepared_subscription(
             query,
             SubscriptionExecutionOptions {
@@ -272,6 +298,28 @@ where
         )
     }
 
+    async fn query_deployment_metadata(
+        self: Arc<Self>,
+        deployment: SubgraphDeploymentId,
+        query: Query,
+    ) -> Result<Arc<q::Value>, Error> {
+        let result = self
+            .execute(
+                query,
+                DeploymentState::meta(),
+                &deployment,
+                None,
+                None,
+                None,
+                None,
+                false,
+            )
+            .await
+            .unwrap_or_else(|e| Arc::new(e));
+
+        metadata_result(result)
+    }
+
     fn load_manager(&self) -> Arc<LoadManager> {
         self.load_manager.clone()
     }
@@ -2,12 +2,17 @@ use graphql_parser::schema::{Name, Value, *};
 use graphql_parser::Pos;
 use inflector::Inflector;
 use lazy_static::lazy_static;
+use std::str::FromStr;
 
 use crate::schema::ast;
 
 use graph::data::{
-    graphql::ext::{DirectiveExt, DocumentExt, ValueExt},
-    schema::{META_FIELD_NAME, META_FIELD_TYPE},
+    graphql::ext::{DirectiveExt, DocumentExt, TypeExt, ValueExt},
+    schema::{
+        AGGREGATE_OF_DIRECTIVE, AGGREGATE_TYPE_SUFFIX, DELETIONS_FIELD_NAME, DELETION_TYPE,
+        META_FIELD_NAME, META_FIELD_TYPE, PAGE_INFO_TYPE, PAGE_OF_DIRECTIVE, PAGE_TYPE_SUFFIX,
+        VERSION_OF_DIRECTIVE, VERSION_TYPE_SUFFIX,
+    },
 };
 use graph::prelude::*;
 
@@ -20,6 +25,7 @@ pub enum APISchemaError {
 }
 
 const BLOCK_HEIGHT: &str = "Block_height";
+const BLOCK_CHANGED_FILTER: &str = "BlockChangedFilter";
 
 /// Derives a full-fledged GraphQL API schema from an input schema.
 ///
@@ -38,6 +44,7 @@ pub fn api_schema(input_schema: &Document) -> Result<Document, APISchemaError> {
     add_builtin_scalar_types(&mut schema)?;
     add_order_direction_enum(&mut schema);
     add_block_height_type(&mut schema);
+    add_block_changed_filter_type(&mut schema);
     add_meta_field_type(&mut schema);
     add_types_for_object_types(&mut schema, &object_types)?;
     add_types_for_interface_types(&mut schema, &interface_types)?;
@@ -174,6 +181,27 @@ fn add_block_height_type(schema: &mut Document) {
     schema.definitions.push(def);
 }
 
+/// Adds a global `BlockChangedFilter` type to the schema. The
+/// `_change_block` filter accepts values of this type
+fn add_block_changed_filter_type(schema: &mut Document) {
+    let typedef = TypeDefinition::InputObject(InputObjectType {
+        position: Pos::default(),
+        description: None,
+        name: BLOCK_CHANGED_FILTER.to_string(),
+        directives: vec![],
+        fields: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "number_gte".to_owned(),
+            value_type: Type::NonNullType(Box::new(Type::NamedType("Int".to_owned()))),
+            default_value: None,
+            directives: vec![],
+        }],
+    });
+    let def = Definition::TypeDefinition(typedef);
+    schema.definitions.push(def);
+}
+
 /// Adds a global `_Meta_` type to the schema. The `_meta` field
 /// accepts values of this type
 fn add_meta_field_type(schema: &mut Document) {
@@ -196,6 +224,9 @@ fn add_types_for_object_types(
     for object_type in object_types {
         add_order_by_type(schema, &object_type.name, &object_type.fields)?;
         add_filter_type(schema, &object_type.name, &object_type.fields)?;
+        add_aggregate_type(schema, &object_type.name, &object_type.fields)?;
+        add_version_type(schema, &object_type.name, &object_type.fields)?;
+        add_page_type(schema, &object_type.name)?;
     }
     Ok(())
 }
@@ -229,11 +260,16 @@ fn add_order_by_type(
                 directives: vec![],
                 values: fields
                     .iter()
-                    .map(|field| &field.name)
+                    .map(|field| field.name.to_owned())
+                    .chain(
+                        fields
+                            .iter()
+                            .flat_map(|field| child_order_by_values(schema, field)),
+                    )
                     .map(|name| EnumValue {
                         position: Pos::default(),
                         description: None,
-                        name: name.to_owned(),
+                        name,
                         directives: vec![],
                     })
                     .collect(),
@@ -246,6 +282,30 @@ fn add_order_by_type(
     Ok(())
 }
 
+/// Generates the `<field>__<child field>` values that order by the scalar
+/// fields of the entity that `field` references. We only allow that for
+/// fields that reference a single entity of an object type and are not
+/// derived, since those can be ordered with a simple join
+fn child_order_by_values(schema: &Document, field: &Field) -> Vec<Name> {
+    if ast::is_list_or_non_null_list_field(field)
+        || ast::get_derived_from_directive(field).is_some()
+    {
+        return vec![];
+    }
+    match ast::get_named_type(schema, field.field_type.get_base_type()) {
+        Some(TypeDefinition::Object(object_type)) => object_type
+            .fields
+            .iter()
+            .filter(|child_field| {
+                !ast::is_list_or_non_null_list_field(child_field)
+                    && ValueType::is_scalar(child_field.field_type.get_base_type())
+            })
+            .map(|child_field| format!("{}__{}", field.name, child_field.name))
+            .collect(),
+        _ => vec![],
+    }
+}
+
 /// Adds a `<type_name>_filter` enum type for the given fields to the schema.
 fn add_filter_type(
     schema: &mut Document,
@@ -255,7 +315,7 @@ fn add_filter_type(
     let filter_type_name = format!("{}_filter", type_name).to_string();
     match ast::get_named_type(schema, &filter_type_name) {
         None => {
-            let input_values = field_input_values(schema, fields)?;
+            let mut input_values = field_input_values(schema, fields)?;
 
             // Don't generate an input object with no fields, this makes the JS
             // graphql library, which graphiql uses, very confused and graphiql
@@ -264,12 +324,42 @@ fn add_filter_type(
             if input_values.is_empty() {
                 return Ok(());
             }
+
+            // Allow combining filters with `and: [..]` and `or: [..]` unless
+            // the type has fields with these names
+            for combinator in &["and", "or"] {
+                if fields.iter().all(|field| field.name != *combinator) {
+                    input_values.push(InputValue {
+                        position: Pos::default(),
+                        description: None,
+                        name: combinator.to_string(),
+                        value_type: Type::ListType(Box::new(Type::NonNullType(Box::new(
+                            Type::NamedType(filter_type_name.clone()),
+                        )))),
+                        default_value: None,
+                        directives: vec![],
+                    });
+                }
+            }
+
+            // Only match entities that changed at or after a given block
+            if fields.iter().all(|field| field.name != "_change_block") {
+                input_values.push(InputValue {
+                    position: Pos::default(),
+                    description: None,
+                    name: "_change_block".to_owned(),
+                    value_type: Type::NamedType(BLOCK_CHANGED_FILTER.to_owned()),
+                    default_value: None,
+                    directives: vec![],
+                });
+            }
+
             let typedef = TypeDefinition::InputObject(InputObjectType {
                 position: Pos::default(),
                 description: None,
                 name: filter_type_name,
                 directives: vec![],
-                fields: field_input_values(schema, fields)?,
+                fields: input_values,
             });
             let def = Definition::TypeDefinition(typedef);
             schema.definitions.push(def);
@@ -280,6 +370,190 @@ fn add_filter_type(
     Ok(())
 }
 
+/// The directive `@<name>(type: "<type_name>")` that marks a type as
+/// generated from `type_name`
+fn generated_from_directive(name: &str, type_name: &Name) -> Directive {
+    Directive {
+        name: name.to_owned(),
+        position: Pos::default(),
+        arguments: vec![("type".to_owned(), Value::String(type_name.clone()))],
+    }
+}
+
+/// Adds a `<type_name>_aggregate` object type that holds the result of
+/// aggregation queries over `type_name`. It always has a `count`, and
+/// fields `<field>_sum`, `<field>_min`, `<field>_max` and `<field>_avg`
+/// for each numeric field that is not a list
+fn add_aggregate_type(
+    schema: &mut Document,
+    type_name: &Name,
+    fields: &[Field],
+) -> Result<(), APISchemaError> {
+    let aggregate_type_name = format!("{}{}", type_name, AGGREGATE_TYPE_SUFFIX);
+    if ast::get_named_type(schema, &aggregate_type_name).is_some() {
+        return Err(APISchemaError::TypeExists(aggregate_type_name));
+    }
+
+    let aggregate_field = |name: String, type_name: &str| Field {
+        position: Pos::default(),
+        description: None,
+        name,
+        arguments: vec![],
+        field_type: Type::NamedType(type_name.to_owned()),
+        directives: vec![],
+    };
+
+    let mut aggregate_fields = vec![Field {
+        field_type: Type::NonNullType(Box::new(Type::NamedType("BigInt".to_owned()))),
+        ..aggregate_field("count".to_owned(), "BigInt")
+    }];
+    for numeric_field in fields
+        .iter()
+        .filter(|field| !ast::is_list_or_non_null_list_field(field))
+    {
+        let base_type = numeric_field.field_type.get_base_type();
+        let (sum, min_max) = match ValueType::from_str(base_type) {
+            Ok(ValueType::Int) => ("BigInt", "Int"),
+            Ok(ValueType::BigInt) => ("BigInt", "BigInt"),
+            Ok(ValueType::BigDecimal) => ("BigDecimal", "BigDecimal"),
+            _ => continue,
+        };
+        let name = &numeric_field.name;
+        aggregate_fields.push(aggregate_field(format!("{}_sum", name), sum));
+        aggregate_fields.push(aggregate_field(format!("{}_min", name), min_max));
+        aggregate_fields.push(aggregate_field(format!("{}_max", name), min_max));
+        aggregate_fields.push(aggregate_field(format!("{}_avg", name), "BigDecimal"));
+    }
+
+    let typedef = TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: None,
+        name: aggregate_type_name,
+        implements_interfaces: vec![],
+        directives: vec![generated_from_directive(AGGREGATE_OF_DIRECTIVE, type_name)],
+        fields: aggregate_fields,
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
+/// Adds a `<type_name>_version` object type that holds one version of an
+/// entity in the result of history queries. It has all the fields of
+/// `type_name` that are not derived, where references to other entities
+/// are replaced by their ids, and `_block_from` and `_block_to` for the
+/// blocks during which the version was current
+fn add_version_type(
+    schema: &mut Document,
+    type_name: &Name,
+    fields: &[Field],
+) -> Result<(), APISchemaError> {
+    fn with_base_type(field_type: &Type, base_type: &str) -> Type {
+        match field_type {
+            Type::NamedType(_) => Type::NamedType(base_type.to_owned()),
+            Type::ListType(inner) => Type::ListType(Box::new(with_base_type(inner, base_type))),
+            Type::NonNullType(inner) => {
+                Type::NonNullType(Box::new(with_base_type(inner, base_type)))
+            }
+        }
+    }
+
+    let version_type_name = format!("{}{}", type_name, VERSION_TYPE_SUFFIX);
+    if ast::get_named_type(schema, &version_type_name).is_some() {
+        return Err(APISchemaError::TypeExists(version_type_name));
+    }
+
+    let version_field = |name: &str, description: Option<String>, field_type: Type| Field {
+        position: Pos::default(),
+        description,
+        name: name.to_owned(),
+        arguments: vec![],
+        field_type,
+        directives: vec![],
+    };
+
+    let mut version_fields = vec![
+        version_field(
+            "_block_from",
+            Some("The first block in which this version was current".to_owned()),
+            Type::NonNullType(Box::new(Type::NamedType("Int".to_owned()))),
+        ),
+        version_field(
+            "_block_to",
+            Some(
+                "The block in which this version was replaced or removed; \
+                 null if the version is still current"
+                    .to_owned(),
+            ),
+            Type::NamedType("Int".to_owned()),
+        ),
+    ];
+    for field in fields
+        .iter()
+        .filter(|field| ast::get_derived_from_directive(field).is_none())
+    {
+        let field_type = match ast::get_named_type(schema, field.field_type.get_base_type()) {
+            Some(TypeDefinition::Object(_)) | Some(TypeDefinition::Interface(_)) => {
+                with_base_type(&field.field_type, "String")
+            }
+            _ => field.field_type.clone(),
+        };
+        version_fields.push(version_field(
+            &field.name,
+            field.description.clone(),
+            field_type,
+        ));
+    }
+
+    let typedef = TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: None,
+        name: version_type_name,
+        implements_interfaces: vec![],
+        directives: vec![generated_from_directive(VERSION_OF_DIRECTIVE, type_name)],
+        fields: version_fields,
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
+/// Adds a `<type_name>_page` object type that holds one page of a
+/// collection of `type_name`: the entities on the page in `items`, and
+/// the cursors needed to get to the pages around it in `pageInfo`
+fn add_page_type(schema: &mut Document, type_name: &Name) -> Result<(), APISchemaError> {
+    let page_type_name = format!("{}{}", type_name, PAGE_TYPE_SUFFIX);
+    if ast::get_named_type(schema, &page_type_name).is_some() {
+        return Err(APISchemaError::TypeExists(page_type_name));
+    }
+
+    let page_field = |name: &str, field_type: Type| Field {
+        position: Pos::default(),
+        description: None,
+        name: name.to_owned(),
+        arguments: vec![],
+        field_type: Type::NonNullType(Box::new(field_type)),
+        directives: vec![],
+    };
+
+    let typedef = TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: None,
+        name: page_type_name,
+        implements_interfaces: vec![],
+        directives: vec![generated_from_directive(PAGE_OF_DIRECTIVE, type_name)],
+        fields: vec![
+            page_field(
+                "items",
+                Type::ListType(Box::new(Type::NonNullType(Box::new(Type::NamedType(
+                    type_name.to_owned(),
+                ))))),
+            ),
+            page_field("pageInfo", Type::NamedType(PAGE_INFO_TYPE.to_owned())),
+        ],
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
 /// Generates `*_filter` input values for the given set of fields.
 fn field_input_values(
     schema: &Document,
@@ -308,9 +582,9 @@ fn field_filter_input_values(
                 .ok_or_else(|| APISchemaError::TypeNotFound(name.clone()))?;
             Ok(match named_type {
                 TypeDefinition::Object(_) | TypeDefinition::Interface(_) => {
-                    // Only add `where` filter fields for object and interface fields
-                    // if they are not @derivedFrom
-                    if ast::get_derived_from_directive(field).is_some() {
+                    // Only add `where` filter fields for the ids of referenced
+                    // entities if the field is not @derivedFrom
+                    let mut input_values = if ast::get_derived_from_directive(field).is_some() {
                         vec![]
                     } else {
                         // We allow filtering with `where: { other: "some-id" }` and
@@ -322,7 +596,9 @@ fn field_filter_input_values(
                             field,
                             &ScalarType::new(Name::from("String")),
                         )
-                    }
+                    };
+                    input_values.push(field_child_filter_input_value(field, name));
+                    input_values
                 }
                 TypeDefinition::Scalar(ref t) => field_scalar_filter_input_values(schema, field, t),
                 TypeDefinition::Enum(ref t) => field_enum_filter_input_values(schema, field, t),
@@ -413,37 +689,59 @@ fn field_list_filter_input_values(
     ast::get_type_definition_from_type(schema, field_type).and_then(|typedef| {
         // Decide what type of values can be passed to the filter. In the case
         // one-to-many or many-to-many object or interface fields that are not
-        // derived, we allow ID strings to be passed on.
+        // derived, we allow ID strings to be passed on. All object and
+        // interface fields can be filtered by the fields of the entities
+        // they refer to
         let input_field_type = match typedef {
-            TypeDefinition::Interface(_) | TypeDefinition::Object(_) => {
-                if ast::get_derived_from_directive(field).is_some() {
-                    return None;
+            TypeDefinition::Interface(InterfaceType { name, .. })
+            | TypeDefinition::Object(ObjectType { name, .. }) => {
+                let mut input_values = if ast::get_derived_from_directive(field).is_some() {
+                    vec![]
                 } else {
-                    Type::NamedType("String".into())
-                }
+                    list_input_values(field, Type::NamedType("String".into()))
+                };
+                input_values.push(field_child_filter_input_value(field, name));
+                return Some(input_values);
             }
             TypeDefinition::Scalar(ref t) => Type::NamedType(t.name.to_owned()),
             TypeDefinition::Enum(ref t) => Type::NamedType(t.name.to_owned()),
             TypeDefinition::InputObject(_) | TypeDefinition::Union(_) => return None,
         };
 
-        Some(
-            vec!["", "not", "contains", "not_contains"]
-                .into_iter()
-                .map(|filter_type| {
-                    input_value(
-                        &field.name,
-                        filter_type,
-                        Type::ListType(Box::new(Type::NonNullType(Box::new(
-                            input_field_type.clone(),
-                        )))),
-                    )
-                })
-                .collect(),
-        )
+        Some(list_input_values(field, input_field_type))
     })
 }
 
+/// Generates the `*_filter` input values for a list field whose elements
+/// have type `input_field_type`
+fn list_input_values(field: &Field, input_field_type: Type) -> Vec<InputValue> {
+    vec!["", "not", "contains", "not_contains"]
+        .into_iter()
+        .map(|filter_type| {
+            input_value(
+                &field.name,
+                filter_type,
+                Type::ListType(Box::new(Type::NonNullType(Box::new(
+                    input_field_type.clone(),
+                )))),
+            )
+        })
+        .collect()
+}
+
+/// Generates the `<field>_` input value that filters by the fields of the
+/// entities that `field` refers to, which have type `type_name`
+fn field_child_filter_input_value(field: &Field, type_name: &Name) -> InputValue {
+    InputValue {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}_", field.name),
+        value_type: Type::NamedType(format!("{}_filter", type_name)),
+        default_value: None,
+        directives: vec![],
+    }
+}
+
 /// Generates a `*_filter` input value for the given field name, suffix and value type.
 fn input_value(name: &Name, suffix: &'static str, value_type: Type) -> InputValue {
     InputValue {
@@ -478,6 +776,21 @@ fn add_query_type(
         .chain(interface_types.iter().map(|t| &t.name))
         .flat_map(|name| query_fields_for_type(schema, name))
         .collect::<Vec<Field>>();
+    fields.extend(
+        object_types
+            .iter()
+            .map(|t| aggregate_query_field_for_type(schema, &t.name)),
+    );
+    fields.extend(
+        object_types
+            .iter()
+            .map(|t| history_query_field_for_type(&t.name)),
+    );
+    fields.extend(
+        object_types
+            .iter()
+            .map(|t| page_query_field_for_type(schema, &t.name)),
+    );
     let mut fulltext_fields = schema
         .get_fulltext_directives()
         .iter()
@@ -485,6 +798,7 @@ fn add_query_type(
         .collect();
     fields.append(&mut fulltext_fields);
     fields.push(meta_field());
+    fields.push(deletions_field());
 
     let typedef = TypeDefinition::Object(ObjectType {
         position: Pos::default(),
@@ -651,6 +965,96 @@ fn query_fields_for_type(schema: &Document, type_name: &Name) -> Vec<Field> {
     ]
 }
 
+/// Generates the `Query` field for aggregation queries over the given
+/// type (e.g. `userAggregate`)
+fn aggregate_query_field_for_type(schema: &Document, type_name: &Name) -> Field {
+    let mut arguments = vec![];
+    let filter_name = format!("{}_filter", type_name);
+    if ast::get_input_object_definitions(schema)
+        .iter()
+        .any(|o| o.name == filter_name)
+    {
+        arguments.push(input_value(
+            &"where".to_string(),
+            "",
+            Type::NamedType(filter_name),
+        ));
+    }
+    arguments.push(block_argument());
+
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}Aggregate", type_name.as_str().to_camel_case()),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::NamedType(format!(
+            "{}{}",
+            type_name, AGGREGATE_TYPE_SUFFIX
+        )))),
+        directives: vec![],
+    }
+}
+
+/// Generates the `Query` field that lists the versions of one entity of
+/// the given type (e.g. `userHistory`)
+fn history_query_field_for_type(type_name: &Name) -> Field {
+    let mut first = input_value(&"first".to_string(), "", Type::NamedType("Int".to_string()));
+    first.default_value = Some(Value::Int(100.into()));
+
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}History", type_name.as_str().to_camel_case()),
+        arguments: vec![
+            input_value(
+                &"id".to_string(),
+                "",
+                Type::NonNullType(Box::new(Type::NamedType("ID".to_string()))),
+            ),
+            first,
+            input_value(
+                &"from_block".to_string(),
+                "",
+                Type::NamedType("Int".to_string()),
+            ),
+            input_value(
+                &"to_block".to_string(),
+                "",
+                Type::NamedType("Int".to_string()),
+            ),
+        ],
+        field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+            Box::new(Type::NamedType(format!(
+                "{}{}",
+                type_name, VERSION_TYPE_SUFFIX
+            ))),
+        ))))),
+        directives: vec![],
+    }
+}
+
+/// Generates the `Query` field that returns one page of a collection of
+/// the given type (e.g. `usersPage`)
+fn page_query_field_for_type(schema: &Document, type_name: &Name) -> Field {
+    let input_objects = ast::get_input_object_definitions(schema);
+    let mut arguments = collection_arguments_for_named_type(&input_objects, type_name);
+    // Pages are walked with cursors, not by skipping entities
+    arguments.retain(|argument| argument.name != "skip");
+    arguments.push(block_argument());
+
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}Page", type_name.to_plural().to_camel_case()),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::NamedType(format!(
+            "{}{}",
+            type_name, PAGE_TYPE_SUFFIX
+        )))),
+        directives: vec![],
+    }
+}
+
 fn meta_field() -> Field {
     lazy_static! {
         static ref META_FIELD: Field = Field {
@@ -675,6 +1079,41 @@ fn meta_field() -> Field {
     META_FIELD.clone()
 }
 
+/// The `_deletions` field lists the entities that were removed in a
+/// range of blocks. Like collections, it returns at most `first` entries
+/// at a time, and clients walk through longer lists with `after`
+fn deletions_field() -> Field {
+    let mut first = input_value(&"first".to_string(), "", Type::NamedType("Int".to_string()));
+    first.default_value = Some(Value::Int(100.into()));
+
+    Field {
+        position: Pos::default(),
+        description: Some("Entities removed in blocks since `number_gte`".to_string()),
+        name: DELETIONS_FIELD_NAME.to_string(),
+        arguments: vec![
+            InputValue {
+                position: Pos::default(),
+                description: None,
+                name: String::from("number_gte"),
+                value_type: Type::NonNullType(Box::new(Type::NamedType("Int".to_string()))),
+                default_value: None,
+                directives: vec![],
+            },
+            first,
+            input_value(
+                &"after".to_string(),
+                "",
+                Type::NamedType("String".to_string()),
+            ),
+            block_argument(),
+        ],
+        field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+            Box::new(Type::NamedType(DELETION_TYPE.to_string())),
+        ))))),
+        directives: vec![],
+    }
+}
+
 /// Generates arguments for collection queries of a named type (e.g. User).
 fn collection_arguments_for_named_type(
     input_objects: &[InputObjectType],
@@ -692,6 +1131,16 @@ fn collection_arguments_for_named_type(
     let mut args = vec![
         skip,
         first,
+        input_value(
+            &"after".to_string(),
+            "",
+            Type::NamedType("String".to_string()),
+        ),
+        input_value(
+            &"before".to_string(),
+            "",
+            Type::NamedType("String".to_string()),
+        ),
         input_value(
             &"orderBy".to_string(),
             "",
@@ -798,6 +1247,7 @@ fn add_field_arguments(
 
 #[cfg(test)]
 mod tests {
+    use graph::data::schema::{AGGREGATE_OF_DIRECTIVE, PAGE_OF_DIRECTIVE, VERSION_OF_DIRECTIVE};
     use graphql_parser::schema::*;
 
     use super::api_schema;
@@ -866,6 +1316,207 @@ mod tests {
         assert_eq!(values, [&"id".to_string(), &"name".to_string()]);
     }
 
+    #[test]
+    fn api_schema_contains_child_order_by_values() {
+        let input_schema = parse_schema(
+            "type Account { id: ID!, balance: BigInt!, tags: [String!]!, owner: User! }
+             type User { id: ID!, name: String!, account: Account, accounts: [Account!]! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let enum_type = match ast::get_named_type(&schema, &"User_orderBy".to_string()) {
+            Some(TypeDefinition::Enum(t)) => t,
+            _ => panic!("User_orderBy type is missing in derived API schema"),
+        };
+        let values: Vec<&str> = enum_type
+            .values
+            .iter()
+            .map(|value| value.name.as_str())
+            .collect();
+        assert_eq!(
+            values,
+            [
+                "id",
+                "name",
+                "account",
+                "accounts",
+                "account__id",
+                "account__balance"
+            ]
+        );
+    }
+
+    #[test]
+    fn api_schema_contains_aggregate_types_and_fields() {
+        let input_schema = parse_schema(
+            "type Account { id: ID!, name: String!, count: Int!, balance: BigInt, \
+                            rate: BigDecimal!, history: [Int!]! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let aggregate_type = match ast::get_named_type(&schema, &"Account_aggregate".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("Account_aggregate type is missing in derived API schema"),
+        };
+        let fields: Vec<(&str, String)> = aggregate_type
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.field_type.to_string()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("count", "BigInt!".to_owned()),
+                ("count_sum", "BigInt".to_owned()),
+                ("count_min", "Int".to_owned()),
+                ("count_max", "Int".to_owned()),
+                ("count_avg", "BigDecimal".to_owned()),
+                ("balance_sum", "BigInt".to_owned()),
+                ("balance_min", "BigInt".to_owned()),
+                ("balance_max", "BigInt".to_owned()),
+                ("balance_avg", "BigDecimal".to_owned()),
+                ("rate_sum", "BigDecimal".to_owned()),
+                ("rate_min", "BigDecimal".to_owned()),
+                ("rate_max", "BigDecimal".to_owned()),
+                ("rate_avg", "BigDecimal".to_owned()),
+            ]
+        );
+
+        let query_type = match ast::get_named_type(&schema, &"Query".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("Query type is missing in derived API schema"),
+        };
+        let directive =
+            ast::get_object_type_directive(aggregate_type, AGGREGATE_OF_DIRECTIVE.to_owned())
+                .expect("Account_aggregate is marked as generated");
+        assert_eq!(
+            directive.arguments,
+            vec![("type".to_owned(), Value::String("Account".to_owned()))]
+        );
+
+        let field = ast::get_field(query_type, &"accountAggregate".to_string())
+            .expect("accountAggregate field is missing on Query");
+        assert_eq!("Account_aggregate!", field.field_type.to_string());
+        let args: Vec<&str> = field
+            .arguments
+            .iter()
+            .map(|arg| arg.name.as_str())
+            .collect();
+        assert_eq!(args, ["where", "block"]);
+    }
+
+    #[test]
+    fn api_schema_contains_page_types_and_fields() {
+        let input_schema = parse_schema("type User { id: ID!, name: String! }")
+            .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let page_type = match ast::get_named_type(&schema, &"User_page".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("User_page type is missing in derived API schema"),
+        };
+        let fields: Vec<(&str, String)> = page_type
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.field_type.to_string()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("items", "[User!]!".to_owned()),
+                ("pageInfo", "_PageInfo_!".to_owned()),
+            ]
+        );
+        let directive = ast::get_object_type_directive(page_type, PAGE_OF_DIRECTIVE.to_owned())
+            .expect("User_page is marked as generated");
+        assert_eq!(
+            directive.arguments,
+            vec![("type".to_owned(), Value::String("User".to_owned()))]
+        );
+
+        let query_type = match ast::get_named_type(&schema, &"Query".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("Query type is missing in derived API schema"),
+        };
+        let field = ast::get_field(query_type, &"usersPage".to_string())
+            .expect("usersPage field is missing on Query");
+        assert_eq!("User_page!", field.field_type.to_string());
+        let args: Vec<&str> = field
+            .arguments
+            .iter()
+            .map(|arg| arg.name.as_str())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "first",
+                "after",
+                "before",
+                "orderBy",
+                "orderDirection",
+                "where",
+                "block"
+            ]
+        );
+    }
+
+    #[test]
+    fn api_schema_contains_version_types_and_history_fields() {
+        let input_schema = parse_schema(
+            "type User { id: ID!, name: String!, friends: [User!]!, \
+                         posts: [Post!]! @derivedFrom(field: \"author\") }
+             type Post { id: ID!, author: User! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let version_type = match ast::get_named_type(&schema, &"User_version".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("User_version type is missing in derived API schema"),
+        };
+        let fields: Vec<(&str, String)> = version_type
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.field_type.to_string()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("_block_from", "Int!".to_owned()),
+                ("_block_to", "Int".to_owned()),
+                ("id", "ID!".to_owned()),
+                ("name", "String!".to_owned()),
+                ("friends", "[String!]!".to_owned()),
+            ]
+        );
+
+        let query_type = match ast::get_named_type(&schema, &"Query".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("Query type is missing in derived API schema"),
+        };
+        let directive =
+            ast::get_object_type_directive(version_type, VERSION_OF_DIRECTIVE.to_owned())
+                .expect("User_version is marked as generated");
+        assert_eq!(
+            directive.arguments,
+            vec![("type".to_owned(), Value::String("User".to_owned()))]
+        );
+
+        let field = ast::get_field(query_type, &"userHistory".to_string())
+            .expect("userHistory field is missing on Query type");
+        assert_eq!(field.field_type.to_string(), "[User_version!]!");
+        assert_eq!(
+            field
+                .arguments
+                .iter()
+                .map(|argument| argument.name.as_str())
+                .collect::<Vec<_>>(),
+            ["id", "first", "from_block", "to_block"]
+        );
+    }
+
     #[test]
     fn api_schema_contains_object_type_filter_enum() {
         let input_schema = parse_schema(
@@ -937,6 +1588,7 @@ mod tests {
                 "pets_not",
                 "pets_contains",
                 "pets_not_contains",
+                "pets_",
                 "favoritePet",
                 "favoritePet_not",
                 "favoritePet_gt",
@@ -951,6 +1603,12 @@ mod tests {
                 "favoritePet_not_starts_with",
                 "favoritePet_ends_with",
                 "favoritePet_not_ends_with",
+                "favoritePet_",
+                "leastFavoritePet_",
+                "mostFavoritePets_",
+                "and",
+                "or",
+                "_change_block",
             ]
             .iter()
             .map(|name| name.to_string())
@@ -1011,6 +1669,8 @@ mod tests {
             [
                 "skip",
                 "first",
+                "after",
+                "before",
                 "orderBy",
                 "orderDirection",
                 "where",
@@ -1102,6 +1762,8 @@ mod tests {
             [
                 "skip",
                 "first",
+                "after",
+                "before",
                 "orderBy",
                 "orderDirection",
                 "where",
@@ -2,5 +2,8 @@ mod prefetch;
 mod query;
 mod resolver;
 
-pub use self::query::{build_query, parse_subgraph_id};
+pub use self::query::{
+    build_aggregate_query, build_deletions_query, build_history_query, build_query,
+    cursor_order_by, entity_type_name, generated_from, parse_subgraph_id,
+};
 pub use self::resolver::StoreResolver;
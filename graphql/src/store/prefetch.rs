@@ -11,16 +11,22 @@ use std::rc::Rc;
 use std::time::Instant;
 
 use graph::data::graphql::*;
+use graph::data::schema::{
+    AGGREGATE_OF_DIRECTIVE, DELETION_TYPE, PAGE_OF_DIRECTIVE, VERSION_OF_DIRECTIVE,
+};
 use graph::prelude::{
-    ApiSchema, BlockNumber, ChildMultiplicity, EntityCollection, EntityFilter, EntityLink,
-    EntityOrder, EntityWindow, Logger, ParentLink, QueryExecutionError, QueryStore,
-    Value as StoreValue, WindowAttribute,
+    ApiSchema, BlockNumber, ChildMultiplicity, EntityCollection, EntityCursor, EntityFilter,
+    EntityLink, EntityOrder, EntityQuery, EntityRange, EntityWindow, Logger, ParentLink,
+    QueryExecutionError, QueryStore, Value as StoreValue, WindowAttribute,
 };
 
 use crate::execution::{ExecutionContext, Resolver};
 use crate::query::ast as qast;
 use crate::schema::ast as sast;
-use crate::store::{build_query, StoreResolver};
+use crate::store::{
+    build_aggregate_query, build_deletions_query, build_history_query, build_query,
+    cursor_order_by, entity_type_name, generated_from, parse_subgraph_id, StoreResolver,
+};
 
 lazy_static! {
     static ref ARG_FIRST: String = String::from("first");
@@ -221,7 +227,7 @@ impl<'a> JoinCond<'a> {
             };
         JoinCond {
             parent_type: parent_type.name.as_str(),
-            child_type: child_type.name.as_str(),
+            child_type: entity_type_name(child_type),
             relation,
         }
     }
@@ -497,6 +503,37 @@ fn execute_selection_set<'a>(
                 .object_or_interface(field.field_type.get_base_type())
                 .expect("we only collect fields that are objects or interfaces");
 
+            if let ObjectOrInterface::Object(object_type) = child_type {
+                if generated_from(object_type, AGGREGATE_OF_DIRECTIVE).is_some() {
+                    match execute_aggregate(resolver, ctx, &fields, object_type) {
+                        Ok(node) => Join::perform(parents, vec![node], response_key),
+                        Err(e) => errors.push(e),
+                    }
+                    continue;
+                }
+                if generated_from(object_type, VERSION_OF_DIRECTIVE).is_some() {
+                    match execute_history(resolver, ctx, &fields, object_type) {
+                        Ok(nodes) => Join::perform(parents, nodes, response_key),
+                        Err(e) => errors.push(e),
+                    }
+                    continue;
+                }
+                if generated_from(object_type, PAGE_OF_DIRECTIVE).is_some() {
+                    match execute_page(resolver, ctx, &fields, object_type) {
+                        Ok(node) => Join::perform(parents, vec![node], response_key),
+                        Err(mut e) => errors.append(&mut e),
+                    }
+                    continue;
+                }
+                if object_type.name == DELETION_TYPE {
+                    match execute_deletions(resolver, ctx, &fields) {
+                        Ok(nodes) => Join::perform(parents, nodes, response_key),
+                        Err(e) => errors.push(e),
+                    }
+                    continue;
+                }
+            }
+
             let join = Join::new(
                 ctx.query.schema.as_ref(),
                 type_cond,
@@ -790,6 +827,8 @@ fn execute_field(
         }
     }?;
 
+    resolver.check_imported_block(&parse_subgraph_id(join.child_type)?)?;
+
     let multiplicity = if sast::is_list_or_non_null_list_field(field_definition) {
         ChildMultiplicity::Many
     } else {
@@ -802,7 +841,7 @@ fn execute_field(
         &join,
         argument_values,
         multiplicity,
-        ctx.query.schema.types_for_interface(),
+        ctx.query.schema.as_ref(),
         resolver.block_number(),
         ctx.max_first,
         ctx.max_skip,
@@ -811,6 +850,346 @@ fn execute_field(
     .map_err(|e| vec![e])
 }
 
+/// Run the aggregation query for a root `<type>Aggregate` field. The
+/// result is a single node that contains the aggregates selected by
+/// `fields`
+fn execute_aggregate(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    fields: &[&q::Field],
+    aggregate_type: &s::ObjectType,
+) -> Result<Node, QueryExecutionError> {
+    let query_type = ctx.query.schema.query_type.as_ref();
+    let arguments = crate::execution::coerce_argument_values(ctx, query_type, fields[0])
+        .map_err(|mut errors| errors.remove(0))?;
+
+    let selected = crate::execution::collect_fields(
+        ctx,
+        aggregate_type,
+        fields.iter().map(|field| &field.selection_set),
+    );
+    let mut names: Vec<_> = selected
+        .values()
+        .map(|fields| &fields[0].name)
+        .filter(|name| !name.starts_with("__"))
+        .collect();
+    names.sort();
+    names.dedup();
+
+    resolver.check_imported_block(&parse_subgraph_id(aggregate_type)?)?;
+
+    let query = build_aggregate_query(
+        aggregate_type,
+        resolver.block_number(),
+        &arguments,
+        &names,
+        ctx.query.schema.as_ref(),
+    )?;
+    let values = resolver.store.aggregate(query)?;
+    let entity = names
+        .into_iter()
+        .cloned()
+        .zip(values.into_iter().map(q::Value::from))
+        .collect::<BTreeMap<_, _>>();
+    Ok(Node::from(entity))
+}
+
+/// Look up the versions of an entity for a root `<type>History` field.
+/// Each version becomes one node that has the entity's attributes and the
+/// block range during which the version was current
+fn execute_history(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    fields: &[&q::Field],
+    version_type: &s::ObjectType,
+) -> Result<Vec<Node>, QueryExecutionError> {
+    let query_type = ctx.query.schema.query_type.as_ref();
+    let arguments = crate::execution::coerce_argument_values(ctx, query_type, fields[0])
+        .map_err(|mut errors| errors.remove(0))?;
+
+    resolver.check_imported_block(&parse_subgraph_id(version_type)?)?;
+
+    let query = build_history_query(
+        version_type,
+        resolver.block_number(),
+        &arguments,
+        ctx.max_first,
+        ctx.query.schema.as_ref(),
+    )?;
+    Ok(resolver
+        .store
+        .entity_history(query)?
+        .into_iter()
+        .map(|version| {
+            let mut node = version.data;
+            node.insert(
+                "__typename".to_owned(),
+                q::Value::String(version_type.name.clone()),
+            );
+            node.insert(
+                "_block_from".to_owned(),
+                q::Value::Int(version.block_from.into()),
+            );
+            node.insert(
+                "_block_to".to_owned(),
+                version
+                    .block_to
+                    .map(|block| q::Value::Int(block.into()))
+                    .unwrap_or(q::Value::Null),
+            );
+            Node::from(node)
+        })
+        .collect())
+}
+
+/// Fetch one page of a collection for a root `<types>Page` field. The
+/// result is a single node whose `items` are the entities on the page, and
+/// whose `pageInfo` says where the page is in the collection
+fn execute_page<'a>(
+    resolver: &StoreResolver,
+    ctx: &'a ExecutionContext<impl Resolver>,
+    fields: &[&'a q::Field],
+    page_type: &'a s::ObjectType,
+) -> Result<Node, Vec<QueryExecutionError>> {
+    let type_name = generated_from(page_type, PAGE_OF_DIRECTIVE)
+        .ok_or_else(|| vec![QueryExecutionError::NamedTypeError(page_type.name.clone())])?;
+    let entity_type = ctx
+        .query
+        .schema
+        .document()
+        .get_object_type_definition(type_name)
+        .ok_or_else(|| vec![QueryExecutionError::NamedTypeError(type_name.to_owned())])?;
+    let (items, page_info) = fetch_page(resolver, ctx, fields, entity_type).map_err(|e| vec![e])?;
+
+    let mut page = Node::from(BTreeMap::new());
+    page.entity.insert(
+        "__typename".to_owned(),
+        q::Value::String(page_type.name.clone()),
+    );
+    let selected = collect_fields(
+        ctx,
+        page_type.into(),
+        fields.iter().map(|field| &field.selection_set),
+    );
+    for (response_key, collected_fields) in selected {
+        for (_, fields) in collected_fields {
+            let children = match fields[0].name.as_str() {
+                "items" => {
+                    let grouped_field_set = collect_fields(
+                        ctx,
+                        entity_type.into(),
+                        fields.iter().map(|field| &field.selection_set),
+                    );
+                    execute_selection_set(resolver, ctx, items.clone(), grouped_field_set)?
+                }
+                _ => vec![page_info.clone()],
+            };
+            page.children.insert(
+                response_key.clone(),
+                children.into_iter().map(Rc::new).collect(),
+            );
+        }
+    }
+    Ok(page)
+}
+
+/// Get the entities on a page of `entity_type` together with a node for
+/// its `pageInfo`. We ask the store for one more entity than is on the
+/// page to find out whether there is a next page
+fn fetch_page(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    fields: &[&q::Field],
+    entity_type: &s::ObjectType,
+) -> Result<(Vec<Node>, Node), QueryExecutionError> {
+    let query_type = ctx.query.schema.query_type.as_ref();
+    let arguments = crate::execution::coerce_argument_values(ctx, query_type, fields[0])
+        .map_err(|mut errors| errors.remove(0))?;
+
+    resolver.check_imported_block(&parse_subgraph_id(entity_type)?)?;
+
+    let mut query = build_query(
+        entity_type,
+        resolver.block_number(),
+        &arguments,
+        ctx.query.schema.as_ref(),
+        ctx.max_first,
+        0,
+    )?;
+    query.query_id = Some(ctx.query.query_id.clone());
+    query.logger = Some(ctx.logger.clone());
+    let first = query.range.first.expect("build_query always sets first") as usize;
+    let after = query.range.after.clone();
+    let mut previous = query.clone();
+    query.range.first = Some(first as u32 + 1);
+
+    // Entities of imported types have to be renamed like in `fetch`
+    let typename = entity_type.name.clone();
+    let renamed = entity_type_name(entity_type) != entity_type.name;
+    let mut items: Vec<Node> = resolver
+        .store
+        .find_query_values(query)?
+        .into_iter()
+        .map(|mut entity| {
+            if renamed {
+                entity.insert("__typename".to_owned(), q::Value::String(typename.clone()));
+            }
+            Node::from(entity)
+        })
+        .collect();
+    let has_next_page = items.len() > first;
+    items.truncate(first);
+
+    let order = previous.order.clone();
+    let order_by = cursor_order_by(&arguments);
+    let cursor = |node: &Node| page_cursor(resolver, ctx, entity_type, &order, order_by, node);
+    let start = items.first().map(&cursor).transpose()?;
+    let end = items.last().map(&cursor).transpose()?;
+
+    // Look for one entity before the page, or, for an empty page, before
+    // the `after` cursor
+    let has_previous_page = match start.clone().or(after) {
+        Some(bound) => {
+            previous.range = EntityRange {
+                first: Some(1),
+                skip: 0,
+                after: None,
+                before: Some(bound),
+            };
+            !resolver.store.find_query_values(previous)?.is_empty()
+        }
+        None => false,
+    };
+
+    let encode = |cursor: Option<EntityCursor>| {
+        cursor
+            .map(|cursor| q::Value::String(cursor.encode()))
+            .unwrap_or(q::Value::Null)
+    };
+    let mut page_info = BTreeMap::new();
+    page_info.insert("hasNextPage".to_owned(), q::Value::Boolean(has_next_page));
+    page_info.insert(
+        "hasPreviousPage".to_owned(),
+        q::Value::Boolean(has_previous_page),
+    );
+    page_info.insert("startCursor".to_owned(), encode(start));
+    page_info.insert("endCursor".to_owned(), encode(end));
+    Ok((items, Node::from(page_info)))
+}
+
+/// The cursor for `node` in a collection of `entity_type` that is sorted
+/// by `order`. When sorting by a field of a referenced entity, we have to
+/// look that entity up to get the value of the sort key
+fn page_cursor(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    entity_type: &s::ObjectType,
+    order: &EntityOrder,
+    order_by: &str,
+    node: &Node,
+) -> Result<EntityCursor, QueryExecutionError> {
+    fn store_value(
+        object_type: &s::ObjectType,
+        entity: &BTreeMap<String, q::Value>,
+        attr: &str,
+    ) -> Result<StoreValue, QueryExecutionError> {
+        let field = sast::get_field(object_type, &attr.to_owned()).ok_or_else(|| {
+            QueryExecutionError::EntityFieldError(object_type.name.clone(), attr.to_owned())
+        })?;
+        match entity.get(attr) {
+            Some(value) => StoreValue::from_query_value(value, &field.field_type),
+            None => Ok(StoreValue::Null),
+        }
+    }
+
+    let id = node
+        .id()
+        .map_err(|e| QueryExecutionError::ResolveEntitiesError(e.to_string()))?;
+    let value = match order {
+        EntityOrder::Ascending(attr, _) | EntityOrder::Descending(attr, _) => {
+            store_value(entity_type, &node.entity, attr)?
+        }
+        EntityOrder::ChildAscending(child, _) | EntityOrder::ChildDescending(child, _) => {
+            let child_type = sast::get_field(entity_type, &child.attr)
+                .and_then(|field| {
+                    ctx.query
+                        .schema
+                        .document()
+                        .get_object_type_definition(field.field_type.get_base_type())
+                })
+                .ok_or_else(|| {
+                    QueryExecutionError::EntityFieldError(
+                        entity_type.name.clone(),
+                        child.attr.clone(),
+                    )
+                })?;
+            match node.get(&child.attr) {
+                Some(q::Value::String(child_id)) => {
+                    let query = EntityQuery::new(
+                        parse_subgraph_id(entity_type)?,
+                        resolver.block_number(),
+                        EntityCollection::All(vec![child.entity_type.clone()]),
+                    )
+                    .filter(EntityFilter::Equal(
+                        ARG_ID.to_owned(),
+                        StoreValue::from(child_id.as_str()),
+                    ))
+                    .first(1);
+                    match resolver.store.find_query_values(query)?.first() {
+                        Some(child_entity) => {
+                            store_value(child_type, child_entity, &child.child_attr)?
+                        }
+                        None => StoreValue::Null,
+                    }
+                }
+                _ => StoreValue::Null,
+            }
+        }
+        EntityOrder::Default | EntityOrder::Unordered => StoreValue::from(id.as_str()),
+    };
+    Ok(EntityCursor::new(order_by, value, &id))
+}
+
+/// List the entities that were removed since the block given in the
+/// `number_gte` argument of the root `_deletions` field. Since pruning
+/// removes the versions of deleted entities, `number_gte` must not be
+/// before the history horizon of the subgraph
+fn execute_deletions(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    fields: &[&q::Field],
+) -> Result<Vec<Node>, QueryExecutionError> {
+    let query_type = ctx.query.schema.query_type.as_ref();
+    let arguments = crate::execution::coerce_argument_values(ctx, query_type, fields[0])
+        .map_err(|mut errors| errors.remove(0))?;
+
+    let query = build_deletions_query(
+        ctx.query.schema.id().clone(),
+        resolver.block_number(),
+        &arguments,
+        ctx.max_first,
+    )?;
+    StoreResolver::check_history_horizon(
+        resolver.store.as_ref(),
+        &query.subgraph_id,
+        "number_gte",
+        query.since,
+    )?;
+    Ok(resolver
+        .store
+        .deletions(query)?
+        .into_iter()
+        .map(|deletion| {
+            let mut node = BTreeMap::new();
+            node.insert("cursor".to_owned(), q::Value::String(deletion.cursor()));
+            node.insert("entity".to_owned(), q::Value::String(deletion.entity_type));
+            node.insert("id".to_owned(), q::Value::String(deletion.entity_id));
+            node.insert("block".to_owned(), q::Value::Int(deletion.block.into()));
+            Node::from(node)
+        })
+        .collect())
+}
+
 /// Query child entities for `parents` from the store. The `join` indicates
 /// in which child field to look for the parent's id/join field. When
 /// `is_single` is `true`, there is at most one child per parent.
@@ -821,7 +1200,7 @@ fn fetch(
     join: &Join<'_>,
     arguments: HashMap<&q::Name, q::Value>,
     multiplicity: ChildMultiplicity,
-    types_for_interface: &BTreeMap<s::Name, Vec<s::ObjectType>>,
+    schema: &ApiSchema,
     block: BlockNumber,
     max_first: u32,
     max_skip: u32,
@@ -831,7 +1210,7 @@ fn fetch(
         join.child_type,
         block,
         &arguments,
-        types_for_interface,
+        schema,
         max_first,
         max_skip,
     )?;
@@ -861,7 +1240,25 @@ fn fetch(
         query.collection = EntityCollection::Window(windows);
     }
 
-    store
-        .find_query_values(query)
-        .map(|entities| entities.into_iter().map(|entity| entity.into()).collect())
+    // Entities of types imported under a different name come back from
+    // the store with the name they have in the deployment that stores
+    // them; rename them so they match the type in our schema
+    let typename = match join.child_type {
+        ObjectOrInterface::Object(object) if entity_type_name(object) != object.name => {
+            Some(object.name.clone())
+        }
+        _ => None,
+    };
+
+    store.find_query_values(query).map(|entities| {
+        entities
+            .into_iter()
+            .map(|mut entity| {
+                if let Some(typename) = &typename {
+                    entity.insert("__typename".to_owned(), q::Value::String(typename.clone()));
+                }
+                entity.into()
+            })
+            .collect()
+    })
 }
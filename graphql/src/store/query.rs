@@ -1,8 +1,13 @@
-use graphql_parser::{query as q, query::Name, schema as s, schema::ObjectType};
+use graphql_parser::{query as q, schema as s, Pos};
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::mem::discriminant;
 
-use graph::data::graphql::ObjectOrInterface;
+use graph::components::store::{
+    Aggregate, AggregateQuery, Child as StoreChild, ChildOrder, EntityDeletion,
+    EntityDeletionsQuery, EntityHistoryQuery,
+};
+use graph::data::graphql::{DocumentExt, ObjectOrInterface, TypeExt};
+use graph::data::schema::{AGGREGATE_OF_DIRECTIVE, ORIGINAL_NAME_DIRECTIVE, VERSION_OF_DIRECTIVE};
 use graph::prelude::*;
 
 use crate::schema::ast as sast;
@@ -13,6 +18,15 @@ enum OrderDirection {
     Descending,
 }
 
+/// What `orderBy` refers to: either a field of the entity itself, or, for
+/// values of the form `<field>__<child field>`, a field of the entity that
+/// `field` references
+#[derive(Debug)]
+enum OrderByValue {
+    Direct(String),
+    Child(ChildOrder),
+}
+
 /// Builds a EntityQuery from GraphQL arguments.
 ///
 /// Panics if `entity` is not present in `schema`.
@@ -20,39 +34,193 @@ pub fn build_query<'a>(
     entity: impl Into<ObjectOrInterface<'a>>,
     block: BlockNumber,
     arguments: &HashMap<&q::Name, q::Value>,
-    types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+    schema: &ApiSchema,
     max_first: u32,
     max_skip: u32,
 ) -> Result<EntityQuery, QueryExecutionError> {
     let entity = entity.into();
-    let entity_types = EntityCollection::All(match &entity {
-        ObjectOrInterface::Object(object) => vec![object.name.clone()],
-        ObjectOrInterface::Interface(interface) => types_for_interface[&interface.name]
-            .iter()
-            .map(|o| o.name.clone())
-            .collect(),
-    });
+    let entity_types = EntityCollection::All(entity_type_names(entity, schema));
     let mut query = EntityQuery::new(parse_subgraph_id(entity)?, block, entity_types)
         .range(build_range(arguments, max_first, max_skip)?);
-    if let Some(filter) = build_filter(entity, arguments)? {
+    if let Some(filter) = build_filter(entity, arguments, schema)? {
         query = query.filter(filter);
     }
     let order = match (
-        build_order_by(entity, arguments)?,
+        build_order_by(entity, arguments, schema)?,
         build_order_direction(arguments)?,
     ) {
-        (Some((attr, value_type)), OrderDirection::Ascending) => {
+        (Some((OrderByValue::Direct(attr), value_type)), OrderDirection::Ascending) => {
             EntityOrder::Ascending(attr, value_type)
         }
-        (Some((attr, value_type)), OrderDirection::Descending) => {
+        (Some((OrderByValue::Direct(attr), value_type)), OrderDirection::Descending) => {
             EntityOrder::Descending(attr, value_type)
         }
+        (Some((OrderByValue::Child(child), value_type)), OrderDirection::Ascending) => {
+            EntityOrder::ChildAscending(child, value_type)
+        }
+        (Some((OrderByValue::Child(child), value_type)), OrderDirection::Descending) => {
+            EntityOrder::ChildDescending(child, value_type)
+        }
         (None, _) => EntityOrder::Default,
     };
     query = query.order(order);
+
+    // A cursor is a position in the order it was taken from and means
+    // nothing in any other order
+    let order_by = cursor_order_by(arguments);
+    for cursor in query.range.after.iter().chain(query.range.before.iter()) {
+        if cursor.order_by != order_by {
+            return Err(QueryExecutionError::InvalidCursor(cursor.encode()));
+        }
+    }
     Ok(query)
 }
 
+/// The `orderBy` that cursors for a query with `arguments` have to be
+/// taken from
+pub fn cursor_order_by(arguments: &HashMap<&q::Name, q::Value>) -> &str {
+    match arguments.get(&"orderBy".to_string()) {
+        Some(q::Value::Enum(name)) => name.as_str(),
+        _ => "id",
+    }
+}
+
+/// Builds an `AggregateQuery` that computes the fields `field_names` of
+/// the `<Type>_aggregate` type `aggregate_type`. The aggregates in the
+/// query are in the same order as `field_names`
+pub fn build_aggregate_query(
+    aggregate_type: &s::ObjectType,
+    block: BlockNumber,
+    arguments: &HashMap<&q::Name, q::Value>,
+    field_names: &[&String],
+    schema: &ApiSchema,
+) -> Result<AggregateQuery, QueryExecutionError> {
+    let type_name = generated_from(aggregate_type, AGGREGATE_OF_DIRECTIVE)
+        .ok_or_else(|| QueryExecutionError::NamedTypeError(aggregate_type.name.clone()))?;
+    let entity = schema
+        .document()
+        .get_object_type_definition(type_name)
+        .ok_or_else(|| QueryExecutionError::NamedTypeError(type_name.to_owned()))?;
+
+    let aggregates = field_names
+        .iter()
+        .map(|name| {
+            if name.as_str() == "count" {
+                return Ok(Aggregate::Count);
+            }
+            let mut parts = name.rsplitn(2, '_');
+            match (parts.next(), parts.next()) {
+                (Some("sum"), Some(attr)) => Ok(Aggregate::Sum(attr.to_owned())),
+                (Some("min"), Some(attr)) => Ok(Aggregate::Min(attr.to_owned())),
+                (Some("max"), Some(attr)) => Ok(Aggregate::Max(attr.to_owned())),
+                (Some("avg"), Some(attr)) => Ok(Aggregate::Avg(attr.to_owned())),
+                _ => Err(QueryExecutionError::UnknownField(
+                    Pos::default(),
+                    aggregate_type.name.clone(),
+                    name.to_string(),
+                )),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(AggregateQuery {
+        subgraph_id: parse_subgraph_id(entity)?,
+        block,
+        entity_type: entity_type_name(entity).to_owned(),
+        filter: build_filter(entity.into(), arguments, schema)?,
+        aggregates,
+    })
+}
+
+/// Builds the query for a `<type>History` field from its arguments. The
+/// versions are restricted to blocks up to `block`
+pub fn build_history_query(
+    version_type: &s::ObjectType,
+    block: BlockNumber,
+    arguments: &HashMap<&q::Name, q::Value>,
+    max_first: u32,
+    schema: &ApiSchema,
+) -> Result<EntityHistoryQuery, QueryExecutionError> {
+    let type_name = generated_from(version_type, VERSION_OF_DIRECTIVE)
+        .ok_or_else(|| QueryExecutionError::NamedTypeError(version_type.name.clone()))?;
+    let entity = schema
+        .document()
+        .get_object_type_definition(type_name)
+        .ok_or_else(|| QueryExecutionError::NamedTypeError(type_name.to_owned()))?;
+
+    let block_argument = |name: &str| match arguments.get(&name.to_string()) {
+        Some(q::Value::Int(n)) => n
+            .as_i64()
+            .and_then(|n| BlockNumber::try_from(n).ok())
+            .filter(|n| *n >= 0)
+            .map(Some)
+            .ok_or_else(|| {
+                QueryExecutionError::InvalidArgumentError(
+                    Pos::default(),
+                    name.to_owned(),
+                    q::Value::Int(n.clone()),
+                )
+            }),
+        _ => Ok(None),
+    };
+
+    let entity_id = match arguments.get(&"id".to_string()) {
+        Some(q::Value::String(id)) => id.clone(),
+        _ => unreachable!("id is a required argument"),
+    };
+
+    Ok(EntityHistoryQuery {
+        subgraph_id: parse_subgraph_id(entity)?,
+        entity_type: entity_type_name(entity).to_owned(),
+        entity_id,
+        from_block: block_argument("from_block")?.unwrap_or(0),
+        to_block: block_argument("to_block")?.unwrap_or(block).min(block),
+        first: build_range(arguments, max_first, 0)?
+            .first
+            .expect("build_range always sets first"),
+    })
+}
+
+/// Builds the query for the root `_deletions` field of `subgraph_id` from
+/// its arguments. Only deletions in blocks up to `block` are listed
+pub fn build_deletions_query(
+    subgraph_id: SubgraphDeploymentId,
+    block: BlockNumber,
+    arguments: &HashMap<&q::Name, q::Value>,
+    max_first: u32,
+) -> Result<EntityDeletionsQuery, QueryExecutionError> {
+    let since = match arguments.get(&"number_gte".to_string()) {
+        Some(q::Value::Int(n)) => n
+            .as_i64()
+            .and_then(|n| BlockNumber::try_from(n).ok())
+            .filter(|n| *n >= 0)
+            .ok_or_else(|| {
+                QueryExecutionError::InvalidArgumentError(
+                    Pos::default(),
+                    "number_gte".to_owned(),
+                    q::Value::Int(n.clone()),
+                )
+            })?,
+        _ => unreachable!("number_gte is a required argument"),
+    };
+
+    let after = match arguments.get(&"after".to_string()) {
+        Some(q::Value::String(cursor)) => Some(EntityDeletion::from_cursor(cursor)?),
+        Some(q::Value::Null) | None => None,
+        _ => unreachable!("cursors are Strings"),
+    };
+
+    Ok(EntityDeletionsQuery {
+        subgraph_id,
+        since,
+        block,
+        first: build_range(arguments, max_first, 0)?
+            .first
+            .expect("build_range always sets first"),
+        after,
+    })
+}
+
 /// Parses GraphQL arguments into a EntityRange, if present.
 fn build_range(
     arguments: &HashMap<&q::Name, q::Value>,
@@ -89,19 +257,40 @@ fn build_range(
         _ => unreachable!("skip is an Int with a default value"),
     };
 
+    let cursor = |name: &str| match arguments.get(&name.to_string()) {
+        Some(q::Value::String(cursor)) => EntityCursor::decode(cursor).map(Some),
+        Some(q::Value::Null) | None => Ok(None),
+        _ => unreachable!("cursors are Strings"),
+    };
+
     Ok(EntityRange {
         first: Some(first),
         skip,
+        after: cursor("after")?,
+        before: cursor("before")?,
     })
 }
 
+/// The names of the entity types that the store needs to look at to find
+/// entities of type `entity`
+fn entity_type_names(entity: ObjectOrInterface, schema: &ApiSchema) -> Vec<String> {
+    match entity {
+        ObjectOrInterface::Object(object) => vec![entity_type_name(object).to_owned()],
+        ObjectOrInterface::Interface(interface) => schema.types_for_interface()[&interface.name]
+            .iter()
+            .map(|o| o.name.clone())
+            .collect(),
+    }
+}
+
 /// Parses GraphQL arguments into an EntityFilter, if present.
 fn build_filter(
     entity: ObjectOrInterface,
     arguments: &HashMap<&q::Name, q::Value>,
+    schema: &ApiSchema,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     match arguments.get(&"where".to_string()) {
-        Some(q::Value::Object(object)) => build_filter_from_object(entity, object),
+        Some(q::Value::Object(object)) => build_filter_from_object(entity, object, schema),
         Some(q::Value::Null) => Ok(None),
         None => match arguments.get(&"text".to_string()) {
             Some(q::Value::Object(filter)) => build_fulltext_filter_from_object(filter),
@@ -134,6 +323,7 @@ fn build_fulltext_filter_from_object(
 fn build_filter_from_object(
     entity: ObjectOrInterface,
     object: &BTreeMap<q::Name, q::Value>,
+    schema: &ApiSchema,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     Ok(Some(EntityFilter::And({
         object
@@ -141,6 +331,18 @@ fn build_filter_from_object(
             .map(|(key, value)| {
                 use self::sast::FilterOp::*;
 
+                // `and` and `or` combine the filters in their list, unless
+                // the entity has a field with that name
+                match key.as_str() {
+                    "and" | "or" if sast::get_field(entity, key).is_none() => {
+                        return build_combined_filter(entity, key, value, schema);
+                    }
+                    "_change_block" if sast::get_field(entity, key).is_none() => {
+                        return build_change_block_filter(value);
+                    }
+                    _ => (),
+                }
+
                 let (field_name, op) = sast::parse_field_as_filter(key);
 
                 let field = sast::get_field(entity, &field_name).ok_or_else(|| {
@@ -150,6 +352,10 @@ fn build_filter_from_object(
                     )
                 })?;
 
+                if let Child = op {
+                    return build_child_filter(field, value, schema);
+                }
+
                 let ty = &field.field_type;
                 let store_value = Value::from_query_value(value, &ty)?;
 
@@ -168,12 +374,100 @@ fn build_filter_from_object(
                     EndsWith => EntityFilter::EndsWith(field_name, store_value),
                     NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
                     Equal => EntityFilter::Equal(field_name, store_value),
+                    Child => unreachable!("child filters were handled above"),
                 })
             })
             .collect::<Result<Vec<EntityFilter>, QueryExecutionError>>()?
     })))
 }
 
+/// Build the filter for `and: [..]` or `or: [..]` from the list of
+/// filters in `value`
+fn build_combined_filter(
+    entity: ObjectOrInterface,
+    combinator: &str,
+    value: &q::Value,
+    schema: &ApiSchema,
+) -> Result<EntityFilter, QueryExecutionError> {
+    let filters = match value {
+        q::Value::List(filters) => filters
+            .iter()
+            .map(|filter| match filter {
+                q::Value::Object(object) => {
+                    build_filter_from_object(entity, object, schema).map(|filter| {
+                        filter.expect("build_filter_from_object always returns a filter")
+                    })
+                }
+                _ => Err(QueryExecutionError::InvalidFilterError),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    Ok(match combinator {
+        "and" => EntityFilter::And(filters),
+        _ => EntityFilter::Or(filters),
+    })
+}
+
+/// Build the filter for `_change_block: { number_gte: .. }`
+fn build_change_block_filter(value: &q::Value) -> Result<EntityFilter, QueryExecutionError> {
+    let number = match value {
+        q::Value::Object(object) => object.get("number_gte"),
+        _ => None,
+    };
+    match number {
+        Some(q::Value::Int(number)) => number
+            .as_i64()
+            .and_then(|number| BlockNumber::try_from(number).ok())
+            .map(EntityFilter::ChangeBlockGte)
+            .ok_or(QueryExecutionError::InvalidFilterError),
+        _ => Err(QueryExecutionError::InvalidFilterError),
+    }
+}
+
+/// Turn a filter `field_: { .. }` on the entities that `field` refers to
+/// into a filter on the parent entities. For interfaces, the filter
+/// matches if any of the implementing types match
+fn build_child_filter(
+    field: &s::Field,
+    value: &q::Value,
+    schema: &ApiSchema,
+) -> Result<EntityFilter, QueryExecutionError> {
+    let object = match value {
+        q::Value::Object(object) => object,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    let type_name = field.field_type.get_base_type();
+    let child_type = schema
+        .document()
+        .object_or_interface(type_name)
+        .ok_or_else(|| QueryExecutionError::NamedTypeError(type_name.to_owned()))?;
+    let filter = build_filter_from_object(child_type, object, schema)?
+        .expect("build_filter_from_object always returns a filter");
+
+    // For `@derivedFrom` fields, the child entities point to the parent
+    let (attr, derived) = match sast::get_derived_from_field(child_type, field) {
+        Some(derived_from) => (derived_from.name.clone(), true),
+        None => (field.name.clone(), false),
+    };
+    let mut children = entity_type_names(child_type, schema)
+        .into_iter()
+        .map(|entity_type| {
+            EntityFilter::Child(StoreChild {
+                attr: attr.clone(),
+                entity_type,
+                filter: Box::new(filter.clone()),
+                derived,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(if children.len() == 1 {
+        children.pop().expect("we just checked there is an element")
+    } else {
+        EntityFilter::Or(children)
+    })
+}
+
 /// Parses a list of GraphQL values into a vector of entity field values.
 fn list_values(value: Value, filter_type: &str) -> Result<Vec<Value>, QueryExecutionError> {
     match value {
@@ -206,14 +500,18 @@ fn list_values(value: Value, filter_type: &str) -> Result<Vec<Value>, QueryExecu
 fn build_order_by(
     entity: ObjectOrInterface,
     arguments: &HashMap<&q::Name, q::Value>,
-) -> Result<Option<(String, ValueType)>, QueryExecutionError> {
+    schema: &ApiSchema,
+) -> Result<Option<(OrderByValue, ValueType)>, QueryExecutionError> {
     match arguments.get(&"orderBy".to_string()) {
         Some(q::Value::Enum(name)) => {
+            if sast::get_field(entity, &name).is_none() && name.contains("__") {
+                return build_child_order_by(entity, name, schema).map(Some);
+            }
             let field = sast::get_field(entity, &name).ok_or_else(|| {
                 QueryExecutionError::EntityFieldError(entity.name().to_owned(), name.clone())
             })?;
             sast::get_field_value_type(&field.field_type)
-                .map(|value_type| Some((name.to_owned(), value_type)))
+                .map(|value_type| Some((OrderByValue::Direct(name.to_owned()), value_type)))
                 .map_err(|_| {
                     QueryExecutionError::OrderByNotSupportedError(
                         entity.name().to_owned(),
@@ -222,13 +520,62 @@ fn build_order_by(
                 })
         }
         _ => match arguments.get(&"text".to_string()) {
-            Some(q::Value::Object(filter)) => build_fulltext_order_by_from_object(filter),
+            Some(q::Value::Object(filter)) => {
+                build_fulltext_order_by_from_object(filter).map(|order| {
+                    order.map(|(attr, value_type)| (OrderByValue::Direct(attr), value_type))
+                })
+            }
             None => Ok(None),
             _ => Err(QueryExecutionError::InvalidFilterError),
         },
     }
 }
 
+/// Parses an `orderBy` value of the form `<field>__<child field>`, where
+/// `field` references a single entity of an object type
+fn build_child_order_by(
+    entity: ObjectOrInterface,
+    name: &str,
+    schema: &ApiSchema,
+) -> Result<(OrderByValue, ValueType), QueryExecutionError> {
+    let not_supported =
+        || QueryExecutionError::OrderByNotSupportedError(entity.name().to_owned(), name.to_owned());
+
+    let mut parts = name.splitn(2, "__");
+    let (attr, child_attr) = match (parts.next(), parts.next()) {
+        (Some(attr), Some(child_attr)) => (attr, child_attr),
+        _ => return Err(not_supported()),
+    };
+    let field = sast::get_field(entity, &attr.to_owned()).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(entity.name().to_owned(), attr.to_owned())
+    })?;
+    if sast::is_list_or_non_null_list_field(field)
+        || sast::get_derived_from_directive(field).is_some()
+    {
+        return Err(not_supported());
+    }
+    let child_type = match sast::get_named_type(schema.document(), field.field_type.get_base_type())
+    {
+        Some(s::TypeDefinition::Object(child_type)) => child_type,
+        _ => return Err(not_supported()),
+    };
+    // The referenced entities have to live in the same store as the parent
+    if parse_subgraph_id(child_type)? != parse_subgraph_id(entity)? {
+        return Err(not_supported());
+    }
+    let child_field = sast::get_field(child_type, &child_attr.to_owned()).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(child_type.name.to_owned(), child_attr.to_owned())
+    })?;
+    let value_type =
+        sast::get_field_value_type(&child_field.field_type).map_err(|_| not_supported())?;
+    let child = ChildOrder {
+        attr: attr.to_owned(),
+        entity_type: entity_type_name(child_type).to_owned(),
+        child_attr: child_attr.to_owned(),
+    };
+    Ok((OrderByValue::Child(child), value_type))
+}
+
 fn build_fulltext_order_by_from_object(
     object: &BTreeMap<q::Name, q::Value>,
 ) -> Result<Option<(String, ValueType)>, QueryExecutionError> {
@@ -283,6 +630,30 @@ pub fn parse_subgraph_id<'a>(
         .map_err(|_| QueryExecutionError::SubgraphDeploymentIdError(entity_name.to_owned()))
 }
 
+/// The name under which the deployment that stores entities of type
+/// `object` knows them. That is the name of the type, unless it was
+/// imported from another subgraph under a different name
+pub fn entity_type_name(object: &s::ObjectType) -> &str {
+    sast::get_object_type_directive(object, String::from(ORIGINAL_NAME_DIRECTIVE))
+        .and_then(|directive| directive.arguments.iter().find(|(name, _)| name == "name"))
+        .and_then(|(_, value)| match value {
+            s::Value::String(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .unwrap_or(object.name.as_str())
+}
+
+/// The name of the type from which the API schema generated `object`, if
+/// `object` is marked as generated with `directive`
+pub fn generated_from<'a>(object: &'a s::ObjectType, directive: &str) -> Option<&'a str> {
+    sast::get_object_type_directive(object, directive.to_owned())
+        .and_then(|directive| directive.arguments.iter().find(|(name, _)| name == "type"))
+        .and_then(|(_, value)| match value {
+            s::Value::String(name) => Some(name.as_str()),
+            _ => None,
+        })
+}
+
 /// Recursively collects entities involved in a query field as `(subgraph ID, name)` tuples.
 pub fn collect_entities_from_query_field(
     schema: &s::Document,
@@ -312,7 +683,8 @@ pub fn collect_entities_from_query_field(
                         // Obtain the subgraph ID from the object type
                         if let Ok(subgraph_id) = parse_subgraph_id(object_type) {
                             // Add the (subgraph_id, entity_name) tuple to the result set
-                            entities.insert((subgraph_id, object_type.name.to_owned()));
+                            entities
+                                .insert((subgraph_id, entity_type_name(object_type).to_owned()));
                         }
                     }
 
@@ -340,14 +712,31 @@ mod tests {
     };
     use std::collections::{BTreeMap, HashMap};
 
+    use graph::data::graphql::DocumentExt;
     use graph::prelude::*;
 
-    use super::build_query;
+    use super::{build_query, StoreChild};
+
+    const SUBGRAPH_ID: &str = "QmZ5dsusHwD1PEbx6L4dLCWkDsk1BLhrx9mPsGyPvTxPCM";
+
+    fn api_schema(text: &str) -> ApiSchema {
+        let id = SubgraphDeploymentId::new(SUBGRAPH_ID).unwrap();
+        let mut schema = Schema::parse(text, id.clone()).unwrap();
+        schema.document = crate::schema::api_schema(&schema.document).unwrap();
+        schema.add_subgraph_id_directives(id);
+        ApiSchema::from_api_schema(schema).unwrap()
+    }
+
+    /// The tests that build object types by hand only need a schema to
+    /// satisfy `build_query`
+    fn default_schema() -> ApiSchema {
+        api_schema("type Thing @entity { id: ID! }")
+    }
 
     fn default_object() -> ObjectType {
         let subgraph_id_argument = (
             s::Name::from("id"),
-            s::Value::String("QmZ5dsusHwD1PEbx6L4dLCWkDsk1BLhrx9mPsGyPvTxPCM".to_string()),
+            s::Value::String(SUBGRAPH_ID.to_string()),
         );
         let subgraph_id_directive = Directive {
             name: "subgraphId".to_string(),
@@ -423,7 +812,7 @@ mod tests {
                 &object("Entity1"),
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -436,7 +825,7 @@ mod tests {
                 &object("Entity2"),
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -453,7 +842,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -473,7 +862,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -489,7 +878,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -509,7 +898,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -525,7 +914,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -547,7 +936,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -564,7 +953,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -584,7 +973,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -604,7 +993,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -626,7 +1015,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -643,7 +1032,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -660,7 +1049,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -680,7 +1069,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -689,10 +1078,77 @@ mod tests {
             EntityRange {
                 first: Some(100),
                 skip: 50,
+                after: None,
+                before: None,
+            },
+        );
+    }
+
+    #[test]
+    fn build_query_yields_cursor() {
+        let after = "after".to_string();
+        let before = "before".to_string();
+        let order_by = "orderBy".to_string();
+        let first = EntityCursor::new("name", Value::from("a"), "1");
+        let last = EntityCursor::new("name", Value::from("z"), "9");
+        let mut args = default_arguments();
+        args.insert(&order_by, q::Value::Enum("name".to_string()));
+        args.insert(&after, q::Value::String(first.encode()));
+        args.insert(&before, q::Value::String(last.encode()));
+        assert_eq!(
+            build_query(
+                &default_object(),
+                BLOCK_NUMBER_MAX,
+                &args,
+                &default_schema(),
+                std::u32::MAX,
+                std::u32::MAX
+            )
+            .unwrap()
+            .range,
+            EntityRange {
+                first: Some(100),
+                skip: 0,
+                after: Some(first),
+                before: Some(last),
             },
         );
     }
 
+    #[test]
+    fn build_query_rejects_invalid_cursors() {
+        let after = "after".to_string();
+        let order_by = "orderBy".to_string();
+        let by_id = EntityCursor::new("id", Value::from("1"), "1");
+
+        // A raw entity id is not a cursor
+        let mut args = default_arguments();
+        args.insert(&after, q::Value::String("1".to_owned()));
+        assert!(build_query(
+            &default_object(),
+            BLOCK_NUMBER_MAX,
+            &args,
+            &default_schema(),
+            std::u32::MAX,
+            std::u32::MAX
+        )
+        .is_err());
+
+        // A cursor for a different order
+        let mut args = default_arguments();
+        args.insert(&order_by, q::Value::Enum("name".to_string()));
+        args.insert(&after, q::Value::String(by_id.encode()));
+        assert!(build_query(
+            &default_object(),
+            BLOCK_NUMBER_MAX,
+            &args,
+            &default_schema(),
+            std::u32::MAX,
+            std::u32::MAX
+        )
+        .is_err());
+    }
+
     #[test]
     fn build_query_yields_filters() {
         let whre = "where".to_string();
@@ -712,7 +1168,7 @@ mod tests {
                 },
                 BLOCK_NUMBER_MAX,
                 &args,
-                &BTreeMap::new(),
+                &default_schema(),
                 std::u32::MAX,
                 std::u32::MAX,
             )
@@ -724,4 +1180,151 @@ mod tests {
             )]))
         )
     }
+
+    #[test]
+    fn build_query_yields_child_filters() {
+        let schema = api_schema(
+            r#"
+            type Owner @entity {
+                id: ID!
+                name: String!
+                tokens: [Token!]! @derivedFrom(field: "owner")
+            }
+            type Token @entity { id: ID!, owner: Owner! }"#,
+        );
+        let whre = "where".to_string();
+        let query_filter = |entity: &str, key: &str, child_key: &str| {
+            let mut args = default_arguments();
+            args.insert(
+                &whre,
+                q::Value::Object(BTreeMap::from_iter(vec![(
+                    key.to_owned(),
+                    q::Value::Object(BTreeMap::from_iter(vec![(
+                        child_key.to_owned(),
+                        q::Value::String("a".to_owned()),
+                    )])),
+                )])),
+            );
+            build_query(
+                schema
+                    .document()
+                    .get_object_type_definition(entity)
+                    .unwrap(),
+                BLOCK_NUMBER_MAX,
+                &args,
+                &schema,
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter
+        };
+        let child_filter = |attr: &str, entity_type: &str, child_attr: &str, derived| {
+            Some(EntityFilter::And(vec![EntityFilter::Child(StoreChild {
+                attr: attr.to_owned(),
+                entity_type: entity_type.to_owned(),
+                filter: Box::new(EntityFilter::And(vec![EntityFilter::Equal(
+                    child_attr.to_owned(),
+                    Value::String("a".to_owned()),
+                )])),
+                derived,
+            })]))
+        };
+
+        assert_eq!(
+            child_filter("owner", "Owner", "name", false),
+            query_filter("Token", "owner_", "name")
+        );
+        assert_eq!(
+            child_filter("owner", "Token", "id", true),
+            query_filter("Owner", "tokens_", "id")
+        );
+    }
+
+    #[test]
+    fn build_query_yields_child_order() {
+        let schema = api_schema(
+            r#"
+            type Owner @entity { id: ID!, balance: BigInt! }
+            type Token @entity { id: ID!, owner: Owner!, owners: [Owner!]! }"#,
+        );
+        let order_by = "orderBy".to_string();
+        let order_direction = "orderDirection".to_string();
+        let query_order = |name: &str| {
+            let mut args = default_arguments();
+            args.insert(&order_by, q::Value::Enum(name.to_owned()));
+            args.insert(&order_direction, q::Value::Enum("desc".to_owned()));
+            build_query(
+                schema
+                    .document()
+                    .get_object_type_definition("Token")
+                    .unwrap(),
+                BLOCK_NUMBER_MAX,
+                &args,
+                &schema,
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .map(|query| query.order)
+        };
+
+        assert_eq!(
+            EntityOrder::ChildDescending(
+                ChildOrder {
+                    attr: "owner".to_owned(),
+                    entity_type: "Owner".to_owned(),
+                    child_attr: "balance".to_owned(),
+                },
+                ValueType::BigInt
+            ),
+            query_order("owner__balance").unwrap()
+        );
+        assert!(query_order("owners__balance").is_err());
+        assert!(query_order("owner__missing").is_err());
+    }
+
+    #[test]
+    fn build_query_yields_combined_filters() {
+        let schema = api_schema("type Thing @entity { id: ID!, name: String! }");
+        let whre = "where".to_string();
+        let name_is = |name: &str| {
+            q::Value::Object(BTreeMap::from_iter(vec![(
+                "name".to_owned(),
+                q::Value::String(name.to_owned()),
+            )]))
+        };
+        let mut args = default_arguments();
+        args.insert(
+            &whre,
+            q::Value::Object(BTreeMap::from_iter(vec![(
+                "or".to_owned(),
+                q::Value::List(vec![name_is("a"), name_is("b")]),
+            )])),
+        );
+        let name_filter = |name: &str| {
+            EntityFilter::And(vec![EntityFilter::Equal(
+                "name".to_owned(),
+                Value::String(name.to_owned()),
+            )])
+        };
+        assert_eq!(
+            Some(EntityFilter::And(vec![EntityFilter::Or(vec![
+                name_filter("a"),
+                name_filter("b")
+            ])])),
+            build_query(
+                schema
+                    .document()
+                    .get_object_type_definition("Thing")
+                    .unwrap(),
+                BLOCK_NUMBER_MAX,
+                &args,
+                &schema,
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter
+        );
+    }
 }
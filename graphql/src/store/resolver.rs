@@ -76,6 +76,55 @@ impl StoreResolver {
             .unwrap_or(BLOCK_NUMBER_MAX)
     }
 
+    /// Fail if `subgraph`, a deployment that this resolver's deployment
+    /// imports types from, has not yet indexed the block at which this
+    /// resolver looks up entities
+    pub(crate) fn check_imported_block(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+    ) -> Result<(), QueryExecutionError> {
+        let block = self.block_number();
+        if subgraph == &self.deployment || block == BLOCK_NUMBER_MAX {
+            return Ok(());
+        }
+        let latest = self
+            .store
+            .block_ptr(subgraph.clone())
+            .map_err(StoreError::from)?
+            .map(|ptr| ptr.number as BlockNumber);
+        match latest {
+            Some(latest) if latest >= block => Ok(()),
+            _ => Err(QueryExecutionError::ImportedDeploymentBehind(
+                subgraph.clone(),
+                latest,
+                block,
+            )),
+        }
+    }
+
+    /// Fail if the entity versions needed to answer queries at block
+    /// `number` have already been pruned from `subgraph`
+    pub(crate) fn check_history_horizon(
+        store: &dyn QueryStore,
+        subgraph: &SubgraphDeploymentId,
+        field: &str,
+        number: BlockNumber,
+    ) -> Result<(), QueryExecutionError> {
+        let horizon = store.history_horizon(subgraph)?;
+        if number < horizon {
+            Err(QueryExecutionError::ValueParseError(
+                field.to_owned(),
+                format!(
+                    "subgraph {} only keeps history from block number {} on \
+                     and data for block number {} has been pruned",
+                    subgraph, horizon, number
+                ),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
     fn locate_block(
         store: &dyn QueryStore,
         bc: BlockConstraint,
@@ -101,6 +150,7 @@ impl StoreResolver {
                                 ),
                             ))
                         } else {
+                            Self::check_history_horizon(store, &subgraph, "block.number", number)?;
                             // We don't have a way here to look the block hash up from
                             // the database, and even if we did, there is no guarantee
                             // that we have the block in our cache. We therefore
@@ -117,14 +167,14 @@ impl StoreResolver {
                     .block_number(&subgraph, hash)
                     .map_err(|e| e.into())
                     .and_then(|number| {
-                        number
-                            .ok_or_else(|| {
-                                QueryExecutionError::ValueParseError(
-                                    "block.hash".to_owned(),
-                                    "no block with that hash found".to_owned(),
-                                )
-                            })
-                            .map(|number| EthereumBlockPointer::from((hash, number as u64)))
+                        let number = number.ok_or_else(|| {
+                            QueryExecutionError::ValueParseError(
+                                "block.hash".to_owned(),
+                                "no block with that hash found".to_owned(),
+                            )
+                        })?;
+                        Self::check_history_horizon(store, &subgraph, "block.hash", number)?;
+                        Ok(EthereumBlockPointer::from((hash, number as u64)))
                     }),
                 BlockConstraint::Latest => store
                     .block_ptr(subgraph.clone())
@@ -2,7 +2,7 @@
 extern crate pretty_assertions;
 
 use graphql_parser::{query as q, Pos};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::iter::FromIterator;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -18,7 +18,7 @@ use graph::prelude::{
 use graph::{
     data::graphql::{object, object_value},
     data::query::CacheStatus,
-    prelude::{NodeId, SubgraphName},
+    prelude::{web3::types::H256, NodeId, SubgraphName},
 };
 use graph_graphql::prelude::*;
 use test_store::{
@@ -98,6 +98,7 @@ fn insert_test_entities(store: &impl Store, id: SubgraphDeploymentId) {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        features: BTreeSet::new(),
     };
 
     let deployment = SubgraphDeploymentEntity::new(&manifest, false, None);
@@ -252,6 +253,7 @@ async fn execute_query_document_with_variables(
         reorg_count: 0,
         max_reorg_depth: 0,
         latest_ethereum_block_number: BLOCK_NUMBER_MAX,
+        has_non_fatal_errors: false,
     };
 
     runner
@@ -848,6 +850,56 @@ fn query_complexity() {
             QueryError::ExecutionError(QueryExecutionError::TooComplex(1_010_200, _)) => (),
             _ => panic!("did not catch complexity"),
         };
+
+        // Every branch of an `or` filter counts towards the complexity
+        // of the collection it filters
+        let query = Query::new(
+            Arc::new(api_test_schema(&id)),
+            graphql_parser::parse_query(
+                "query {
+                musicians(first: 100, where: { or: [{ name: \"John\" }, { name: \"Tom\" }] }) {
+                    name
+                }
+            }",
+            )
+            .unwrap(),
+            None,
+            None,
+        );
+        let result = graph::spawn_blocking_allow_panic(move || {
+            execute_subgraph_query_with_complexity(query, Some(299))
+        })
+        .await
+        .unwrap();
+        match result.to_result().unwrap_err()[0] {
+            QueryError::ExecutionError(QueryExecutionError::TooComplex(300, _)) => (),
+            _ => panic!("did not count filter complexity"),
+        };
+
+        // Listing deletions costs as much as listing `first` entities
+        let query = Query::new(
+            Arc::new(api_test_schema(&id)),
+            graphql_parser::parse_query(
+                "query {
+                _deletions(number_gte: 0, first: 500) {
+                    entity
+                    id
+                }
+            }",
+            )
+            .unwrap(),
+            None,
+            None,
+        );
+        let result = graph::spawn_blocking_allow_panic(move || {
+            execute_subgraph_query_with_complexity(query, Some(499))
+        })
+        .await
+        .unwrap();
+        match result.to_result().unwrap_err()[0] {
+            QueryError::ExecutionError(QueryExecutionError::TooComplex(500, _)) => (),
+            _ => panic!("did not count deletions complexity"),
+        };
     })
 }
 
@@ -855,7 +907,7 @@ fn query_complexity() {
 fn query_complexity_subscriptions() {
     run_test_sequentially(setup, |_, id| async move {
         let logger = Logger::root(slog::Discard, o!());
-        let store = STORE.clone().query_store(true);
+        let store = STORE.clone().query_store(&id, true).unwrap();
         let store_resolver = StoreResolver::for_subscription(&logger, id.clone(), store);
 
         let query = Query::new(
@@ -918,7 +970,7 @@ fn query_complexity_subscriptions() {
             None,
         );
 
-        let store = STORE.clone().query_store(true);
+        let store = STORE.clone().query_store(&id, true).unwrap();
         let store_resolver = StoreResolver::for_subscription(&logger, id.clone(), store);
 
         let options = SubscriptionExecutionOptions {
@@ -1263,7 +1315,7 @@ fn cannot_filter_by_derved_relationship_fields() {
 fn subscription_gets_result_even_without_events() {
     run_test_sequentially(setup, |_, id| async move {
         let logger = Logger::root(slog::Discard, o!());
-        let store = STORE.clone().query_store(true);
+        let store = STORE.clone().query_store(&id, true).unwrap();
         let store_resolver = StoreResolver::for_subscription(&logger, id.clone(), store);
 
         let query = Query::new(
@@ -1376,6 +1428,458 @@ fn can_use_nested_filter() {
     })
 }
 
+#[test]
+fn can_combine_filters_with_and_or() {
+    run_test_sequentially(setup, |_, id| async move {
+        check_musicians_at(
+            &id,
+            "query { musicians(orderBy: id, where: { or: [{ name: \"John\" }, { name: \"Tom\" }] }) { id } }",
+            None,
+            Ok(vec!["m1", "m3"]),
+            "or",
+        )
+        .await;
+
+        check_musicians_at(
+            &id,
+            "query { musicians(orderBy: id, where: { and: [{ name_gt: \"J\" }, \
+                                                    { or: [{ mainBand: \"b2\" }, { name: \"Lisa\" }] }] }) { id } }",
+            None,
+            Ok(vec!["m2", "m3"]),
+            "and with nested or",
+        )
+        .await;
+
+        check_musicians_at(
+            &id,
+            "query { musicians(orderBy: id, where: { name_not: \"Lisa\", or: [{ mainBand: \"b1\" }, { mainBand: \"b2\" }] }) { id } }",
+            None,
+            Ok(vec!["m1", "m3"]),
+            "or next to other filters",
+        )
+        .await;
+    })
+}
+
+#[test]
+fn can_paginate_with_cursor() {
+    run_test_sequentially(setup, |_, id| async move {
+        const PAGE: &str = "query($after: String) {
+            musiciansPage(first: 2, orderBy: name, after: $after) {
+                items { id mainBand { id } }
+                pageInfo { hasNextPage hasPreviousPage startCursor endCursor }
+            }
+        }";
+
+        async fn page(id: &SubgraphDeploymentId, after: Option<String>) -> q::Value {
+            let query = graphql_parser::parse_query(PAGE).expect("invalid test query");
+            let vars = after.map(|after| {
+                let mut map = HashMap::new();
+                map.insert("after".to_owned(), q::Value::String(after));
+                QueryVariables::new(map)
+            });
+            let result = execute_query_document_with_variables(id, query, vars).await;
+            match extract_data!(result) {
+                Some(q::Value::Object(mut data)) => data.remove("musiciansPage").unwrap(),
+                data => panic!("unexpected result {:?}", data),
+            }
+        }
+
+        fn get<'a>(value: &'a q::Value, name: &str) -> &'a q::Value {
+            match value {
+                q::Value::Object(map) => map.get(name).expect("field is in the result"),
+                _ => panic!("expected an object but got {:?}", value),
+            }
+        }
+
+        fn cursor(page: &q::Value, name: &str) -> String {
+            match get(get(page, "pageInfo"), name) {
+                q::Value::String(cursor) => cursor.clone(),
+                value => panic!("expected a cursor but got {:?}", value),
+            }
+        }
+
+        let first = page(&id, None).await;
+        assert_eq!(
+            get(&first, "items"),
+            &q::Value::List(vec![
+                object! { id: "m1", mainBand: object! { id: "b1" } },
+                object! { id: "m2", mainBand: object! { id: "b1" } },
+            ])
+        );
+        assert_eq!(
+            get(get(&first, "pageInfo"), "hasNextPage"),
+            &q::Value::Boolean(true)
+        );
+        assert_eq!(
+            get(get(&first, "pageInfo"), "hasPreviousPage"),
+            &q::Value::Boolean(false)
+        );
+
+        let end = cursor(&first, "endCursor");
+        let second = page(&id, Some(end.clone())).await;
+        assert_eq!(
+            get(&second, "items"),
+            &q::Value::List(vec![
+                object! { id: "m3", mainBand: object! { id: "b2" } },
+                object! { id: "m4", mainBand: q::Value::Null },
+            ])
+        );
+        assert_eq!(
+            get(get(&second, "pageInfo"), "hasNextPage"),
+            &q::Value::Boolean(false)
+        );
+        assert_eq!(
+            get(get(&second, "pageInfo"), "hasPreviousPage"),
+            &q::Value::Boolean(true)
+        );
+
+        // Cursors from pages also work for plain collections
+        check_musicians_at(
+            &id,
+            "query($before: String) { musicians(orderBy: name, before: $before) { id } }",
+            Some(("before", q::Value::String(cursor(&second, "startCursor")))),
+            Ok(vec!["m1", "m2"]),
+            "before the second page",
+        )
+        .await;
+
+        check_musicians_at(
+            &id,
+            "query($after: String) { musicians(first: 1, skip: 1, orderBy: name, after: $after) { id } }",
+            Some(("after", q::Value::String(end.clone()))),
+            Ok(vec!["m4"]),
+            "cursor and skip",
+        )
+        .await;
+
+        check_musicians_at(
+            &id,
+            "query($after: String) { musicians(orderBy: id, after: $after) { id } }",
+            Some(("after", q::Value::String(end))),
+            Err("is not a valid cursor for this query"),
+            "cursor for a different order",
+        )
+        .await;
+
+        check_musicians_at(
+            &id,
+            "query { musicians(orderBy: name, after: \"m1\") { id } }",
+            None,
+            Err("`m1` is not a valid cursor for this query"),
+            "entity id as the cursor",
+        )
+        .await;
+    })
+}
+
+#[test]
+fn can_order_by_child_field() {
+    run_test_sequentially(setup, |_, id| async move {
+        // Valerie has no main band and sorts last in both directions
+        check_musicians_at(
+            &id,
+            "query { musicians(orderBy: mainBand__name) { id } }",
+            None,
+            Ok(vec!["m3", "m1", "m2", "m4"]),
+            "ascending",
+        )
+        .await;
+
+        check_musicians_at(
+            &id,
+            "query { musicians(orderBy: mainBand__name, orderDirection: desc) { id } }",
+            None,
+            Ok(vec!["m1", "m2", "m3", "m4"]),
+            "descending",
+        )
+        .await;
+
+        check_musicians_at(
+            &id,
+            "query { musicians(orderBy: mainBand__name, after: \"m1\") { id } }",
+            None,
+            Ok(vec!["m2", "m4"]),
+            "with cursor",
+        )
+        .await;
+
+        let query = graphql_parser::parse_query(
+            "query { bands(orderBy: id) { id members(orderBy: mainBand__name) { id } } }",
+        )
+        .expect("invalid test query");
+        let result = execute_query_document(&id, query).await;
+        let band = |id: &str, members: Vec<&str>| {
+            object! {
+                id: id,
+                members: members.into_iter().map(|id| object! { id: id }).collect::<Vec<_>>(),
+            }
+        };
+        let exp = object! {
+            bands: vec![band("b1", vec!["m3", "m1", "m2"]), band("b2", vec!["m3", "m1"])],
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_query_aggregates() {
+    run_test_sequentially(setup, |_, id| async move {
+        let query = graphql_parser::parse_query(
+            "query {
+                songStatAggregate { count played_sum played_min played_max played_avg }
+                none: songStatAggregate(where: { played_gt: 100 }) { count played_sum }
+                musicianAggregate(where: { name_in: [\"John\", \"Tom\", \"Valerie\"] }) { count }
+                before: musicianAggregate(block: { number: 0 }) { count }
+            }",
+        )
+        .expect("invalid test query");
+        let result = execute_query_document(&id, query).await;
+        let exp = object! {
+            songStatAggregate: object! {
+                count: "2",
+                played_sum: "25",
+                played_min: 10,
+                played_max: 15,
+                played_avg: "12.5",
+            },
+            none: object! {
+                count: "0",
+                played_sum: q::Value::Null,
+            },
+            musicianAggregate: object! { count: "3" },
+            before: object! { count: "2" },
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_query_changes_since_block() {
+    run_test_sequentially(setup, |_, id| async move {
+        let query = graphql_parser::parse_query(
+            "query {
+                musicians(where: { _change_block: { number_gte: 1 } }) { id }
+                _deletions(number_gte: 0) { entity id block }
+            }",
+        )
+        .expect("invalid test query");
+        let result = execute_query_document(&id, query).await;
+        let exp = object! {
+            musicians: vec![object! { id: "m3" }, object! { id: "m4" }],
+            _deletions: Vec::<q::Value>::new(),
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+
+        // Redo block 1 so that it removes two musicians
+        STORE
+            .revert_block_operations(id.clone(), BLOCK_ONE.clone(), GENESIS_PTR.clone())
+            .unwrap();
+        let remove = |entity_id: &str| EntityOperation::Remove {
+            key: EntityKey {
+                subgraph_id: id.clone(),
+                entity_type: "Musician".to_owned(),
+                entity_id: entity_id.to_owned(),
+            },
+        };
+        transact_entity_operations(
+            &*STORE,
+            id.clone(),
+            BLOCK_ONE.clone(),
+            vec![remove("m1"), remove("m2")],
+        )
+        .unwrap();
+
+        let query = graphql_parser::parse_query(
+            "query {
+                _deletions(number_gte: 1) { entity id block }
+                before: _deletions(number_gte: 0, block: { number: 0 }) { id }
+            }",
+        )
+        .expect("invalid test query");
+        let result = execute_query_document(&id, query).await;
+        let exp = object! {
+            _deletions: vec![
+                object! { entity: "Musician", id: "m1", block: 1 },
+                object! { entity: "Musician", id: "m2", block: 1 },
+            ],
+            before: Vec::<q::Value>::new(),
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+
+        // Walk through the deletions one at a time
+        const PAGE: &str = "query($after: String) {
+            _deletions(number_gte: 1, first: 1, after: $after) { id cursor }
+        }";
+        async fn page(id: &SubgraphDeploymentId, after: Option<String>) -> (q::Value, String) {
+            let query = graphql_parser::parse_query(PAGE).expect("invalid test query");
+            let vars = after.map(|after| {
+                let mut map = HashMap::new();
+                map.insert("after".to_owned(), q::Value::String(after));
+                QueryVariables::new(map)
+            });
+            let result = execute_query_document_with_variables(id, query, vars).await;
+            let mut deletions = match extract_data!(result) {
+                Some(q::Value::Object(mut data)) => match data.remove("_deletions") {
+                    Some(q::Value::List(deletions)) => deletions,
+                    deletions => panic!("unexpected deletions {:?}", deletions),
+                },
+                data => panic!("unexpected result {:?}", data),
+            };
+            assert_eq!(1, deletions.len());
+            match deletions.pop() {
+                Some(q::Value::Object(mut deletion)) => match deletion.remove("cursor") {
+                    Some(q::Value::String(cursor)) => (q::Value::Object(deletion), cursor),
+                    cursor => panic!("expected a cursor but got {:?}", cursor),
+                },
+                deletion => panic!("unexpected deletion {:?}", deletion),
+            }
+        }
+
+        let (first, cursor) = page(&id, None).await;
+        assert_eq!(object! { id: "m1" }, first);
+        let (second, _) = page(&id, Some(cursor)).await;
+        assert_eq!(object! { id: "m2" }, second);
+
+        // Deletions from before the history horizon can not be listed since
+        // pruning removed them
+        let block_200: EthereumBlockPointer = (H256::from_low_u64_be(200), 200u64).into();
+        transact_entity_operations(&*STORE, id.clone(), block_200, vec![]).unwrap();
+        STORE.store().set_history_blocks(&id, Some(60)).unwrap();
+        STORE.store().prune(&*LOGGER).unwrap();
+        let query = graphql_parser::parse_query(
+            "query {
+                _deletions(number_gte: 1) { entity id block }
+            }",
+        )
+        .expect("invalid test query");
+        let result = execute_query_document(&id, query).await;
+        match &result.to_result().unwrap_err()[0] {
+            QueryError::ExecutionError(QueryExecutionError::ValueParseError(field, _))
+                if field == "number_gte" => {}
+            e => panic!("expected number_gte to be rejected, got {:?}", e),
+        }
+    })
+}
+
+#[test]
+fn can_query_entity_history() {
+    run_test_sequentially(setup, |_, id| async move {
+        // Redo block 1 so that it renames a musician
+        STORE
+            .revert_block_operations(id.clone(), BLOCK_ONE.clone(), GENESIS_PTR.clone())
+            .unwrap();
+        let rename = EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: id.clone(),
+                entity_type: "Musician".to_owned(),
+                entity_id: "m1".to_owned(),
+            },
+            data: Entity::from(vec![("name", Value::from("Johnny"))]),
+        };
+        transact_entity_operations(&*STORE, id.clone(), BLOCK_ONE.clone(), vec![rename]).unwrap();
+
+        let query = graphql_parser::parse_query(
+            "query {
+                musicianHistory(id: \"m1\") { name mainBand _block_from _block_to }
+                first: musicianHistory(id: \"m1\", first: 1) { name }
+                later: musicianHistory(id: \"m1\", from_block: 1) { name }
+                earlier: musicianHistory(id: \"m1\", to_block: 0) { name }
+            }",
+        )
+        .expect("invalid test query");
+        let result = execute_query_document(&id, query).await;
+        let exp = object! {
+            musicianHistory: vec![
+                object! { name: "John", mainBand: "b1", _block_from: 0, _block_to: 1 },
+                object! { name: "Johnny", mainBand: "b1", _block_from: 1, _block_to: q::Value::Null },
+            ],
+            first: vec![object! { name: "John" }],
+            later: vec![object! { name: "Johnny" }],
+            earlier: vec![object! { name: "John" }],
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_query_imported_types() {
+    run_test_sequentially(setup, |_, id| async move {
+        async fn query_importer(importer: &SubgraphDeploymentId) -> QueryResult {
+            let query =
+                graphql_parser::parse_query("query { fans { name favorite { __typename name } } }")
+                    .expect("invalid test query");
+            let schema = STORE.api_schema(importer).expect("importer has a schema");
+            let state = STORE
+                .deployment_state_from_id(importer.clone())
+                .expect("failed to get state");
+            let runner = Arc::new(GraphQlRunner::new(
+                &*LOGGER,
+                STORE.clone(),
+                LOAD_MANAGER.clone(),
+            ));
+            let query = Query::new(schema, query, None, None);
+            runner
+                .run_query_with_complexity(query, state, None, None, None, None, false)
+                .await
+                .as_ref()
+                .clone()
+        }
+
+        const IMPORTER_GQL: &str = "
+            type _Schema_ @import(types: [{ name: \"Band\", as: \"Group\" }], from: { id: \"graphqlTestsQuery\" })
+
+            type Fan @entity {
+                id: ID!
+                name: String!
+                favorite: Group
+            }";
+
+        let importer = SubgraphDeploymentId::new("graphqlTestsImporter").unwrap();
+        test_store::create_test_subgraph(&importer, IMPORTER_GQL);
+        let fan = EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: importer.clone(),
+                entity_type: "Fan".to_owned(),
+                entity_id: "f1".to_owned(),
+            },
+            data: Entity::from(vec![
+                ("id", Value::from("f1")),
+                ("name", Value::from("Fiona")),
+                ("favorite", Value::from("b1")),
+            ]),
+        };
+        transact_entity_operations(&*STORE, importer.clone(), GENESIS_PTR.clone(), vec![fan])
+            .unwrap();
+        transact_entity_operations(&*STORE, importer.clone(), BLOCK_ONE.clone(), vec![]).unwrap();
+
+        // Both deployments are at block 1
+        let result = query_importer(&importer).await;
+        let exp = object! {
+            fans: vec![object! {
+                name: "Fiona",
+                favorite: object! { __typename: "Group", name: "The Musicians" }
+            }],
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+
+        // Once the imported deployment falls behind the importer, data for
+        // the imported types is not available at the importer's block
+        STORE
+            .revert_block_operations(id.clone(), BLOCK_ONE.clone(), GENESIS_PTR.clone())
+            .unwrap();
+        let result = query_importer(&importer).await;
+        match &result.to_result().unwrap_err()[0] {
+            QueryError::ExecutionError(QueryExecutionError::ImportedDeploymentBehind(
+                deployment,
+                Some(0),
+                1,
+            )) if deployment == &id => { /* expected */ }
+            e => panic!("unexpected error for imported deployment behind: {:?}", e),
+        }
+    })
+}
+
 async fn check_musicians_at(
     id: &SubgraphDeploymentId,
     query: &str,
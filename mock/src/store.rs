@@ -108,6 +108,7 @@ impl Store for MockStore {
         _block_ptr_to: EthereumBlockPointer,
         _mods: Vec<EntityModification>,
         _stopwatch: StopwatchMetrics,
+        _deterministic_errors: Vec<SubgraphError>,
     ) -> Result<bool, StoreError> {
         unimplemented!()
     }
@@ -129,6 +130,14 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn rewind(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
     fn subscribe(&self, _entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
         unimplemented!()
     }
@@ -146,6 +155,7 @@ impl Store for MockStore {
             reorg_count: 0,
             max_reorg_depth: 0,
             latest_ethereum_block_number: 0,
+            has_non_fatal_errors: false,
         })
     }
 
@@ -172,6 +182,18 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn pause_subgraph(&self, _: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn resume_subgraph(&self, _: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn set_running(&self, _: &SubgraphDeploymentId, _: bool) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
     fn start_subgraph_deployment(
         &self,
         _logger: &Logger,
@@ -180,6 +202,10 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn unfail(&self, _: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
     fn migrate_subgraph_deployment(
         &self,
         _logger: &Logger,
@@ -197,7 +223,11 @@ impl Store for MockStore {
         unimplemented!()
     }
 
-    fn query_store(self: Arc<Self>, _: bool) -> Arc<dyn QueryStore + Send + Sync> {
+    fn query_store(
+        self: Arc<Self>,
+        _: &SubgraphDeploymentId,
+        _: bool,
+    ) -> Result<Arc<dyn QueryStore + Send + Sync>, StoreError> {
         unimplemented!()
     }
 
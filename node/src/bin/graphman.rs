@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use prometheus::Registry;
+use structopt::StructOpt;
+
+use graph::{
+    log::logger,
+    prelude::{format_err, info, tokio, Error},
+};
+use graph_core::MetricsRegistry;
+use graph_node::{config, manager, store_builder::StoreBuilder};
+
+#[derive(Clone, Debug, StructOpt)]
+#[structopt(
+    name = "graphman",
+    about = "Management tool for a graph-node installation",
+    author = "Graph Protocol, Inc."
+)]
+pub struct Opt {
+    #[structopt(
+        long,
+        env = "GRAPH_NODE_CONFIG",
+        conflicts_with = "postgres-url",
+        required_unless = "postgres-url",
+        help = "the name of the configuration file"
+    )]
+    pub config: Option<String>,
+    #[structopt(
+        long,
+        value_name = "URL",
+        env = "POSTGRES_URL",
+        conflicts_with = "config",
+        required_unless = "config",
+        help = "Location of the Postgres database used for storing entities"
+    )]
+    pub postgres_url: Option<String>,
+    #[structopt(
+        long,
+        default_value = "graphman",
+        value_name = "NODE_ID",
+        help = "a unique identifier for this node"
+    )]
+    pub node_id: String,
+    #[structopt(long, help = "Enable debug logging")]
+    pub debug: bool,
+    #[structopt(subcommand)]
+    pub cmd: Command,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum Command {
+    /// Show the shard, assignment, health and block pointer of deployments
+    ///
+    /// With no argument, show all deployments. Otherwise, show the
+    /// deployment with the given id or the deployments of the subgraph
+    /// with the given name
+    Info { search: Option<String> },
+    /// Assign a deployment to a node, which makes that node index it
+    Reassign { id: String, node: String },
+    /// Remove the assignment of a deployment so that it stops indexing
+    Unassign { id: String },
+    /// Stop indexing a deployment while keeping its assignment
+    Pause { id: String },
+    /// Resume indexing a paused deployment
+    Resume { id: String },
+    /// Remove deployments that are neither the current nor the pending
+    /// version of any subgraph
+    RemoveUnused {
+        /// Only list the deployments that would be removed
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Rewind a deployment to an earlier block
+    ///
+    /// All changes that blocks after the given block made are removed. The
+    /// deployment must be paused or unassigned while it is being rewound
+    Rewind {
+        id: String,
+        block_hash: String,
+        block_number: u64,
+    },
+    /// Print the database layout of a deployment
+    Layout { id: String },
+    /// Limit how many blocks of history a deployment keeps
+    ///
+    /// Without a number of blocks, the deployment keeps all of its
+    /// history. Queries for blocks that are older than the history the
+    /// deployment keeps fail
+    History { id: String, blocks: Option<i32> },
+    /// Remove entity versions that deployments no longer need to keep
+    Prune,
+    /// Copy a deployment into a shard and switch it over to the copy
+    ///
+    /// The shard can be the one the deployment is in already, in which
+    /// case the data is copied into a new database namespace. The
+    /// deployment keeps indexing while its data is copied. Graph nodes
+    /// that only query the deployment need to be restarted afterwards
+    Copy { id: String, shard: String },
+    /// Export the entities of a deployment to CSV files
+    ///
+    /// One file is written for each entity type, together with a
+    /// `schema.json` that describes the columns of each file
+    Export {
+        id: String,
+        /// The directory to write the files to
+        dir: String,
+        /// The block at which to export entities; defaults to the latest
+        /// block of the deployment
+        #[structopt(long)]
+        block: Option<i32>,
+    },
+}
+
+impl From<Opt> for config::Opt {
+    fn from(opt: Opt) -> Self {
+        config::Opt {
+            postgres_url: opt.postgres_url,
+            config: opt.config,
+            // Only used if the configuration does not set a pool size;
+            // graphman needs very few connections
+            store_connection_pool_size: 5,
+            node_id: opt.node_id,
+            ..Default::default()
+        }
+    }
+}
+
+async fn run(opt: Opt) -> Result<(), Error> {
+    let logger = logger(opt.debug);
+
+    let config = config::Config::load(&logger, &opt.clone().into())
+        .map_err(|e| format_err!("configuration error: {}", e))?;
+    info!(logger, "Connecting to the database");
+
+    let prometheus_registry = Arc::new(Registry::new());
+    let registry = Arc::new(MetricsRegistry::new(logger.clone(), prometheus_registry));
+    let store = StoreBuilder::new(&logger, &config, registry).store();
+
+    use Command::*;
+    match opt.cmd {
+        Info { search } => manager::info::run(store, search),
+        Reassign { id, node } => manager::assign::reassign(store, &id, &node),
+        Unassign { id } => manager::assign::unassign(store, &id),
+        Pause { id } => manager::assign::pause(store, &id),
+        Resume { id } => manager::assign::resume(store, &id),
+        RemoveUnused { dry_run } => manager::remove::unused(store, dry_run),
+        Rewind {
+            id,
+            block_hash,
+            block_number,
+        } => manager::rewind::run(store, &id, &block_hash, block_number),
+        Layout { id } => manager::layout::run(store, &id),
+        History { id, blocks } => manager::history::set(store, &id, blocks),
+        Prune => manager::history::prune(store, &logger),
+        Copy { id, shard } => manager::copy::run(store, &logger, &id, &shard).await,
+        Export { id, dir, block } => manager::export::run(store, &logger, &id, &dir, block),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let opt = Opt::from_args();
+    if let Err(e) = run(opt).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,6 +1,6 @@
 use graph::prelude::{
     anyhow::{anyhow, Result},
-    info, serde_json, Logger,
+    info, serde_json, DeploymentPlacer, Logger, NodeId,
 };
 
 use regex::Regex;
@@ -9,16 +9,28 @@ use std::collections::BTreeMap;
 use std::fs::read_to_string;
 use url::Url;
 
-const PRIMARY: &str = "primary";
+pub const PRIMARY: &str = "primary";
 const ANY_NAME: &str = ".*";
 
-use crate::opt::Opt;
+/// The command line options that influence the configuration. Everything
+/// that runs off a `Config` converts its own command line options into
+/// this struct
+#[derive(Clone, Debug, Default)]
+pub struct Opt {
+    pub postgres_url: Option<String>,
+    pub config: Option<String>,
+    pub store_connection_pool_size: u32,
+    pub postgres_secondary_hosts: Vec<String>,
+    pub postgres_host_weights: Vec<usize>,
+    pub disable_block_ingestor: bool,
+    pub node_id: String,
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     #[serde(rename = "store")]
-    stores: BTreeMap<String, Shard>,
-    deployment: Deployment,
+    pub stores: BTreeMap<String, Shard>,
+    pub deployment: Deployment,
     ingestor: Ingestor,
 }
 
@@ -47,6 +59,17 @@ impl Config {
             shard.validate(opt)?;
         }
         self.deployment.validate()?;
+
+        // Check that deployment rules only reference existing stores
+        for (i, rule) in self.deployment.rules.iter().enumerate() {
+            if !self.stores.contains_key(&rule.store) {
+                return Err(anyhow!(
+                    "unknown shard {} in deployment rule {}",
+                    rule.store,
+                    i
+                ));
+            }
+        }
         Ok(())
     }
 
@@ -179,8 +202,8 @@ impl Replica {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Deployment {
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Deployment {
     #[serde(rename = "rule")]
     rules: Vec<Rule>,
 }
@@ -208,27 +231,36 @@ impl Deployment {
         Ok(())
     }
 
-    // This needs to be moved to some sort of trait
-    #[allow(dead_code)]
-    fn place(&self, name: &str, network: &str, default: &str) -> Option<(&str, Vec<String>)> {
-        if self.rules.is_empty() {
-            // This can only happen if we have only command line arguments and no
-            // configuration file
-            Some((PRIMARY, vec![default.to_string()]))
-        } else {
-            self.rules
-                .iter()
-                .find(|rule| rule.matches(name, network))
-                .map(|rule| (rule.store.as_str(), rule.indexers.clone()))
-        }
-    }
-
     fn from_opt(_: &Opt) -> Self {
         Self { rules: vec![] }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl DeploymentPlacer for Deployment {
+    fn place(&self, name: &str, network: &str) -> Result<Option<(String, Vec<NodeId>)>, String> {
+        // If we only have command line arguments and no configuration
+        // file, there are no rules, and the caller decides placement.
+        // Indexer names were checked during validation; we still return
+        // an error rather than panic so that a bad name is reported back
+        // to whoever is deploying
+        match self.rules.iter().find(|rule| rule.matches(name, network)) {
+            Some(rule) => {
+                let indexers = rule
+                    .indexers
+                    .iter()
+                    .map(|idx| {
+                        NodeId::new(idx.clone())
+                            .map_err(|()| format!("{} is not a valid node name", idx))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Some((rule.store.clone(), indexers)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct Rule {
     #[serde(rename = "match", default)]
     pred: Predicate,
@@ -250,11 +282,14 @@ impl Rule {
         if self.indexers.is_empty() {
             return Err(anyhow!("useless rule without indexers"));
         }
+        for indexer in &self.indexers {
+            NodeId::new(indexer).map_err(|()| anyhow!("invalid node id {}", indexer))?;
+        }
         Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct Predicate {
     #[serde(with = "serde_regex", default = "any_name")]
     name: Regex,
@@ -0,0 +1,4 @@
+pub mod config;
+pub mod manager;
+pub mod opt;
+pub mod store_builder;
@@ -11,8 +11,9 @@ use std::time::Duration;
 use structopt::StructOpt;
 use tokio::sync::mpsc;
 
-use graph::components::ethereum::{EthereumNetworks, NodeCapabilities};
+use graph::components::ethereum::{EthereumNetworks, NodeCapabilities, REORG_THRESHOLD};
 use graph::components::forward;
+use graph::components::server::persisted::PersistedQueries;
 use graph::data::graphql::effort::LoadManager;
 use graph::log::logger;
 use graph::prelude::{IndexNodeServer as _, JsonRpcServer as _, *};
@@ -34,21 +35,11 @@ use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
 use graph_store_postgres::NetworkStore as DieselNetworkStore;
 use graphql_parser::query as q;
 
-mod config;
-mod opt;
-mod store_builder;
-
-use config::Config;
-use store_builder::StoreBuilder;
+use graph_node::config::Config;
+use graph_node::opt;
+use graph_node::store_builder::StoreBuilder;
 
 lazy_static! {
-    // Default to an Ethereum reorg threshold to 50 blocks
-    static ref REORG_THRESHOLD: u64 = env::var("ETHEREUM_REORG_THRESHOLD")
-        .ok()
-        .map(|s| u64::from_str(&s)
-            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
-        .unwrap_or(50);
-
     // Default to an ancestor count of 50 blocks
     static ref ANCESTOR_COUNT: u64 = env::var("ETHEREUM_ANCESTOR_COUNT")
         .ok()
@@ -112,7 +103,7 @@ async fn main() {
         render_testament!(TESTAMENT)
     );
 
-    let config = match Config::load(&logger, &opt) {
+    let config = match Config::load(&logger, &opt.clone().into()) {
         Err(e) => {
             eprintln!("configuration error: {}", e);
             std::process::exit(1);
@@ -209,6 +200,8 @@ async fn main() {
         metrics_registry.cheap_clone(),
     ));
     let store_builder2 = store_builder.clone();
+    store_builder.store().start_forgetting_copied_deployments();
+    store_builder.store().start_pruning(&logger);
 
     graph::spawn(
         futures::stream::FuturesOrdered::from_iter(stores_eth_networks.flatten().into_iter().map(
@@ -255,17 +248,20 @@ async fn main() {
                 store_builder.store(),
                 load_manager,
             ));
+            let persisted_queries = Arc::new(PersistedQueries::from_env(&logger));
             let mut graphql_server = GraphQLQueryServer::new(
                 &logger_factory,
                 graphql_metrics_registry,
                 graphql_runner.clone(),
                 store_builder.store(),
+                persisted_queries.clone(),
                 node_id.clone(),
             );
             let subscription_server = GraphQLSubscriptionServer::new(
                 &logger,
                 graphql_runner.clone(),
                 store_builder.store(),
+                persisted_queries,
             );
 
             let mut index_node_server = IndexNodeServer::new(
@@ -331,7 +327,7 @@ async fn main() {
                 network_stores.clone(),
                 eth_networks.clone(),
                 node_id.clone(),
-                *REORG_THRESHOLD,
+                *REORG_THRESHOLD as u64,
                 metrics_registry.clone(),
             );
             let runtime_host_builder = WASMRuntimeHostBuilder::new(
@@ -384,6 +380,7 @@ async fn main() {
                 eth_networks.clone(),
                 node_id.clone(),
                 version_switching_mode,
+                Arc::new(config.deployment.clone()),
             ));
             graph::spawn(
                 subgraph_registrar
@@ -705,7 +702,7 @@ fn start_block_ingestor(
     // otherwise BlockStream will not work properly.
     // BlockStream expects the blocks after the reorg threshold to be present in the
     // database.
-    assert!(*ANCESTOR_COUNT >= *REORG_THRESHOLD);
+    assert!(*ANCESTOR_COUNT >= *REORG_THRESHOLD as u64);
 
     info!(logger, "Starting block ingestors");
 
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use graph::prelude::{format_err, Error, NodeId, Store as _};
+use graph_store_postgres::ShardedStore;
+
+use super::deployment_id;
+
+/// Assign the deployment `id` to the node `node`, which makes `node` start
+/// indexing it
+pub fn reassign(store: Arc<ShardedStore>, id: &str, node: &str) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+    let node = NodeId::new(node).map_err(|()| format_err!("illegal node id `{}`", node))?;
+
+    match store.assigned_node(&id)? {
+        Some(current) if current == node => {
+            println!("deployment {} is already assigned to {}", id, node);
+            return Ok(());
+        }
+        Some(current) => println!("reassigning {} from {} to {}", id, current, node),
+        None => println!("assigning {} to {}", id, node),
+    }
+    store.reassign_subgraph(&id, &node)?;
+    Ok(())
+}
+
+/// Remove the assignment for the deployment `id` so that no node indexes
+/// it any longer
+pub fn unassign(store: Arc<ShardedStore>, id: &str) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+
+    match store.assigned_node(&id)? {
+        Some(node) => {
+            println!("unassigning {} from {}", id, node);
+            store.unassign_subgraph(&id)?;
+        }
+        None => println!("deployment {} is not assigned to any node", id),
+    }
+    Ok(())
+}
+
+/// Stop indexing the deployment `id` without changing its assignment
+pub fn pause(store: Arc<ShardedStore>, id: &str) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+
+    println!("pausing {}", id);
+    store.pause_subgraph(&id)?;
+    Ok(())
+}
+
+/// Resume indexing the deployment `id` on the node it is assigned to
+pub fn resume(store: Arc<ShardedStore>, id: &str) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+
+    println!("resuming {}", id);
+    store.resume_subgraph(&id)?;
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use graph::prelude::{Error, Logger};
+use graph_store_postgres::ShardedStore;
+
+use super::deployment_id;
+
+/// Copy the deployment `id` into `shard` and switch it over to the copy
+pub async fn run(
+    store: Arc<ShardedStore>,
+    logger: &Logger,
+    id: &str,
+    shard: &str,
+) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+
+    let current = store.shard(&id)?;
+    if current == shard {
+        println!("copying {} to a new namespace in shard {}", id, shard);
+    } else {
+        println!("copying {} from shard {} to shard {}", id, current, shard);
+    }
+    store.copy_deployment(logger, &id, shard).await?;
+    println!("{} now uses the copy in shard {}", id, shard);
+    Ok(())
+}
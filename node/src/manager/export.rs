@@ -0,0 +1,28 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use graph::prelude::{format_err, BlockNumber, Error, Logger};
+use graph_store_postgres::ShardedStore;
+
+use super::deployment_id;
+
+/// Export the entities of the deployment `id` at `block`, or at its
+/// latest block, as CSV files into the directory `dir`
+pub fn run(
+    store: Arc<ShardedStore>,
+    logger: &Logger,
+    id: &str,
+    dir: &str,
+    block: Option<BlockNumber>,
+) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+    let dir = Path::new(dir);
+    if !dir.is_dir() {
+        return Err(format_err!("{} is not a directory", dir.display()));
+    }
+
+    for path in store.export(logger, &id, block, dir)? {
+        println!("wrote {}", path.display());
+    }
+    Ok(())
+}
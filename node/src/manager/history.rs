@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use graph::prelude::{BlockNumber, Error, Logger};
+use graph_store_postgres::ShardedStore;
+
+use super::deployment_id;
+
+/// Make the deployment `id` keep only `blocks` blocks of history, or all
+/// of its history if `blocks` is `None`
+pub fn set(store: Arc<ShardedStore>, id: &str, blocks: Option<BlockNumber>) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+
+    match blocks {
+        Some(blocks) => println!("keeping {} blocks of history for {}", blocks, id),
+        None => println!("keeping all history for {}", id),
+    }
+    store.set_history_blocks(&id, blocks)?;
+    Ok(())
+}
+
+/// Remove the history that deployments no longer need to keep right away
+/// instead of waiting for the next time a graph node prunes them
+pub fn prune(store: Arc<ShardedStore>, logger: &Logger) -> Result<(), Error> {
+    store.prune(logger)?;
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use graph::data::subgraph::status;
+use graph::prelude::{Error, Store as _};
+use graph_store_postgres::ShardedStore;
+
+/// Print information about the deployments that match `search`, which can
+/// be a deployment id or the name of a subgraph. Without `search`, print
+/// information about all deployments
+pub fn run(store: Arc<ShardedStore>, search: Option<String>) -> Result<(), Error> {
+    let filter = match search {
+        None => status::Filter::Deployments(vec![]),
+        Some(search) if search.starts_with("Qm") && search.len() == 46 => {
+            status::Filter::Deployments(vec![search])
+        }
+        Some(name) => status::Filter::SubgraphName(name),
+    };
+
+    let infos = store.status(filter)?;
+    if infos.is_empty() {
+        println!("no matching deployments");
+        return Ok(());
+    }
+
+    for info in infos {
+        let shard = store.shard(&super::deployment_id(&info.subgraph)?)?;
+        println!("{:-<78}", "");
+        println!("{:<14} | {}", "deployment", info.subgraph);
+        println!("{:<14} | {}", "shard", shard);
+        println!(
+            "{:<14} | {}",
+            "node",
+            info.node.as_deref().unwrap_or("(unassigned)")
+        );
+        println!("{:<14} | {}", "paused", info.paused);
+        println!("{:<14} | {}", "health", info.health.as_str());
+        if let Some(next_retry_at) = info.next_retry_at {
+            println!(
+                "{:<14} | attempt {} at {} (Unix time)",
+                "retry", info.retry_count, next_retry_at
+            );
+        }
+        println!("{:<14} | {}", "synced", info.synced);
+        println!("{:<14} | {}", "entities", info.entity_count);
+        match info.history_blocks {
+            Some(blocks) => println!(
+                "{:<14} | {} blocks, from block {}",
+                "history", blocks, info.history_horizon
+            ),
+            None => println!("{:<14} | all blocks", "history"),
+        }
+        for chain in info.chains {
+            let number = |block: Option<status::EthereumBlock>| {
+                block
+                    .map(|block| block.to_ptr().number.to_string())
+                    .unwrap_or("-".to_owned())
+            };
+            println!("{:<14} | {}", "network", chain.network);
+            println!("{:<14} | {}", "  latest block", number(chain.latest_block));
+            println!(
+                "{:<14} | {}",
+                "  chain head",
+                number(chain.chain_head_block)
+            );
+        }
+        if let Some(error) = info.fatal_error {
+            println!("{:<14} | {}", "fatal error", error.message);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use graph::prelude::{format_err, Error};
+use graph_store_postgres::ShardedStore;
+
+use super::deployment_id;
+
+/// Print the database schema in which the deployment `id` is stored and
+/// the DDL for its tables
+pub fn run(store: Arc<ShardedStore>, id: &str) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+    let shard = store.shard(&id)?;
+    let layout = store.layout(&id)?;
+
+    println!("-- deployment {}", id);
+    println!("-- shard {}, namespace {}", shard, layout.catalog.schema);
+    let ddl = layout
+        .as_ddl()
+        .map_err(|e| format_err!("failed to generate DDL: {}", e))?;
+    println!("{}", ddl);
+    Ok(())
+}
@@ -0,0 +1,18 @@
+//! The commands that `graphman` offers for administering deployments.
+//! They all work directly against the database and do not need a running
+//! graph node
+use graph::prelude::{format_err, Error, SubgraphDeploymentId};
+
+pub mod assign;
+pub mod copy;
+pub mod export;
+pub mod history;
+pub mod info;
+pub mod layout;
+pub mod remove;
+pub mod rewind;
+
+/// Parse `id` as the id of a deployment
+fn deployment_id(id: &str) -> Result<SubgraphDeploymentId, Error> {
+    SubgraphDeploymentId::new(id).map_err(|id| format_err!("illegal deployment id `{}`", id))
+}
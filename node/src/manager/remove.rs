@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use graph::prelude::Error;
+use graph_store_postgres::ShardedStore;
+
+/// Remove all deployments that are not used by any subgraph. With
+/// `dry_run`, only list the deployments that would be removed
+pub fn unused(store: Arc<ShardedStore>, dry_run: bool) -> Result<(), Error> {
+    let unused = store.unused_deployments()?;
+    if unused.is_empty() {
+        println!("there are no unused deployments");
+        return Ok(());
+    }
+
+    for id in unused {
+        let shard = store.shard(&id)?;
+        if dry_run {
+            println!("would remove {} from shard {}", id, shard);
+        } else {
+            println!("removing {} from shard {}", id, shard);
+            store.remove_deployment(&id)?;
+        }
+    }
+    Ok(())
+}
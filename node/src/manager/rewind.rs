@@ -0,0 +1,26 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use graph::prelude::{format_err, web3::types::H256, Error, EthereumBlockPointer, Store as _};
+use graph_store_postgres::ShardedStore;
+
+use super::deployment_id;
+
+/// Rewind the deployment `id` to the block with the given `hash` and
+/// `number`, removing all changes that later blocks made
+pub fn run(store: Arc<ShardedStore>, id: &str, hash: &str, number: u64) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+    let hash = H256::from_str(hash.trim_start_matches("0x"))
+        .map_err(|e| format_err!("illegal block hash `{}`: {}", hash, e))?;
+    let block_ptr = EthereumBlockPointer { hash, number };
+
+    let current = store
+        .block_ptr(id.clone())?
+        .ok_or_else(|| format_err!("deployment {} has not processed any blocks", id))?;
+    println!(
+        "rewinding {} from block {} to block {}",
+        id, current.number, block_ptr.number
+    );
+    store.rewind(id, block_ptr)?;
+    Ok(())
+}
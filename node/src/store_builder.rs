@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use graph::{
@@ -8,38 +9,104 @@ use graph_core::MetricsRegistry;
 use graph_store_postgres::connection_pool::ConnectionPool;
 use graph_store_postgres::{
     ChainHeadUpdateListener as PostgresChainHeadUpdateListener, ChainStore as DieselChainStore,
-    NetworkStore as DieselNetworkStore, Store as DieselStore, SubscriptionManager,
+    NetworkStore as DieselNetworkStore, ShardedStore as DieselShardedStore, Store as DieselStore,
+    SubscriptionManager, PRIMARY_SHARD,
 };
 
-use crate::config::Config;
+use crate::config::{Config, Shard};
 
 pub struct StoreBuilder {
-    store: Arc<DieselStore>,
-    conn_pool: ConnectionPool,
+    store: Arc<DieselShardedStore>,
+    primary_pool: ConnectionPool,
     chain_head_update_listener: Arc<PostgresChainHeadUpdateListener>,
 }
 
 impl StoreBuilder {
     pub fn new(logger: &Logger, config: &Config, registry: Arc<MetricsRegistry>) -> Self {
         let primary = config.primary_store();
+        let primary_pool = Self::main_pool(logger, PRIMARY_SHARD, primary, registry.cheap_clone());
 
+        // Set up the primary first since all other shards need it for
+        // chain data
+        let mut stores = HashMap::new();
+        for (name, shard) in &config.stores {
+            let pool = if name == PRIMARY_SHARD {
+                primary_pool.clone()
+            } else {
+                Self::main_pool(logger, name, shard, registry.cheap_clone())
+            };
+            let store = Self::make_store(
+                logger,
+                name,
+                shard,
+                pool,
+                primary_pool.clone(),
+                registry.cheap_clone(),
+            );
+            stores.insert(name.clone(), store);
+        }
+
+        let store = Arc::new(DieselShardedStore::new(
+            stores,
+            Arc::new(config.deployment.clone()),
+        ));
+
+        let chain_head_update_listener = Arc::new(PostgresChainHeadUpdateListener::new(
+            &logger,
+            registry.clone(),
+            primary.connection.to_owned(),
+        ));
+
+        Self {
+            store,
+            primary_pool,
+            chain_head_update_listener,
+        }
+    }
+
+    /// Create the connection pool for the main database of `shard`. For
+    /// backwards compatibility of metrics, the pool for the primary is
+    /// called `main`
+    fn main_pool(
+        logger: &Logger,
+        name: &str,
+        shard: &Shard,
+        registry: Arc<MetricsRegistry>,
+    ) -> ConnectionPool {
         info!(
             logger,
-            "Connecting to Postgres (primary)";
-            "url" => SafeDisplay(primary.connection.as_str()),
-            "conn_pool_size" => primary.pool_size,
-            "weight" => primary.weight
+            "Connecting to Postgres";
+            "shard" => name,
+            "url" => SafeDisplay(shard.connection.as_str()),
+            "conn_pool_size" => shard.pool_size,
+            "weight" => shard.weight
         );
-        let conn_pool = ConnectionPool::create(
-            "main",
-            primary.connection.to_owned(),
-            primary.pool_size,
+        let pool_name = if name == PRIMARY_SHARD {
+            "main".to_string()
+        } else {
+            name.to_string()
+        };
+        ConnectionPool::create(
+            &pool_name,
+            shard.connection.to_owned(),
+            shard.pool_size,
             &logger,
-            registry.cheap_clone(),
-        );
+            registry,
+        )
+    }
 
-        let mut weights: Vec<_> = vec![primary.weight];
-        let read_only_conn_pools: Vec<_> = primary
+    /// Create the store for `shard`, including its read replicas, with
+    /// `pool` as the pool for its main database
+    fn make_store(
+        logger: &Logger,
+        name: &str,
+        shard: &Shard,
+        pool: ConnectionPool,
+        primary_pool: ConnectionPool,
+        registry: Arc<MetricsRegistry>,
+    ) -> Arc<DieselStore> {
+        let mut weights: Vec<_> = vec![shard.weight];
+        let read_only_conn_pools: Vec<_> = shard
             .replicas
             .values()
             .enumerate()
@@ -47,12 +114,18 @@ impl StoreBuilder {
                 info!(
                     &logger,
                     "Connecting to Postgres (read replica {})", i+1;
+                    "shard" => name,
                     "url" => SafeDisplay(replica.connection.as_str()),
                     "weight" => replica.weight
                 );
                 weights.push(replica.weight);
+                let pool_name = if name == PRIMARY_SHARD {
+                    format!("replica{}", i)
+                } else {
+                    format!("{}.replica{}", name, i)
+                };
                 ConnectionPool::create(
-                    &format!("replica{}", i),
+                    &pool_name,
                     replica.connection.clone(),
                     replica.pool_size,
                     &logger,
@@ -63,29 +136,19 @@ impl StoreBuilder {
 
         let subscriptions = Arc::new(SubscriptionManager::new(
             logger.clone(),
-            primary.connection.to_owned(),
+            shard.connection.to_owned(),
         ));
 
-        let store = Arc::new(DieselStore::new(
+        Arc::new(DieselStore::new(
             logger,
-            subscriptions.clone(),
-            conn_pool.clone(),
-            read_only_conn_pools.clone(),
+            name.to_string(),
+            subscriptions,
+            pool,
+            primary_pool,
+            read_only_conn_pools,
             weights,
-            registry.clone(),
-        ));
-
-        let chain_head_update_listener = Arc::new(PostgresChainHeadUpdateListener::new(
-            &logger,
-            registry.clone(),
-            primary.connection.to_owned(),
-        ));
-
-        Self {
-            store,
-            conn_pool,
-            chain_head_update_listener,
-        }
+            registry,
+        ))
     }
 
     /// Return a store that includes a `ChainStore` for the given network
@@ -98,14 +161,14 @@ impl StoreBuilder {
             network_name,
             network_identifier,
             self.chain_head_update_listener.clone(),
-            self.conn_pool.clone(),
+            self.primary_pool.clone(),
         );
         Arc::new(DieselNetworkStore::new(self.store.clone(), chain_store))
     }
 
     /// Return the store for subgraph and other storage; this store can
     /// handle everything besides being a `ChainStore`
-    pub fn store(&self) -> Arc<DieselStore> {
+    pub fn store(&self) -> Arc<DieselShardedStore> {
         self.store.cheap_clone()
     }
 }
@@ -0,0 +1,122 @@
+//! Tests for the `graphman` commands. They run against the database
+//! configured in `THEGRAPH_STORE_POSTGRES_DIESEL_URL`
+use graph::data::subgraph::status;
+use graph::prelude::{NodeId, Store as _, StoreError, SubgraphDeploymentId, SubgraphName};
+use graph_node::manager;
+use graph_store_postgres::NetworkStore;
+use test_store::*;
+
+const SUBGRAPH_GQL: &str = "
+    type User @entity {
+        id: ID!,
+        name: String
+    }
+";
+
+fn setup(name: &str) -> SubgraphDeploymentId {
+    let id = SubgraphDeploymentId::new(name).unwrap();
+    remove_subgraphs();
+    create_test_subgraph(&id, SUBGRAPH_GQL);
+    id
+}
+
+fn status(store: &NetworkStore, id: &SubgraphDeploymentId) -> status::Info {
+    store
+        .status(status::Filter::Deployments(vec![id.to_string()]))
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+#[test]
+fn reassign_and_unassign() {
+    run_test_sequentially(
+        || setup("managerAssign"),
+        |store, id| async move {
+            let left = NodeId::new("left").unwrap();
+
+            manager::assign::reassign(store.store(), id.as_str(), "left").unwrap();
+            assert_eq!(
+                Some(left.clone()),
+                store.store().assigned_node(&id).unwrap()
+            );
+
+            // Reassigning to the same node is fine
+            manager::assign::reassign(store.store(), id.as_str(), "left").unwrap();
+            assert_eq!(Some(left), store.store().assigned_node(&id).unwrap());
+
+            manager::assign::unassign(store.store(), id.as_str()).unwrap();
+            assert_eq!(None, store.store().assigned_node(&id).unwrap());
+
+            // Unassigning twice does nothing
+            manager::assign::unassign(store.store(), id.as_str()).unwrap();
+            assert_eq!(None, store.store().assigned_node(&id).unwrap());
+
+            assert!(manager::assign::reassign(store.store(), "not a deployment", "left").is_err());
+        },
+    )
+}
+
+#[test]
+fn pause_and_resume() {
+    run_test_sequentially(
+        || setup("managerPause"),
+        |store, id| async move {
+            manager::assign::pause(store.store(), id.as_str()).unwrap();
+            assert!(status(store.as_ref(), &id).paused);
+
+            manager::assign::resume(store.store(), id.as_str()).unwrap();
+            assert!(!status(store.as_ref(), &id).paused);
+        },
+    )
+}
+
+#[test]
+fn set_history() {
+    run_test_sequentially(
+        || setup("managerHistory"),
+        |store, id| async move {
+            manager::history::set(store.store(), id.as_str(), Some(1000)).unwrap();
+            assert_eq!(Some(1000), status(store.as_ref(), &id).history_blocks);
+
+            // Too little history to revert reorgs
+            assert!(manager::history::set(store.store(), id.as_str(), Some(1)).is_err());
+            assert_eq!(Some(1000), status(store.as_ref(), &id).history_blocks);
+
+            manager::history::set(store.store(), id.as_str(), None).unwrap();
+            assert_eq!(None, status(store.as_ref(), &id).history_blocks);
+        },
+    )
+}
+
+#[test]
+fn remove_unused() {
+    run_test_sequentially(
+        || setup("managerRemove"),
+        |store, id| async move {
+            // The deployment is the current version of its subgraph
+            manager::remove::unused(store.store(), false).unwrap();
+            assert_eq!("primary", store.store().shard(&id).unwrap());
+
+            store
+                .remove_subgraph(SubgraphName::new(id.as_str()).unwrap())
+                .unwrap();
+            assert_eq!(
+                vec![id.clone()],
+                store.store().unused_deployments().unwrap()
+            );
+
+            // A dry run leaves the deployment alone
+            manager::remove::unused(store.store(), true).unwrap();
+            assert_eq!("primary", store.store().shard(&id).unwrap());
+
+            manager::remove::unused(store.store(), false).unwrap();
+            assert!(store.store().unused_deployments().unwrap().is_empty());
+            assert_eq!(None, store.store().assigned_node(&id).unwrap());
+            match store.store().shard(&id) {
+                Err(StoreError::DeploymentNotFound(_)) => (),
+                res => panic!("deployment should be gone, but got {:?}", res),
+            }
+        },
+    )
+}
@@ -1,5 +1,5 @@
 use std::cmp::PartialEq;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 
@@ -18,7 +18,7 @@ use graph::components::ethereum::*;
 use graph::components::store::Store;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::components::three_box::ThreeBoxAdapter;
-use graph::data::subgraph::{Mapping, Source};
+use graph::data::subgraph::{Mapping, Source, SubgraphFeature};
 use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
@@ -45,6 +45,7 @@ struct RuntimeHostConfig {
     data_source_context: Option<DataSourceContext>,
     contract: Source,
     templates: Arc<Vec<DataSourceTemplate>>,
+    features: BTreeSet<SubgraphFeature>,
 }
 
 pub struct RuntimeHostBuilder<S> {
@@ -121,6 +122,7 @@ where
         subgraph_id: SubgraphDeploymentId,
         data_source: DataSource,
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
+        features: BTreeSet<SubgraphFeature>,
         mapping_request_sender: Sender<MappingRequest>,
         metrics: Arc<HostMetrics>,
     ) -> Result<Self::Host, Error> {
@@ -157,6 +159,7 @@ where
                 data_source_context: data_source.context,
                 contract: data_source.source,
                 templates,
+                features,
             },
             mapping_request_sender,
             metrics,
@@ -226,6 +229,7 @@ impl RuntimeHost {
             config.data_source_network,
             config.data_source_context,
             config.templates,
+            config.features,
             config.mapping.abis,
             ethereum_adapter,
             link_resolver,
@@ -6,20 +6,32 @@ use graph::components::ethereum::*;
 use graph::components::store::EntityKey;
 use graph::components::subgraph::{ProofOfIndexingEvent, SharedProofOfIndexing};
 use graph::components::three_box::ThreeBoxAdapter;
+use graph::data::graphql::ext::DirectiveFinder;
+use graph::data::graphql::{DocumentExt, ObjectTypeExt, TypeExt};
+use graph::data::schema::ROLLUP_DIRECTIVE;
 use graph::data::store;
+use graph::data::subgraph::SubgraphFeature;
 use graph::prelude::serde_json;
 use graph::prelude::{slog::b, slog::record_static, *};
 use semver::Version;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::ops::Deref;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 use web3::types::H160;
 
 use graph_graphql::prelude::validate_entity;
+use graph_graphql::schema::ast as sast;
+use secp256k1::recovery::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, Secp256k1, VerifyOnly};
+use sha2::Digest;
 
 use crate::module::{WasmInstance, WasmInstanceContext};
 
+lazy_static! {
+    static ref SECP256K1: Secp256k1<VerifyOnly> = Secp256k1::verification_only();
+}
+
 pub(crate) enum EthereumCallError {
     /// We might have detected a reorg.
     PossibleReorg(anyhow::Error),
@@ -48,7 +60,7 @@ impl From<failure::Error> for HostExportError {
 }
 
 pub(crate) struct HostExports {
-    subgraph_id: SubgraphDeploymentId,
+    pub(crate) subgraph_id: SubgraphDeploymentId,
     pub(crate) api_version: Version,
     data_source_name: String,
     data_source_address: Option<Address>,
@@ -60,6 +72,7 @@ pub(crate) struct HostExports {
     /// networks but will be expanded for ipfs and the availability chain.
     causality_region: String,
     templates: Arc<Vec<DataSourceTemplate>>,
+    pub(crate) features: BTreeSet<SubgraphFeature>,
     abis: Vec<MappingABI>,
     ethereum_adapter: Arc<dyn EthereumAdapter>,
     pub(crate) link_resolver: Arc<dyn LinkResolver>,
@@ -85,6 +98,7 @@ impl HostExports {
         data_source_network: String,
         data_source_context: Option<DataSourceContext>,
         templates: Arc<Vec<DataSourceTemplate>>,
+        features: BTreeSet<SubgraphFeature>,
         abis: Vec<MappingABI>,
         ethereum_adapter: Arc<dyn EthereumAdapter>,
         link_resolver: Arc<dyn LinkResolver>,
@@ -104,6 +118,7 @@ impl HostExports {
             data_source_context,
             causality_region,
             templates,
+            features,
             abis,
             ethereum_adapter,
             link_resolver,
@@ -143,6 +158,30 @@ impl HostExports {
         )))
     }
 
+    /// Fail if mappings are not allowed to change entities of type
+    /// `entity_type`. Entities of rollups are maintained by the store, and
+    /// changing them from a mapping would fail the block every time it is
+    /// processed
+    pub(crate) fn check_entity_type_writable(
+        &self,
+        entity_type: &str,
+    ) -> Result<(), HostExportError> {
+        let schema = self.store.input_schema(&self.subgraph_id)?;
+        let is_rollup = schema
+            .document
+            .get_object_type_definition(entity_type)
+            .and_then(|object_type| object_type.find_directive(ROLLUP_DIRECTIVE.to_owned()))
+            .is_some();
+        if is_rollup {
+            Err(HostExportError::Deterministic(anyhow::anyhow!(
+                "entities of type `{}` are maintained by a rollup and can not be changed by mappings",
+                entity_type
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
     pub(crate) fn store_set(
         &self,
         logger: &Logger,
@@ -234,6 +273,7 @@ impl HostExports {
     pub(crate) fn store_get(
         &self,
         state: &mut BlockState,
+        host_metrics: &HostMetrics,
         entity_type: String,
         entity_id: String,
     ) -> Result<Option<Entity>, anyhow::Error> {
@@ -243,9 +283,84 @@ impl HostExports {
             entity_id: entity_id.clone(),
         };
 
+        if state.entity_cache.is_cached(&store_key) {
+            host_metrics.observe_store_get("cache");
+        } else {
+            host_metrics.observe_store_get("database");
+        }
         Ok(state.entity_cache.get(&store_key)?)
     }
 
+    /// Like `store_get`, but only finds entities that were written in the
+    /// current block and never queries the database
+    pub(crate) fn store_get_in_block(
+        &self,
+        state: &BlockState,
+        entity_type: String,
+        entity_id: String,
+    ) -> Option<Entity> {
+        let store_key = EntityKey {
+            subgraph_id: self.subgraph_id.clone(),
+            entity_type,
+            entity_id,
+        };
+
+        state.entity_cache.get_in_block(&store_key)
+    }
+
+    /// Load the entities that the `@derivedFrom` field `field` of the
+    /// entity `entity_type` with id `entity_id` refers to
+    pub(crate) fn store_load_related(
+        &self,
+        state: &mut BlockState,
+        entity_type: String,
+        entity_id: String,
+        field: String,
+    ) -> Result<Vec<Entity>, HostExportError> {
+        let schema = self.store.input_schema(&self.subgraph_id)?;
+        let object_type = schema
+            .document
+            .get_object_type_definition(&entity_type)
+            .with_context(|| format!("Entity type `{}` does not exist", entity_type))
+            .map_err(HostExportError::Deterministic)?;
+        let field_def = object_type
+            .field(&field)
+            .with_context(|| {
+                format!(
+                    "Entity type `{}` does not have a field `{}`",
+                    entity_type, field
+                )
+            })
+            .map_err(HostExportError::Deterministic)?;
+
+        let child_type_name = field_def.field_type.get_base_type();
+        let derived_from = schema
+            .document
+            .object_or_interface(child_type_name)
+            .and_then(|child_type| sast::get_derived_from_field(child_type, field_def))
+            .with_context(|| {
+                format!(
+                    "Field `{}` of entity type `{}` is not a @derivedFrom field",
+                    field, entity_type
+                )
+            })
+            .map_err(HostExportError::Deterministic)?;
+        let attr = if sast::is_list_or_non_null_list_field(derived_from) {
+            WindowAttribute::List(derived_from.name.clone())
+        } else {
+            WindowAttribute::Scalar(derived_from.name.clone())
+        };
+        let child_types = match schema.types_for_interface().get(child_type_name) {
+            Some(types) => types.iter().map(|t| t.name.clone()).collect(),
+            None => vec![child_type_name.clone()],
+        };
+
+        state
+            .entity_cache
+            .load_related(&self.subgraph_id, child_types, &attr, &entity_id)
+            .map_err(|e| HostExportError::Unknown(e.into()))
+    }
+
     /// Returns `Ok(None)` if the call was reverted.
     pub(crate) fn ethereum_call(
         &self,
@@ -355,6 +470,26 @@ impl HostExports {
         result.map_err(Into::into)
     }
 
+    /// Encodes `token` the way Solidity's `abi.encode` encodes a single value.
+    pub(crate) fn ethereum_encode(&self, token: Token) -> Vec<u8> {
+        ethabi::encode(&[token])
+    }
+
+    /// Decodes `data` as a value of the type described by `types`, which uses
+    /// Solidity's notation, e.g. `(address,uint256[])`.
+    pub(crate) fn ethereum_decode(
+        &self,
+        types: String,
+        data: Vec<u8>,
+    ) -> Result<Token, anyhow::Error> {
+        let param_type = ethabi::param_type::Reader::read(&types)
+            .map_err(|e| anyhow::anyhow!("Failed to parse ABI type `{}`: {}", types, e))?;
+        ethabi::decode(&[param_type], &data)
+            .map_err(|e| anyhow::anyhow!("Failed to decode data as `{}`: {}", types, e))?
+            .pop()
+            .with_context(|| format!("Decoding data as `{}` produced no value", types))
+    }
+
     /// Prints the module of `n` in hex.
     /// Integers are encoded using the least amount of digits (no leading zero digits).
     /// Their encoding may be of uneven length. The number zero encodes as "0x0".
@@ -476,6 +611,47 @@ impl HostExports {
         tiny_keccak::keccak256(&input)
     }
 
+    pub(crate) fn crypto_sha256(&self, input: Vec<u8>) -> Vec<u8> {
+        sha2::Sha256::digest(&input).to_vec()
+    }
+
+    /// This is the NIST SHA3-256, which differs from `keccak256` in its
+    /// padding and therefore does not produce the same hashes as Solidity's
+    /// `sha3`, which is an alias for `keccak256`.
+    pub(crate) fn crypto_sha3(&self, input: Vec<u8>) -> Vec<u8> {
+        sha3::Sha3_256::digest(&input).to_vec()
+    }
+
+    pub(crate) fn crypto_ripemd160(&self, input: Vec<u8>) -> Vec<u8> {
+        ripemd160::Ripemd160::digest(&input).to_vec()
+    }
+
+    /// Recovers the address that signed the 32 byte `hash`. The `signature` is
+    /// 65 bytes long and consists of `r`, `s` and `v`, where `v` is either 0 or
+    /// 1, or 27 or 28 like in Ethereum transactions. Returns `None` for any
+    /// input from which no address can be recovered.
+    pub(crate) fn crypto_ecrecover(&self, hash: Vec<u8>, signature: Vec<u8>) -> Option<H160> {
+        if hash.len() != 32 || signature.len() != 65 {
+            return None;
+        }
+        let v = match signature[64] {
+            v @ 0..=1 => v,
+            v @ 27..=28 => v - 27,
+            _ => return None,
+        };
+        let recovery_id = RecoveryId::from_i32(v as i32).ok()?;
+        let signature = RecoverableSignature::from_compact(&signature[..64], recovery_id).ok()?;
+        let message = Message::from_slice(&hash).ok()?;
+        let public_key = SECP256K1.recover(&message, &signature).ok()?;
+
+        // The address is the last 20 bytes of the hash of the public key,
+        // without the leading byte that marks it as uncompressed
+        let public_key = public_key.serialize_uncompressed();
+        Some(H160::from_slice(
+            &tiny_keccak::keccak256(&public_key[1..])[12..],
+        ))
+    }
+
     pub(crate) fn big_int_plus(&self, x: BigInt, y: BigInt) -> BigInt {
         x + y
     }
@@ -511,6 +687,37 @@ impl HostExports {
         x.pow(exponent)
     }
 
+    pub(crate) fn big_int_bit_or(&self, x: BigInt, y: BigInt) -> BigInt {
+        x | y
+    }
+
+    pub(crate) fn big_int_bit_and(&self, x: BigInt, y: BigInt) -> BigInt {
+        x & y
+    }
+
+    /// Like `<<` on an `int256` in Solidity, the result wraps around to the
+    /// range of `int256`, and shifting by 256 or more bits gives zero.
+    pub(crate) fn big_int_left_shift(&self, x: BigInt, bits: u32) -> BigInt {
+        match u8::try_from(bits) {
+            Ok(bits) => (x << bits).wrapping_int256(),
+            Err(_) => BigInt::from(0),
+        }
+    }
+
+    /// Like `>>` on an `int256` in Solidity, `x` is first wrapped around to
+    /// the range of `int256`. Shifting by 255 or more bits leaves only the
+    /// sign, i.e., gives `0` or `-1`.
+    pub(crate) fn big_int_right_shift(&self, x: BigInt, bits: u32) -> BigInt {
+        x.wrapping_int256() >> u8::try_from(bits).unwrap_or(u8::max_value())
+    }
+
+    /// Expects a decimal string.
+    pub(crate) fn big_int_from_string(&self, s: String) -> Result<BigInt, HostExportError> {
+        BigInt::from_str(&s)
+            .with_context(|| format!("string is not a BigInt: `{}`", s))
+            .map_err(HostExportError::Deterministic)
+    }
+
     /// Useful for IPFS hashes stored as bytes
     pub(crate) fn bytes_to_base58(&self, bytes: Vec<u8>) -> String {
         ::bs58::encode(&bytes).into_string()
@@ -551,6 +758,14 @@ impl HostExports {
         x.to_string()
     }
 
+    pub(crate) fn big_decimal_truncate(&self, x: BigDecimal, decimals: u32) -> BigDecimal {
+        x.truncated(decimals.into())
+    }
+
+    pub(crate) fn big_decimal_round(&self, x: BigDecimal, decimals: u32) -> BigDecimal {
+        x.rounded(decimals.into())
+    }
+
     pub(crate) fn big_decimal_from_string(&self, s: String) -> Result<BigDecimal, anyhow::Error> {
         BigDecimal::from_str(&s).with_context(|| format!("string  is not a BigDecimal: '{}'", s))
     }
@@ -592,7 +807,7 @@ impl HostExports {
             .clone();
 
         // Remember that we need to create this data source
-        state.created_data_sources.push(DataSourceTemplateInfo {
+        state.push_created_data_source(DataSourceTemplateInfo {
             data_source: self.data_source_name.clone(),
             template,
             params,
@@ -11,10 +11,12 @@ use wasmtime::{Memory, Trap};
 use crate::host_exports;
 use crate::mapping::MappingContext;
 use anyhow::Error;
-use ethabi::LogParam;
+use ethabi::{LogParam, Token};
 use graph::components::ethereum::*;
 use graph::components::subgraph::MappingError;
 use graph::data::store;
+use graph::data::subgraph::schema::SubgraphError;
+use graph::data::subgraph::SubgraphFeature;
 use graph::prelude::*;
 use host_exports::HostExportError;
 use web3::types::{Log, Transaction, U256};
@@ -195,7 +197,6 @@ impl WasmInstance {
         std::cell::Ref::map(self.instance_ctx.borrow(), |i| i.as_ref().unwrap())
     }
 
-    #[cfg(test)]
     pub(crate) fn instance_ctx_mut(&self) -> std::cell::RefMut<'_, WasmInstanceContext> {
         std::cell::RefMut::map(self.instance_ctx.borrow_mut(), |i| i.as_mut().unwrap())
     }
@@ -205,13 +206,20 @@ impl WasmInstance {
         self.instance.get_func(func_name).unwrap()
     }
 
+    /// Invoke `handler` with `arg`. If the handler fails, the changes it
+    /// made are discarded. When the subgraph opted into non-fatal errors, a
+    /// deterministic failure is recorded in the block state instead of
+    /// being returned
     fn invoke_handler<C>(&mut self, handler: &str, arg: AscPtr<C>) -> Result<(), MappingError> {
         let func = self
             .instance
             .get_func(handler)
-            .with_context(|| format!("function {} not found", handler))?;
+            .with_context(|| format!("function {} not found", handler))?
+            .get1()?;
+
+        self.instance_ctx_mut().ctx.state.enter_handler();
 
-        func.get1()?(arg.wasm_ptr()).map_err(|trap: Trap| {
+        let result = func(arg.wasm_ptr()).map_err(|trap: Trap| {
             if self.instance_ctx().possible_reorg {
                 MappingError::PossibleReorg(trap.into())
             } else if trap.to_string().contains(TRAP_TIMEOUT) {
@@ -241,7 +249,37 @@ impl WasmInstance {
                     _ => MappingError::Unknown(e),
                 }
             }
-        })
+        });
+
+        let mut instance_ctx = self.instance_ctx_mut();
+        let ctx = &mut instance_ctx.ctx;
+        match result {
+            Ok(()) => ctx
+                .state
+                .exit_handler()
+                .map_err(|e| MappingError::Unknown(Error::from(e))),
+            Err(MappingError::Deterministic(e))
+                if ctx
+                    .host_exports
+                    .features
+                    .contains(&SubgraphFeature::NonFatalErrors) =>
+            {
+                let error = SubgraphError {
+                    subgraph_id: ctx.host_exports.subgraph_id.clone(),
+                    message: format!("{:#}", e),
+                    block_ptr: Some(EthereumBlockPointer::from(ctx.block.as_ref())),
+                    handler: Some(handler.to_owned()),
+                    deterministic: true,
+                };
+                ctx.state
+                    .exit_handler_and_discard_changes_due_to_error(error);
+                Ok(())
+            }
+            Err(e) => {
+                ctx.state.exit_handler_and_discard_changes();
+                Err(e)
+            }
+        }
     }
 }
 
@@ -430,9 +468,19 @@ impl WasmInstance {
             )?;
         }
 
+        link!("ethereum.encode", ethereum_encode, token_ptr);
+        link!("ethereum.decode", ethereum_decode, types_ptr, data_ptr);
+
         link!("abort", abort, message_ptr, file_name_ptr, line, column);
 
         link!("store.get", store_get, "host_export_store_get", entity, id);
+        link!(
+            "store.getInBlock",
+            store_get_in_block,
+            "host_export_store_get_in_block",
+            entity,
+            id
+        );
         link!(
             "store.set",
             store_set,
@@ -441,6 +489,14 @@ impl WasmInstance {
             id,
             data
         );
+        link!(
+            "store.loadRelated",
+            store_load_related,
+            "host_export_store_load_related",
+            entity,
+            id,
+            field
+        );
 
         link!("ipfs.cat", ipfs_cat, "host_export_ipfs_cat", hash_ptr);
         link!(
@@ -470,6 +526,15 @@ impl WasmInstance {
         link!("json.toBigInt", json_to_big_int, ptr);
 
         link!("crypto.keccak256", crypto_keccak_256, ptr);
+        link!("crypto.sha256", crypto_sha256, ptr);
+        link!("crypto.sha3", crypto_sha3, ptr);
+        link!("crypto.ripemd160", crypto_ripemd160, ptr);
+        link!(
+            "crypto.ecrecover",
+            crypto_ecrecover,
+            hash_ptr,
+            signature_ptr
+        );
 
         link!("bigInt.plus", big_int_plus, x_ptr, y_ptr);
         link!("bigInt.minus", big_int_minus, x_ptr, y_ptr);
@@ -478,6 +543,11 @@ impl WasmInstance {
         link!("bigInt.dividedByDecimal", big_int_divided_by_decimal, x, y);
         link!("bigInt.mod", big_int_mod, x_ptr, y_ptr);
         link!("bigInt.pow", big_int_pow, x_ptr, exp);
+        link!("bigInt.bitOr", big_int_bit_or, x_ptr, y_ptr);
+        link!("bigInt.bitAnd", big_int_bit_and, x_ptr, y_ptr);
+        link!("bigInt.leftShift", big_int_left_shift, x_ptr, bits);
+        link!("bigInt.rightShift", big_int_right_shift, x_ptr, bits);
+        link!("bigInt.fromString", big_int_from_string, ptr);
 
         link!("bigDecimal.toString", big_decimal_to_string, ptr);
         link!("bigDecimal.fromString", big_decimal_from_string, ptr);
@@ -486,6 +556,8 @@ impl WasmInstance {
         link!("bigDecimal.times", big_decimal_times, x_ptr, y_ptr);
         link!("bigDecimal.dividedBy", big_decimal_divided_by, x, y);
         link!("bigDecimal.equals", big_decimal_equals, x_ptr, y_ptr);
+        link!("bigDecimal.truncate", big_decimal_truncate, x_ptr, decimals);
+        link!("bigDecimal.round", big_decimal_round, x_ptr, decimals);
 
         link!("dataSource.create", data_source_create, name, params);
         link!(
@@ -701,9 +773,13 @@ impl WasmInstanceContext {
         id_ptr: AscPtr<AscString>,
         data_ptr: AscPtr<AscEntity>,
     ) -> Result<(), Trap> {
-        let entity = self.asc_get(entity_ptr);
+        let entity: String = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
         let data = self.try_asc_get(data_ptr)?;
+        try_host_export!(
+            self,
+            self.ctx.host_exports.check_entity_type_writable(&entity)
+        );
         self.ctx.host_exports.store_set(
             &self.ctx.logger,
             &mut self.ctx.state,
@@ -716,9 +792,17 @@ impl WasmInstanceContext {
     }
 
     /// function store.remove(entity: string, id: string): void
-    fn store_remove(&mut self, entity_ptr: AscPtr<AscString>, id_ptr: AscPtr<AscString>) {
-        let entity = self.asc_get(entity_ptr);
+    fn store_remove(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+    ) -> Result<(), Trap> {
+        let entity: String = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
+        try_host_export!(
+            self,
+            self.ctx.host_exports.check_entity_type_writable(&entity)
+        );
         self.ctx.host_exports.store_remove(
             &self.ctx.logger,
             &mut self.ctx.state,
@@ -726,6 +810,7 @@ impl WasmInstanceContext {
             entity,
             id,
         );
+        Ok(())
     }
 
     /// function store.get(entity: string, id: string): Entity | null
@@ -737,10 +822,12 @@ impl WasmInstanceContext {
         let start = Instant::now();
         let entity_ptr = self.asc_get(entity_ptr);
         let id_ptr = self.asc_get(id_ptr);
-        let entity_option =
-            self.ctx
-                .host_exports
-                .store_get(&mut self.ctx.state, entity_ptr, id_ptr)?;
+        let entity_option = self.ctx.host_exports.store_get(
+            &mut self.ctx.state,
+            &self.host_metrics,
+            entity_ptr,
+            id_ptr,
+        )?;
 
         let ret = Ok(match entity_option {
             Some(entity) => {
@@ -759,6 +846,56 @@ impl WasmInstanceContext {
         ret
     }
 
+    /// function store.getInBlock(entity: string, id: string): Entity | null
+    fn store_get_in_block(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscEntity>, Trap> {
+        let start = Instant::now();
+        let entity = self.asc_get(entity_ptr);
+        let id = self.asc_get(id_ptr);
+        let entity_option = self
+            .ctx
+            .host_exports
+            .store_get_in_block(&self.ctx.state, entity, id);
+
+        let ret = Ok(match entity_option {
+            Some(entity) => self.asc_new(&entity),
+            None => AscPtr::null(),
+        });
+
+        self.host_metrics
+            .observe_host_fn_execution_time(start.elapsed().as_secs_f64(), "store_get_in_block");
+
+        ret
+    }
+
+    /// function store.loadRelated(entity: string, id: string, field: string): Array<Entity>
+    fn store_load_related(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+        field_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<Array<AscPtr<AscEntity>>>, Trap> {
+        let start = Instant::now();
+        let entity = self.asc_get(entity_ptr);
+        let id = self.asc_get(id_ptr);
+        let field = self.asc_get(field_ptr);
+        let entities = try_host_export!(
+            self,
+            self.ctx
+                .host_exports
+                .store_load_related(&mut self.ctx.state, entity, id, field)
+        );
+        let ret = Ok(self.asc_new(entities.as_slice()));
+
+        self.host_metrics
+            .observe_host_fn_execution_time(start.elapsed().as_secs_f64(), "store_load_related");
+
+        ret
+    }
+
     /// function ethereum.call(call: SmartContractCall): Array<Token> | null
     fn ethereum_call(
         &mut self,
@@ -779,6 +916,36 @@ impl WasmInstanceContext {
         }
     }
 
+    /// function ethereum.encode(token: ethereum.Value): Bytes
+    fn ethereum_encode(
+        &mut self,
+        token_ptr: AscPtr<AscEnum<EthereumValueKind>>,
+    ) -> AscPtr<Uint8Array> {
+        let token: Token = self.asc_get(token_ptr);
+        let data = self.ctx.host_exports.ethereum_encode(token);
+        self.asc_new(&*data)
+    }
+
+    /// function ethereum.decode(types: String, data: Bytes): ethereum.Value | null
+    fn ethereum_decode(
+        &mut self,
+        types_ptr: AscPtr<AscString>,
+        data_ptr: AscPtr<Uint8Array>,
+    ) -> AscPtr<AscEnum<EthereumValueKind>> {
+        let types: String = self.asc_get(types_ptr);
+        let data: Vec<u8> = self.asc_get(data_ptr);
+        match self.ctx.host_exports.ethereum_decode(types, data) {
+            Ok(token) => self.asc_new(&token),
+
+            // Return null in case of error.
+            Err(e) => {
+                info!(&self.ctx.logger, "Failed ethereum.decode, returning `null`";
+                      "error" => e.to_string());
+                AscPtr::null()
+            }
+        }
+    }
+
     /// function typeConversion.bytesToString(bytes: Bytes): string
     fn bytes_to_string(&mut self, bytes_ptr: AscPtr<Uint8Array>) -> AscPtr<AscString> {
         let string = host_exports::bytes_to_string(&self.ctx.logger, self.asc_get(bytes_ptr));
@@ -930,10 +1097,9 @@ impl WasmInstanceContext {
                 .entity_cache
                 .extend(output_state.entity_cache)
                 .map_err(anyhow::Error::from)?;
-            self.ctx
-                .state
-                .created_data_sources
-                .extend(output_state.created_data_sources);
+            for ds in output_state.created_data_sources {
+                self.ctx.state.push_created_data_source(ds);
+            }
         }
 
         Ok(())
@@ -993,6 +1159,41 @@ impl WasmInstanceContext {
         Ok(hash_ptr)
     }
 
+    /// function crypto.sha256(input: Bytes): Bytes
+    fn crypto_sha256(&mut self, input_ptr: AscPtr<Uint8Array>) -> AscPtr<Uint8Array> {
+        let hash = self.ctx.host_exports.crypto_sha256(self.asc_get(input_ptr));
+        self.asc_new(&*hash)
+    }
+
+    /// function crypto.sha3(input: Bytes): Bytes
+    fn crypto_sha3(&mut self, input_ptr: AscPtr<Uint8Array>) -> AscPtr<Uint8Array> {
+        let hash = self.ctx.host_exports.crypto_sha3(self.asc_get(input_ptr));
+        self.asc_new(&*hash)
+    }
+
+    /// function crypto.ripemd160(input: Bytes): Bytes
+    fn crypto_ripemd160(&mut self, input_ptr: AscPtr<Uint8Array>) -> AscPtr<Uint8Array> {
+        let hash = self
+            .ctx
+            .host_exports
+            .crypto_ripemd160(self.asc_get(input_ptr));
+        self.asc_new(&*hash)
+    }
+
+    /// function crypto.ecrecover(hash: Bytes, signature: Bytes): Address | null
+    fn crypto_ecrecover(
+        &mut self,
+        hash_ptr: AscPtr<Uint8Array>,
+        signature_ptr: AscPtr<Uint8Array>,
+    ) -> AscPtr<AscH160> {
+        let hash: Vec<u8> = self.asc_get(hash_ptr);
+        let signature: Vec<u8> = self.asc_get(signature_ptr);
+        match self.ctx.host_exports.crypto_ecrecover(hash, signature) {
+            Some(address) => self.asc_new(&address),
+            None => AscPtr::null(),
+        }
+    }
+
     /// function bigInt.plus(x: BigInt, y: BigInt): BigInt
     fn big_int_plus(
         &mut self,
@@ -1093,6 +1294,77 @@ impl WasmInstanceContext {
         Ok(result_ptr)
     }
 
+    /// function bigInt.bitOr(x: BigInt, y: BigInt): BigInt
+    fn big_int_bit_or(
+        &mut self,
+        x_ptr: AscPtr<AscBigInt>,
+        y_ptr: AscPtr<AscBigInt>,
+    ) -> Result<AscPtr<AscBigInt>, Trap> {
+        let result = self
+            .ctx
+            .host_exports
+            .big_int_bit_or(self.asc_get(x_ptr), self.asc_get(y_ptr));
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
+    /// function bigInt.bitAnd(x: BigInt, y: BigInt): BigInt
+    fn big_int_bit_and(
+        &mut self,
+        x_ptr: AscPtr<AscBigInt>,
+        y_ptr: AscPtr<AscBigInt>,
+    ) -> Result<AscPtr<AscBigInt>, Trap> {
+        let result = self
+            .ctx
+            .host_exports
+            .big_int_bit_and(self.asc_get(x_ptr), self.asc_get(y_ptr));
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
+    /// function bigInt.leftShift(x: BigInt, bits: u32): BigInt
+    fn big_int_left_shift(
+        &mut self,
+        x_ptr: AscPtr<AscBigInt>,
+        bits: u32,
+    ) -> Result<AscPtr<AscBigInt>, Trap> {
+        let result = self
+            .ctx
+            .host_exports
+            .big_int_left_shift(self.asc_get(x_ptr), bits);
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
+    /// function bigInt.rightShift(x: BigInt, bits: u32): BigInt
+    fn big_int_right_shift(
+        &mut self,
+        x_ptr: AscPtr<AscBigInt>,
+        bits: u32,
+    ) -> Result<AscPtr<AscBigInt>, Trap> {
+        let result = self
+            .ctx
+            .host_exports
+            .big_int_right_shift(self.asc_get(x_ptr), bits);
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
+    /// function bigInt.fromString(s: string): BigInt
+    fn big_int_from_string(
+        &mut self,
+        string_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscBigInt>, Trap> {
+        let result = try_host_export!(
+            self,
+            self.ctx
+                .host_exports
+                .big_int_from_string(self.asc_get(string_ptr))
+        );
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
     /// function typeConversion.bytesToBase58(bytes: Bytes): string
     fn bytes_to_base58(
         &mut self,
@@ -1184,6 +1456,32 @@ impl WasmInstanceContext {
         Ok(self.asc_new(&result))
     }
 
+    /// function bigDecimal.truncate(x: BigDecimal, decimals: u32): BigDecimal
+    fn big_decimal_truncate(
+        &mut self,
+        x_ptr: AscPtr<AscBigDecimal>,
+        decimals: u32,
+    ) -> Result<AscPtr<AscBigDecimal>, Trap> {
+        let result = self
+            .ctx
+            .host_exports
+            .big_decimal_truncate(self.try_asc_get(x_ptr)?, decimals);
+        Ok(self.asc_new(&result))
+    }
+
+    /// function bigDecimal.round(x: BigDecimal, decimals: u32): BigDecimal
+    fn big_decimal_round(
+        &mut self,
+        x_ptr: AscPtr<AscBigDecimal>,
+        decimals: u32,
+    ) -> Result<AscPtr<AscBigDecimal>, Trap> {
+        let result = self
+            .ctx
+            .host_exports
+            .big_decimal_round(self.try_asc_get(x_ptr)?, decimals);
+        Ok(self.asc_new(&result))
+    }
+
     /// function bigDecimal.equals(x: BigDecimal, y: BigDecimal): bool
     fn big_decimal_equals(
         &mut self,
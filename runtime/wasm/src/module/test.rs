@@ -1,6 +1,6 @@
 use ethabi::Token;
 use hex;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io::Cursor;
 use std::str::FromStr;
 
@@ -151,6 +151,7 @@ fn mock_host_exports(
         data_source.network.unwrap(),
         data_source.context,
         Arc::new(data_source.templates),
+        BTreeSet::new(),
         data_source.mapping.abis,
         mock_ethereum_adapter,
         Arc::new(graph_core::LinkResolver::from(
@@ -454,6 +455,91 @@ async fn crypto_keccak256() {
     );
 }
 
+/// The results of the hash functions end up in entities, and therefore in
+/// the proof of indexing; check them against known vectors so that a change
+/// in their implementation does not go unnoticed.
+#[tokio::test]
+async fn crypto_hashes() {
+    let mut module = test_module("cryptoHashes", mock_data_source("wasm_test/crypto.wasm"));
+
+    let cases: Vec<(&str, &str, &str, &str)> = vec![
+        (
+            "",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a",
+            "9c1185a5c5e9fc54612808977ee8f548b2258d31",
+        ),
+        (
+            "abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+            "8eb208f7e05d987a9b044a8e98c6b087f15a0bfc",
+        ),
+    ];
+
+    for (input, sha256, sha3, ripemd160) in cases {
+        let input_bytes = input.as_bytes();
+        let input: AscPtr<Uint8Array> = module.asc_new(input_bytes);
+
+        let hash = module.instance_ctx_mut().crypto_sha256(input);
+        let hash: Vec<u8> = module.asc_get(hash);
+        assert_eq!(hex::encode(hash), sha256);
+
+        let hash = module.instance_ctx_mut().crypto_sha3(input);
+        let hash: Vec<u8> = module.asc_get(hash);
+        assert_eq!(hex::encode(&hash), sha3);
+        assert_ne!(hash, tiny_keccak::keccak256(input_bytes).to_vec());
+
+        let hash = module.instance_ctx_mut().crypto_ripemd160(input);
+        let hash: Vec<u8> = module.asc_get(hash);
+        assert_eq!(hex::encode(hash), ripemd160);
+    }
+}
+
+#[tokio::test]
+async fn crypto_ecrecover() {
+    let mut module = test_module("cryptoEcrecover", mock_data_source("wasm_test/crypto.wasm"));
+
+    // keccak256("graph-node"), signed with the private key
+    // 0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318
+    let hash =
+        hex::decode("9e7ae8185ab45ac692fca66526e387fbb53c5753103f546d46f5d4b2ac445bdf").unwrap();
+    let mut signature = hex::decode(
+        "bb50e2d89a4ed70663d080659fe0ad4b9bc3e06c17a227433966cb59ceee020d\
+         2c79f6d04648da88aa2184ea2aaf9d7a80d9ace4bcf35f298aa4e8c8dae97217\
+         1b",
+    )
+    .unwrap();
+    let signer = H160::from_str("2c7536e3605d9c16a7a3d7b1898e529396a65c23").unwrap();
+
+    let mut recover = |hash: &[u8], signature: &[u8]| -> Option<H160> {
+        let hash: AscPtr<Uint8Array> = module.asc_new(hash);
+        let signature: AscPtr<Uint8Array> = module.asc_new(signature);
+        let address = module.instance_ctx_mut().crypto_ecrecover(hash, signature);
+        if address.is_null() {
+            None
+        } else {
+            Some(module.asc_get(address))
+        }
+    };
+
+    assert_eq!(recover(&hash, &signature), Some(signer));
+
+    // `v` can also be given as 0 or 1
+    signature[64] = 0;
+    assert_eq!(recover(&hash, &signature), Some(signer));
+
+    // A different recovery id yields a different address
+    signature[64] = 1;
+    assert_ne!(recover(&hash, &signature), Some(signer));
+
+    // Invalid input never traps, so that it can't make indexing fail
+    signature[64] = 29;
+    assert_eq!(recover(&hash, &signature), None);
+    assert_eq!(recover(&hash[1..], &signature[..64]), None);
+    assert_eq!(recover(&hash, &[0; 65]), None);
+}
+
 #[tokio::test]
 async fn big_int_to_hex() {
     let mut module = test_module(
@@ -548,6 +634,96 @@ async fn big_int_arithmetic() {
     assert_eq!(result, BigInt::from(1));
 }
 
+#[tokio::test]
+async fn big_int_bitwise() {
+    let mut module = test_module(
+        "BigIntBitwise",
+        mock_data_source("wasm_test/big_int_arithmetic.wasm"),
+    );
+
+    // -256 | 15 = -241
+    let x: AscPtr<AscBigInt> = module.asc_new(&BigInt::from(-256));
+    let y: AscPtr<AscBigInt> = module.asc_new(&BigInt::from(15));
+    let result_ptr = module.instance_ctx_mut().big_int_bit_or(x, y).unwrap();
+    let result: BigInt = module.asc_get(result_ptr);
+    assert_eq!(result, BigInt::from(-241));
+
+    // -1 & 255 = 255
+    let x: AscPtr<AscBigInt> = module.asc_new(&BigInt::from(-1));
+    let y: AscPtr<AscBigInt> = module.asc_new(&BigInt::from(255));
+    let result_ptr = module.instance_ctx_mut().big_int_bit_and(x, y).unwrap();
+    let result: BigInt = module.asc_get(result_ptr);
+    assert_eq!(result, BigInt::from(255));
+
+    // 3 << 4 = 48
+    let x: AscPtr<AscBigInt> = module.asc_new(&BigInt::from(3));
+    let result_ptr = module.instance_ctx_mut().big_int_left_shift(x, 4).unwrap();
+    let result: BigInt = module.asc_get(result_ptr);
+    assert_eq!(result, BigInt::from(48));
+
+    // -7 >> 1 = -4
+    let x: AscPtr<AscBigInt> = module.asc_new(&BigInt::from(-7));
+    let result_ptr = module.instance_ctx_mut().big_int_right_shift(x, 1).unwrap();
+    let result: BigInt = module.asc_get(result_ptr);
+    assert_eq!(result, BigInt::from(-4));
+
+    // Shifts work like they do for `int256` in Solidity: results wrap
+    // around past 2^255, and shifts by 256 or more bits are allowed
+    let two = BigInt::from(2);
+    let mut shift = |op: &str, x: BigInt, bits: u32| -> BigInt {
+        let x: AscPtr<AscBigInt> = module.asc_new(&x);
+        let ctx = module.instance_ctx_mut();
+        let result_ptr = match op {
+            "<<" => ctx.big_int_left_shift(x, bits),
+            _ => ctx.big_int_right_shift(x, bits),
+        }
+        .unwrap();
+        module.asc_get(result_ptr)
+    };
+    let min = BigInt::from(0) - two.clone().pow(255);
+    let max = two.clone().pow(255) - BigInt::from(1);
+    assert_eq!(shift("<<", BigInt::from(1), 255), min);
+    assert_eq!(
+        shift("<<", BigInt::from(3), 254),
+        BigInt::from(0) - two.pow(254)
+    );
+    assert_eq!(shift("<<", max.clone(), 1), BigInt::from(-2));
+    assert_eq!(shift("<<", BigInt::from(1), 256), BigInt::from(0));
+    assert_eq!(
+        shift("<<", BigInt::from(-1), u32::max_value()),
+        BigInt::from(0)
+    );
+    assert_eq!(shift(">>", min.clone(), 255), BigInt::from(-1));
+    assert_eq!(shift(">>", max, 256), BigInt::from(0));
+    assert_eq!(shift(">>", BigInt::from(-7), 1000), BigInt::from(-1));
+    // 2^255 is not an `int256`; it wraps around to -2^255
+    assert_eq!(shift(">>", BigInt::from(0) - min, 254), BigInt::from(-2));
+
+    let s: AscPtr<AscString> = module.asc_new("-123456789012345678901234567890");
+    let result_ptr = module.instance_ctx_mut().big_int_from_string(s).unwrap();
+    let result: BigInt = module.asc_get(result_ptr);
+    assert_eq!(result.to_string(), "-123456789012345678901234567890");
+}
+
+/// Asking for a field that can not be loaded is a bug in the mapping and
+/// must fail the subgraph deterministically
+#[tokio::test]
+async fn load_related_rejects_invalid_fields() {
+    let mut module = test_module("loadRelated", mock_data_source("wasm_test/abort.wasm"));
+
+    for (entity, field) in &[("Nope", "things"), ("Thing", "nope"), ("Thing", "extra")] {
+        let entity: AscPtr<AscString> = module.asc_new(*entity);
+        let id: AscPtr<AscString> = module.asc_new("one");
+        let field: AscPtr<AscString> = module.asc_new(*field);
+        module.instance_ctx_mut().deterministic_host_trap = false;
+        assert!(module
+            .instance_ctx_mut()
+            .store_load_related(entity, id, field)
+            .is_err());
+        assert!(module.instance_ctx().deterministic_host_trap);
+    }
+}
+
 #[tokio::test]
 async fn abort() {
     let module = test_module("abort", mock_data_source("wasm_test/abort.wasm"));
@@ -360,3 +360,173 @@ async fn invalid_discriminant() {
     let ptr: u32 = func().unwrap();
     let _value: Value = module.try_asc_get(ptr.into()).unwrap();
 }
+
+/// A small xorshift generator, so that the round-trip test below sees a
+/// variety of values while staying reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// The ABI types that the round-trip test generates values for.
+enum AbiType {
+    Address,
+    Bool,
+    Bytes,
+    FixedBytes(usize),
+    Int,
+    Uint,
+    String,
+    Array(Box<AbiType>),
+    FixedArray(Box<AbiType>, usize),
+    Tuple(Vec<AbiType>),
+}
+
+impl AbiType {
+    fn arbitrary(rng: &mut Rng, depth: u32) -> Self {
+        let kinds = if depth == 0 { 7 } else { 10 };
+        match rng.below(kinds) {
+            0 => AbiType::Address,
+            1 => AbiType::Bool,
+            2 => AbiType::Bytes,
+            3 => AbiType::FixedBytes(1 + rng.below(32) as usize),
+            4 => AbiType::Int,
+            5 => AbiType::Uint,
+            6 => AbiType::String,
+            7 => AbiType::Array(Box::new(AbiType::arbitrary(rng, depth - 1))),
+            8 => AbiType::FixedArray(
+                Box::new(AbiType::arbitrary(rng, depth - 1)),
+                1 + rng.below(3) as usize,
+            ),
+            _ => AbiType::Tuple(
+                (0..1 + rng.below(3))
+                    .map(|_| AbiType::arbitrary(rng, depth - 1))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            AbiType::Address => "address".to_owned(),
+            AbiType::Bool => "bool".to_owned(),
+            AbiType::Bytes => "bytes".to_owned(),
+            AbiType::FixedBytes(len) => format!("bytes{}", len),
+            AbiType::Int => "int256".to_owned(),
+            AbiType::Uint => "uint256".to_owned(),
+            AbiType::String => "string".to_owned(),
+            AbiType::Array(inner) => format!("{}[]", inner.name()),
+            AbiType::FixedArray(inner, len) => format!("{}[{}]", inner.name(), len),
+            AbiType::Tuple(inner) => format!(
+                "({})",
+                inner
+                    .iter()
+                    .map(AbiType::name)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+
+    fn arbitrary_token(&self, rng: &mut Rng) -> Token {
+        match self {
+            AbiType::Address => Token::Address(H160::from_slice(&rng.bytes(20))),
+            AbiType::Bool => Token::Bool(rng.below(2) == 1),
+            AbiType::Bytes => {
+                let len = rng.below(70) as usize;
+                Token::Bytes(rng.bytes(len))
+            }
+            AbiType::FixedBytes(len) => Token::FixedBytes(rng.bytes(*len)),
+            AbiType::Int => Token::Int(U256([rng.next(), rng.next(), rng.next(), rng.next()])),
+            AbiType::Uint => Token::Uint(U256([rng.next(), rng.next(), rng.next(), rng.next()])),
+            AbiType::String => {
+                let len = rng.below(40);
+                Token::String(
+                    (0..len)
+                        .map(|_| char::from(b' ' + rng.below(95) as u8))
+                        .collect(),
+                )
+            }
+            AbiType::Array(inner) => {
+                let len = rng.below(4);
+                Token::Array((0..len).map(|_| inner.arbitrary_token(rng)).collect())
+            }
+            AbiType::FixedArray(inner, len) => {
+                Token::FixedArray((0..*len).map(|_| inner.arbitrary_token(rng)).collect())
+            }
+            AbiType::Tuple(inner) => {
+                Token::Tuple(inner.iter().map(|t| t.arbitrary_token(rng)).collect())
+            }
+        }
+    }
+}
+
+/// Encode random values with `ethereum.encode` and check that decoding the
+/// result with `ethereum.decode` produces the original value.
+#[tokio::test]
+async fn abi_ethereum_encode_decode_roundtrip() {
+    let mut module = test_module(
+        "abiEthereumEncodeDecodeRoundtrip",
+        mock_data_source("wasm_test/abi_token.wasm"),
+    );
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+    for _ in 0..200 {
+        let abi_type = AbiType::arbitrary(&mut rng, 2);
+        let token = abi_type.arbitrary_token(&mut rng);
+
+        let token_ptr = module.asc_new(&token);
+        let data_ptr = module.instance_ctx_mut().ethereum_encode(token_ptr);
+        let data: Vec<u8> = module.asc_get(data_ptr);
+        assert_eq!(data, ethabi::encode(&[token.clone()]));
+
+        let types_ptr = module.asc_new(&abi_type.name());
+        let decoded_ptr = module
+            .instance_ctx_mut()
+            .ethereum_decode(types_ptr, data_ptr);
+        assert!(
+            !decoded_ptr.is_null(),
+            "failed to decode {}",
+            abi_type.name()
+        );
+        let decoded: Token = module.asc_get(decoded_ptr);
+
+        assert_eq!(token, decoded, "round trip for {}", abi_type.name());
+    }
+}
+
+#[tokio::test]
+async fn abi_ethereum_decode_invalid_input() {
+    let mut module = test_module(
+        "abiEthereumDecodeInvalidInput",
+        mock_data_source("wasm_test/abi_token.wasm"),
+    );
+
+    let data_ptr: AscPtr<Uint8Array> = module.asc_new(&*vec![1u8; 32]);
+    let types_ptr = module.asc_new("no_such_type");
+    let decoded_ptr = module
+        .instance_ctx_mut()
+        .ethereum_decode(types_ptr, data_ptr);
+    assert!(decoded_ptr.is_null());
+
+    // A `string` needs at least an offset and a length
+    let types_ptr = module.asc_new("string");
+    let decoded_ptr = module
+        .instance_ctx_mut()
+        .ethereum_decode(types_ptr, data_ptr);
+    assert!(decoded_ptr.is_null());
+}
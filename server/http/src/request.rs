@@ -1,7 +1,7 @@
 use graph::prelude::serde_json;
-use graphql_parser;
 use hyper::body::Bytes;
 
+use graph::components::server::persisted::{persisted_query_hash, PersistedQueries};
 use graph::components::server::query::GraphQLServerError;
 use graph::prelude::*;
 
@@ -10,15 +10,22 @@ pub struct GraphQLRequest {
     body: Bytes,
     schema: Arc<ApiSchema>,
     network: Option<String>,
+    persisted_queries: Arc<PersistedQueries>,
 }
 
 impl GraphQLRequest {
     /// Creates a new GraphQLRequest future based on an HTTP request and a result sender.
-    pub fn new(body: Bytes, schema: Arc<ApiSchema>, network: Option<String>) -> Self {
+    pub fn new(
+        body: Bytes,
+        schema: Arc<ApiSchema>,
+        network: Option<String>,
+        persisted_queries: Arc<PersistedQueries>,
+    ) -> Self {
         GraphQLRequest {
             body,
             schema,
             network,
+            persisted_queries,
         }
     }
 }
@@ -40,22 +47,35 @@ impl Future for GraphQLRequest {
             GraphQLServerError::ClientError(String::from("Request data is not an object"))
         })?;
 
-        // Ensure the JSON data has a "query" field
-        let query_value = obj.get("query").ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from(
-                "The \"query\" field is missing in request data",
-            ))
-        })?;
+        let hash = persisted_query_hash(obj.get("extensions"))?;
+
+        // Ensure the JSON data has a "query" field, unless the request is for
+        // a persisted query
+        let query_value = match (obj.get("query"), hash) {
+            (None, Some(_)) => None,
+            (None, None) => {
+                return Err(GraphQLServerError::ClientError(String::from(
+                    "The \"query\" field is missing in request data",
+                )))
+            }
+            (Some(query_value), _) => Some(query_value),
+        };
 
         // Ensure the "query" field is a string
-        let query_string = query_value.as_str().ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from("The \"query\" field is not a string"))
-        })?;
+        let query_string = query_value
+            .map(|query_value| {
+                query_value.as_str().ok_or_else(|| {
+                    GraphQLServerError::ClientError(String::from(
+                        "The \"query\" field is not a string",
+                    ))
+                })
+            })
+            .transpose()?;
 
-        // Parse the "query" field of the JSON body
-        let document = graphql_parser::parse_query(query_string).map_err(|e| {
-            GraphQLServerError::from(QueryError::ParseError(Arc::new(e.compat().into())))
-        })?;
+        // Parse the "query" field of the JSON body, or look it up by its hash
+        let query = self
+            .persisted_queries
+            .resolve(&schema.schema.id, hash, query_string)?;
 
         // Parse the "variables" field of the JSON body, if present
         let variables = match obj.get("variables") {
@@ -70,9 +90,10 @@ impl Future for GraphQLRequest {
             )),
         }?;
 
-        Ok(Async::Ready(Query::new(
+        Ok(Async::Ready(Query::with_shape_hash(
             schema,
-            document,
+            query.document,
+            query.shape_hash,
             variables,
             self.network.clone(),
         )))
@@ -89,6 +110,7 @@ mod tests {
     use graph::prelude::*;
 
     use super::GraphQLRequest;
+    use graph::components::server::persisted::{query_hash, PersistedQueries};
 
     const EXAMPLE_SCHEMA: &'static str = "type Query @entity { users: [User!] }";
 
@@ -100,6 +122,7 @@ mod tests {
             hyper::body::Bytes::from("!@#)%"),
             Arc::new(ApiSchema::from_api_schema(schema).unwrap()),
             None,
+            Arc::new(PersistedQueries::default()),
         );
         request.wait().expect_err("Should reject invalid JSON");
     }
@@ -112,6 +135,7 @@ mod tests {
             hyper::body::Bytes::from("{}"),
             Arc::new(ApiSchema::from_api_schema(schema).unwrap()),
             None,
+            Arc::new(PersistedQueries::default()),
         );
         request
             .wait()
@@ -126,6 +150,7 @@ mod tests {
             hyper::body::Bytes::from("{\"query\": 5}"),
             Arc::new(ApiSchema::from_api_schema(schema).unwrap()),
             None,
+            Arc::new(PersistedQueries::default()),
         );
         request
             .wait()
@@ -140,6 +165,7 @@ mod tests {
             hyper::body::Bytes::from("{\"query\": \"foo\"}"),
             Arc::new(ApiSchema::from_api_schema(schema).unwrap()),
             None,
+            Arc::new(PersistedQueries::default()),
         );
         request.wait().expect_err("Should reject broken queries");
     }
@@ -152,10 +178,11 @@ mod tests {
             hyper::body::Bytes::from("{\"query\": \"{ user { name } }\"}"),
             Arc::new(ApiSchema::from_api_schema(schema).unwrap()),
             None,
+            Arc::new(PersistedQueries::default()),
         );
         let query = request.wait().expect("Should accept valid queries");
         assert_eq!(
-            query.document,
+            *query.document,
             graphql_parser::parse_query("{ user { name } }").unwrap()
         );
     }
@@ -174,11 +201,12 @@ mod tests {
             ),
             Arc::new(ApiSchema::from_api_schema(schema).unwrap()),
             None,
+            Arc::new(PersistedQueries::default()),
         );
         let query = request.wait().expect("Should accept null variables");
 
         let expected_query = graphql_parser::parse_query("{ user { name } }").unwrap();
-        assert_eq!(query.document, expected_query);
+        assert_eq!(*query.document, expected_query);
         assert_eq!(query.variables, None);
     }
 
@@ -196,6 +224,7 @@ mod tests {
             ),
             Arc::new(ApiSchema::from_api_schema(schema).unwrap()),
             None,
+            Arc::new(PersistedQueries::default()),
         );
         request.wait().expect_err("Should reject non-map variables");
     }
@@ -216,6 +245,7 @@ mod tests {
             ),
             Arc::new(ApiSchema::from_api_schema(schema).unwrap()),
             None,
+            Arc::new(PersistedQueries::default()),
         );
         let query = request.wait().expect("Should accept valid queries");
 
@@ -234,7 +264,73 @@ mod tests {
             .into_iter(),
         ));
 
-        assert_eq!(query.document, expected_query);
+        assert_eq!(*query.document, expected_query);
         assert_eq!(query.variables, Some(expected_variables));
     }
+
+    #[test]
+    fn accepts_persisted_queries() {
+        let schema =
+            Schema::parse(EXAMPLE_SCHEMA, SubgraphDeploymentId::new("test").unwrap()).unwrap();
+        let schema = Arc::new(ApiSchema::from_api_schema(schema).unwrap());
+        let persisted_queries = Arc::new(PersistedQueries::default());
+        let hash = query_hash("{ user { name } }");
+        let expected_query = graphql_parser::parse_query("{ user { name } }").unwrap();
+
+        let hash_only = format!(
+            "{{\"extensions\": {{\"persistedQuery\": {{\"version\": 1, \"sha256Hash\": \"{}\"}}}}}}",
+            hash
+        );
+        let with_query = format!(
+            "{{\"query\": \"{{ user {{ name }} }}\", \"extensions\": {{\"persistedQuery\": {{\"version\": 1, \"sha256Hash\": \"{}\"}}}}}}",
+            hash
+        );
+
+        let request = GraphQLRequest::new(
+            hyper::body::Bytes::from(hash_only.clone()),
+            schema.clone(),
+            None,
+            persisted_queries.clone(),
+        );
+        request
+            .wait()
+            .expect_err("Should reject unknown persisted queries");
+
+        let request = GraphQLRequest::new(
+            hyper::body::Bytes::from(with_query),
+            schema.clone(),
+            None,
+            persisted_queries.clone(),
+        );
+        let query = request.wait().expect("Should accept persisted queries");
+        assert_eq!(*query.document, expected_query);
+
+        let request = GraphQLRequest::new(
+            hyper::body::Bytes::from(hash_only),
+            schema,
+            None,
+            persisted_queries,
+        );
+        let query = request
+            .wait()
+            .expect("Should accept known persisted queries by hash");
+        assert_eq!(*query.document, expected_query);
+    }
+
+    #[test]
+    fn rejects_unsupported_persisted_query_versions() {
+        let schema =
+            Schema::parse(EXAMPLE_SCHEMA, SubgraphDeploymentId::new("test").unwrap()).unwrap();
+        let request = GraphQLRequest::new(
+            hyper::body::Bytes::from(
+                "{\"extensions\": {\"persistedQuery\": {\"version\": 2, \"sha256Hash\": \"abc\"}}}",
+            ),
+            Arc::new(ApiSchema::from_api_schema(schema).unwrap()),
+            None,
+            Arc::new(PersistedQueries::default()),
+        );
+        request
+            .wait()
+            .expect_err("Should reject unsupported persisted query versions");
+    }
 }
@@ -5,6 +5,7 @@ use hyper::service::make_service_fn;
 use hyper::Server;
 
 use crate::service::{GraphQLService, GraphQLServiceMetrics};
+use graph::components::server::persisted::PersistedQueries;
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
 
 /// Errors that may occur when starting the server.
@@ -26,6 +27,7 @@ pub struct GraphQLServer<Q, S> {
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    persisted_queries: Arc<PersistedQueries>,
     node_id: NodeId,
 }
 
@@ -36,6 +38,7 @@ impl<Q, S> GraphQLServer<Q, S> {
         metrics_registry: Arc<impl MetricsRegistry>,
         graphql_runner: Arc<Q>,
         store: Arc<S>,
+        persisted_queries: Arc<PersistedQueries>,
         node_id: NodeId,
     ) -> Self {
         let logger = logger_factory.component_logger(
@@ -52,6 +55,7 @@ impl<Q, S> GraphQLServer<Q, S> {
             metrics,
             graphql_runner,
             store,
+            persisted_queries,
             node_id,
         }
     }
@@ -84,6 +88,7 @@ where
         let graphql_runner = self.graphql_runner.clone();
         let metrics = self.metrics.clone();
         let store = self.store.clone();
+        let persisted_queries = self.persisted_queries.clone();
         let node_id = self.node_id.clone();
         let new_service = make_service_fn(move |_| {
             futures03::future::ok::<_, Error>(GraphQLService::new(
@@ -91,6 +96,7 @@ where
                 metrics.clone(),
                 graphql_runner.clone(),
                 store.clone(),
+                persisted_queries.clone(),
                 ws_port,
                 node_id.clone(),
             ))
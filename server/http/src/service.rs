@@ -5,6 +5,7 @@ use std::task::Context;
 use std::task::Poll;
 use std::time::Instant;
 
+use graph::components::server::persisted::PersistedQueries;
 use graph::components::server::query::GraphQLServerError;
 use graph::data::subgraph::schema::{SubgraphEntity, SUBGRAPHS_ID};
 use graph::prelude::*;
@@ -76,6 +77,7 @@ pub struct GraphQLService<Q, S> {
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    persisted_queries: Arc<PersistedQueries>,
     ws_port: u16,
     node_id: NodeId,
 }
@@ -87,6 +89,7 @@ impl<Q, S> Clone for GraphQLService<Q, S> {
             metrics: self.metrics.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            persisted_queries: self.persisted_queries.clone(),
             ws_port: self.ws_port,
             node_id: self.node_id.clone(),
         }
@@ -104,6 +107,7 @@ where
         metrics: Arc<GraphQLServiceMetrics>,
         graphql_runner: Arc<Q>,
         store: Arc<S>,
+        persisted_queries: Arc<PersistedQueries>,
         ws_port: u16,
         node_id: NodeId,
     ) -> Self {
@@ -112,6 +116,7 @@ where
             metrics,
             graphql_runner,
             store,
+            persisted_queries,
             ws_port,
             node_id,
         }
@@ -248,7 +253,10 @@ where
         let body = hyper::body::to_bytes(request_body)
             .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
             .await?;
-        let query = GraphQLRequest::new(body, schema, network).compat().await;
+        let query =
+            GraphQLRequest::new(body, schema, network, self.persisted_queries.cheap_clone())
+                .compat()
+                .await;
 
         let result = match query {
             Ok(query) => service.graphql_runner.run_query(query, state, false).await,
@@ -431,6 +439,7 @@ mod tests {
     use hyper::{Body, Method, Request};
     use std::collections::BTreeMap;
 
+    use graph::components::server::persisted::PersistedQueries;
     use graph::data::graphql::effort::LoadManager;
     use graph::prelude::*;
     use graph_mock::{mock_store_with_users_subgraph, MockMetricsRegistry};
@@ -495,8 +504,16 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service =
-            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+        let persisted_queries = Arc::new(PersistedQueries::default());
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            store,
+            persisted_queries,
+            8001,
+            node_id,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
@@ -528,8 +545,16 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service =
-            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+        let persisted_queries = Arc::new(PersistedQueries::default());
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            store,
+            persisted_queries,
+            8001,
+            node_id,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
@@ -4,6 +4,7 @@ use hyper::{Body, Client, Request};
 use std::collections::BTreeMap;
 use std::time::Duration;
 
+use graph::components::server::persisted::PersistedQueries;
 use graph::data::graphql::effort::LoadManager;
 use graph::prelude::*;
 
@@ -95,7 +96,7 @@ mod test {
                 let (store, id) = mock_store_with_users_subgraph();
                 let query_runner = Arc::new(TestGraphQlRunner);
                 let node_id = NodeId::new("test").unwrap();
-                let mut server = HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, store, node_id);
+                let mut server = HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, store, Arc::new(PersistedQueries::default()), node_id);
                 let http_server = server
                     .serve(8001, 8002)
                     .expect("Failed to start GraphQL server");
@@ -142,6 +143,7 @@ mod test {
                 metrics_registry,
                 query_runner,
                 store,
+                Arc::new(PersistedQueries::default()),
                 node_id,
             );
             let http_server = server
@@ -229,6 +231,7 @@ mod test {
                 metrics_registry,
                 query_runner,
                 store,
+                Arc::new(PersistedQueries::default()),
                 node_id,
             );
             let http_server = server
@@ -281,6 +284,7 @@ mod test {
                 metrics_registry,
                 query_runner,
                 store,
+                Arc::new(PersistedQueries::default()),
                 node_id,
             );
             let http_server = server
@@ -141,7 +141,7 @@ mod tests {
         );
         let query = request.wait().expect("Should accept valid queries");
         assert_eq!(
-            query.document,
+            *query.document,
             graphql_parser::parse_query("{ user { name } }").unwrap()
         );
     }
@@ -163,7 +163,7 @@ mod tests {
         let query = request.wait().expect("Should accept null variables");
 
         let expected_query = graphql_parser::parse_query("{ user { name } }").unwrap();
-        assert_eq!(query.document, expected_query);
+        assert_eq!(*query.document, expected_query);
         assert_eq!(query.variables, None);
     }
 
@@ -217,7 +217,7 @@ mod tests {
             .into_iter(),
         ));
 
-        assert_eq!(query.document, expected_query);
+        assert_eq!(*query.document, expected_query);
         assert_eq!(query.variables, Some(expected_variables));
     }
 }
@@ -6,6 +6,7 @@ extern crate serde;
 use graph::prelude::futures03::channel::{mpsc, oneshot};
 use graph::prelude::futures03::SinkExt;
 use graph::prelude::serde_json;
+use graph::prelude::web3::types::H256;
 use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
 use jsonrpc_http_server::{
     jsonrpc_core::{self, Compatibility, IoHandler, Params, Value},
@@ -29,6 +30,9 @@ const JSON_RPC_DEPLOY_ERROR: i64 = 0;
 const JSON_RPC_REMOVE_ERROR: i64 = 1;
 const JSON_RPC_CREATE_ERROR: i64 = 2;
 const JSON_RPC_REASSIGN_ERROR: i64 = 3;
+const JSON_RPC_REWIND_ERROR: i64 = 4;
+const JSON_RPC_PAUSE_ERROR: i64 = 5;
+const JSON_RPC_RESUME_ERROR: i64 = 6;
 
 #[derive(Debug, Deserialize)]
 struct SubgraphCreateParams {
@@ -53,6 +57,18 @@ struct SubgraphReassignParams {
     node_id: NodeId,
 }
 
+#[derive(Debug, Deserialize)]
+struct SubgraphPauseParams {
+    ipfs_hash: SubgraphDeploymentId,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphRewindParams {
+    ipfs_hash: SubgraphDeploymentId,
+    block_hash: H256,
+    block_number: u64,
+}
+
 pub struct JsonRpcServer<R> {
     registrar: Arc<R>,
     http_port: u16,
@@ -151,6 +167,79 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
             )),
         }
     }
+
+    /// Handler for the `subgraph_pause` endpoint.
+    async fn pause_handler(
+        &self,
+        params: SubgraphPauseParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_pause request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .pause_subgraph(params.ipfs_hash.clone())
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_pause",
+                e,
+                JSON_RPC_PAUSE_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_resume` endpoint.
+    async fn resume_handler(
+        &self,
+        params: SubgraphPauseParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_resume request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .resume_subgraph(params.ipfs_hash.clone())
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_resume",
+                e,
+                JSON_RPC_RESUME_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_rewind` endpoint.
+    async fn rewind_handler(
+        &self,
+        params: SubgraphRewindParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_rewind request"; "params" => format!("{:?}", params));
+
+        let block_ptr = EthereumBlockPointer {
+            hash: params.block_hash,
+            number: params.block_number,
+        };
+        match self
+            .registrar
+            .rewind_subgraph(params.ipfs_hash.clone(), block_ptr)
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_rewind",
+                e,
+                JSON_RPC_REWIND_ERROR,
+                params,
+            )),
+        }
+    }
 }
 
 impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
@@ -276,6 +365,51 @@ where
             .compat()
         });
 
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("subgraph_pause", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.pause_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("subgraph_resume", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.resume_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("subgraph_rewind", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.rewind_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
         ServerBuilder::new(handler)
             // Enable REST API:
             // POST /<method>/<param1>/<param2>
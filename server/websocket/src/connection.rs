@@ -1,7 +1,6 @@
 use futures::future::IntoFuture;
 use futures::sync::mpsc;
 use futures03::stream::SplitStream;
-use graphql_parser::parse_query;
 use http::StatusCode;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
@@ -12,6 +11,7 @@ use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
 use tokio_tungstenite::WebSocketStream;
 use uuid::Uuid;
 
+use graph::components::server::persisted::{persisted_query_hash, PersistedQueries};
 use graph::prelude::*;
 
 lazy_static! {
@@ -26,9 +26,10 @@ lazy_static! {
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct StartPayload {
-    query: String,
+    query: Option<String>,
     variables: Option<serde_json::Value>,
     operation_name: Option<String>,
+    extensions: Option<serde_json::Value>,
 }
 
 /// GraphQL/WebSocket message received from a client.
@@ -172,6 +173,7 @@ pub struct GraphQlConnection<Q, S> {
     graphql_runner: Arc<Q>,
     stream: WebSocketStream<S>,
     schema: Arc<ApiSchema>,
+    persisted_queries: Arc<PersistedQueries>,
 }
 
 impl<Q, S> GraphQlConnection<Q, S>
@@ -185,6 +187,7 @@ where
         schema: Arc<ApiSchema>,
         stream: WebSocketStream<S>,
         graphql_runner: Arc<Q>,
+        persisted_queries: Arc<PersistedQueries>,
     ) -> Self {
         GraphQlConnection {
             id: Uuid::new_v4().to_string(),
@@ -192,6 +195,7 @@ where
             graphql_runner,
             stream,
             schema,
+            persisted_queries,
         }
     }
 
@@ -202,6 +206,7 @@ where
         connection_id: String,
         schema: Arc<ApiSchema>,
         graphql_runner: Arc<Q>,
+        persisted_queries: Arc<PersistedQueries>,
     ) -> Result<(), WsError> {
         let mut operations = Operations::new(msg_sink.clone());
 
@@ -260,15 +265,24 @@ where
                         }
                     }
 
-                    // Parse the GraphQL query document; respond with a GQL_ERROR if
-                    // the query is invalid
-                    let query = match parse_query(&payload.query) {
+                    // Parse the GraphQL query document, or look it up by its hash;
+                    // respond with a GQL_ERROR if the query is invalid or not allowed
+                    // for this deployment
+                    let query =
+                        persisted_query_hash(payload.extensions.as_ref()).and_then(|hash| {
+                            persisted_queries.resolve(
+                                &schema.schema.id,
+                                hash,
+                                payload.query.as_deref(),
+                            )
+                        });
+                    let query = match query {
                         Ok(query) => query,
                         Err(e) => {
                             return send_error_string(
                                 &msg_sink,
                                 id.clone(),
-                                format!("Invalid query: {}: {}", payload.query, e),
+                                format!("Invalid query: {}", e),
                             );
                         }
                     };
@@ -301,7 +315,13 @@ where
                     let subscription = Subscription {
                         // Subscriptions currently do not benefit from the generational cache
                         // anyways, so don't bother passing a network.
-                        query: Query::new(schema.clone(), query, variables, None),
+                        query: Query::with_shape_hash(
+                            schema.clone(),
+                            query.document,
+                            query.shape_hash,
+                            variables,
+                            None,
+                        ),
                     };
 
                     debug!(logger, "Start operation";
@@ -404,6 +424,7 @@ where
             self.id.clone(),
             self.schema.clone(),
             self.graphql_runner.clone(),
+            self.persisted_queries.clone(),
         );
 
         // Send outgoing messages asynchronously
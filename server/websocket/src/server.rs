@@ -1,3 +1,4 @@
+use graph::components::server::persisted::PersistedQueries;
 use graph::data::subgraph::schema::SUBGRAPHS_ID;
 use graph::prelude::{SubscriptionServer as SubscriptionServerTrait, *};
 use http::{HeaderValue, Response, StatusCode};
@@ -14,6 +15,7 @@ pub struct SubscriptionServer<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    persisted_queries: Arc<PersistedQueries>,
 }
 
 impl<Q, S> SubscriptionServer<Q, S>
@@ -21,11 +23,17 @@ where
     Q: GraphQlRunner,
     S: SubgraphDeploymentStore + Store,
 {
-    pub fn new(logger: &Logger, graphql_runner: Arc<Q>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: &Logger,
+        graphql_runner: Arc<Q>,
+        store: Arc<S>,
+        persisted_queries: Arc<PersistedQueries>,
+    ) -> Self {
         SubscriptionServer {
             logger: logger.new(o!("component" => "SubscriptionServer")),
             graphql_runner,
             store,
+            persisted_queries,
         }
     }
 
@@ -103,6 +111,7 @@ where
             let graphql_runner = self.graphql_runner.clone();
             let store = self.store.clone();
             let store2 = self.store.clone();
+            let persisted_queries = self.persisted_queries.clone();
 
             // Subgraph that the request is resolved to (if any)
             let subgraph_id = Arc::new(Mutex::new(None));
@@ -166,6 +175,7 @@ where
                             schema,
                             ws_stream,
                             graphql_runner.clone(),
+                            persisted_queries.clone(),
                         );
 
                         graph::spawn_allow_panic(service.into_future().compat());
@@ -2,6 +2,7 @@ extern crate clap;
 extern crate graph_store_postgres;
 
 use clap::App;
+use std::collections::HashMap;
 use std::fs;
 use std::process::exit;
 
@@ -324,7 +325,7 @@ pub fn main() {
         "Failed to construct catalog",
     );
     let layout = ensure(
-        Layout::new(&schema, catalog, false),
+        Layout::new(&schema, catalog, &HashMap::new(), false),
         "Failed to construct Mapping",
     );
     match kind {
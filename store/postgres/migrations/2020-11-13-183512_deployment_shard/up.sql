@@ -0,0 +1,47 @@
+-- Track which shard holds the data for a deployment. Every database has a
+-- deployment_schemas table; the entries in the primary record the shard
+-- for all deployments so that queries and writes can be routed to the
+-- right database
+alter table deployment_schemas
+  add column shard text not null default 'primary';
+
+-- Databases other than the primary do not have chain data. Use an outer
+-- join against ethereum_networks so that deployments in those databases
+-- still show up in the view
+drop view subgraphs.subgraph_deployment_detail;
+
+create view subgraphs.subgraph_deployment_detail as
+select sd.*,
+       decode(en.head_block_hash,'hex') as ethereum_head_block_hash,
+       en.head_block_number as ethereum_head_block_number,
+       ecds.network,
+       sda.node_id
+  from subgraphs.subgraph_deployment sd
+    inner join
+       subgraphs.subgraph_manifest sm
+         on (sd.manifest = sm.id)
+    inner join
+       subgraphs.ethereum_contract_data_source ecds
+         on (ecds.id = sm.data_sources[1])
+    left outer join
+       ethereum_networks en
+         on (en.name = ecds.network)
+    left outer join
+       subgraphs.subgraph_deployment_assignment sda
+         on (sd.id = sda.id)
+union all
+select sd.*,
+       decode(en.head_block_hash,'hex') as ethereum_head_block_hash,
+       en.head_block_number as ethereum_head_block_number,
+       split_part(sd.id, '_', 3) as network,
+       sda.node_id
+  from subgraphs.subgraph_deployment sd
+    inner join
+       subgraphs.subgraph_manifest sm
+         on (sd.manifest = sm.id and sm.data_sources[1] is null)
+    left outer join
+       ethereum_networks en
+         on (en.name = split_part(sd.id, '_', 3))
+    left outer join
+       subgraphs.subgraph_deployment_assignment sda
+         on (sd.id = sda.id);
@@ -0,0 +1 @@
+drop table public.running_deployments;
@@ -0,0 +1,9 @@
+-- The deployments that are being indexed right now. A node adds to the
+-- count of indexers when it starts indexing a deployment and subtracts
+-- from it once the indexer has stopped; the row is removed when the count
+-- drops to zero. This lets others wait until a paused deployment has
+-- really stopped indexing
+create table public.running_deployments(
+  subgraph   text primary key,
+  indexers   int not null
+);
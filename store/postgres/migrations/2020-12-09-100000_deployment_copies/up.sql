@@ -0,0 +1,14 @@
+-- Copies of deployments that are in progress. An entry is made before the
+-- database schema for the copy is created, and marked as switched once
+-- the deployment uses the copy. The entry is only removed once the old
+-- data, or the copy if the deployment was never switched over to it, has
+-- been removed, so that an interrupted copy can always be finished or
+-- undone
+create table public.deployment_copies(
+  subgraph       text primary key,
+  src_shard      text not null,
+  src_namespace  text not null,
+  dst_shard      text not null,
+  dst_namespace  text not null,
+  switched       boolean not null default false
+);
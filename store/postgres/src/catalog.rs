@@ -1,27 +1,27 @@
 use diesel::pg::PgConnection;
 use diesel::prelude::RunQueryDsl;
 use diesel::sql_types::Text;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use graph::prelude::StoreError;
 
-use crate::relational::SqlName;
+use crate::relational::{IdType, SqlName};
 
 /// Information about what tables and columns we have in the database
 #[derive(Debug, Clone)]
 pub struct Catalog {
     pub schema: String,
-    text_columns: HashMap<String, HashSet<String>>,
+    /// Maps table names to a map of column names to the name of the
+    /// column's type in Postgres. Arrays have the name of the type of
+    /// their elements prefixed with `_`
+    columns: HashMap<String, HashMap<String, String>>,
 }
 
 impl Catalog {
     pub fn new(conn: &PgConnection, schema: String) -> Result<Self, StoreError> {
         SqlName::check_valid_identifier(&schema, "database schema")?;
-        let text_columns = get_text_columns(conn, &schema)?;
-        Ok(Catalog {
-            schema,
-            text_columns,
-        })
+        let columns = get_columns(conn, &schema)?;
+        Ok(Catalog { schema, columns })
     }
 
     /// Make a catalog as if the given `schema` did not exist in the database
@@ -31,28 +31,56 @@ impl Catalog {
         SqlName::check_valid_identifier(&schema, "database schema")?;
         Ok(Catalog {
             schema,
-            text_columns: HashMap::default(),
+            columns: HashMap::default(),
+        })
+    }
+
+    /// Make a catalog for a copy of the tables in `self` that lives in the
+    /// database schema `schema`. Tables in the copy use the same column
+    /// types as the tables in `self`
+    pub fn for_copy(&self, schema: String) -> Result<Self, StoreError> {
+        SqlName::check_valid_identifier(&schema, "database schema")?;
+        Ok(Catalog {
+            schema,
+            columns: self.columns.clone(),
         })
     }
 
     /// Return `true` if `table` exists and contains the given `column` and
     /// if that column is of data type `text`
     pub fn is_existing_text_column(&self, table: &SqlName, column: &SqlName) -> bool {
-        self.text_columns
+        self.column_type(table, column) == Some("text")
+    }
+
+    /// Return the type of the ids that the existing `column` in `table`
+    /// stores, or `None` if there is no such column. The column can hold
+    /// a single id or a list of ids. This is used for columns that
+    /// reference other entities so that the type of such a column never
+    /// changes once it has been created
+    pub(crate) fn existing_id_type(&self, table: &SqlName, column: &SqlName) -> Option<IdType> {
+        match self.column_type(table, column)? {
+            "text" | "_text" => Some(IdType::String),
+            "bytea" | "_bytea" => Some(IdType::Bytes),
+            _ => None,
+        }
+    }
+
+    fn column_type(&self, table: &SqlName, column: &SqlName) -> Option<&str> {
+        self.columns
             .get(table.as_str())
-            .map(|cols| cols.contains(column.as_str()))
-            .unwrap_or(false)
+            .and_then(|cols| cols.get(column.as_str()))
+            .map(|typ| typ.as_str())
     }
 }
 
-fn get_text_columns(
+fn get_columns(
     conn: &PgConnection,
     schema: &str,
-) -> Result<HashMap<String, HashSet<String>>, StoreError> {
+) -> Result<HashMap<String, HashMap<String, String>>, StoreError> {
     const QUERY: &str = "
-        select table_name, column_name
+        select table_name, column_name, udt_name
           from information_schema.columns
-         where table_schema = $1 and data_type = 'text'";
+         where table_schema = $1";
 
     #[derive(Debug, QueryableByName)]
     struct Column {
@@ -60,16 +88,18 @@ fn get_text_columns(
         pub table_name: String,
         #[sql_type = "Text"]
         pub column_name: String,
+        #[sql_type = "Text"]
+        pub udt_name: String,
     }
 
-    let map: HashMap<String, HashSet<String>> = diesel::sql_query(QUERY)
+    let map: HashMap<String, HashMap<String, String>> = diesel::sql_query(QUERY)
         .bind::<Text, _>(schema)
         .load::<Column>(conn)?
         .into_iter()
         .fold(HashMap::new(), |mut map, col| {
             map.entry(col.table_name)
                 .or_default()
-                .insert(col.column_name);
+                .insert(col.column_name, col.udt_name);
             map
         });
     Ok(map)
@@ -0,0 +1,684 @@
+//! Copy a deployment into a new database schema, either in the shard it
+//! is in already or in a different shard, while the deployment keeps
+//! indexing. The copy happens in three stages:
+//!
+//!   1. Copy all rows that exist when the copy starts in batches. This is
+//!      where the bulk of the time is spent, and the source deployment
+//!      keeps processing blocks while it happens
+//!   2. Catch up with the changes the source made in the meantime. Each
+//!      round reverts the copy to a block that lies far enough back that
+//!      the source could not have changed anything before it, and then
+//!      copies all versions that were created or ended after that block
+//!      from a consistent snapshot of the source. Rounds are repeated
+//!      until the copy is close behind the source
+//!   3. Wait for the indexer of the paused source to record that it
+//!      stopped, lock the source so that it can not advance, do a last
+//!      round of catching up and switch the deployment over to the copy.
+//!      When the copy is in a different shard, the metadata for the
+//!      deployment is moved along with it. The old data is deleted after
+//!      the switch, and other processes are told to forget where they
+//!      thought the deployment was stored
+//!
+//! The primary keeps a record of each copy from before its database schema
+//! is created until the copy or the old data has been removed. The switch
+//! to the copy happens when the primary marks that record as switched.
+//! Since the databases involved can not be changed atomically, every step
+//! before and after that can be repeated, and `recover` uses the record to
+//! either finish or undo a copy that was interrupted. A database schema is
+//! only ever dropped when neither the primary nor the shard it is in use it
+//!
+//! Data is moved between databases by serializing rows with `row_to_json`
+//! and reading them back with `json_populate_recordset`
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{BigInt, Nullable};
+use diesel::{sql_query, Connection, RunQueryDsl};
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::components::ethereum::REORG_THRESHOLD;
+use graph::data::subgraph::schema::{POI_OBJECT, SUBGRAPHS_ID};
+use graph::prelude::{
+    format_err, info, tokio, BlockNumber, Logger, StoreError, SubgraphDeploymentId,
+};
+
+use crate::entities::{self as e, CopyState};
+use crate::metadata;
+use crate::relational::{Layout, Table, VID_COLUMN};
+use crate::relational_queries::{
+    CopyDestinationQuery, CopyRows, CopySourceQuery, DeleteRowsQuery, DeleteVidsQuery, JsonRow,
+};
+use crate::sharded_store::PRIMARY_SHARD;
+use crate::store::Store;
+
+/// The number of rows we copy with one query
+const BATCH_SIZE: i64 = 10_000;
+
+/// Stop catching up and lock the source once the copy is at most this
+/// many blocks behind it
+const FINAL_LAG: BlockNumber = 10;
+
+/// The maximum number of rounds of catching up before we lock the source
+/// regardless of how far the copy is behind it
+const MAX_CATCH_UP_ROUNDS: usize = 10;
+
+/// How often we check whether the indexer of the paused source stopped
+const STOP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long we wait for the paused source to stop indexing before we give
+/// up on the copy
+const MAX_STOP_WAIT: Duration = Duration::from_secs(120);
+
+/// Metadata that is only kept in the primary, or that is a view, and that
+/// therefore never moves with a deployment
+const PRIMARY_METADATA: &[&str] = &[
+    "Subgraph",
+    "SubgraphVersion",
+    "SubgraphDeploymentAssignment",
+    "SubgraphDeploymentDetail",
+];
+
+/// Errors are not identified by the deployment id, but refer to the
+/// deployment in this column
+const SUBGRAPH_ERROR: &str = "SubgraphError";
+const SUBGRAPH_ERROR_DEPLOYMENT_COLUMN: &str = "subgraph_id";
+
+#[derive(QueryableByName)]
+struct MaxVid {
+    #[sql_type = "Nullable<BigInt>"]
+    vid: Option<i64>,
+}
+
+fn max_vid(conn: &PgConnection, table: &Table) -> Result<i64, StoreError> {
+    let query = format!(
+        "select max({}) as vid from {}",
+        VID_COLUMN,
+        table.qualified_name.as_str()
+    );
+    Ok(sql_query(query)
+        .get_result::<MaxVid>(conn)?
+        .vid
+        .unwrap_or(0))
+}
+
+/// Copy the rows selected by `rows` from `src` to `dst` in batches and
+/// return how many rows were copied. With `keep_vid`, existing rows in
+/// `dst` with the same `vid` as a copied row are replaced
+fn copy_rows(
+    src_conn: &PgConnection,
+    src: &Table,
+    dst_conn: &PgConnection,
+    dst: &Table,
+    rows: CopyRows,
+    keep_vid: bool,
+) -> Result<usize, StoreError> {
+    let mut count = 0;
+    let mut last_vid = 0;
+    loop {
+        let batch: Vec<JsonRow> =
+            CopySourceQuery::new(src, rows.clone(), last_vid, BATCH_SIZE).load(src_conn)?;
+        last_vid = match batch.last() {
+            Some(row) => row.vid,
+            None => break,
+        };
+        if keep_vid {
+            let vids = batch.iter().map(|row| row.vid).collect::<Vec<_>>();
+            DeleteVidsQuery::new(dst, &vids).execute(dst_conn)?;
+        }
+        let data = format!(
+            "[{}]",
+            batch
+                .iter()
+                .map(|row| row.data.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        CopyDestinationQuery::new(dst, &data, keep_vid).execute(dst_conn)?;
+        count += batch.len();
+        if (batch.len() as i64) < BATCH_SIZE {
+            break;
+        }
+    }
+    Ok(count)
+}
+
+/// The copy of one deployment from the store `src` into a new database
+/// schema in the store `dst`. The copy is recorded in the `primary` while
+/// it is in progress
+pub(crate) struct DeploymentCopy {
+    logger: Logger,
+    id: SubgraphDeploymentId,
+    primary: Arc<Store>,
+    src: Arc<Store>,
+    dst: Arc<Store>,
+    state: CopyState,
+    src_layout: Arc<Layout>,
+    dst_layout: Layout,
+    /// The latest block of the source at the time of the last snapshot
+    /// we copied from. Only blocks up to `block - REORG_THRESHOLD` are
+    /// guaranteed to be final in the copy
+    block: BlockNumber,
+}
+
+impl DeploymentCopy {
+    /// Record the copy of `id` from `src_shard` to `dst_shard` in the
+    /// `primary` and create the tables for it in a new database schema in
+    /// `dst`. The tables are empty until `copy_data` is called
+    pub fn new(
+        logger: &Logger,
+        id: &SubgraphDeploymentId,
+        primary: Arc<Store>,
+        src_shard: &str,
+        src: Arc<Store>,
+        dst_shard: &str,
+        dst: Arc<Store>,
+    ) -> Result<Self, StoreError> {
+        let src_layout = src.layout(id)?;
+        let (schema, imports) = {
+            let conn = src.get_conn()?;
+            let schema = metadata::subgraph_schema(&conn, id.clone())?;
+            let imports = metadata::imported_schemas(&conn, &schema)?;
+            (schema, imports)
+        };
+
+        let conn = dst.get_conn()?;
+        let namespace = e::allocate_schema_name(&conn)?;
+        let catalog = src_layout.catalog.for_copy(namespace.clone())?;
+        let dst_layout = Layout::new(
+            &schema,
+            catalog,
+            &imports,
+            src_layout.tables.contains_key(POI_OBJECT),
+        )?;
+        let ddl = dst_layout
+            .as_ddl()
+            .map_err(|_| StoreError::Unknown(format_err!("failed to generate DDL for layout")))?;
+
+        // Record the copy before creating anything so that whatever we
+        // create can always be found and removed again
+        let state = CopyState {
+            subgraph: id.to_string(),
+            src_shard: src_shard.to_owned(),
+            src_namespace: src_layout.catalog.schema.clone(),
+            dst_shard: dst_shard.to_owned(),
+            dst_namespace: namespace.clone(),
+            switched: false,
+        };
+        e::start_copy(&*primary.get_conn()?, &state)?;
+
+        let res = conn.transaction(|| -> Result<(), StoreError> {
+            conn.batch_execute(&format!("create schema {}", namespace))?;
+            conn.batch_execute(&ddl)?;
+            Ok(())
+        });
+        if let Err(e) = res {
+            remove_copy(&primary, &dst, id, &state)?;
+            return Err(e);
+        }
+        info!(logger, "Created tables for copy";
+              "subgraph" => id.as_str(), "namespace" => namespace);
+
+        Ok(DeploymentCopy {
+            logger: logger.clone(),
+            id: id.clone(),
+            primary,
+            src,
+            dst,
+            state,
+            src_layout,
+            dst_layout,
+            block: 0,
+        })
+    }
+
+    /// The name of the database schema that holds the copy
+    pub fn namespace(&self) -> &str {
+        &self.dst_layout.catalog.schema
+    }
+
+    fn same_db(&self) -> bool {
+        Arc::ptr_eq(&self.src, &self.dst)
+    }
+
+    /// Pairs of source and destination tables
+    fn tables(&self) -> impl Iterator<Item = (&Arc<Table>, &Arc<Table>)> {
+        let dst = &self.dst_layout;
+        self.src_layout
+            .tables
+            .iter()
+            .filter_map(move |(object, src)| dst.tables.get(object).map(|dst| (src, dst)))
+    }
+
+    /// Copy all rows that exist in the source at the time this is called.
+    /// The source keeps changing while we copy, and the result is not a
+    /// consistent picture of any one block until the copy has caught up
+    pub fn copy_data(&mut self) -> Result<(), StoreError> {
+        let src_conn = self.src.get_conn()?;
+        let dst_conn = self.dst.get_conn()?;
+
+        let start = Instant::now();
+        let (block, max_vids) = src_conn
+            .build_transaction()
+            .repeatable_read()
+            .read_only()
+            .run(|| -> Result<_, StoreError> {
+                let block = metadata::latest_block_number(&src_conn, &self.id)?;
+                let max_vids = self
+                    .tables()
+                    .map(|(src, _)| max_vid(&src_conn, src))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((block, max_vids))
+            })?;
+
+        for ((src, dst), max_vid) in self.tables().zip(max_vids) {
+            let table_start = Instant::now();
+            let count = copy_rows(
+                &src_conn,
+                src,
+                &dst_conn,
+                dst,
+                CopyRows::UpTo(max_vid),
+                true,
+            )?;
+            info!(self.logger, "Copied {} {} entities", count, src.object;
+                  "time_ms" => table_start.elapsed().as_millis());
+        }
+        self.block = block;
+        info!(self.logger, "Finished copying data";
+              "subgraph" => self.id.as_str(),
+              "block" => block,
+              "time_ms" => start.elapsed().as_millis());
+        Ok(())
+    }
+
+    /// Bring the copy up to date with the source at the block the source
+    /// is at in the snapshot `src_conn` sees. The copy is reverted to a
+    /// block that is final in both, and everything that changed in the
+    /// source after that block is copied again. Return the block that the
+    /// copy is now at
+    fn apply_changes(
+        &self,
+        src_conn: &PgConnection,
+        dst_conn: &PgConnection,
+    ) -> Result<BlockNumber, StoreError> {
+        let base = (self.block - *REORG_THRESHOLD).max(0);
+        let block = metadata::latest_block_number(src_conn, &self.id)?;
+
+        // `revert_block` removes everything at and after the block passed
+        // to it; we want to keep `base`
+        self.dst_layout.revert_block(dst_conn, base + 1)?;
+        let mut count = 0;
+        for (src, dst) in self.tables() {
+            count += copy_rows(
+                src_conn,
+                src,
+                dst_conn,
+                dst,
+                CopyRows::ChangedAfter(base),
+                true,
+            )?;
+        }
+        info!(self.logger, "Caught up with source";
+              "subgraph" => self.id.as_str(),
+              "from" => base,
+              "to" => block,
+              "versions" => count);
+        Ok(block)
+    }
+
+    /// Repeatedly catch up with the source until the copy is less than
+    /// `FINAL_LAG` blocks behind it
+    pub fn catch_up(&mut self) -> Result<(), StoreError> {
+        let src_conn = self.src.get_conn()?;
+        let dst_conn = self.dst.get_conn()?;
+
+        for _ in 0..MAX_CATCH_UP_ROUNDS {
+            let start = Instant::now();
+            let block = src_conn
+                .build_transaction()
+                .repeatable_read()
+                .read_only()
+                .run(|| dst_conn.transaction(|| self.apply_changes(&src_conn, &dst_conn)))?;
+            let lag = block - self.block;
+            self.block = block;
+            info!(self.logger, "Finished round of catching up";
+                  "subgraph" => self.id.as_str(),
+                  "lag" => lag,
+                  "time_ms" => start.elapsed().as_millis());
+            if lag <= FINAL_LAG {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the metadata for the deployment from the source to the
+    /// destination. Rows are selected in the same way in which
+    /// `remove_metadata` selects them
+    fn copy_metadata(
+        &self,
+        src_conn: &PgConnection,
+        dst_conn: &PgConnection,
+    ) -> Result<(), StoreError> {
+        let src_meta = self.src.layout(&*SUBGRAPHS_ID)?;
+        let dst_meta = self.dst.layout(&*SUBGRAPHS_ID)?;
+
+        let mut prefixes = metadata::dynamic_data_source_ids(src_conn, &self.id)?;
+        prefixes.push(self.id.to_string());
+        for (object, src) in src_meta
+            .tables
+            .iter()
+            .filter(|(object, _)| !PRIMARY_METADATA.contains(&object.as_str()))
+        {
+            let dst = dst_meta.table_for_entity(object)?;
+            let rows = if object == SUBGRAPH_ERROR {
+                CopyRows::Matching(SUBGRAPH_ERROR_DEPLOYMENT_COLUMN, self.id.as_str())
+            } else {
+                CopyRows::WithPrefix(&prefixes)
+            };
+            copy_rows(src_conn, src, dst_conn, dst, rows, false)?;
+        }
+        Ok(())
+    }
+
+    /// Set the `vid` sequences of the copied tables past the largest `vid`
+    /// we copied so that new versions do not collide with copied ones
+    fn reset_vid_sequences(&self, conn: &PgConnection) -> Result<(), StoreError> {
+        for table in self.dst_layout.tables.values() {
+            let query = format!(
+                "select setval(pg_get_serial_sequence('{table}', '{vid}'), \
+                                coalesce(max({vid}), 1), max({vid}) is not null) \
+                   from {table}",
+                table = table.qualified_name.as_str(),
+                vid = VID_COLUMN
+            );
+            conn.batch_execute(&query)?;
+        }
+        Ok(())
+    }
+
+    /// Wait until the indexer of the paused deployment has recorded in the
+    /// primary that it stopped, and return the block at which the source
+    /// stopped
+    pub async fn wait_for_stop(&self) -> Result<BlockNumber, StoreError> {
+        let start = Instant::now();
+        while self.primary.is_running(&self.id)? {
+            if start.elapsed() > MAX_STOP_WAIT {
+                return Err(StoreError::Unknown(format_err!(
+                    "deployment {} kept indexing for {}s after it was paused",
+                    self.id,
+                    MAX_STOP_WAIT.as_secs()
+                )));
+            }
+            tokio::time::delay_for(STOP_POLL_INTERVAL).await;
+        }
+        let conn = self.src.get_conn()?;
+        metadata::latest_block_number(&conn, &self.id)
+    }
+
+    /// Stop the source from advancing, copy the last changes and make the
+    /// copy the storage for the deployment. The source must have stopped
+    /// indexing at `stopped_at`. When the source is in a different
+    /// database than the copy, the metadata of the deployment is moved
+    /// into the copy's database, too. In either case, the old data is
+    /// deleted.
+    ///
+    /// The deployment is switched over to the copy when the primary
+    /// records the switch. If anything fails before that, the copy is
+    /// removed; if anything fails after it, `recover` finishes the switch
+    pub fn finish(self, stopped_at: BlockNumber) -> Result<(), StoreError> {
+        let start = Instant::now();
+        let src_conn = self.src.get_conn()?;
+        // Set once the primary records that the deployment uses the copy
+        let switched = Cell::new(false);
+
+        let res = src_conn.transaction(|| -> Result<(), StoreError> {
+            // Lock the tables before the deployment row; writers change
+            // them in that order, too
+            let tables = self
+                .src_layout
+                .tables
+                .values()
+                .map(|table| table.qualified_name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            src_conn.batch_execute(&format!(
+                "lock table {} in share row exclusive mode",
+                tables
+            ))?;
+            metadata::lock_deployment(&src_conn, &self.id)?;
+
+            let block = metadata::latest_block_number(&src_conn, &self.id)?;
+            if block != stopped_at {
+                return Err(StoreError::Unknown(format_err!(
+                    "deployment {} advanced from block {} to {} while we were \
+                     switching it to its copy",
+                    self.id,
+                    stopped_at,
+                    block
+                )));
+            }
+
+            // Nothing uses the copy yet, and it does not matter if these
+            // changes are committed even though we fail later
+            let dst_conn = self.dst.get_conn()?;
+            dst_conn.transaction(|| -> Result<(), StoreError> {
+                self.apply_changes(&src_conn, &dst_conn)?;
+                self.reset_vid_sequences(&dst_conn)?;
+                if !self.same_db() {
+                    self.copy_metadata(&src_conn, &dst_conn)?;
+                }
+                Ok(())
+            })?;
+
+            let primary_conn = self.primary.get_conn()?;
+            primary_conn.transaction(|| -> Result<(), StoreError> {
+                e::set_copy_switched(&primary_conn, &self.state.subgraph)?;
+                if self.state.dst_shard == PRIMARY_SHARD {
+                    e::switch_schema(&primary_conn, &self.id, self.namespace(), PRIMARY_SHARD)?;
+                    e::send_schema_change(&primary_conn, &self.id)?;
+                } else if !self.same_db() {
+                    e::forget_shard(&primary_conn, &self.id)?;
+                    e::record_shard(&primary_conn, &self.id, &self.state.dst_shard)?;
+                }
+                Ok(())
+            })?;
+            switched.set(true);
+
+            activate_copy(&dst_conn, &self.id, &self.state)?;
+            remove_source(&self.src, &src_conn, &self.id, &self.state)
+        });
+
+        match res {
+            Ok(()) => {
+                e::forget_copy(&*self.primary.get_conn()?, &self.state.subgraph)?;
+                info!(self.logger, "Switched deployment to copy";
+                      "subgraph" => self.id.as_str(),
+                      "namespace" => self.namespace(),
+                      "shard" => &self.state.dst_shard,
+                      "time_ms" => start.elapsed().as_millis());
+                Ok(())
+            }
+            Err(e) if switched.get() => Err(StoreError::Unknown(format_err!(
+                "deployment {} now uses its copy in shard {}, but removing the old \
+                 data from shard {} failed; copying the deployment again finishes \
+                 removing it: {}",
+                self.id,
+                self.state.dst_shard,
+                self.state.src_shard,
+                e
+            ))),
+            Err(e) => {
+                self.abandon()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Remove the copy after something went wrong
+    pub fn abandon(&self) -> Result<(), StoreError> {
+        remove_copy(&self.primary, &self.dst, &self.id, &self.state)
+    }
+}
+
+/// Delete the metadata for `id` that `DeploymentCopy::copy_metadata`
+/// copies from the database of `store`
+fn remove_metadata(
+    store: &Store,
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<(), StoreError> {
+    let meta = store.layout(&*SUBGRAPHS_ID)?;
+
+    let mut prefixes = metadata::dynamic_data_source_ids(conn, id)?;
+    prefixes.push(id.to_string());
+    for (object, table) in meta
+        .tables
+        .iter()
+        .filter(|(object, _)| !PRIMARY_METADATA.contains(&object.as_str()))
+    {
+        let rows = if object == SUBGRAPH_ERROR {
+            CopyRows::Matching(SUBGRAPH_ERROR_DEPLOYMENT_COLUMN, id.as_str())
+        } else {
+            CopyRows::WithPrefix(&prefixes)
+        };
+        DeleteRowsQuery::new(table, rows).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Return `true` if the database behind `conn`, which is the database of
+/// `shard`, stores the data for `id` in the database schema `namespace`
+fn uses_namespace(
+    conn: &PgConnection,
+    shard: &str,
+    id: &SubgraphDeploymentId,
+    namespace: &str,
+) -> Result<bool, StoreError> {
+    Ok(e::find_location(conn, id)?
+        .map(|(location, name)| location == shard && name == namespace)
+        .unwrap_or(false))
+}
+
+/// Return `true` if the primary, which `conn` is connected to, sends
+/// everybody looking for `id` to the copy described by `state`
+fn primary_uses_copy(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    state: &CopyState,
+) -> Result<bool, StoreError> {
+    if state.switched {
+        return Ok(true);
+    }
+    if state.dst_shard == PRIMARY_SHARD {
+        return uses_namespace(conn, PRIMARY_SHARD, id, &state.dst_namespace);
+    }
+    // If the copy is in the same shard as the source, the shard decides
+    // which of the two is used
+    Ok(state.src_shard != state.dst_shard
+        && e::find_shard(conn, id)?.as_deref() == Some(state.dst_shard.as_str()))
+}
+
+/// Make the copy described by `state` the storage for `id` in the copy's
+/// shard; `conn` must be connected to that shard. A copy in the primary
+/// was already made the storage when the primary recorded the switch.
+/// This can be repeated any number of times
+fn activate_copy(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    state: &CopyState,
+) -> Result<(), StoreError> {
+    if state.dst_shard == PRIMARY_SHARD {
+        return Ok(());
+    }
+    conn.transaction(|| -> Result<(), StoreError> {
+        e::switch_schema(conn, id, &state.dst_namespace, &state.dst_shard)?;
+        e::send_schema_change(conn, id)
+    })
+}
+
+/// Delete the data, and the metadata if it moved with the copy, that the
+/// source of the copy described by `state` left behind once `id` uses the
+/// copy; `conn` must be connected to the database of `src`. This can be
+/// repeated any number of times
+fn remove_source(
+    src: &Store,
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    state: &CopyState,
+) -> Result<(), StoreError> {
+    if state.src_shard != state.dst_shard {
+        remove_metadata(src, conn, id)?;
+        if state.src_shard != PRIMARY_SHARD {
+            e::forget_shard(conn, id)?;
+        }
+    }
+    if uses_namespace(conn, &state.src_shard, id, &state.src_namespace)? {
+        return Err(StoreError::ConstraintViolation(format!(
+            "refusing to delete {} since deployment {} still uses it in shard {}",
+            state.src_namespace, id, state.src_shard
+        )));
+    }
+    e::drop_namespace(conn, &state.src_namespace)
+}
+
+/// Remove the copy described by `state` since `id` was never switched
+/// over to it. The database schema of the copy is only dropped if neither
+/// the primary nor the copy's shard use it. This can be repeated any
+/// number of times
+fn remove_copy(
+    primary: &Store,
+    dst: &Store,
+    id: &SubgraphDeploymentId,
+    state: &CopyState,
+) -> Result<(), StoreError> {
+    let primary_conn = primary.get_conn()?;
+    let dst_conn = dst.get_conn()?;
+    if primary_uses_copy(&primary_conn, id, state)?
+        || uses_namespace(&dst_conn, &state.dst_shard, id, &state.dst_namespace)?
+    {
+        return Err(StoreError::ConstraintViolation(format!(
+            "refusing to remove the copy {} of deployment {} in shard {} since the \
+             deployment uses it",
+            state.dst_namespace, id, state.dst_shard
+        )));
+    }
+    dst_conn.transaction(|| -> Result<(), StoreError> {
+        if state.src_shard != state.dst_shard {
+            remove_metadata(dst, &dst_conn, id)?;
+        }
+        e::drop_namespace(&dst_conn, &state.dst_namespace)
+    })?;
+    e::forget_copy(&primary_conn, &state.subgraph)
+}
+
+/// Bring the copy described by `state`, which was interrupted, to an end.
+/// If the primary recorded that `id` uses the copy, the switch to it is
+/// finished and the old data removed. Otherwise, the copy is removed
+pub(crate) fn recover(
+    logger: &Logger,
+    primary: &Store,
+    src: &Store,
+    dst: &Store,
+    id: &SubgraphDeploymentId,
+    state: &CopyState,
+) -> Result<(), StoreError> {
+    if state.switched {
+        activate_copy(&*dst.get_conn()?, id, state)?;
+        let src_conn = src.get_conn()?;
+        src_conn.transaction(|| remove_source(src, &src_conn, id, state))?;
+        e::forget_copy(&*primary.get_conn()?, &state.subgraph)?;
+        info!(logger, "Finished switching to interrupted copy";
+              "subgraph" => id.as_str(),
+              "namespace" => &state.dst_namespace,
+              "shard" => &state.dst_shard);
+    } else {
+        remove_copy(primary, dst, id, state)?;
+        info!(logger, "Removed interrupted copy";
+              "subgraph" => id.as_str(),
+              "namespace" => &state.dst_namespace,
+              "shard" => &state.dst_shard);
+    }
+    Ok(())
+}
@@ -13,9 +13,9 @@ use graph::{
     prelude::web3::types::H256,
 };
 use std::ops::Bound;
-use std::{convert::TryFrom, str::FromStr};
+use std::{collections::HashMap, convert::TryFrom, str::FromStr};
 
-use crate::metadata::{subgraph, subgraph_version};
+use crate::metadata::{subgraph, subgraph_deployment_assignment, subgraph_version};
 
 // This is not a real table, only a view. We can use diesel to read from it
 // but write attempts will fail
@@ -34,6 +34,10 @@ table! {
         latest_ethereum_block_hash -> Nullable<Binary>,
         latest_ethereum_block_number -> Nullable<Numeric>,
         entity_count -> Numeric,
+        retry_count -> Integer,
+        next_retry_at -> Nullable<Numeric>,
+        history_blocks -> Nullable<Integer>,
+        history_horizon -> Integer,
         graft_base -> Nullable<Text>,
         graft_block_hash -> Nullable<Binary>,
         graft_block_number -> Nullable<Numeric>,
@@ -41,6 +45,7 @@ table! {
         ethereum_head_block_number -> Nullable<Numeric>,
         network -> Text,
         node_id -> Nullable<Text>,
+        paused -> Nullable<Bool>,
         // We don't map block_range
         // block_range -> Range<Integer>,
     }
@@ -71,6 +76,10 @@ struct Detail {
     latest_ethereum_block_hash: Option<Bytes>,
     latest_ethereum_block_number: Option<BigDecimal>,
     entity_count: BigDecimal,
+    retry_count: i32,
+    next_retry_at: Option<BigDecimal>,
+    history_blocks: Option<i32>,
+    history_horizon: i32,
     graft_base: Option<String>,
     graft_block_hash: Option<Bytes>,
     graft_block_number: Option<BigDecimal>,
@@ -78,6 +87,7 @@ struct Detail {
     ethereum_head_block_number: Option<BigDecimal>,
     network: String,
     node_id: Option<String>,
+    paused: Option<bool>,
 }
 
 #[derive(Queryable, QueryableByName)]
@@ -97,7 +107,8 @@ struct ErrorDetail {
     block_range: (Bound<i32>, Bound<i32>),
 }
 
-struct DetailAndError(Detail, Option<ErrorDetail>);
+/// A deployment together with its fatal error and its non-fatal errors
+struct DetailAndError(Detail, Option<ErrorDetail>, Vec<ErrorDetail>);
 
 fn block(
     id: &str,
@@ -143,7 +154,7 @@ impl TryFrom<ErrorDetail> for SubgraphError {
         } = value;
         let block_ptr = block(
             subgraph_id.as_deref().unwrap_or("unknown"),
-            "fatal_error",
+            "subgraph_error",
             block_hash,
             block_number,
         )?
@@ -153,12 +164,12 @@ impl TryFrom<ErrorDetail> for SubgraphError {
             .transpose()
             .map_err(|id| {
                 StoreError::ConstraintViolation(format!(
-                    "invalid subgraph id `{}` in fatal error",
+                    "invalid subgraph id `{}` in subgraph error",
                     id
                 ))
             })?
             .ok_or_else(|| {
-                StoreError::ConstraintViolation(format!("missing subgraph id for fatal error"))
+                StoreError::ConstraintViolation(format!("missing subgraph id for subgraph error"))
             })?;
         Ok(SubgraphError {
             subgraph_id,
@@ -174,7 +185,7 @@ impl TryFrom<DetailAndError> for status::Info {
     type Error = StoreError;
 
     fn try_from(detail_and_error: DetailAndError) -> Result<Self, Self::Error> {
-        let DetailAndError(detail, error) = detail_and_error;
+        let DetailAndError(detail, error, non_fatal_errors) = detail_and_error;
 
         let Detail {
             vid: _,
@@ -190,6 +201,10 @@ impl TryFrom<DetailAndError> for status::Info {
             latest_ethereum_block_hash,
             latest_ethereum_block_number,
             entity_count,
+            retry_count,
+            next_retry_at,
+            history_blocks,
+            history_horizon,
             graft_base: _,
             graft_block_hash: _,
             graft_block_number: _,
@@ -197,6 +212,7 @@ impl TryFrom<DetailAndError> for status::Info {
             ethereum_head_block_number,
             network,
             node_id,
+            paused,
         } = detail;
 
         let chain_head_block = block(
@@ -230,16 +246,35 @@ impl TryFrom<DetailAndError> for status::Info {
                 id
             ))
         })?;
+        let next_retry_at = next_retry_at
+            .map(|at| {
+                at.to_u64().ok_or_else(|| {
+                    StoreError::ConstraintViolation(format!(
+                        "the nextRetryAt for {} is not representable as a u64",
+                        id
+                    ))
+                })
+            })
+            .transpose()?;
         let fatal_error = error.map(|e| SubgraphError::try_from(e)).transpose()?;
+        let non_fatal_errors = non_fatal_errors
+            .into_iter()
+            .map(SubgraphError::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
         Ok(status::Info {
             subgraph: id,
             synced,
             health,
             fatal_error,
-            non_fatal_errors: vec![],
+            non_fatal_errors,
             chains: vec![chain],
             entity_count,
             node: node_id,
+            paused: paused.unwrap_or(false),
+            retry_count,
+            next_retry_at,
+            history_blocks,
+            history_horizon,
         })
     }
 }
@@ -267,22 +302,31 @@ pub(crate) fn deployment_statuses(
     use subgraph_error as e;
 
     // Empty deployments means 'all of them'
-    if deployments.is_empty() {
+    let details = if deployments.is_empty() {
         d::table
             .left_outer_join(e::table.on(d::fatal_error.eq(e::id.nullable())))
             .load::<(Detail, Option<ErrorDetail>)>(conn)?
-            .into_iter()
-            .map(|(detail, error)| status::Info::try_from(DetailAndError(detail, error)))
-            .collect()
     } else {
         d::table
             .left_outer_join(e::table.on(d::fatal_error.eq(e::id.nullable())))
             .filter(d::id.eq_any(&deployments))
             .load::<(Detail, Option<ErrorDetail>)>(conn)?
-            .into_iter()
-            .map(|(detail, error)| status::Info::try_from(DetailAndError(detail, error)))
-            .collect()
-    }
+    };
+
+    details
+        .into_iter()
+        .map(|(detail, error)| {
+            let non_fatal_errors = if detail.non_fatal_errors.is_empty() {
+                vec![]
+            } else {
+                e::table
+                    .filter(e::id.eq_any(&detail.non_fatal_errors))
+                    .order_by(e::vid)
+                    .load::<ErrorDetail>(conn)?
+            };
+            status::Info::try_from(DetailAndError(detail, error, non_fatal_errors))
+        })
+        .collect()
 }
 
 pub fn subgraph_version(
@@ -308,3 +352,47 @@ pub fn subgraph_version(
     };
     Ok(deployment.optional()?.flatten())
 }
+
+/// Only the primary keeps track of deployment assignments and chain heads.
+/// For deployments whose status was read from another shard, fill that
+/// information in from `conn`, which must be a connection to the primary
+pub(crate) fn fill_from_primary(
+    conn: &PgConnection,
+    infos: &mut [status::Info],
+) -> Result<(), StoreError> {
+    use crate::db_schema::ethereum_networks as n;
+    use subgraph_deployment_assignment as a;
+
+    let ids: Vec<_> = infos.iter().map(|info| info.subgraph.as_str()).collect();
+    let assignments: HashMap<String, (String, bool)> = a::table
+        .filter(a::id.eq_any(&ids))
+        .select((a::id, a::node_id, a::paused))
+        .load::<(String, String, bool)>(conn)?
+        .into_iter()
+        .map(|(id, node, paused)| (id, (node, paused)))
+        .collect();
+    let heads: HashMap<String, (Option<String>, Option<i64>)> = n::table
+        .select((n::name, n::head_block_hash, n::head_block_number))
+        .load::<(String, Option<String>, Option<i64>)>(conn)?
+        .into_iter()
+        .map(|(name, hash, number)| (name, (hash, number)))
+        .collect();
+
+    for info in infos.iter_mut() {
+        let assignment = assignments.get(&info.subgraph);
+        info.node = assignment.map(|(node, _)| node.clone());
+        info.paused = assignment.map(|(_, paused)| *paused).unwrap_or(false);
+        for chain in info.chains.iter_mut() {
+            if let Some((Some(hash), Some(number))) = heads.get(&chain.network) {
+                let hash = H256::from_str(hash.trim_start_matches("0x")).map_err(|e| {
+                    StoreError::ConstraintViolation(format!(
+                        "invalid head block hash `{}` for network {}: {}",
+                        hash, chain.network, e
+                    ))
+                })?;
+                chain.chain_head_block = Some(status::EthereumBlock::new(hash, *number as u64));
+            }
+        }
+    }
+    Ok(())
+}
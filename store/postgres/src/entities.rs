@@ -36,18 +36,20 @@ use std::ops::Deref as _;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
+use graph::components::store::{Aggregate, EntityDeletion, EntityVersion};
 use graph::data::schema::Schema as SubgraphSchema;
 use graph::data::subgraph::schema::{POI_OBJECT, POI_TABLE, SUBGRAPHS_ID};
 use graph::prelude::{
-    debug, format_err, info, serde_json, warn, BlockNumber, Entity, EntityCollection, EntityFilter,
-    EntityKey, EntityOrder, EntityRange, Error, EthereumBlockPointer, Logger, QueryExecutionError,
-    StoreError, StoreEvent, SubgraphDeploymentId, BLOCK_NUMBER_MAX,
+    debug, format_err, info, serde_json, warn, BlockNumber, Entity, EntityChange,
+    EntityChangeOperation, EntityCollection, EntityFilter, EntityKey, EntityModification,
+    EntityOrder, EntityRange, Error, EthereumBlockPointer, Logger, QueryExecutionError, StoreError,
+    StoreEvent, SubgraphDeploymentId, Value, BLOCK_NUMBER_MAX,
 };
 
 use crate::block_range::block_number;
 use crate::metadata;
 use crate::notification_listener::JsonNotification;
-use crate::relational::{Catalog, Layout};
+use crate::relational::{Catalog, Layout, SqlName};
 
 #[cfg(debug_assertions)]
 lazy_static! {
@@ -131,11 +133,48 @@ mod public {
             migrating -> Bool,
             /// See comment on DeploymentSchemaState
             state -> crate::entities::public::DeploymentSchemaStateMapping,
+            /// The name of the shard that holds the data for this
+            /// deployment
+            shard -> Text,
+        }
+    }
+
+    table! {
+        running_deployments(subgraph) {
+            subgraph -> Text,
+            /// How many indexers are running for the deployment. This is
+            /// only ever more than one while an indexer that was stopped
+            /// is still shutting down and a new one has already started
+            indexers -> Integer,
+        }
+    }
+
+    table! {
+        deployment_copies(subgraph) {
+            subgraph -> Text,
+            src_shard -> Text,
+            src_namespace -> Text,
+            dst_shard -> Text,
+            dst_namespace -> Text,
+            /// Set once the deployment uses the copy
+            switched -> Bool,
         }
     }
 }
 
-use public::deployment_schemas;
+use public::{deployment_copies, deployment_schemas, running_deployments};
+
+/// A copy of a deployment that is in progress. See `crate::copy` for how
+/// copying works
+#[derive(Queryable, Clone, Debug)]
+pub(crate) struct CopyState {
+    pub subgraph: String,
+    pub src_shard: String,
+    pub src_namespace: String,
+    pub dst_shard: String,
+    pub dst_namespace: String,
+    pub switched: bool,
+}
 
 /// Information about the database schema that stores the entities for a
 /// subgraph. The schemas are versioned by subgraph, which makes it possible
@@ -175,6 +214,8 @@ struct Schema {
     /// locks. When the data is in place, the migration updates `version` to
     /// the new version we migrated to, and sets the state to `Ready`
     state: public::DeploymentSchemaState,
+    /// The shard in which the data for the deployment is stored
+    shard: String,
 }
 
 /// A cache for storage objects as constructing them takes a bit of
@@ -282,6 +323,14 @@ impl Connection<'_> {
         self.storage.find(&self.conn, entity, id, block)
     }
 
+    /// Compute the changes to the subgraph's rollups caused by `mods`
+    pub(crate) fn rollup_modifications(
+        &self,
+        mods: &[EntityModification],
+    ) -> Result<Vec<EntityModification>, StoreError> {
+        crate::rollup::modifications(&self.conn, &self.storage, mods)
+    }
+
     /// Returns a sequence of `(type, entity)`.
     /// If the entity isn't present that means it wasn't found.
     pub(crate) fn find_many(
@@ -307,6 +356,40 @@ impl Connection<'_> {
         )
     }
 
+    pub(crate) fn aggregate(
+        &self,
+        entity_type: &str,
+        filter: Option<EntityFilter>,
+        aggregates: &[Aggregate],
+        block: BlockNumber,
+    ) -> Result<Vec<Value>, QueryExecutionError> {
+        self.storage
+            .aggregate(&self.conn, entity_type, filter, aggregates, block)
+    }
+
+    pub(crate) fn deletions(
+        &self,
+        since: BlockNumber,
+        block: BlockNumber,
+        first: u32,
+        after: Option<&EntityDeletion>,
+    ) -> Result<Vec<EntityDeletion>, QueryExecutionError> {
+        self.storage
+            .deletions(&self.conn, since, block, first, after)
+    }
+
+    pub(crate) fn history(
+        &self,
+        entity_type: &str,
+        id: &str,
+        from: BlockNumber,
+        to: BlockNumber,
+        first: u32,
+    ) -> Result<Vec<EntityVersion>, QueryExecutionError> {
+        self.storage
+            .history(&self.conn, entity_type, id, from, to, first)
+    }
+
     pub(crate) fn conflicting_entity(
         &self,
         entity_id: &String,
@@ -387,7 +470,12 @@ impl Connection<'_> {
             .number
             .try_into()
             .expect("block numbers fit into an i32");
+        self.revert_from(block)
+    }
 
+    /// Revert all changes to the subgraph and its metadata that were made
+    /// at `block` or later
+    pub(crate) fn revert_from(&self, block: BlockNumber) -> Result<(StoreEvent, i32), StoreError> {
         // Revert the block in the subgraph itself
         let (event, count) = self.storage.revert_block(&self.conn, block)?;
         // Revert the meta data changes that correspond to this subgraph.
@@ -398,9 +486,27 @@ impl Connection<'_> {
         let meta_event =
             self.metadata
                 .revert_metadata(&self.conn, &self.storage.subgraph, block)?;
+        metadata::revert_subgraph_errors(&self.conn, &self.storage.subgraph, block)?;
         Ok((event.extend(meta_event), count))
     }
 
+    /// Remove all entity versions that are not needed to answer queries at
+    /// `horizon` or later blocks, and record `horizon` as the new history
+    /// horizon of the subgraph. Return the number of versions removed
+    pub(crate) fn prune(&self, horizon: BlockNumber) -> Result<usize, StoreError> {
+        let count = self.storage.prune(&self.conn, horizon)?;
+        metadata::set_history_horizon(&self.conn, &self.storage.subgraph, horizon)?;
+        Ok(count)
+    }
+
+    /// Remove all metadata for `subgraph`
+    pub(crate) fn remove_metadata(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+    ) -> Result<StoreEvent, StoreError> {
+        self.metadata.remove_metadata(&self.conn, subgraph)
+    }
+
     pub(crate) fn update_entity_count(&self, count: i32) -> Result<(), StoreError> {
         if count == 0 {
             return Ok(());
@@ -615,7 +721,11 @@ impl Connection<'_> {
     /// It is an error if `deployment_schemas` already has an entry for this
     /// `subgraph_id`. Note that `self` must be a connection for the subgraph
     /// of subgraphs
-    pub(crate) fn create_schema(&self, schema: &SubgraphSchema) -> Result<(), StoreError> {
+    pub(crate) fn create_schema(
+        &self,
+        schema: &SubgraphSchema,
+        shard: &str,
+    ) -> Result<(), StoreError> {
         use self::public::DeploymentSchemaState as s;
         use self::public::DeploymentSchemaVersion as v;
 
@@ -640,6 +750,7 @@ impl Connection<'_> {
                 deployment_schemas::subgraph.eq(schema.id.to_string()),
                 deployment_schemas::version.eq(v::Relational),
                 deployment_schemas::state.eq(s::Init),
+                deployment_schemas::shard.eq(shard),
             ))
             .returning(deployment_schemas::name)
             .get_results(self.conn.deref())?;
@@ -652,7 +763,18 @@ impl Connection<'_> {
 
         let layout = Layout::create_relational_schema(&self.conn, schema, schema_name.to_owned())?;
         // See if we are grafting and check that the graft is permissible
-        if let Some((base, _)) = metadata::deployment_graft(&self.conn, &schema.id)? {
+        if let Some((base, block)) = metadata::deployment_graft(&self.conn, &schema.id)? {
+            let horizon = metadata::history_horizon(&self.conn, &base)?;
+            if (block.number as BlockNumber) < horizon {
+                return Err(StoreError::Unknown(format_err!(
+                    "The subgraph `{}` cannot be used as the graft base for `{}` \
+                     at block {} because its history before block {} has been pruned",
+                    &base,
+                    &schema.id,
+                    block.number,
+                    horizon
+                )));
+            }
             let base = &Connection::layout(&self.conn, &base)?;
             let errors = layout.can_copy_from(&base);
             if !errors.is_empty() {
@@ -695,7 +817,8 @@ impl Connection<'_> {
                 let subgraph_schema = metadata::subgraph_schema(conn, subgraph.to_owned())?;
                 let has_poi = supports_proof_of_indexing(conn, subgraph, &schema.name)?;
                 let catalog = Catalog::new(conn, schema.name)?;
-                Layout::new(&subgraph_schema, catalog, has_poi)?
+                let imports = metadata::imported_schemas(conn, &subgraph_schema)?;
+                Layout::new(&subgraph_schema, catalog, &imports, has_poi)?
             }
         };
         Ok(layout)
@@ -714,6 +837,159 @@ fn find_schema(
         .optional()?)
 }
 
+/// Return the name of the shard that holds the data for `subgraph`, or
+/// `None` if this database does not know about `subgraph`
+pub(crate) fn find_shard(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+) -> Result<Option<String>, StoreError> {
+    Ok(deployment_schemas::table
+        .select(deployment_schemas::shard)
+        .filter(deployment_schemas::subgraph.eq(subgraph.as_str()))
+        .first::<String>(conn)
+        .optional()?)
+}
+
+/// Return the shard and the name of the database schema that this
+/// database records for `subgraph`, or `None` if it does not know about
+/// `subgraph`
+pub(crate) fn find_location(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+) -> Result<Option<(String, String)>, StoreError> {
+    Ok(deployment_schemas::table
+        .select((deployment_schemas::shard, deployment_schemas::name))
+        .filter(deployment_schemas::subgraph.eq(subgraph.as_str()))
+        .first::<(String, String)>(conn)
+        .optional()?)
+}
+
+/// Reserve a name for a new database schema in this database that no
+/// deployment uses yet
+pub(crate) fn allocate_schema_name(conn: &PgConnection) -> Result<String, StoreError> {
+    #[derive(QueryableByName)]
+    struct Name {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    let name = diesel::sql_query("select 'sgd' || nextval('deployment_schemas_id_seq') as name")
+        .get_result::<Name>(conn)?
+        .name;
+    Ok(name)
+}
+
+/// Make the database schema `name` in this database the one that holds
+/// the data for `subgraph`, and record that this database is in `shard`.
+/// The entry for `subgraph` in `deployment_schemas` is created if it does
+/// not exist yet. The database schema that was used for `subgraph` before,
+/// if any, is left alone
+pub(crate) fn switch_schema(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    name: &str,
+    shard: &str,
+) -> Result<(), StoreError> {
+    use self::public::DeploymentSchemaState as s;
+    use self::public::DeploymentSchemaVersion as v;
+
+    let updated = diesel::update(
+        deployment_schemas::table.filter(deployment_schemas::subgraph.eq(subgraph.as_str())),
+    )
+    .set((
+        deployment_schemas::name.eq(name),
+        deployment_schemas::version.eq(v::Relational),
+        deployment_schemas::state.eq(s::Ready),
+        deployment_schemas::shard.eq(shard),
+    ))
+    .execute(conn)?;
+    if updated == 0 {
+        diesel::insert_into(deployment_schemas::table)
+            .values((
+                deployment_schemas::subgraph.eq(subgraph.as_str()),
+                deployment_schemas::name.eq(name),
+                deployment_schemas::version.eq(v::Relational),
+                deployment_schemas::state.eq(s::Ready),
+                deployment_schemas::shard.eq(shard),
+            ))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Change the shard that this database records for `subgraph` without
+/// touching any data
+pub(crate) fn set_shard(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    shard: &str,
+) -> Result<(), StoreError> {
+    diesel::update(
+        deployment_schemas::table.filter(deployment_schemas::subgraph.eq(subgraph.as_str())),
+    )
+    .set(deployment_schemas::shard.eq(shard))
+    .execute(conn)?;
+    Ok(())
+}
+
+/// The entity type of the store events that announce that the data for a
+/// deployment is now stored in a different database schema. There is no
+/// such entity; the events only tell other processes to forget the
+/// `Layout` they might have cached for the deployment
+pub(crate) const DEPLOYMENT_SCHEMA: &str = "DeploymentSchema";
+
+/// Announce that the data for `subgraph` moved to a different database
+/// schema. Listeners only see the announcement once the current
+/// transaction commits
+pub(crate) fn send_schema_change(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+) -> Result<(), StoreError> {
+    let event = StoreEvent::new(vec![EntityChange {
+        subgraph_id: SUBGRAPHS_ID.clone(),
+        entity_type: DEPLOYMENT_SCHEMA.to_owned(),
+        entity_id: subgraph.to_string(),
+        operation: EntityChangeOperation::Set,
+    }]);
+    let v = serde_json::to_value(&event)?;
+    JsonNotification::send("store_events", &v, conn)
+}
+
+/// Drop the database schema `name` and everything in it. The schema must
+/// not be in use by any deployment any longer
+pub(crate) fn drop_namespace(conn: &PgConnection, name: &str) -> Result<(), StoreError> {
+    SqlName::check_valid_identifier(name, "database schema")?;
+    let query = format!("drop schema if exists {} cascade", name);
+    conn.batch_execute(&*query)?;
+    Ok(())
+}
+
+/// Record in this database that the data for `subgraph` is stored in
+/// `shard`. This is used in the primary to keep track of deployments that
+/// live in other shards. The entry does not correspond to a database schema
+/// in this database, and must never be used to construct a `Layout`
+pub(crate) fn record_shard(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    shard: &str,
+) -> Result<(), StoreError> {
+    use self::public::DeploymentSchemaState as s;
+    use self::public::DeploymentSchemaVersion as v;
+
+    if find_shard(conn, subgraph)?.is_some() {
+        return Ok(());
+    }
+    diesel::insert_into(deployment_schemas::table)
+        .values((
+            deployment_schemas::subgraph.eq(subgraph.as_str()),
+            deployment_schemas::version.eq(v::Relational),
+            deployment_schemas::state.eq(s::Ready),
+            deployment_schemas::shard.eq(shard),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
 fn supports_proof_of_indexing(
     conn: &diesel::pg::PgConnection,
     subgraph_id: &SubgraphDeploymentId,
@@ -736,6 +1012,117 @@ fn supports_proof_of_indexing(
     Ok(result.len() > 0)
 }
 
+/// Remove the record that the data for `subgraph` is stored in another
+/// shard. This is the counterpart to `record_shard`
+pub(crate) fn forget_shard(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+) -> Result<(), StoreError> {
+    diesel::delete(deployment_schemas::table)
+        .filter(deployment_schemas::subgraph.eq(subgraph.as_str()))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Record that the copy `state` is starting. It is an error if another
+/// copy of the same deployment is in progress
+pub(crate) fn start_copy(conn: &PgConnection, state: &CopyState) -> Result<(), StoreError> {
+    use self::deployment_copies as c;
+
+    if let Some(other) = find_copy(conn, &state.subgraph)? {
+        return Err(StoreError::ConstraintViolation(format!(
+            "deployment {} is already being copied to {} in shard {}",
+            other.subgraph, other.dst_namespace, other.dst_shard
+        )));
+    }
+    diesel::insert_into(c::table)
+        .values((
+            c::subgraph.eq(&state.subgraph),
+            c::src_shard.eq(&state.src_shard),
+            c::src_namespace.eq(&state.src_namespace),
+            c::dst_shard.eq(&state.dst_shard),
+            c::dst_namespace.eq(&state.dst_namespace),
+            c::switched.eq(state.switched),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Return the copy of `subgraph` that is in progress, if there is one
+pub(crate) fn find_copy(
+    conn: &PgConnection,
+    subgraph: &str,
+) -> Result<Option<CopyState>, StoreError> {
+    use self::deployment_copies as c;
+
+    Ok(c::table
+        .filter(c::subgraph.eq(subgraph))
+        .first::<CopyState>(conn)
+        .optional()?)
+}
+
+/// Record that `subgraph` now uses its copy
+pub(crate) fn set_copy_switched(conn: &PgConnection, subgraph: &str) -> Result<(), StoreError> {
+    use self::deployment_copies as c;
+
+    diesel::update(c::table.filter(c::subgraph.eq(subgraph)))
+        .set(c::switched.eq(true))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Remove the record of the copy of `subgraph` once it is complete or has
+/// been undone
+pub(crate) fn forget_copy(conn: &PgConnection, subgraph: &str) -> Result<(), StoreError> {
+    use self::deployment_copies as c;
+
+    diesel::delete(c::table.filter(c::subgraph.eq(subgraph))).execute(conn)?;
+    Ok(())
+}
+
+/// Record that an indexer for `subgraph` started or stopped running
+pub(crate) fn set_running(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    running: bool,
+) -> Result<(), StoreError> {
+    use self::running_deployments as r;
+
+    conn.transaction(|| -> Result<(), StoreError> {
+        if running {
+            diesel::insert_into(r::table)
+                .values((r::subgraph.eq(subgraph.as_str()), r::indexers.eq(1)))
+                .on_conflict(r::subgraph)
+                .do_update()
+                .set(r::indexers.eq(r::indexers + 1))
+                .execute(conn)?;
+        } else {
+            diesel::delete(r::table)
+                .filter(r::subgraph.eq(subgraph.as_str()))
+                .filter(r::indexers.le(1))
+                .execute(conn)?;
+            diesel::update(r::table.filter(r::subgraph.eq(subgraph.as_str())))
+                .set(r::indexers.eq(r::indexers - 1))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Return `true` if any indexer for `subgraph` is still running
+pub(crate) fn is_running(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+) -> Result<bool, StoreError> {
+    use self::running_deployments as r;
+
+    Ok(r::table
+        .filter(r::subgraph.eq(subgraph.as_str()))
+        .count()
+        .get_result::<i64>(conn)?
+        > 0)
+}
+
 /// Delete all entities. This function exists solely for integration tests
 /// and should never be called from any other code. Unfortunately, Rust makes
 /// it very hard to export items just for testing
@@ -782,8 +1169,7 @@ pub fn delete_all_entities_for_test_use_only(
 /// Drop the schema for `subgraph`. This deletes all data for the subgraph,
 /// and can not be reversed. It does not remove any of the metadata in
 /// `subgraphs.entities` associated with the subgraph
-#[cfg(debug_assertions)]
-fn drop_schema(
+pub(crate) fn drop_schema(
     conn: &diesel::pg::PgConnection,
     subgraph: &SubgraphDeploymentId,
 ) -> Result<usize, StoreError> {
@@ -0,0 +1,234 @@
+//! Export the entities of a deployment as of a given block to CSV files,
+//! one file per entity type. Alongside the data, we write a `schema.json`
+//! that describes the columns of each file in terms of GraphQL value
+//! types, so that consumers do not have to guess how to interpret them.
+//!
+//! Values are written so that they survive the trip through a text file
+//! without loss: `BigInt` and `BigDecimal` values are written as strings,
+//! `Bytes` as `0x`-prefixed hex strings, and lists as JSON arrays
+use diesel::pg::PgConnection;
+use diesel::RunQueryDsl;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use graph::data::subgraph::schema::POI_OBJECT;
+use graph::prelude::{
+    format_err, info, serde_json, BlockNumber, Entity, Logger, StoreError, Value,
+};
+
+use crate::relational::{Column, ColumnType, Layout, Table};
+use crate::relational_queries::{ExportData, ExportQuery};
+
+/// The number of entities we read with one query
+const BATCH_SIZE: i64 = 5_000;
+
+/// The name of the file describing the columns of all exported files
+pub const SCHEMA_FILE: &str = "schema.json";
+
+fn io_error(path: &Path, e: std::io::Error) -> StoreError {
+    StoreError::Unknown(format_err!("failed to write {}: {}", path.display(), e))
+}
+
+/// The name of the GraphQL value type of the values in `column`. Enums
+/// are exported as strings, and `Bytes` ids as the same hex strings as
+/// other `Bytes`
+fn value_type(column: &Column) -> &'static str {
+    match column.column_type {
+        ColumnType::Boolean => "Boolean",
+        ColumnType::BigDecimal => "BigDecimal",
+        ColumnType::BigInt => "BigInt",
+        ColumnType::Bytes | ColumnType::BytesId => "Bytes",
+        ColumnType::Int => "Int",
+        ColumnType::String | ColumnType::Enum(_) | ColumnType::TSVector(_) => "String",
+    }
+}
+
+/// The columns of `table` that we export. Fulltext columns are derived
+/// from other columns and left out
+fn exported_columns(table: &Table) -> impl Iterator<Item = &Column> {
+    table.columns.iter().filter(|column| !column.is_fulltext())
+}
+
+/// Describe the columns of the file for `table`
+fn table_schema(table: &Table) -> serde_json::Value {
+    let columns = exported_columns(table)
+        .map(|column| {
+            serde_json::json!({
+                "name": column.field,
+                "type": value_type(column),
+                "list": column.is_list(),
+                "nullable": column.is_nullable(),
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({ "file": file_name(table), "columns": columns })
+}
+
+fn file_name(table: &Table) -> String {
+    format!("{}.csv", table.object)
+}
+
+/// Turn `value` into JSON for use inside a list. Only numbers that
+/// always fit into a JSON number and booleans are not strings
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Int(i) => serde_json::Value::from(*i),
+        Value::Bool(b) => serde_json::Value::Bool(*b),
+        Value::Null => serde_json::Value::Null,
+        Value::List(values) => serde_json::Value::Array(values.iter().map(json_value).collect()),
+        value => serde_json::Value::String(text_value(value)),
+    }
+}
+
+/// The text for `value` in a CSV file before quoting
+fn text_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Int(i) => i.to_string(),
+        Value::BigDecimal(d) => d.to_string(),
+        Value::BigInt(i) => i.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Bytes(b) => b.to_string(),
+        Value::Null => String::new(),
+        Value::List(_) => json_value(value).to_string(),
+    }
+}
+
+/// Quote `field` for a CSV file if it contains characters that have a
+/// special meaning in CSV
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn csv_line<I: Iterator<Item = String>>(fields: I) -> String {
+    let mut line = fields
+        .map(|field| csv_field(&field))
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
+
+/// Write all entities in `table` that are visible at `block` to a CSV
+/// file in `dir` and return the number of entities written
+fn export_table(
+    conn: &PgConnection,
+    layout: &Layout,
+    table: &Table,
+    block: BlockNumber,
+    path: &Path,
+) -> Result<usize, StoreError> {
+    let file = File::create(path).map_err(|e| io_error(path, e))?;
+    let mut out = BufWriter::new(file);
+
+    let header = csv_line(exported_columns(table).map(|column| column.field.clone()));
+    out.write_all(header.as_bytes())
+        .map_err(|e| io_error(path, e))?;
+
+    let mut count = 0;
+    let mut last_vid = 0;
+    loop {
+        let batch: Vec<ExportData> =
+            ExportQuery::new(table, block, last_vid, BATCH_SIZE).load(conn)?;
+        last_vid = match batch.last() {
+            Some(data) => data.vid,
+            None => break,
+        };
+        let size = batch.len();
+        for data in batch {
+            let entity: Entity = data.entity.deserialize_with_layout(layout)?;
+            let line = csv_line(exported_columns(table).map(|column| {
+                entity
+                    .get(&column.field)
+                    .map(text_value)
+                    .unwrap_or_default()
+            }));
+            out.write_all(line.as_bytes())
+                .map_err(|e| io_error(path, e))?;
+        }
+        count += size;
+        if (size as i64) < BATCH_SIZE {
+            break;
+        }
+    }
+    out.flush().map_err(|e| io_error(path, e))?;
+    Ok(count)
+}
+
+/// Export all entities of the deployment with `layout` that are visible
+/// at `block` into CSV files in the directory `dir`, which must exist.
+/// Return the paths of all files that were written
+pub(crate) fn export(
+    logger: &Logger,
+    conn: &PgConnection,
+    layout: &Layout,
+    block: BlockNumber,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, StoreError> {
+    // Sort tables so that the output does not depend on hash order
+    let tables = layout
+        .tables
+        .values()
+        .filter(|table| table.object != POI_OBJECT)
+        .map(|table| (table.object.as_str(), table.as_ref()))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut paths = Vec::new();
+    for table in tables.values() {
+        let start = Instant::now();
+        let path = dir.join(file_name(table));
+        let count = export_table(conn, layout, table, block, &path)?;
+        info!(logger, "Exported {} {} entities", count, table.object;
+              "file" => path.display().to_string(),
+              "time_ms" => start.elapsed().as_millis());
+        paths.push(path);
+    }
+
+    let schema = serde_json::json!({
+        "subgraph": layout.subgraph.as_str(),
+        "block": block,
+        "entities": tables.values().map(|table| table_schema(table)).collect::<Vec<_>>(),
+    });
+    let path = dir.join(SCHEMA_FILE);
+    let text = serde_json::to_string_pretty(&schema)
+        .map_err(|e| StoreError::Unknown(format_err!("failed to format schema: {}", e)))?;
+    std::fs::write(&path, text).map_err(|e| io_error(&path, e))?;
+    paths.push(path);
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_line, text_value};
+    use graph::prelude::{BigInt, Value};
+
+    #[test]
+    fn csv_quoting() {
+        let line = csv_line(
+            vec!["plain", "with,comma", "with \"quote\"", "two\nlines", ""]
+                .into_iter()
+                .map(str::to_owned),
+        );
+        assert_eq!(
+            "plain,\"with,comma\",\"with \"\"quote\"\"\",\"two\nlines\",\r\n",
+            line
+        );
+    }
+
+    #[test]
+    fn text_values() {
+        let big = BigInt::from(u64::max_value()) * BigInt::from(10);
+        assert_eq!("184467440737095516150", text_value(&Value::BigInt(big)));
+
+        let list = Value::List(vec![Value::Int(1), Value::String("a".to_owned())]);
+        assert_eq!("[1,\"a\"]", text_value(&list));
+    }
+}
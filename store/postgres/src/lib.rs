@@ -25,9 +25,11 @@ mod catalog;
 mod chain_head_listener;
 mod chain_store;
 pub mod connection_pool;
+mod copy;
 mod db_schema;
 mod detail;
 mod entities;
+mod export;
 mod functions;
 mod jsonb;
 mod metadata;
@@ -36,6 +38,8 @@ mod notification_listener;
 pub mod query_store;
 pub mod relational;
 mod relational_queries;
+mod rollup;
+mod sharded_store;
 mod sql_value;
 pub mod store;
 mod store_events;
@@ -56,5 +60,6 @@ pub mod layout_for_tests {
 pub use self::chain_head_listener::ChainHeadUpdateListener;
 pub use self::chain_store::ChainStore;
 pub use self::network_store::NetworkStore;
+pub use self::sharded_store::{ShardedStore, PRIMARY_SHARD};
 pub use self::store::{Store, StoreConfig};
 pub use self::store_events::SubscriptionManager;
@@ -5,9 +5,11 @@ use diesel::prelude::{
     ExpressionMethods, JoinOnDsl, NullableExpressionMethods, OptionalExtension, QueryDsl,
     RunQueryDsl,
 };
-use diesel::sql_types::Text;
+use diesel::sql_types::{Bool, Text};
+use graph::data::schema::SchemaReference;
 use graph::data::subgraph::schema::{
-    generate_entity_id, SubgraphDeploymentAssignmentEntity, SubgraphManifestEntity, SUBGRAPHS_ID,
+    generate_entity_id, SubgraphDeploymentAssignmentEntity, SubgraphError, SubgraphManifestEntity,
+    SUBGRAPHS_ID,
 };
 use graph::prelude::{
     bigdecimal::ToPrimitive, entity, format_err, web3::types::H256, BigDecimal, BlockNumber,
@@ -15,10 +17,12 @@ use graph::prelude::{
     NodeId, Schema, StoreError, StoreEvent, SubgraphDeploymentEntity, SubgraphDeploymentId,
     SubgraphName, SubgraphVersionSwitchingMode, TypedEntity,
 };
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::block_range::UNVERSIONED_RANGE;
+use crate::block_range::{BlockRange, UNVERSIONED_RANGE};
 
 // Diesel tables for some of the metadata
 // See also: ed42d219c6704a4aab57ce1ea66698e7
@@ -77,6 +81,10 @@ table! {
         reorg_count -> Integer,
         current_reorg_depth -> Integer,
         max_reorg_depth -> Integer,
+        retry_count -> Integer,
+        next_retry_at -> Nullable<Numeric>,
+        history_blocks -> Nullable<Integer>,
+        history_horizon -> Integer,
         block_range -> Range<Integer>,
     }
 }
@@ -87,6 +95,7 @@ table! {
         id -> Text,
         node_id -> Text,
         cost -> Numeric,
+        paused -> Bool,
         block_range -> Range<Integer>,
     }
 }
@@ -209,6 +218,28 @@ pub fn subgraph_schema(
     res.map_err(|e| StoreError::Unknown(e))
 }
 
+/// Load the schemas of all deployments that `schema` imports from,
+/// directly or through other imports. Deployments that do not exist in
+/// this shard are left out
+pub fn imported_schemas(
+    conn: &PgConnection,
+    schema: &Schema,
+) -> Result<HashMap<SchemaReference, Arc<Schema>>, StoreError> {
+    let mut schemas = HashMap::new();
+    let mut pending = schema.imported_schemas();
+    while let Some(schema_ref) = pending.pop() {
+        if schemas.contains_key(&schema_ref)
+            || !deployment_exists(conn, schema_ref.subgraph().as_str())?
+        {
+            continue;
+        }
+        let imported = subgraph_schema(conn, schema_ref.subgraph().clone())?;
+        pending.extend(imported.imported_schemas());
+        schemas.insert(schema_ref, Arc::new(imported));
+    }
+    Ok(schemas)
+}
+
 pub fn subgraph_network(
     conn: &PgConnection,
     id: &SubgraphDeploymentId,
@@ -290,6 +321,30 @@ pub fn revert_block_ptr(
         .map_err(|e| e.into())
 }
 
+/// Set the block pointer of the deployment `id` back to `ptr` as part of
+/// rewinding the deployment. Unlike `revert_block_ptr`, this is not
+/// counted as a reorg
+pub fn rewind_block_ptr(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    ptr: EthereumBlockPointer,
+) -> Result<StoreEvent, StoreError> {
+    use subgraph_deployment as d;
+
+    // Work around a Diesel issue with serializing BigDecimals to numeric
+    let number = format!("{}::numeric", ptr.number);
+
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set((
+            d::latest_ethereum_block_number.eq(sql(&number)),
+            d::latest_ethereum_block_hash.eq(ptr.hash.as_bytes()),
+            d::current_reorg_depth.eq(0),
+        ))
+        .execute(conn)
+        .map(|_| block_ptr_store_event(id))
+        .map_err(|e| e.into())
+}
+
 fn convert_to_u32(number: Option<i32>, field: &str, subgraph: &str) -> Result<u32, StoreError> {
     number
         .ok_or_else(|| {
@@ -331,34 +386,20 @@ fn latest_as_block_number(
     }
 }
 
-pub fn deployment_state_from_name(
+/// Return the id of the deployment that is the current version of the
+/// subgraph `name`
+pub fn current_deployment_for_subgraph(
     conn: &PgConnection,
     name: SubgraphName,
-) -> Result<DeploymentState, StoreError> {
+) -> Result<SubgraphDeploymentId, StoreError> {
     use subgraph as s;
-    use subgraph_deployment as d;
     use subgraph_version as v;
 
     let mut rows = s::table
         .left_outer_join(v::table.on(s::current_version.eq(v::id.nullable())))
-        .left_outer_join(d::table.on(v::deployment.eq(d::id)))
         .filter(s::name.eq(name.as_str()))
-        .select((
-            s::id,
-            v::id.nullable(),
-            d::id.nullable(),
-            d::reorg_count.nullable(),
-            d::max_reorg_depth.nullable(),
-            d::latest_ethereum_block_number.nullable(),
-        ))
-        .load::<(
-            String,
-            Option<String>,
-            Option<String>,
-            Option<i32>,
-            Option<i32>,
-            Option<BigDecimal>,
-        )>(conn)?;
+        .select((s::id, v::id.nullable(), v::deployment.nullable()))
+        .load::<(String, Option<String>, Option<String>)>(conn)?;
     if rows.len() == 0 {
         Err(StoreError::QueryExecutionError(format!(
             "Subgraph `{}` not found",
@@ -370,8 +411,7 @@ pub fn deployment_state_from_name(
             name.as_str()
         )))
     } else {
-        let (_, vid, did, reorg_count, max_reorg_depth, latest_ethereum_block_number) =
-            rows.pop().unwrap();
+        let (_, vid, did) = rows.pop().unwrap();
         match (vid, did) {
             (None, _) => Err(StoreError::QueryExecutionError(format!(
                 "The subgraph `{}` has no current version. \
@@ -385,26 +425,13 @@ pub fn deployment_state_from_name(
                 vid,
                 name.as_str()
             ))),
-            (Some(_), Some(did)) => {
-                let id = SubgraphDeploymentId::new(did).map_err(|s| {
-                    StoreError::ConstraintViolation(format!(
-                        "Illegal deployment id `{}` for current version of `{}`",
-                        s,
-                        name.as_str()
-                    ))
-                })?;
-                let reorg_count = convert_to_u32(reorg_count, "reorg_count", name.as_str())?;
-                let max_reorg_depth =
-                    convert_to_u32(max_reorg_depth, "max_reorg_depth", name.as_str())?;
-                let latest_ethereum_block_number =
-                    latest_as_block_number(latest_ethereum_block_number, name.as_str())?;
-                Ok(DeploymentState {
-                    id,
-                    reorg_count,
-                    max_reorg_depth,
-                    latest_ethereum_block_number,
-                })
-            }
+            (Some(_), Some(did)) => SubgraphDeploymentId::new(did).map_err(|s| {
+                StoreError::ConstraintViolation(format!(
+                    "Illegal deployment id `{}` for current version of `{}`",
+                    s,
+                    name.as_str()
+                ))
+            }),
         }
     }
 }
@@ -422,15 +449,22 @@ pub fn deployment_state_from_id(
             d::reorg_count,
             d::max_reorg_depth,
             d::latest_ethereum_block_number,
+            sql::<Bool>("cardinality(non_fatal_errors) > 0"),
         ))
-        .first::<(String, i32, i32, Option<BigDecimal>)>(conn)
+        .first::<(String, i32, i32, Option<BigDecimal>, bool)>(conn)
         .optional()?
     {
         None => Err(StoreError::QueryExecutionError(format!(
             "No data found for subgraph {}",
             id
         ))),
-        Some((_, reorg_count, max_reorg_depth, latest_ethereum_block_number)) => {
+        Some((
+            _,
+            reorg_count,
+            max_reorg_depth,
+            latest_ethereum_block_number,
+            has_non_fatal_errors,
+        )) => {
             let reorg_count = convert_to_u32(Some(reorg_count), "reorg_count", id.as_str())?;
             let max_reorg_depth =
                 convert_to_u32(Some(max_reorg_depth), "max_reorg_depth", id.as_str())?;
@@ -442,6 +476,7 @@ pub fn deployment_state_from_id(
                 reorg_count,
                 max_reorg_depth,
                 latest_ethereum_block_number,
+                has_non_fatal_errors,
             })
         }
     }
@@ -477,16 +512,15 @@ fn remove_unused_assignments(conn: &PgConnection) -> Result<Vec<EntityChange>, S
         .collect::<Vec<_>>())
 }
 
-/// Mark the deployment `id` as synced, and promote it to the current version
-/// everywhere where it was the pending version so far, and remove any
-/// assignments that are not needed any longer as a result. Return the changes
-/// that were made to assignments in the process
-pub fn deployment_synced(
+/// Promote the deployment `id` to the current version everywhere where it
+/// was the pending version so far, and remove any assignments that are not
+/// needed any longer as a result. Return the changes that were made to
+/// assignments in the process
+pub fn promote_pending_version(
     conn: &PgConnection,
     id: &SubgraphDeploymentId,
 ) -> Result<Vec<EntityChange>, StoreError> {
     use subgraph as s;
-    use subgraph_deployment as d;
     use subgraph_version as v;
 
     // Subgraphs where we need to promote the version
@@ -509,11 +543,16 @@ pub fn deployment_synced(
 
     // Clean up assignments if we could possibly have changed any
     // subgraph versions
-    let changes = if pending_subgraph_versions.is_empty() {
-        vec![]
+    if pending_subgraph_versions.is_empty() {
+        Ok(vec![])
     } else {
-        remove_unused_assignments(conn)?
-    };
+        remove_unused_assignments(conn)
+    }
+}
+
+/// Mark the deployment `id` as synced
+pub fn set_synced(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
 
     // This seems to get called a lot by the block stream, even if the
     // deployment is already synced. Avoid allocating a txid if we are
@@ -525,8 +564,23 @@ pub fn deployment_synced(
     )
     .set(d::synced.eq(true))
     .execute(conn)?;
+    Ok(())
+}
 
-    Ok(changes)
+/// Returns `true` if the deployment `id` exists and is synced. A deployment
+/// that does not exist is treated the same as one that is not synced
+pub fn exists_and_synced(conn: &PgConnection, id: &str) -> Result<bool, StoreError> {
+    use subgraph_deployment as d;
+
+    // The `optional` only comes into play if data is corrupted/missing;
+    // ignoring that via `optional` makes it possible to fix a missing version
+    // or deployment by deploying over it.
+    Ok(d::table
+        .filter(d::id.eq(id))
+        .select(d::synced)
+        .first(conn)
+        .optional()?
+        .unwrap_or(false))
 }
 
 /// Returns `true` if the deployment `id` exists
@@ -574,15 +628,22 @@ pub fn create_subgraph(conn: &PgConnection, name: &SubgraphName) -> Result<Strin
     }
 }
 
-pub fn create_subgraph_version(
+/// Create a new version of the subgraph `name` that uses the deployment
+/// `id`. Since the deployment data might live in a different database than
+/// `conn`, the check whether the current version of the subgraph is synced
+/// is done with `exists_and_synced`
+pub fn create_subgraph_version<F>(
     conn: &PgConnection,
     name: SubgraphName,
     id: &SubgraphDeploymentId,
     node_id: NodeId,
     mode: SubgraphVersionSwitchingMode,
-) -> Result<Vec<EntityChange>, StoreError> {
+    exists_and_synced: F,
+) -> Result<Vec<EntityChange>, StoreError>
+where
+    F: Fn(&str) -> Result<bool, StoreError>,
+{
     use subgraph as s;
-    use subgraph_deployment as d;
     use subgraph_deployment_assignment as a;
     use subgraph_version as v;
     use SubgraphVersionSwitchingMode::*;
@@ -612,16 +673,8 @@ pub fn create_subgraph_version(
 
     // See if the current version of that subgraph is synced. If the subgraph
     // has no current version, we treat it the same as if it were not synced
-    // The `optional` below only comes into play if data is corrupted/missing;
-    // ignoring that via `optional` makes it possible to fix a missing version
-    // or deployment by deploying over it.
     let current_exists_and_synced = match &current_deployment {
-        Some(current_deployment) => d::table
-            .filter(d::id.eq(&current_deployment))
-            .select(d::synced)
-            .first(conn)
-            .optional()?
-            .unwrap_or(false),
+        Some(current_deployment) => exists_and_synced(current_deployment)?,
         None => false,
     };
 
@@ -749,8 +802,144 @@ pub fn reassign_subgraph(
     }
 }
 
+/// Pause or resume indexing of the deployment `id`. A paused deployment
+/// keeps its assignment, but the node it is assigned to stops indexing it
+/// until it is resumed. It is an error if the deployment is not assigned
+pub fn set_paused(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    paused: bool,
+) -> Result<Vec<EntityChange>, StoreError> {
+    use subgraph_deployment_assignment as a;
+
+    let updates = update(a::table.filter(a::id.eq(id.as_str())))
+        .set(a::paused.eq(paused))
+        .execute(conn)?;
+    match updates {
+        0 => Err(StoreError::DeploymentNotFound(id.to_string())),
+        1 => {
+            let op = MetadataOperation::Set {
+                entity: SubgraphDeploymentAssignmentEntity::TYPENAME,
+                id: id.to_string(),
+                data: entity! { paused: paused },
+            };
+            Ok(vec![op.into()])
+        }
+        _ => {
+            // `id` is the primary key of the subgraph_deployment_assignment table,
+            // and we can therefore only update no or one entry
+            unreachable!()
+        }
+    }
+}
+
+/// Return `true` if the deployment `id` is assigned and paused
+pub fn is_paused(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<bool, StoreError> {
+    use subgraph_deployment_assignment as a;
+
+    Ok(a::table
+        .filter(a::id.eq(id.as_str()))
+        .select(a::paused)
+        .first::<bool>(conn)
+        .optional()?
+        .unwrap_or(false))
+}
+
+/// Remove the assignment of the deployment `id` so that no node indexes
+/// it any longer. It is not an error if the deployment is not assigned
+pub fn unassign_subgraph(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<EntityChange>, StoreError> {
+    use subgraph_deployment_assignment as a;
+
+    let deletes = delete(a::table.filter(a::id.eq(id.as_str()))).execute(conn)?;
+    match deletes {
+        0 => Ok(vec![]),
+        1 => {
+            let op = MetadataOperation::Remove {
+                entity: SubgraphDeploymentAssignmentEntity::TYPENAME,
+                id: id.to_string(),
+            };
+            Ok(vec![op.into()])
+        }
+        _ => {
+            // `id` is the primary key of the subgraph_deployment_assignment table,
+            // and we can therefore only delete no or one entry
+            unreachable!()
+        }
+    }
+}
+
+/// Delete all errors that were recorded for the deployment `id`
+pub fn remove_subgraph_errors(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<(), StoreError> {
+    use subgraph_error as e;
+
+    delete(e::table.filter(e::subgraph_id.eq(id.as_str()))).execute(conn)?;
+    Ok(())
+}
+
+/// Return the node to which the deployment `id` is assigned, or `None` if
+/// it is not assigned to any node
+pub fn assigned_node(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<NodeId>, StoreError> {
+    use subgraph_deployment_assignment as a;
+
+    a::table
+        .filter(a::id.eq(id.as_str()))
+        .select(a::node_id)
+        .first::<String>(conn)
+        .optional()?
+        .map(|node| {
+            NodeId::new(&node).map_err(|()| {
+                StoreError::ConstraintViolation(format!(
+                    "invalid node id `{}` in assignment for {}",
+                    node, id
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Return all deployments that are neither the current nor the pending
+/// version of any subgraph. Those deployments can be removed without
+/// affecting any subgraph
+pub fn unused_deployments(conn: &PgConnection) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+    const QUERY: &str = "
+    select ds.subgraph as id
+      from public.deployment_schemas ds
+     where ds.subgraph != 'subgraphs'
+       and not exists (select 1
+                         from subgraphs.subgraph s, subgraphs.subgraph_version v
+                        where v.id in (s.current_version, s.pending_version)
+                          and v.deployment = ds.subgraph)
+     order by ds.subgraph
+    ";
+    #[derive(QueryableByName)]
+    struct Unused {
+        #[sql_type = "Text"]
+        id: String,
+    }
+
+    diesel::sql_query(QUERY)
+        .load::<Unused>(conn)?
+        .into_iter()
+        .map(|unused| {
+            SubgraphDeploymentId::new(unused.id).map_err(|id| {
+                StoreError::ConstraintViolation(format!("illegal deployment id {}", id))
+            })
+        })
+        .collect()
+}
+
 /// Clear the `SubgraphHealth::Failed` status of a subgraph and mark it as
-/// healthy or unhealthy depending on whether it also had non-fatal errors
+/// healthy or unhealthy depending on whether it also had non-fatal errors.
+/// Any record of automatic retries of the deployment is cleared, too
 pub fn unfail_deployment(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
     use diesel::dsl::count;
     use subgraph_deployment as d;
@@ -780,7 +969,264 @@ pub fn unfail_deployment(conn: &PgConnection, id: &SubgraphDeploymentId) -> Resu
         d::failed.eq(false),
         d::health.eq(prev_health),
         d::fatal_error.eq::<Option<String>>(None),
+        d::retry_count.eq(0),
+        d::next_retry_at.eq::<Option<BigDecimal>>(None),
     ))
     .execute(conn)?;
     Ok(())
 }
+
+/// Record `errors`, which happened while processing `block`, as non-fatal
+/// errors of the deployment `id`, and mark the deployment as unhealthy
+/// unless it has already failed
+pub fn insert_subgraph_errors(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    errors: Vec<SubgraphError>,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_error as e;
+
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    let mut error_ids: Vec<String> = Vec::new();
+    for error in errors {
+        // Identical errors have the same id; they happen when a handler
+        // fails in the same way for several triggers in one block
+        let error_id = error.id();
+        if error_ids.contains(&error_id) {
+            continue;
+        }
+
+        // Work around a Diesel issue with serializing BigDecimals to numeric
+        let block_number = error
+            .block_ptr
+            .as_ref()
+            .map(|ptr| format!("{}::numeric", ptr.number))
+            .unwrap_or_else(|| "null".to_owned());
+        let block_hash = error.block_ptr.as_ref().map(|ptr| ptr.hash.as_bytes());
+
+        insert_into(e::table)
+            .values((
+                e::id.eq(&error_id),
+                e::subgraph_id.eq(id.as_str()),
+                e::message.eq(&error.message),
+                e::block_number.eq(sql(&block_number)),
+                e::block_hash.eq(block_hash),
+                e::handler.eq(&error.handler),
+                e::deterministic.eq(error.deterministic),
+                e::block_range.eq(BlockRange::from(block..)),
+            ))
+            .execute(conn)?;
+        error_ids.push(error_id);
+    }
+
+    let mut non_fatal_errors = d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::non_fatal_errors)
+        .first::<Vec<String>>(conn)?;
+    non_fatal_errors.extend(error_ids);
+
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set(d::non_fatal_errors.eq(&non_fatal_errors))
+        .execute(conn)?;
+    update(
+        d::table
+            .filter(d::id.eq(id.as_str()))
+            .filter(d::health.eq(SubgraphHealth::Healthy)),
+    )
+    .set(d::health.eq(SubgraphHealth::Unhealthy))
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Remove the non-fatal errors of the deployment `id` that happened at
+/// `block` or later. The deployment becomes healthy again if that removes
+/// all its non-fatal errors and it has not failed
+pub fn revert_subgraph_errors(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_error as e;
+    use SubgraphHealth::*;
+
+    let reverted = delete(
+        e::table
+            .filter(e::subgraph_id.eq(id.as_str()))
+            .filter(sql::<Bool>(&format!("lower(block_range) >= {}", block))),
+    )
+    .returning(e::id)
+    .get_results::<String>(conn)?;
+    if reverted.is_empty() {
+        return Ok(());
+    }
+
+    let non_fatal_errors: Vec<String> = d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::non_fatal_errors)
+        .first::<Vec<String>>(conn)?
+        .into_iter()
+        .filter(|error_id| !reverted.contains(error_id))
+        .collect();
+    let health = if non_fatal_errors.is_empty() {
+        Healthy
+    } else {
+        Unhealthy
+    };
+
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set(d::non_fatal_errors.eq(&non_fatal_errors))
+        .execute(conn)?;
+    update(
+        d::table
+            .filter(d::id.eq(id.as_str()))
+            .filter(d::health.ne(Failed)),
+    )
+    .set(d::health.eq(health))
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Set how many blocks of history the deployment `id` keeps. With `None`,
+/// the deployment keeps all of its history
+pub fn set_history_blocks(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    history_blocks: Option<BlockNumber>,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    let updates = update(d::table.filter(d::id.eq(id.as_str())))
+        .set(d::history_blocks.eq(history_blocks))
+        .execute(conn)?;
+    match updates {
+        0 => Err(StoreError::DeploymentNotFound(id.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Return the earliest block for which the deployment `id` still has its
+/// complete history
+pub fn history_horizon(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<BlockNumber, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::history_horizon)
+        .first::<BlockNumber>(conn)
+        .optional()?
+        .ok_or_else(|| StoreError::DeploymentNotFound(id.to_string()))
+}
+
+/// Move the history horizon of the deployment `id` forward to `horizon`.
+/// The caller must make sure that the entity versions before `horizon`
+/// are removed in the same transaction
+pub fn set_history_horizon(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    horizon: BlockNumber,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(
+        d::table
+            .filter(d::id.eq(id.as_str()))
+            .filter(d::history_horizon.lt(horizon)),
+    )
+    .set(d::history_horizon.eq(horizon))
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Return the deployments whose history needs pruning, together with the
+/// history horizon they should be pruned to. Those are the deployments
+/// that limit their history and whose horizon lags behind their latest
+/// block by more than `history_blocks`
+pub fn deployments_to_prune(
+    conn: &PgConnection,
+) -> Result<Vec<(SubgraphDeploymentId, BlockNumber)>, StoreError> {
+    use subgraph_deployment as d;
+
+    let rows = d::table
+        .filter(d::history_blocks.is_not_null())
+        .filter(d::latest_ethereum_block_number.is_not_null())
+        .select((
+            d::id,
+            d::history_blocks,
+            d::history_horizon,
+            d::latest_ethereum_block_number,
+        ))
+        .load::<(String, Option<BlockNumber>, BlockNumber, Option<BigDecimal>)>(conn)?;
+
+    let mut prunable = Vec::new();
+    for (id, history_blocks, horizon, latest) in rows {
+        let latest = latest_as_block_number(latest, &id)?;
+        // Keep `history_blocks` blocks of history, including `latest`
+        let target = match history_blocks {
+            Some(history_blocks) => latest - history_blocks + 1,
+            None => continue,
+        };
+        if target > horizon {
+            let id = SubgraphDeploymentId::new(id).map_err(|id| {
+                StoreError::ConstraintViolation(format!("illegal deployment id {}", id))
+            })?;
+            prunable.push((id, target));
+        }
+    }
+    Ok(prunable)
+}
+
+/// Return the number of the latest block that the deployment `id` has
+/// processed
+pub fn latest_block_number(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<BlockNumber, StoreError> {
+    use subgraph_deployment as d;
+
+    let latest = d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::latest_ethereum_block_number)
+        .first::<Option<BigDecimal>>(conn)
+        .optional()?
+        .ok_or_else(|| StoreError::DeploymentNotFound(id.to_string()))?;
+    latest_as_block_number(latest, id.as_str())
+}
+
+/// Lock the row for the deployment `id` until the end of the current
+/// transaction. Every block that is processed for the deployment updates
+/// that row, and holding the lock therefore keeps the deployment from
+/// advancing
+pub fn lock_deployment(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::id)
+        .for_update()
+        .first::<String>(conn)
+        .optional()?
+        .ok_or_else(|| StoreError::DeploymentNotFound(id.to_string()))?;
+    Ok(())
+}
+
+/// Return the ids of all dynamic data sources of the deployment `id`
+pub fn dynamic_data_source_ids(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<String>, StoreError> {
+    use dynamic_ethereum_contract_data_source as decds;
+
+    Ok(decds::table
+        .select(decds::id)
+        .filter(decds::deployment.eq(id.as_str()))
+        .load::<String>(conn)?)
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use graph::{
-    data::subgraph::status,
+    data::subgraph::{schema::SubgraphError, status},
     prelude::{
         ethabi,
         web3::types::{Address, H256},
@@ -13,26 +13,26 @@ use graph::{
 };
 
 use crate::chain_store::ChainStore;
-use crate::store::Store;
+use crate::sharded_store::ShardedStore;
 
 pub struct NetworkStore {
-    store: Arc<Store>,
+    store: Arc<ShardedStore>,
     chain_store: ChainStore,
 }
 
 impl NetworkStore {
-    pub fn new(store: Arc<Store>, chain_store: ChainStore) -> Self {
+    pub fn new(store: Arc<ShardedStore>, chain_store: ChainStore) -> Self {
         Self { store, chain_store }
     }
 
-    pub fn store(&self) -> Arc<Store> {
+    pub fn store(&self) -> Arc<ShardedStore> {
         self.store.cheap_clone()
     }
 
     // Only needed for tests
     #[cfg(debug_assertions)]
     pub(crate) fn clear_storage_cache(&self) {
-        self.store.storage_cache.lock().unwrap().clear();
+        self.store.clear_storage_cache();
     }
 
     // Only for tests to simplify their handling of test fixtures, so that
@@ -121,9 +121,15 @@ impl StoreTrait for NetworkStore {
         block_ptr_to: EthereumBlockPointer,
         mods: Vec<graph::prelude::EntityModification>,
         stopwatch: graph::prelude::StopwatchMetrics,
+        deterministic_errors: Vec<SubgraphError>,
     ) -> Result<bool, graph::prelude::StoreError> {
-        self.store
-            .transact_block_operations(subgraph_id, block_ptr_to, mods, stopwatch)
+        self.store.transact_block_operations(
+            subgraph_id,
+            block_ptr_to,
+            mods,
+            stopwatch,
+            deterministic_errors,
+        )
     }
 
     fn apply_metadata_operations(
@@ -145,6 +151,14 @@ impl StoreTrait for NetworkStore {
             .revert_block_operations(subgraph_id, block_ptr_from, block_ptr_to)
     }
 
+    fn rewind(
+        &self,
+        subgraph_id: graph::prelude::SubgraphDeploymentId,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), graph::prelude::StoreError> {
+        self.store.rewind(subgraph_id, block_ptr_to)
+    }
+
     fn subscribe(
         &self,
         entities: Vec<graph::prelude::SubgraphEntityPair>,
@@ -186,6 +200,10 @@ impl StoreTrait for NetworkStore {
         self.store.start_subgraph_deployment(logger, subgraph_id)
     }
 
+    fn unfail(&self, subgraph_id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        self.store.unfail(subgraph_id)
+    }
+
     fn migrate_subgraph_deployment(
         &self,
         logger: &graph::prelude::Logger,
@@ -206,9 +224,10 @@ impl StoreTrait for NetworkStore {
 
     fn query_store(
         self: Arc<Self>,
+        id: &SubgraphDeploymentId,
         for_subscription: bool,
-    ) -> Arc<dyn graph::prelude::QueryStore + Send + Sync> {
-        self.store.cheap_clone().query_store(for_subscription)
+    ) -> Result<Arc<dyn graph::prelude::QueryStore + Send + Sync>, StoreError> {
+        self.store.cheap_clone().query_store(id, for_subscription)
     }
 
     fn deployment_synced(&self, id: &graph::prelude::SubgraphDeploymentId) -> Result<(), Error> {
@@ -227,6 +246,18 @@ impl StoreTrait for NetworkStore {
         self.store.reassign_subgraph(id, node)
     }
 
+    fn pause_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        self.store.pause_subgraph(id)
+    }
+
+    fn resume_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        self.store.resume_subgraph(id)
+    }
+
+    fn set_running(&self, id: &SubgraphDeploymentId, running: bool) -> Result<(), StoreError> {
+        self.store.set_running(id, running)
+    }
+
     fn create_subgraph(&self, name: SubgraphName) -> Result<String, StoreError> {
         self.store.create_subgraph(name)
     }
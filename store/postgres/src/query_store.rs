@@ -3,7 +3,10 @@ use std::collections::BTreeMap;
 use web3::types::H256;
 
 use crate::store::ReplicaId;
-use graph::components::store::QueryStore as QueryStoreTrait;
+use graph::components::store::{
+    AggregateQuery, EntityDeletion, EntityDeletionsQuery, EntityHistoryQuery, EntityVersion,
+    QueryStore as QueryStoreTrait,
+};
 use graph::prelude::{Store as _, *};
 
 pub(crate) struct QueryStore {
@@ -38,6 +41,47 @@ impl QueryStoreTrait for QueryStore {
         self.store.execute_query(&conn, query)
     }
 
+    fn aggregate(&self, query: AggregateQuery) -> Result<Vec<Value>, QueryExecutionError> {
+        let conn = self
+            .store
+            .get_entity_conn(&query.subgraph_id, self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        conn.aggregate(
+            &query.entity_type,
+            query.filter,
+            &query.aggregates,
+            query.block,
+        )
+    }
+
+    fn entity_history(
+        &self,
+        query: EntityHistoryQuery,
+    ) -> Result<Vec<EntityVersion>, QueryExecutionError> {
+        let conn = self
+            .store
+            .get_entity_conn(&query.subgraph_id, self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        conn.history(
+            &query.entity_type,
+            &query.entity_id,
+            query.from_block,
+            query.to_block,
+            query.first,
+        )
+    }
+
+    fn deletions(
+        &self,
+        query: EntityDeletionsQuery,
+    ) -> Result<Vec<EntityDeletion>, QueryExecutionError> {
+        let conn = self
+            .store
+            .get_entity_conn(&query.subgraph_id, self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        conn.deletions(query.since, query.block, query.first, query.after.as_ref())
+    }
+
     fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
         assert!(self.for_subscription);
         assert_eq!(self.replica_id, ReplicaId::Main);
@@ -63,6 +107,14 @@ impl QueryStoreTrait for QueryStore {
         self.store.block_number(subgraph_id, block_hash)
     }
 
+    fn history_horizon(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<BlockNumber, StoreError> {
+        let conn = self.store.get_entity_conn(subgraph_id, self.replica_id)?;
+        crate::metadata::history_horizon(&conn.conn, subgraph_id)
+    }
+
     fn wait_stats(&self) -> &PoolWaitStats {
         self.store.wait_stats(self.replica_id)
     }
@@ -23,19 +23,23 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crate::relational_queries::{
-    self as rq, ClampRangeQuery, ConflictingEntityQuery, DeleteByPrefixQuery,
-    DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, FilterCollection, FilterQuery,
-    FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery, UpdateQuery,
+    self as rq, AggregateData, AggregateQuery, ClampRangeQuery, ConflictingEntityQuery,
+    DeleteByPrefixQuery, DeleteDynamicDataSourcesQuery, DeleteQuery, DeletionData, DeletionsQuery,
+    EntityData, FilterCollection, FilterQuery, FindManyQuery, FindQuery, InsertQuery, PruneQuery,
+    RevertClampQuery, RevertRemoveQuery, UpdateQuery,
 };
+use graph::components::store::{Aggregate, Child, EntityDeletion, EntityVersion};
 use graph::data::graphql::ext::{DocumentExt, ObjectTypeExt};
-use graph::data::schema::{FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYPE_NAME};
+use graph::data::schema::{
+    FulltextConfig, FulltextDefinition, RollupDefinition, Schema, SchemaReference, SCHEMA_TYPE_NAME,
+};
 use graph::data::store::BYTES_SCALAR;
 use graph::data::subgraph::schema::{
     DynamicEthereumContractDataSourceEntity, POI_OBJECT, POI_TABLE,
 };
 use graph::prelude::{
     format_err, info, BlockNumber, Entity, EntityChange, EntityChangeOperation, EntityCollection,
-    EntityFilter, EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
+    EntityCursor, EntityFilter, EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
     QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, Value, ValueType,
     BLOCK_NUMBER_MAX,
 };
@@ -199,15 +203,20 @@ pub struct Layout {
     pub enums: EnumMap,
     /// The query to count all entities
     pub count_query: String,
+    /// The rollups declared in the schema
+    pub rollups: Vec<RollupDefinition>,
 }
 
 impl Layout {
     /// Generate a layout for a relational schema for entities in the
     /// GraphQL schema `schema`. The name of the database schema in which
-    /// the subgraph's tables live is in `schema`.
+    /// the subgraph's tables live is in `schema`. The schemas that `schema`
+    /// imports from are in `imports`; they are needed to determine the type
+    /// of columns that reference imported types.
     pub fn new(
         schema: &Schema,
         catalog: Catalog,
+        imports: &HashMap<SchemaReference, Arc<Schema>>,
         create_proof_of_indexing: bool,
     ) -> Result<Self, StoreError> {
         // Extract enum types
@@ -264,12 +273,35 @@ impl Layout {
                 })
         });
 
+        // Types imported from other subgraphs are not part of this schema;
+        // look up their id type in the schema that defines them. If that
+        // schema is not available, references to them are stored as
+        // strings. This only matters when the tables are created; after
+        // that, `Column::new` uses the type that the columns have in the
+        // database
+        let id_types_for_imports = schema
+            .imported_types()
+            .into_iter()
+            .map(|(imported_type, _)| {
+                let alias = imported_type.alias();
+                let id_type = match schema.imported_object_type(alias, imports) {
+                    Ok(object_type) => IdType::try_from(object_type)?,
+                    Err(_) => IdType::String,
+                };
+                Ok((alias.to_owned(), id_type))
+            });
+
         // Map of type name to the type of the ID column for the object_types
         // and interfaces in the schema
-        let id_types = object_types
-            .iter()
-            .map(|obj_type| IdType::try_from(*obj_type).map(|t| (obj_type.name.to_owned(), t)))
-            .chain(id_types_for_interface)
+        let id_types = id_types_for_imports
+            .chain(
+                object_types
+                    .iter()
+                    .map(|obj_type| {
+                        IdType::try_from(*obj_type).map(|t| (obj_type.name.to_owned(), t))
+                    })
+                    .chain(id_types_for_interface),
+            )
             .collect::<Result<IdTypeMap, _>>()?;
 
         // Construct a Table struct for each ObjectType
@@ -318,6 +350,7 @@ impl Layout {
             tables,
             enums,
             count_query,
+            rollups: schema.rollup_definitions(),
         })
     }
 
@@ -363,7 +396,8 @@ impl Layout {
         schema_name: String,
     ) -> Result<Layout, StoreError> {
         let catalog = Catalog::new(conn, schema_name)?;
-        let layout = Self::new(schema, catalog, true)?;
+        let imports = crate::metadata::imported_schemas(conn, schema)?;
+        let layout = Self::new(schema, catalog, &imports, true)?;
         let sql = layout
             .as_ddl()
             .map_err(|_| StoreError::Unknown(format_err!("failed to generate DDL for layout")))?;
@@ -625,8 +659,23 @@ impl Layout {
             );
         }
 
-        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref())?;
+        let mut filter = filter;
+        let bounds = range
+            .after
+            .iter()
+            .map(|cursor| (cursor, true))
+            .chain(range.before.iter().map(|cursor| (cursor, false)));
+        for (cursor, forward) in bounds {
+            let bound = self.cursor_filter(&collection, &order, cursor, forward)?;
+            filter = Some(match filter {
+                Some(filter) => EntityFilter::And(vec![filter, bound]),
+                None => bound,
+            });
+        }
+
+        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref(), block)?;
         let query = FilterQuery::new(
+            &self,
             &filter_collection,
             filter.as_ref(),
             order,
@@ -655,6 +704,229 @@ impl Layout {
             .collect()
     }
 
+    /// Compute `aggregates` over the entities of type `entity_type` that
+    /// are visible at `block` and match `filter`
+    pub fn aggregate(
+        &self,
+        conn: &PgConnection,
+        entity_type: &str,
+        filter: Option<EntityFilter>,
+        aggregates: &[Aggregate],
+        block: BlockNumber,
+    ) -> Result<Vec<Value>, QueryExecutionError> {
+        let table = self.table_for_entity(entity_type)?;
+        let query = AggregateQuery::new(self, table, filter.as_ref(), aggregates, block)?;
+        let data = query
+            .clone()
+            .get_result::<AggregateData>(conn)
+            .map_err(|e| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "{}, query = {:?}",
+                    e,
+                    debug_query(&query).to_string()
+                ))
+            })?;
+        query.values(data)
+    }
+
+    /// List up to `first` of the entities that were removed in a block
+    /// between `since` and `block`, both inclusive, that come after `after`
+    /// in the order of block, entity type and id
+    pub fn deletions(
+        &self,
+        conn: &PgConnection,
+        since: BlockNumber,
+        block: BlockNumber,
+        first: u32,
+        after: Option<&EntityDeletion>,
+    ) -> Result<Vec<EntityDeletion>, QueryExecutionError> {
+        let tables: Vec<_> = self
+            .tables
+            .values()
+            .filter(|table| table.object != POI_OBJECT)
+            .map(|table| table.as_ref())
+            .collect();
+        if tables.is_empty() {
+            return Ok(vec![]);
+        }
+        let after = after.map(|after| {
+            (
+                after.block,
+                after.entity_type.as_str(),
+                after.entity_id.as_str(),
+            )
+        });
+        let query = DeletionsQuery::new(tables, since, block, first as i64, after);
+        let data = query.clone().load::<DeletionData>(conn).map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!(
+                "{}, query = {:?}",
+                e,
+                debug_query(&query).to_string()
+            ))
+        })?;
+        Ok(data
+            .into_iter()
+            .map(|data| EntityDeletion {
+                entity_type: data.entity,
+                entity_id: data.id,
+                block: data.block,
+            })
+            .collect())
+    }
+
+    /// Return up to `first` versions of the entity `entity_type` with id
+    /// `id` that were current at some block between `from` and `to`
+    pub fn history(
+        &self,
+        conn: &PgConnection,
+        entity_type: &str,
+        id: &str,
+        from: BlockNumber,
+        to: BlockNumber,
+        first: u32,
+    ) -> Result<Vec<EntityVersion>, QueryExecutionError> {
+        let table = self.table_for_entity(entity_type)?;
+        let query = VersionsQuery::new(table, id, from, to, first as i64);
+        let versions = query.clone().load::<VersionData>(conn).map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!(
+                "{}, query = {:?}",
+                e,
+                debug_query(&query).to_string()
+            ))
+        })?;
+        versions
+            .into_iter()
+            .map(|version| {
+                let (block_from, block_to) = (version.block_from, version.block_to);
+                version
+                    .deserialize_with_layout(self)
+                    .map(|data| EntityVersion {
+                        block_from,
+                        block_to,
+                        data,
+                    })
+                    .map_err(|e| e.into())
+            })
+            .collect()
+    }
+
+    /// Build a filter that only lets entities through that come after
+    /// `cursor` when they are sorted by `order`, or before it if `forward`
+    /// is `false`. Rows are sorted by `key dir nulls last, id`, and the
+    /// filter has to reproduce that, including the placement of nulls.
+    /// Turning the cursor into a filter makes it possible for Postgres to
+    /// start the scan at the right place in the index on `key` rather than
+    /// skipping rows one by one. When sorting by a child attribute,
+    /// conditions on `key` become conditions on the child entity
+    fn cursor_filter(
+        &self,
+        collection: &EntityCollection,
+        order: &EntityOrder,
+        cursor: &EntityCursor,
+        forward: bool,
+    ) -> Result<EntityFilter, QueryExecutionError> {
+        let id = || PRIMARY_KEY_COLUMN.to_owned();
+        let cursor_id = || Value::from(cursor.id.as_str());
+        let id_beyond = if forward {
+            EntityFilter::GreaterThan(id(), cursor_id())
+        } else {
+            EntityFilter::LessThan(id(), cursor_id())
+        };
+
+        let (attr, child, ascending) = match order {
+            EntityOrder::Ascending(attr, _) => (attr, None, true),
+            EntityOrder::Descending(attr, _) => (attr, None, false),
+            EntityOrder::ChildAscending(child, _) => (&child.child_attr, Some(child), true),
+            EntityOrder::ChildDescending(child, _) => (&child.child_attr, Some(child), false),
+            EntityOrder::Default | EntityOrder::Unordered => return Ok(id_beyond),
+        };
+        if child.is_none() && attr == PRIMARY_KEY_COLUMN {
+            return Ok(if ascending == forward {
+                EntityFilter::GreaterThan(id(), cursor_id())
+            } else {
+                EntityFilter::LessThan(id(), cursor_id())
+            });
+        }
+
+        if child.is_none() {
+            let entity_types: Vec<&str> = match collection {
+                EntityCollection::All(entity_types) => entity_types
+                    .iter()
+                    .map(|entity_type| entity_type.as_str())
+                    .collect(),
+                EntityCollection::Window(windows) => windows
+                    .iter()
+                    .map(|window| window.child_type.as_str())
+                    .collect(),
+            };
+            for entity_type in entity_types {
+                if self
+                    .table_for_entity(entity_type)?
+                    .column_for_field(attr)?
+                    .is_fulltext()
+                {
+                    return Err(QueryExecutionError::NotSupported(
+                        "using a cursor with a fulltext search".to_owned(),
+                    ));
+                }
+            }
+        }
+
+        // Turn a condition on the sort key into a condition on the entity
+        let on_key = |filter: EntityFilter| match child {
+            None => filter,
+            Some(child) => EntityFilter::Child(Child {
+                attr: child.attr.clone(),
+                entity_type: child.entity_type.clone(),
+                filter: Box::new(filter),
+                derived: false,
+            }),
+        };
+        let key_is_null = || match child {
+            None => EntityFilter::Equal(attr.clone(), Value::Null),
+            Some(child) => EntityFilter::Or(vec![
+                EntityFilter::Equal(child.attr.clone(), Value::Null),
+                on_key(EntityFilter::Equal(attr.clone(), Value::Null)),
+            ]),
+        };
+        let key_is_not_null = || match child {
+            None => EntityFilter::Not(attr.clone(), Value::Null),
+            Some(child) => EntityFilter::And(vec![
+                EntityFilter::Not(child.attr.clone(), Value::Null),
+                on_key(EntityFilter::Not(attr.clone(), Value::Null)),
+            ]),
+        };
+
+        let filter = match (&cursor.value, forward) {
+            // Only entities whose key is also null come after a null key,
+            // and all entities with a key come before it
+            (Value::Null, true) => EntityFilter::And(vec![key_is_null(), id_beyond]),
+            (Value::Null, false) => EntityFilter::Or(vec![
+                key_is_not_null(),
+                EntityFilter::And(vec![key_is_null(), id_beyond]),
+            ]),
+            (value, _) => {
+                let beyond = if ascending == forward {
+                    EntityFilter::GreaterThan(attr.clone(), value.clone())
+                } else {
+                    EntityFilter::LessThan(attr.clone(), value.clone())
+                };
+                let mut filters = vec![
+                    on_key(beyond),
+                    EntityFilter::And(vec![
+                        on_key(EntityFilter::Equal(attr.clone(), value.clone())),
+                        id_beyond,
+                    ]),
+                ];
+                if forward {
+                    filters.push(key_is_null());
+                }
+                EntityFilter::Or(filters)
+            }
+        };
+        Ok(filter)
+    }
+
     pub fn update(
         &self,
         conn: &PgConnection,
@@ -772,6 +1044,17 @@ impl Layout {
         Ok((StoreEvent::new(changes), count))
     }
 
+    /// Remove all entity versions that are only visible at blocks before
+    /// `horizon` and return how many versions were removed. Afterwards,
+    /// queries at blocks before `horizon` will return incomplete data
+    pub fn prune(&self, conn: &PgConnection, horizon: BlockNumber) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for table in self.tables.values() {
+            count += PruneQuery::new(table, horizon).execute(conn)?;
+        }
+        Ok(count)
+    }
+
     /// Revert the metadata (dynamic data sources and related entities) for
     /// the given `subgraph`. This function can only be called on the `Layout`
     /// for the metadata subgraph.
@@ -844,6 +1127,44 @@ impl Layout {
         Ok(StoreEvent::new(changes))
     }
 
+    /// Remove all metadata for the given `subgraph`. This function can only
+    /// be called on the `Layout` for the metadata subgraph.
+    pub fn remove_metadata(
+        &self,
+        conn: &PgConnection,
+        subgraph: &SubgraphDeploymentId,
+    ) -> Result<StoreEvent, StoreError> {
+        assert!(self.subgraph.is_meta());
+
+        // Reverting to block 0 removes all dynamic data sources and the
+        // entities that belong to them
+        let mut event = self.revert_metadata(conn, subgraph, 0)?;
+
+        // The ids of all other metadata for the subgraph start with the id
+        // of the subgraph. `SubgraphDeploymentDetail` is a view and does not
+        // have data of its own
+        let prefixes = vec![subgraph.to_string()];
+        let prefix_len = subgraph.as_str().len() as i32;
+        for table in self
+            .tables
+            .values()
+            .filter(|table| table.object != "SubgraphDeploymentDetail")
+        {
+            let changes = DeleteByPrefixQuery::new(table, &prefixes, prefix_len)
+                .get_results(conn)?
+                .into_iter()
+                .map(|data| EntityChange {
+                    subgraph_id: self.subgraph.clone(),
+                    entity_type: table.object.clone(),
+                    entity_id: data.id,
+                    operation: EntityChangeOperation::Removed,
+                })
+                .collect();
+            event = event.extend(StoreEvent::new(changes));
+        }
+        Ok(event)
+    }
+
     pub fn is_cacheable(&self) -> bool {
         // This would be false if we still needed to migrate the Layout, but
         // since there are no migrations in the code right now, it is always
@@ -907,13 +1228,18 @@ impl ColumnType {
         catalog: &Catalog,
         enums: &EnumMap,
         id_types: &IdTypeMap,
-        is_existing_text_column: bool,
+        table: &SqlName,
+        column: &SqlName,
     ) -> Result<ColumnType, StoreError> {
         let name = named_type(field_type);
 
-        // See if its an object type defined in the schema
+        // See if its an object type defined in the schema. A reference
+        // keeps the type its column was created with
         if let Some(id_type) = id_types.get(name) {
-            return Ok(id_type.clone().into());
+            let id_type = catalog
+                .existing_id_type(table, column)
+                .unwrap_or_else(|| id_type.clone());
+            return Ok(id_type.into());
         }
 
         // Check if it's an enum, and if it is, return an appropriate
@@ -922,7 +1248,7 @@ impl ColumnType {
             // We do things this convoluted way to make sure field_type gets
             // snakecased, but the `.` must stay a `.`
             let name = SqlName::qualified_name(&catalog.schema, &SqlName::from(name));
-            if is_existing_text_column {
+            if catalog.is_existing_text_column(table, column) {
                 // We used to have a bug where columns that should have really
                 // been of an enum type were created as text columns. To make
                 // queries work against such misgenerated tables, we pretend
@@ -1006,13 +1332,13 @@ impl Column {
         let column_type = if sql_name.as_str() == PRIMARY_KEY_COLUMN {
             IdType::try_from(&field.field_type)?.into()
         } else {
-            let is_existing_text_column = catalog.is_existing_text_column(table_name, &sql_name);
             ColumnType::from_field_type(
                 &field.field_type,
                 catalog,
                 enums,
                 id_types,
-                is_existing_text_column,
+                table_name,
+                &sql_name,
             )?
         };
         Ok(Column {
@@ -1402,7 +1728,7 @@ mod tests {
         let subgraph = SubgraphDeploymentId::new("subgraph").unwrap();
         let schema = Schema::parse(gql, subgraph).expect("Test schema invalid");
         let catalog = Catalog::make_empty("rel".to_owned()).expect("Can not create catalog");
-        Layout::new(&schema, catalog, false).expect("Failed to construct Layout")
+        Layout::new(&schema, catalog, &HashMap::new(), false).expect("Failed to construct Layout")
     }
 
     #[test]
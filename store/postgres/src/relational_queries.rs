@@ -9,7 +9,7 @@ use diesel::pg::{Pg, PgConnection};
 use diesel::query_builder::{AstPass, QueryFragment, QueryId};
 use diesel::query_dsl::{LoadQuery, RunQueryDsl};
 use diesel::result::{Error as DieselError, QueryResult};
-use diesel::sql_types::{Array, Binary, Bool, Integer, Jsonb, Range, Text};
+use diesel::sql_types::{Array, BigInt, Binary, Bool, Integer, Jsonb, Nullable, Range, Text};
 use diesel::Connection;
 use lazy_static::lazy_static;
 use std::collections::{BTreeMap, HashSet};
@@ -19,6 +19,7 @@ use std::fmt::{self, Display};
 use std::iter::FromIterator;
 use std::str::FromStr;
 
+use graph::components::store::{Aggregate, Child, ChildOrder};
 use graph::data::{schema::FulltextAlgorithm, store::scalar};
 use graph::prelude::{
     format_err, serde_json, Attribute, BlockNumber, ChildMultiplicity, Entity, EntityCollection,
@@ -30,7 +31,9 @@ use crate::block_range::{
     BlockRange, BlockRangeContainsClause, BLOCK_RANGE_COLUMN, BLOCK_RANGE_CURRENT,
 };
 use crate::entities::STRING_PREFIX_SIZE;
-use crate::relational::{Column, ColumnType, IdType, Layout, SqlName, Table, PRIMARY_KEY_COLUMN};
+use crate::relational::{
+    Column, ColumnType, IdType, Layout, SqlName, Table, PRIMARY_KEY_COLUMN, VID_COLUMN,
+};
 use crate::sql_value::SqlValue;
 
 lazy_static! {
@@ -719,27 +722,63 @@ impl<'a> QueryFragment<Pg> for PrefixComparison<'a> {
 /// the `filter` must all come from the given `table`, which is used to
 /// map GraphQL names to column names, and to determine the type of the
 /// column an attribute refers to
+///
+/// Filters on child entities turn into `exists` subqueries against the
+/// child table at the same `block`. The rows of `table` must be available
+/// under the alias `c` in the query that the filter is used in
 #[derive(Debug, Clone)]
 pub struct QueryFilter<'a> {
     filter: &'a EntityFilter,
     table: &'a Table,
+    layout: &'a Layout,
+    block: BlockNumber,
+    /// How many `exists` subqueries for child filters this filter is
+    /// nested in. We use that to give each subquery a distinct alias
+    depth: usize,
 }
 
 impl<'a> QueryFilter<'a> {
-    pub fn new(filter: &'a EntityFilter, table: &'a Table) -> Result<Self, StoreError> {
-        Self::valid_attributes(filter, table)?;
-        Ok(QueryFilter { filter, table })
+    pub fn new(
+        filter: &'a EntityFilter,
+        table: &'a Table,
+        layout: &'a Layout,
+        block: BlockNumber,
+    ) -> Result<Self, StoreError> {
+        Self::valid_attributes(filter, table, layout)?;
+        Ok(QueryFilter {
+            filter,
+            table,
+            layout,
+            block,
+            depth: 0,
+        })
     }
 
-    fn valid_attributes(filter: &'a EntityFilter, table: &'a Table) -> Result<(), StoreError> {
+    fn valid_attributes(
+        filter: &'a EntityFilter,
+        table: &'a Table,
+        layout: &'a Layout,
+    ) -> Result<(), StoreError> {
         use EntityFilter::*;
         match filter {
             And(filters) | Or(filters) => {
                 for filter in filters {
-                    Self::valid_attributes(filter, table)?;
+                    Self::valid_attributes(filter, table, layout)?;
                 }
             }
 
+            Child(child) => {
+                let child_table = layout.table_for_entity(&child.entity_type)?;
+                if child.derived {
+                    child_table.column_for_field(&child.attr)?;
+                } else {
+                    table.column_for_field(&child.attr)?;
+                }
+                Self::valid_attributes(&child.filter, child_table, layout)?;
+            }
+
+            ChangeBlockGte(_) => (),
+
             Contains(attr, _)
             | NotContains(attr, _)
             | Equal(attr, _)
@@ -764,6 +803,17 @@ impl<'a> QueryFilter<'a> {
         QueryFilter {
             filter,
             table: self.table,
+            layout: self.layout,
+            block: self.block,
+            depth: self.depth,
+        }
+    }
+
+    /// The alias for the rows of the table at `depth`
+    fn alias(depth: usize) -> String {
+        match depth {
+            0 => "c".to_owned(),
+            _ => format!("c{}", depth),
         }
     }
 
@@ -911,10 +961,16 @@ impl<'a> QueryFilter<'a> {
             out.push_identifier(column.name.as_str())?;
             out.push_sql(op.as_str());
             match value {
-                Value::BigInt(_) | Value::BigDecimal(_) | Value::Int(_) | Value::String(_) => {
-                    QueryValue(value, &column.column_type).walk_ast(out)?
-                }
-                Value::Bool(_) | Value::Bytes(_) | Value::List(_) | Value::Null => {
+                // Booleans and bytes are not exposed as comparison filters in
+                // GraphQL, but we need them to paginate with a cursor when
+                // sorting by a column of those types
+                Value::BigInt(_)
+                | Value::BigDecimal(_)
+                | Value::Int(_)
+                | Value::String(_)
+                | Value::Bool(_)
+                | Value::Bytes(_) => QueryValue(value, &column.column_type).walk_ast(out)?,
+                Value::List(_) | Value::Null => {
                     return Err(UnsupportedFilter {
                         filter: op.as_str().to_owned(),
                         value: value.clone(),
@@ -1014,6 +1070,79 @@ impl<'a> QueryFilter<'a> {
         Ok(())
     }
 
+    /// Match entity versions that were created at or after `block`
+    fn change_block_gte(&self, block: &'a BlockNumber, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql("lower(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") >= ");
+        out.push_bind_param::<Integer, _>(block)
+    }
+
+    fn child(&self, child: &'a Child, mut out: AstPass<Pg>) -> QueryResult<()> {
+        let child_table = self
+            .layout
+            .table_for_entity(&child.entity_type)
+            .expect("the constructor already checked that all child tables exist");
+        let parent = Self::alias(self.depth);
+        let alias = Self::alias(self.depth + 1);
+        let prefix = format!("{}.", alias);
+
+        // Generate
+        //   exists (select 1 from {child_table} {alias}
+        //            where {alias}.block_range @> {block}
+        //              and {join condition}
+        //              and {child filter})
+        // Conditions in the child filter use unqualified column names,
+        // which refer to the innermost table, i.e., the child table
+        out.push_sql("exists (select 1 from ");
+        out.push_sql(child_table.qualified_name.as_str());
+        out.push_sql(" ");
+        out.push_sql(&alias);
+        out.push_sql(" where ");
+        BlockRangeContainsClause::new(child_table, &prefix, self.block).walk_ast(out.reborrow())?;
+        out.push_sql(" and ");
+        let (child_column, parent_column) = if child.derived {
+            let column = child_table
+                .column_for_field(&child.attr)
+                .expect("the constructor already checked that all attribute names are valid");
+            (column, self.table.primary_key())
+        } else {
+            (child_table.primary_key(), self.column(&child.attr))
+        };
+        // One of the two columns might be a list of ids
+        let (element, element_alias, list, list_alias) = if child_column.is_list() {
+            (parent_column, &parent, child_column, &alias)
+        } else {
+            (child_column, &alias, parent_column, &parent)
+        };
+        out.push_sql(element_alias);
+        out.push_sql(".");
+        out.push_identifier(element.name.as_str())?;
+        if list.is_list() {
+            out.push_sql(" = any(");
+            out.push_sql(list_alias);
+            out.push_sql(".");
+            out.push_identifier(list.name.as_str())?;
+            out.push_sql(")");
+        } else {
+            out.push_sql(" = ");
+            out.push_sql(list_alias);
+            out.push_sql(".");
+            out.push_identifier(list.name.as_str())?;
+        }
+        out.push_sql(" and ");
+        QueryFilter {
+            filter: &child.filter,
+            table: child_table,
+            layout: self.layout,
+            block: self.block,
+            depth: self.depth + 1,
+        }
+        .walk_ast(out.reborrow())?;
+        out.push_sql(")");
+        Ok(())
+    }
+
     fn starts_or_ends_with(
         &self,
         attribute: &Attribute,
@@ -1087,6 +1216,8 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
             NotEndsWith(attr, value) => {
                 self.starts_or_ends_with(attr, value, " not like ", false, out)?
             }
+            Child(child) => self.child(child, out)?,
+            ChangeBlockGte(block) => self.change_block_gte(block, out)?,
         }
         Ok(())
     }
@@ -1154,6 +1285,7 @@ impl<'a> QueryFragment<Pg> for FindManyQuery<'a> {
         //      from schema.<table1> e where {id.is_in($ids1))
         //    union all
         //    ...
+        out.push_sql("select * from (\n");
         for (i, table) in self.tables.iter().enumerate() {
             if i > 0 {
                 out.push_sql("\nunion all\n");
@@ -1428,6 +1560,7 @@ impl<'a> QueryFragment<Pg> for ConflictingEntityQuery<'a> {
         //   select 'Type2' as entity from schema.table2 where id = $1
         //   union all
         //   ...
+        out.push_sql("select * from (\n");
         for (i, table) in self.tables.iter().enumerate() {
             if i > 0 {
                 out.push_sql("\nunion all\n");
@@ -1556,6 +1689,15 @@ impl<'a> ParentLimit<'a> {
         }
     }
 
+    /// Select the sort key in the subquery that `restrict` sorts if it is
+    /// not a column of the child table
+    fn select(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        match self {
+            ParentLimit::Outer => Ok(()),
+            ParentLimit::Ranked(sort_key, _) => sort_key.select_child(out),
+        }
+    }
+
     fn restrict(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         if let ParentLimit::Ranked(sort_key, range) = self {
             out.push_sql(" ");
@@ -1604,6 +1746,7 @@ impl<'a> FilterWindow<'a> {
         layout: &'a Layout,
         window: EntityWindow,
         query_filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         let EntityWindow {
             child_type,
@@ -1612,7 +1755,7 @@ impl<'a> FilterWindow<'a> {
         } = window;
         let table = layout.table_for_entity(&child_type).map(|rc| rc.as_ref())?;
         let query_filter = query_filter
-            .map(|filter| QueryFilter::new(filter, table))
+            .map(|filter| QueryFilter::new(filter, table, layout, block))
             .transpose()?;
         let link = TableLink::new(table, link)?;
         Ok(FilterWindow {
@@ -1653,7 +1796,9 @@ impl<'a> FilterWindow<'a> {
 
         out.push_sql("\n/* children_type_a */  from unnest(");
         column.bind_ids(&self.ids, out)?;
-        out.push_sql(") as p(id) cross join lateral (select * from ");
+        out.push_sql(") as p(id) cross join lateral (select *");
+        limit.select(out)?;
+        out.push_sql(" from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
         BlockRangeContainsClause::new(&self.table, "c.", block).walk_ast(out.reborrow())?;
@@ -1730,7 +1875,9 @@ impl<'a> FilterWindow<'a> {
 
         out.push_sql("\n/* children_type_b */  from unnest(");
         column.bind_ids(&self.ids, out)?;
-        out.push_sql(") as p(id) cross join lateral (select * from ");
+        out.push_sql(") as p(id) cross join lateral (select *");
+        limit.select(out)?;
+        out.push_sql(" from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
         BlockRangeContainsClause::new(&self.table, "c.", block).walk_ast(out.reborrow())?;
@@ -1797,7 +1944,9 @@ impl<'a> FilterWindow<'a> {
         out.push_sql("), reduce_dim(");
         self.table.primary_key().push_matrix(&child_ids, out)?;
         out.push_sql(")) as p(id, child_ids)");
-        out.push_sql(" cross join lateral (select * from ");
+        out.push_sql(" cross join lateral (select *");
+        limit.select(out)?;
+        out.push_sql(" from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
         BlockRangeContainsClause::new(&self.table, "c.", block).walk_ast(out.reborrow())?;
@@ -1868,6 +2017,18 @@ impl<'a> FilterWindow<'a> {
         }
     }
 
+    /// Whether each parent has at most one child in this window. In that
+    /// case, `children` joins the child table directly instead of going
+    /// through a subquery that sorts and limits the children per parent
+    fn single_child(&self) -> bool {
+        match &self.link {
+            TableLink::Direct(_, ChildMultiplicity::Single)
+            | TableLink::Parent(ParentIds::Scalar(_)) => true,
+            TableLink::Direct(_, ChildMultiplicity::Many)
+            | TableLink::Parent(ParentIds::List(_)) => false,
+        }
+    }
+
     /// Select a basic subset of columns from the child table for use in
     /// the `matches` CTE of queries that need to retrieve entities of
     /// different types or entities that link differently to their parents
@@ -1913,6 +2074,7 @@ impl<'a> FilterCollection<'a> {
         layout: &'a Layout,
         collection: EntityCollection,
         filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         match collection {
             EntityCollection::All(entities) => {
@@ -1928,7 +2090,7 @@ impl<'a> FilterCollection<'a> {
                             .map(|rc| rc.as_ref())
                             .and_then(|table| {
                                 filter
-                                    .map(|filter| QueryFilter::new(filter, table))
+                                    .map(|filter| QueryFilter::new(filter, table, layout, block))
                                     .transpose()
                                     .map(|filter| (table, filter))
                             })
@@ -1939,7 +2101,7 @@ impl<'a> FilterCollection<'a> {
             EntityCollection::Window(windows) => {
                 let windows = windows
                     .into_iter()
-                    .map(|window| FilterWindow::new(layout, window, filter))
+                    .map(|window| FilterWindow::new(layout, window, filter, block))
                     .collect::<Result<Vec<_>, _>>()?;
                 let collection = if windows.len() == 1 {
                     let mut windows = windows;
@@ -1983,6 +2145,16 @@ pub enum SortKey<'a> {
         value: Option<&'a str>,
         direction: &'static str,
     },
+    /// Sort by `column` of the entity in `table` whose id is stored in
+    /// `parent_column`. The value of `column` is computed in a subquery
+    /// and made available as `g$sort_key`
+    ChildKey {
+        parent_column: &'a Column,
+        table: &'a Table,
+        column: &'a Column,
+        direction: &'static str,
+        block: BlockNumber,
+    },
 }
 
 impl<'a> SortKey<'a> {
@@ -1990,10 +2162,31 @@ impl<'a> SortKey<'a> {
         order: EntityOrder,
         table: &'a Table,
         filter: Option<&'a EntityFilter>,
+        layout: &'a Layout,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         const ASC: &str = "asc";
         const DESC: &str = "desc";
 
+        fn with_child_key<'a>(
+            table: &'a Table,
+            layout: &'a Layout,
+            child: ChildOrder,
+            direction: &'static str,
+            block: BlockNumber,
+        ) -> Result<SortKey<'a>, QueryExecutionError> {
+            let parent_column = table.column_for_field(&child.attr)?;
+            let child_table = layout.table_for_entity(&child.entity_type)?;
+            let column = child_table.column_for_field(&child.child_attr)?;
+            Ok(SortKey::ChildKey {
+                parent_column,
+                table: child_table.as_ref(),
+                column,
+                direction,
+                block,
+            })
+        }
+
         fn with_key<'a>(
             table: &'a Table,
             attribute: String,
@@ -2029,6 +2222,12 @@ impl<'a> SortKey<'a> {
         match order {
             EntityOrder::Ascending(attr, _) => with_key(table, attr, filter, ASC),
             EntityOrder::Descending(attr, _) => with_key(table, attr, filter, DESC),
+            EntityOrder::ChildAscending(child, _) => {
+                with_child_key(table, layout, child, ASC, block)
+            }
+            EntityOrder::ChildDescending(child, _) => {
+                with_child_key(table, layout, child, DESC, block)
+            }
             EntityOrder::Default => Ok(SortKey::Id),
             EntityOrder::Unordered => Ok(SortKey::None),
         }
@@ -2038,6 +2237,7 @@ impl<'a> SortKey<'a> {
     fn select(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
             SortKey::None | SortKey::Id => Ok(()),
+            SortKey::ChildKey { .. } => self.select_child(out),
             SortKey::Key {
                 column,
                 value: _,
@@ -2070,6 +2270,10 @@ impl<'a> SortKey<'a> {
                 out.push_sql("order by ");
                 SortKey::sort_expr(column, value, direction, out)
             }
+            SortKey::ChildKey { direction, .. } => {
+                out.push_sql("order by ");
+                SortKey::child_sort_expr(direction, out)
+            }
         }
     }
 
@@ -2090,7 +2294,50 @@ impl<'a> SortKey<'a> {
                 out.push_sql("order by g$parent_id, ");
                 SortKey::sort_expr(column, value, direction, out)
             }
+            SortKey::ChildKey { direction, .. } => {
+                out.push_sql("order by g$parent_id, ");
+                SortKey::child_sort_expr(direction, out)
+            }
+        }
+    }
+
+    /// Generate
+    ///   , (select s.{column} from {table} s
+    ///       where s.id = c.{parent_column} and s.block_range @> $block) as g$sort_key
+    /// for a `ChildKey`, and nothing for other sort keys. Queries that sort
+    /// with a `ChildKey` and select `*` from a table must call this so that
+    /// `g$sort_key` is available for sorting
+    fn select_child(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        if let SortKey::ChildKey {
+            parent_column,
+            table,
+            column,
+            direction: _,
+            block,
+        } = self
+        {
+            out.push_sql(", (select s.");
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(" from ");
+            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(" s where s.");
+            out.push_identifier(PRIMARY_KEY_COLUMN)?;
+            out.push_sql(" = c.");
+            out.push_identifier(parent_column.name.as_str())?;
+            out.push_sql(" and ");
+            BlockRangeContainsClause::new(table, "s.", *block).walk_ast(out.reborrow())?;
+            out.push_sql(") as g$sort_key");
         }
+        Ok(())
+    }
+
+    /// Generate
+    ///   g$sort_key direction nulls last, id
+    fn child_sort_expr(direction: &str, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql("g$sort_key ");
+        out.push_sql(direction);
+        out.push_sql(" nulls last, ");
+        out.push_identifier(PRIMARY_KEY_COLUMN)
     }
 
     /// Generate
@@ -2172,6 +2419,7 @@ pub struct FilterQuery<'a> {
 
 impl<'a> FilterQuery<'a> {
     pub fn new(
+        layout: &'a Layout,
         collection: &'a FilterCollection,
         filter: Option<&'a EntityFilter>,
         order: EntityOrder,
@@ -2186,7 +2434,7 @@ impl<'a> FilterQuery<'a> {
         let first_table = collection
             .first_table()
             .expect("an entity query always contains at least one entity type/table");
-        let sort_key = SortKey::new(order, first_table, filter)?;
+        let sort_key = SortKey::new(order, first_table, filter, layout, block)?;
 
         Ok(FilterQuery {
             collection,
@@ -2247,7 +2495,8 @@ impl<'a> FilterQuery<'a> {
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
         Self::select_entity_and_data(table, &mut out);
-        out.push_sql(" from (select * ");
+        out.push_sql(" from (select *");
+        self.sort_key.select_child(&mut out)?;
         self.filtered_rows(table, filter, out.reborrow())?;
         out.push_sql("\n ");
         self.sort_key.order_by(&mut out)?;
@@ -2271,6 +2520,11 @@ impl<'a> FilterQuery<'a> {
         Self::select_entity_and_data(&window.table, &mut out);
         out.push_sql(" from (\n");
         out.push_sql("select c.*, p.id::text as g$parent_id");
+        // Without the subquery that ranks children, `c` is the child table
+        // itself and we have to compute a `ChildKey` here
+        if window.single_child() {
+            self.sort_key.select_child(&mut out)?;
+        }
         window.children(
             ParentLimit::Ranked(&self.sort_key, &self.range),
             self.block,
@@ -2666,6 +2920,700 @@ impl<'a> LoadQuery<PgConnection, RevertEntityData> for RevertClampQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for RevertClampQuery<'a> {}
 
+/// A query that removes all versions that are not visible at `horizon` or
+/// any later block, i.e., all versions whose block range ends at or
+/// before `horizon`. Current versions are never removed
+#[derive(Debug, Clone, Constructor)]
+pub struct PruneQuery<'a> {
+    table: &'a Table,
+    horizon: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for PruneQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table
+        //    where coalesce(upper(block_range), INTMAX) <= $horizon
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where coalesce(upper(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql("), 2147483647) <= ");
+        out.push_bind_param::<Integer, _>(&self.horizon)?;
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for PruneQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for PruneQuery<'a> {}
+
+/// Select which rows of a table `CopySourceQuery` and `DeleteRowsQuery`
+/// operate on
+#[derive(Debug, Clone)]
+pub enum CopyRows<'a> {
+    /// All rows whose `vid` is at most the given one
+    UpTo(i64),
+    /// All versions that were created or that ended after the given block
+    ChangedAfter(BlockNumber),
+    /// All rows whose `id` starts with one of the prefixes
+    WithPrefix(&'a Vec<String>),
+    /// All rows in which the given column has the given value
+    Matching(&'a str, &'a str),
+}
+
+impl<'a> CopyRows<'a> {
+    fn walk_ast(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        match self {
+            CopyRows::UpTo(vid) => {
+                out.push_identifier(VID_COLUMN)?;
+                out.push_sql(" <= ");
+                out.push_bind_param::<BigInt, _>(vid)?;
+            }
+            CopyRows::ChangedAfter(block) => {
+                // Current versions have no upper bound and only count as
+                // changed when they were created after `block`
+                out.push_sql("(lower(");
+                out.push_identifier(BLOCK_RANGE_COLUMN)?;
+                out.push_sql(") > ");
+                out.push_bind_param::<Integer, _>(block)?;
+                out.push_sql(" or upper(");
+                out.push_identifier(BLOCK_RANGE_COLUMN)?;
+                out.push_sql(") > ");
+                out.push_bind_param::<Integer, _>(block)?;
+                out.push_sql(")");
+            }
+            CopyRows::WithPrefix(prefixes) => {
+                out.push_sql("exists (select 1 from unnest(");
+                out.push_bind_param::<Array<Text>, _>(*prefixes)?;
+                out.push_sql("::text[]) as p(prefix) where left(");
+                out.push_identifier(PRIMARY_KEY_COLUMN)?;
+                out.push_sql(", length(p.prefix)) = p.prefix)");
+            }
+            CopyRows::Matching(column, value) => {
+                out.push_identifier(column)?;
+                out.push_sql(" = ");
+                out.push_bind_param::<Text, _>(value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One row of a table, serialized with `row_to_json`
+#[derive(QueryableByName)]
+pub struct JsonRow {
+    #[sql_type = "BigInt"]
+    pub vid: i64,
+    #[sql_type = "Text"]
+    pub data: String,
+}
+
+/// A query that reads up to `limit` rows selected by `rows` from `table`,
+/// in the order of their `vid`, starting after `after_vid`. The rows can
+/// be written to another table with the same structure with
+/// `CopyDestinationQuery`
+#[derive(Debug, Clone, Constructor)]
+pub struct CopySourceQuery<'a> {
+    table: &'a Table,
+    rows: CopyRows<'a>,
+    after_vid: i64,
+    limit: i64,
+}
+
+impl<'a> QueryFragment<Pg> for CopySourceQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   select vid, row_to_json(t)::text as data
+        //     from table t
+        //    where vid > $after_vid and {rows}
+        //    order by vid
+        //    limit $limit
+        out.push_sql("select t.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(", row_to_json(t)::text as data\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" t\n where t.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(" > ");
+        out.push_bind_param::<BigInt, _>(&self.after_vid)?;
+        out.push_sql(" and ");
+        self.rows.walk_ast(&mut out)?;
+        out.push_sql("\n order by t.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql("\n limit ");
+        out.push_bind_param::<BigInt, _>(&self.limit)
+    }
+}
+
+impl<'a> QueryId for CopySourceQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, JsonRow> for CopySourceQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<JsonRow>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for CopySourceQuery<'a> {}
+
+/// A query that inserts rows read with `CopySourceQuery` into `table`.
+/// The `data` is a JSON array of the rows. With `keep_vid` set to `false`,
+/// the rows get new `vid`s from `table`'s sequence instead of keeping the
+/// ones they had in the source
+#[derive(Debug, Clone, Constructor)]
+pub struct CopyDestinationQuery<'a> {
+    table: &'a Table,
+    data: &'a str,
+    keep_vid: bool,
+}
+
+impl<'a> CopyDestinationQuery<'a> {
+    fn push_columns(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        for column in &self.table.columns {
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(", ");
+        }
+        out.push_identifier(BLOCK_RANGE_COLUMN)
+    }
+}
+
+impl<'a> QueryFragment<Pg> for CopyDestinationQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   with data as (
+        //     select * from json_populate_recordset(null::table, $data::json))
+        //   insert into table select * from data
+        // Unless we keep the vid, the columns are listed explicitly so
+        // that `vid` gets its default value
+        out.push_sql("with data as (\n  select * from json_populate_recordset(null::");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(", ");
+        out.push_bind_param::<Text, _>(&self.data)?;
+        out.push_sql("::json))");
+        if self.keep_vid {
+            out.push_sql("\ninsert into ");
+            out.push_sql(self.table.qualified_name.as_str());
+            out.push_sql(" select * from data");
+        } else {
+            out.push_sql("\ninsert into ");
+            out.push_sql(self.table.qualified_name.as_str());
+            out.push_sql("(");
+            self.push_columns(&mut out)?;
+            out.push_sql(")\nselect ");
+            self.push_columns(&mut out)?;
+            out.push_sql(" from data");
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for CopyDestinationQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for CopyDestinationQuery<'a> {}
+
+/// A query that deletes the rows with the given `vids` from `table`
+#[derive(Debug, Clone, Constructor)]
+pub struct DeleteVidsQuery<'a> {
+    table: &'a Table,
+    vids: &'a Vec<i64>,
+}
+
+impl<'a> QueryFragment<Pg> for DeleteVidsQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table where vid = any($vids)
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where ");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(" = any(");
+        out.push_bind_param::<Array<BigInt>, _>(self.vids)?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for DeleteVidsQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for DeleteVidsQuery<'a> {}
+
+/// A query that deletes all rows selected by `rows` from `table`
+#[derive(Debug, Clone, Constructor)]
+pub struct DeleteRowsQuery<'a> {
+    table: &'a Table,
+    rows: CopyRows<'a>,
+}
+
+impl<'a> QueryFragment<Pg> for DeleteRowsQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table where {rows}
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where ");
+        self.rows.walk_ast(&mut out)
+    }
+}
+
+impl<'a> QueryId for DeleteRowsQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for DeleteRowsQuery<'a> {}
+
+/// An entity read by `ExportQuery` together with its `vid`
+#[derive(QueryableByName)]
+pub struct ExportData {
+    #[sql_type = "BigInt"]
+    pub vid: i64,
+    #[diesel(embed)]
+    pub entity: EntityData,
+}
+
+/// A query that reads up to `limit` of the entities in `table` that are
+/// visible at `block`, in the order of their `vid`, starting after
+/// `after_vid`
+#[derive(Debug, Clone, Constructor)]
+pub struct ExportQuery<'a> {
+    table: &'a Table,
+    block: BlockNumber,
+    after_vid: i64,
+    limit: i64,
+}
+
+impl<'a> QueryFragment<Pg> for ExportQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select '..' as entity, to_jsonb(e.*) as data, e.vid
+        //      from schema.table e
+        //     where e.vid > $after_vid and {block_range contains $block}
+        //     order by e.vid
+        //     limit $limit
+        out.push_sql("select ");
+        out.push_bind_param::<Text, _>(&self.table.object)?;
+        out.push_sql(" as entity, to_jsonb(e.*) as data, e.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql("\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" e\n where e.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(" > ");
+        out.push_bind_param::<BigInt, _>(&self.after_vid)?;
+        out.push_sql(" and ");
+        BlockRangeContainsClause::new(&self.table, "e.", self.block).walk_ast(out.reborrow())?;
+        out.push_sql("\n order by e.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql("\n limit ");
+        out.push_bind_param::<BigInt, _>(&self.limit)
+    }
+}
+
+impl<'a> QueryId for ExportQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, ExportData> for ExportQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<ExportData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ExportQuery<'a> {}
+
+/// The result of an `AggregateQuery`, with one entry for each aggregate.
+/// Postgres produces values of different types for different aggregates,
+/// and we read them all as text and turn them into `Value` ourselves
+#[derive(QueryableByName)]
+pub struct AggregateData {
+    #[sql_type = "Array<Nullable<Text>>"]
+    values: Vec<Option<String>>,
+}
+
+/// Compute several aggregates over the entities in `table` that are
+/// visible at `block` and pass `filter` with one query
+#[derive(Debug, Clone)]
+pub struct AggregateQuery<'a> {
+    table: &'a Table,
+    filter: Option<QueryFilter<'a>>,
+    aggregates: Vec<(&'a Aggregate, Option<&'a Column>)>,
+    block: BlockNumber,
+}
+
+impl<'a> AggregateQuery<'a> {
+    pub fn new(
+        layout: &'a Layout,
+        table: &'a Table,
+        filter: Option<&'a EntityFilter>,
+        aggregates: &'a [Aggregate],
+        block: BlockNumber,
+    ) -> Result<Self, QueryExecutionError> {
+        let filter = filter
+            .map(|filter| QueryFilter::new(filter, table, layout, block))
+            .transpose()?;
+        let aggregates = aggregates
+            .iter()
+            .map(|aggregate| {
+                let attr = match aggregate {
+                    Aggregate::Count => return Ok((aggregate, None)),
+                    Aggregate::Sum(attr)
+                    | Aggregate::Min(attr)
+                    | Aggregate::Max(attr)
+                    | Aggregate::Avg(attr) => attr,
+                };
+                let column = table.column_for_field(attr)?;
+                match column.column_type {
+                    ColumnType::Int | ColumnType::BigInt | ColumnType::BigDecimal
+                        if !column.is_list() =>
+                    {
+                        Ok((aggregate, Some(column)))
+                    }
+                    _ => Err(QueryExecutionError::NotSupported(format!(
+                        "can not aggregate `{}.{}` since only Int, BigInt, and \
+                         BigDecimal attributes can be aggregated",
+                        table.object, attr
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<_>, QueryExecutionError>>()?;
+        Ok(AggregateQuery {
+            table,
+            filter,
+            aggregates,
+            block,
+        })
+    }
+
+    /// Turn the text that Postgres returned into values. The type of each
+    /// value follows from the aggregate and the type of the column: `Count`
+    /// is an `Int`, `Avg` always a `BigDecimal`, and summing `Int` columns
+    /// produces a `BigInt` since the sum can overflow an `Int`
+    pub fn values(&self, data: AggregateData) -> Result<Vec<Value>, QueryExecutionError> {
+        fn parse<T: FromStr>(text: &str) -> Result<T, QueryExecutionError> {
+            T::from_str(text).map_err(|_| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "failed to parse aggregate value `{}`",
+                    text
+                ))
+            })
+        }
+
+        self.aggregates
+            .iter()
+            .zip(data.values)
+            .map(|((aggregate, column), text)| {
+                let text = match text {
+                    Some(text) => text,
+                    None => return Ok(Value::Null),
+                };
+                let column_type = column.map(|column| &column.column_type);
+                match (aggregate, column_type) {
+                    (Aggregate::Count, _) => parse(&text).map(Value::BigInt),
+                    (Aggregate::Avg(_), _) | (_, Some(ColumnType::BigDecimal)) => {
+                        parse(&text).map(Value::BigDecimal)
+                    }
+                    (Aggregate::Sum(_), _) | (_, Some(ColumnType::BigInt)) => {
+                        parse(&text).map(Value::BigInt)
+                    }
+                    (_, _) => parse(&text).map(Value::Int),
+                }
+            })
+            .collect()
+    }
+}
+
+impl<'a> QueryFragment<Pg> for AggregateQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select array[count(*)::text, sum(c.attr)::text, ..] as values
+        //      from schema.table c
+        //     where {block_range contains $block}
+        //       and query_filter
+        out.push_sql("select array[");
+        for (i, (aggregate, column)) in self.aggregates.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            let function = match aggregate {
+                Aggregate::Count => "count(",
+                Aggregate::Sum(_) => "sum(",
+                Aggregate::Min(_) => "min(",
+                Aggregate::Max(_) => "max(",
+                Aggregate::Avg(_) => "avg(",
+            };
+            out.push_sql(function);
+            match column {
+                Some(column) => {
+                    out.push_sql("c.");
+                    out.push_identifier(column.name.as_str())?;
+                }
+                None => out.push_sql("*"),
+            }
+            out.push_sql(")::text");
+        }
+        out.push_sql("]::text[] as values\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c\n where ");
+        BlockRangeContainsClause::new(&self.table, "c.", self.block).walk_ast(out.reborrow())?;
+        if let Some(filter) = &self.filter {
+            out.push_sql(" and ");
+            filter.walk_ast(out.reborrow())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for AggregateQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, AggregateData> for AggregateQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<AggregateData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for AggregateQuery<'a> {}
+
+/// An entity that was removed, as found by `DeletionsQuery`
+#[derive(QueryableByName)]
+pub struct DeletionData {
+    #[sql_type = "Text"]
+    pub entity: String,
+    #[sql_type = "Text"]
+    pub id: String,
+    #[sql_type = "Integer"]
+    pub block: BlockNumber,
+}
+
+/// Find up to `first` entities in `tables` whose last version ended in a
+/// block between `since` and `block`, both inclusive, and that are not
+/// visible at `block`. Those are the entities that were removed in these
+/// blocks. If `after` is given, only deletions that come after that
+/// `(block, entity, id)` in the order of the result are returned
+#[derive(Debug, Clone, Constructor)]
+pub struct DeletionsQuery<'a> {
+    tables: Vec<&'a Table>,
+    since: BlockNumber,
+    block: BlockNumber,
+    first: i64,
+    after: Option<(BlockNumber, &'a str, &'a str)>,
+}
+
+impl<'a> QueryFragment<Pg> for DeletionsQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select * from (<deletions>) d
+        //     where (d.block, d.entity, d.id) > ($block, $entity, $id)
+        //     order by block, entity, id
+        //     limit $first
+        // where the `where` clause is only there if we have `after`, and
+        // `<deletions>` is, for each table
+        //    select '..' as entity, c.id::text as id, upper(c.block_range) as block
+        //      from schema.table c
+        //     where upper(c.block_range) between $since and $block
+        //       and not exists (select 1 from schema.table s
+        //                        where s.id = c.id
+        //                          and lower(s.block_range) = upper(c.block_range))
+        //       and not exists (select 1 from schema.table d
+        //                        where d.id = c.id
+        //                          and {d.block_range contains $block})
+        // and combine them with `union all`. Versions that were replaced by
+        // an update have a successor that starts where they end, and are
+        // not deletions
+        out.push_sql("select * from (\n");
+        for (i, table) in self.tables.iter().enumerate() {
+            if i > 0 {
+                out.push_sql("\nunion all\n");
+            }
+            out.push_sql("select ");
+            out.push_bind_param::<Text, _>(&table.object)?;
+            out.push_sql(" as entity, ");
+            match table.primary_key().column_type {
+                ColumnType::BytesId => {
+                    out.push_sql("'0x' || encode(c.id, 'hex')");
+                }
+                _ => out.push_sql("c.id"),
+            }
+            out.push_sql(" as id, upper(c.");
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(") as block\n  from ");
+            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(" c\n where upper(c.");
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(") between ");
+            out.push_bind_param::<Integer, _>(&self.since)?;
+            out.push_sql(" and ");
+            out.push_bind_param::<Integer, _>(&self.block)?;
+            out.push_sql("\n   and not exists (select 1 from ");
+            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(" s where s.id = c.id and lower(s.");
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(") = upper(c.");
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql("))");
+            out.push_sql("\n   and not exists (select 1 from ");
+            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(" d where d.id = c.id and ");
+            BlockRangeContainsClause::new(table, "d.", self.block).walk_ast(out.reborrow())?;
+            out.push_sql(")");
+        }
+        out.push_sql(") d");
+        if let Some((block, entity, id)) = &self.after {
+            out.push_sql("\n where (d.block, d.entity, d.id) > (");
+            out.push_bind_param::<Integer, _>(block)?;
+            out.push_sql(", ");
+            out.push_bind_param::<Text, _>(entity)?;
+            out.push_sql(", ");
+            out.push_bind_param::<Text, _>(id)?;
+            out.push_sql(")");
+        }
+        out.push_sql("\n order by block, entity, id\n limit ");
+        out.push_bind_param::<BigInt, _>(&self.first)
+    }
+}
+
+impl<'a> QueryId for DeletionsQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, DeletionData> for DeletionsQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<DeletionData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for DeletionsQuery<'a> {}
+
+/// One version of an entity as found by `VersionsQuery`
+#[derive(QueryableByName)]
+pub struct VersionData {
+    #[sql_type = "Text"]
+    entity: String,
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+    #[sql_type = "Integer"]
+    pub block_from: BlockNumber,
+    #[sql_type = "Nullable<Integer>"]
+    pub block_to: Option<BlockNumber>,
+}
+
+impl VersionData {
+    pub fn deserialize_with_layout<T: FromEntityData>(
+        self,
+        layout: &Layout,
+    ) -> Result<T, StoreError> {
+        EntityData {
+            entity: self.entity,
+            data: self.data,
+        }
+        .deserialize_with_layout(layout)
+    }
+}
+
+/// Find up to `first` versions of the entity with id `id` that were
+/// current at some block between `from` and `to`, both inclusive
+#[derive(Debug, Clone, Constructor)]
+pub struct VersionsQuery<'a> {
+    table: &'a Table,
+    id: &'a str,
+    from: BlockNumber,
+    to: BlockNumber,
+    first: i64,
+}
+
+impl<'a> QueryFragment<Pg> for VersionsQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select '..' as entity, to_jsonb(c.*) as data,
+        //           lower(c.block_range) as block_from,
+        //           upper(c.block_range) as block_to
+        //      from schema.table c
+        //     where c.id = $id
+        //       and lower(c.block_range) <= $to
+        //       and coalesce(upper(c.block_range), 2147483647) > $from
+        //     order by lower(c.block_range)
+        //     limit $first
+        out.push_sql("select ");
+        out.push_bind_param::<Text, _>(&self.table.object)?;
+        out.push_sql(" as entity, to_jsonb(c.*) as data, lower(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") as block_from, upper(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") as block_to\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c\n where ");
+        self.table.primary_key().eq(&self.id, &mut out)?;
+        out.push_sql(" and lower(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") <= ");
+        out.push_bind_param::<Integer, _>(&self.to)?;
+        out.push_sql(" and coalesce(upper(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql("), 2147483647) > ");
+        out.push_bind_param::<Integer, _>(&self.from)?;
+        out.push_sql("\n order by lower(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(")\n limit ");
+        out.push_bind_param::<BigInt, _>(&self.first)
+    }
+}
+
+impl<'a> QueryId for VersionsQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, VersionData> for VersionsQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<VersionData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for VersionsQuery<'a> {}
+
 #[test]
 fn block_number_max_is_i32_max() {
     // The code in RevertClampQuery::walk_ast embeds i32::MAX
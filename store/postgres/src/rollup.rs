@@ -0,0 +1,217 @@
+//! Maintain the rollups declared with `@rollup` in a subgraph's schema.
+//!
+//! Rollup entities are computed from the entity modifications of each
+//! block right before they are written. Since the resulting rollup
+//! entities are written like any other entity, with the block range of
+//! the block that produced them, reverting a block also reverts the
+//! changes that block made to rollups.
+use diesel::pg::PgConnection;
+use std::collections::BTreeMap;
+
+use graph::data::schema::{RollupDefinition, RollupValue};
+use graph::prelude::{
+    format_err, BigDecimal, BigInt, Entity, EntityKey, EntityModification, StoreError, Value,
+    ValueType, BLOCK_NUMBER_MAX,
+};
+
+use crate::relational::Layout;
+
+/// A bucket of a rollup together with whether it exists in the store
+struct Bucket {
+    data: Entity,
+    exists: bool,
+}
+
+/// Return the start of the bucket of length `interval` that `timestamp`
+/// falls into
+fn bucket_start(timestamp: &Value, interval: i32) -> Result<Value, StoreError> {
+    match timestamp {
+        Value::Int(ts) => Ok(Value::Int(ts - ts.rem_euclid(interval))),
+        Value::BigInt(ts) => {
+            let interval = BigInt::from(interval);
+            let mut offset = ts.clone() % interval.clone();
+            if offset < BigInt::from(0) {
+                offset = offset + interval;
+            }
+            Ok(Value::BigInt(ts.clone() - offset))
+        }
+        _ => Err(format_err!("invalid rollup timestamp `{}`", timestamp).into()),
+    }
+}
+
+/// The id of the rollup entity for `source` is made up of the values of
+/// the dimensions and the start of the bucket, separated by `-`. A `-` or
+/// `\` in the value of a dimension is escaped with a `\`, and a dimension
+/// that is not set is written as `\N`, so that different buckets can never
+/// have the same id
+fn bucket_id(rollup: &RollupDefinition, source: &Entity, start: &Value) -> String {
+    fn escape(value: &Value) -> String {
+        match value {
+            Value::Null => "\\N".to_owned(),
+            value => value.to_string().replace('\\', "\\\\").replace('-', "\\-"),
+        }
+    }
+
+    rollup
+        .dimensions
+        .iter()
+        .map(|dimension| escape(source.get(dimension).unwrap_or(&Value::Null)))
+        .chain(std::iter::once(start.to_string()))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Create a new, empty bucket
+fn empty_bucket(rollup: &RollupDefinition, source: &Entity, id: String, start: Value) -> Entity {
+    let mut data = Entity::new();
+    data.set("id", id);
+    data.set(rollup.timestamp.as_str(), start);
+    for dimension in &rollup.dimensions {
+        data.set(
+            dimension.as_str(),
+            source.get(dimension).cloned().unwrap_or(Value::Null),
+        );
+    }
+    for field in &rollup.fields {
+        let value = match &field.value {
+            RollupValue::Count => Value::Int(0),
+            RollupValue::Sum(_, ValueType::BigInt) => Value::BigInt(BigInt::from(0)),
+            RollupValue::Sum(_, _) => Value::BigDecimal(BigDecimal::zero()),
+            RollupValue::Last(_) => Value::Null,
+        };
+        data.set(field.name.as_str(), value);
+    }
+    data
+}
+
+/// Add the values from `source` to `bucket`
+fn accumulate(
+    rollup: &RollupDefinition,
+    bucket: &mut Entity,
+    source: &Entity,
+) -> Result<(), StoreError> {
+    for field in &rollup.fields {
+        let current = bucket.get(&field.name).cloned().unwrap_or(Value::Null);
+        let value = match &field.value {
+            RollupValue::Count => match current {
+                Value::Int(count) => Value::Int(count.saturating_add(1)),
+                _ => Value::Int(1),
+            },
+            RollupValue::Sum(source_field, _) => {
+                match (current, source.get(source_field).cloned()) {
+                    (current, None) | (current, Some(Value::Null)) => current,
+                    (Value::BigInt(sum), Some(Value::Int(value))) => {
+                        Value::BigInt(sum + BigInt::from(value))
+                    }
+                    (Value::BigInt(sum), Some(Value::BigInt(value))) => Value::BigInt(sum + value),
+                    (Value::BigDecimal(sum), Some(Value::BigDecimal(value))) => {
+                        Value::BigDecimal(sum + value)
+                    }
+                    (_, Some(value)) => {
+                        return Err(format_err!(
+                            "can not add `{}` to `{}.{}`",
+                            value,
+                            rollup.entity_type,
+                            field.name
+                        )
+                        .into())
+                    }
+                }
+            }
+            RollupValue::Last(source_field) => {
+                source.get(source_field).cloned().unwrap_or(Value::Null)
+            }
+        };
+        bucket.set(field.name.as_str(), value);
+    }
+    Ok(())
+}
+
+/// Compute the changes to rollups that result from applying `mods`. Only
+/// newly inserted source entities are added to rollups; changes to or
+/// removals of source entities do not affect rollups that already contain
+/// them. It is an error for `mods` to modify rollups directly.
+///
+/// Source entities are added in the order in which they appear in `mods`,
+/// which `EntityCache::as_modifications` makes the order in which the
+/// mappings wrote them, so that `@last` picks the latest one.
+pub(crate) fn modifications(
+    conn: &PgConnection,
+    layout: &Layout,
+    mods: &[EntityModification],
+) -> Result<Vec<EntityModification>, StoreError> {
+    if layout.rollups.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut buckets: BTreeMap<(String, String), Bucket> = BTreeMap::new();
+    for modification in mods {
+        let key = modification.entity_key();
+        if key.subgraph_id != layout.subgraph {
+            continue;
+        }
+        if let Some(rollup) = layout
+            .rollups
+            .iter()
+            .find(|rollup| rollup.entity_type == key.entity_type)
+        {
+            return Err(format_err!(
+                "entities of type `{}` are maintained by the rollup `{}` and can not be changed",
+                key.entity_type,
+                rollup.entity_type
+            )
+            .into());
+        }
+        let source = match modification {
+            EntityModification::Insert { data, .. } => data,
+            EntityModification::Overwrite { .. } | EntityModification::Remove { .. } => continue,
+        };
+
+        for rollup in layout
+            .rollups
+            .iter()
+            .filter(|rollup| rollup.source == key.entity_type)
+        {
+            let timestamp = source.get(&rollup.timestamp).unwrap_or(&Value::Null);
+            let start = bucket_start(timestamp, rollup.interval.seconds())?;
+            let id = bucket_id(rollup, source, &start);
+
+            let bucket_key = (rollup.entity_type.clone(), id);
+            if !buckets.contains_key(&bucket_key) {
+                let bucket =
+                    match layout.find(conn, &bucket_key.0, &bucket_key.1, BLOCK_NUMBER_MAX)? {
+                        Some(data) => Bucket { data, exists: true },
+                        None => Bucket {
+                            data: empty_bucket(rollup, source, bucket_key.1.clone(), start),
+                            exists: false,
+                        },
+                    };
+                buckets.insert(bucket_key.clone(), bucket);
+            }
+            let bucket = buckets.get_mut(&bucket_key).unwrap();
+            accumulate(rollup, &mut bucket.data, source)?;
+        }
+    }
+
+    Ok(buckets
+        .into_iter()
+        .map(|((entity_type, entity_id), bucket)| {
+            let key = EntityKey {
+                subgraph_id: layout.subgraph.clone(),
+                entity_type,
+                entity_id,
+            };
+            if bucket.exists {
+                EntityModification::Overwrite {
+                    key,
+                    data: bucket.data,
+                }
+            } else {
+                EntityModification::Insert {
+                    key,
+                    data: bucket.data,
+                }
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,794 @@
+use futures03::FutureExt as _;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use graph::{
+    components::store::{DeploymentPlacer, QueryStore as QueryStoreTrait},
+    data::subgraph::schema::{MetadataType, SubgraphDeploymentEntity, SubgraphError, SUBGRAPHS_ID},
+    data::subgraph::status,
+    prelude::{
+        error, ethabi, format_err, futures03, o, tokio, warn, web3::types::Address,
+        web3::types::H256, ApiSchema, BlockNumber, DeploymentState, DynTryFuture, Entity,
+        EntityKey, EntityModification, EntityQuery, Error, EthereumBlockPointer, EthereumCallCache,
+        Future01CompatExt, Logger, MetadataOperation, NodeId, QueryExecutionError, Schema,
+        StopwatchMetrics, Store as StoreTrait, StoreError, StoreEvent, StoreEventStream,
+        StoreEventStreamBox, Stream, SubgraphDeploymentId, SubgraphDeploymentStore,
+        SubgraphEntityPair, SubgraphName, SubgraphVersionSwitchingMode,
+    },
+};
+
+use crate::copy::{self, DeploymentCopy};
+use crate::entities as e;
+use crate::relational::Layout;
+use crate::store::Store;
+
+/// The name of the shard that holds the primary copy of the metadata
+/// about all subgraphs and deployments
+pub const PRIMARY_SHARD: &str = "primary";
+
+lazy_static! {
+    /// How often to check whether deployments need their history pruned.
+    /// Pruning is off unless this is set to a value other than 0
+    static ref PRUNE_INTERVAL: Duration = std::env::var("GRAPH_STORE_PRUNE_INTERVAL")
+        .ok()
+        .map(|s| {
+            s.parse::<u64>()
+                .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_STORE_PRUNE_INTERVAL"))
+        })
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(0));
+}
+
+type EventStream = Box<dyn Stream<Item = Arc<StoreEvent>, Error = ()> + Send>;
+
+/// Multiplex store operations on subgraphs and deployments between a
+/// primary and any number of additional storage shards. The primary holds
+/// the list of subgraphs, their versions and the assignments of
+/// deployments to nodes. It also records in which shard the data for each
+/// deployment is stored. Everything else about a deployment, including its
+/// metadata, lives in the shard that stores its data.
+pub struct ShardedStore {
+    primary: Arc<Store>,
+    stores: HashMap<String, Arc<Store>>,
+    /// Decide which shard new deployments go into
+    placer: Arc<dyn DeploymentPlacer>,
+    /// Cache for the shard that each deployment is stored in. Entries in
+    /// this cache become stale when a deployment is removed or copied to
+    /// another shard. We remove them then, when another process announces
+    /// that it switched a deployment to a copy, and whenever a deployment
+    /// is started
+    shards: Arc<Mutex<HashMap<SubgraphDeploymentId, String>>>,
+}
+
+/// Remove the cached shard and `Layout` of the deployment `id`
+fn forget_location(
+    shards: &Mutex<HashMap<SubgraphDeploymentId, String>>,
+    stores: &HashMap<String, Arc<Store>>,
+    id: &SubgraphDeploymentId,
+) {
+    shards.lock().unwrap().remove(id);
+    for store in stores.values() {
+        store.storage_cache.lock().unwrap().remove(id);
+    }
+}
+
+/// Run `f` on a thread that is allowed to block. Copying a deployment
+/// spends a long time in blocking database calls
+async fn blocking<R, F>(f: F) -> Result<R, StoreError>
+where
+    R: Send + 'static,
+    F: FnOnce() -> Result<R, StoreError> + Send + 'static,
+{
+    graph::spawn_blocking_allow_panic(f)
+        .await
+        .map_err(|e| StoreError::Unknown(format_err!("blocking task failed: {}", e)))?
+}
+
+impl ShardedStore {
+    /// Create a new sharded store. The `stores` must contain an entry for
+    /// the primary under the name `PRIMARY_SHARD`
+    pub fn new(stores: HashMap<String, Arc<Store>>, placer: Arc<dyn DeploymentPlacer>) -> Self {
+        let primary = stores
+            .get(PRIMARY_SHARD)
+            .expect("we always have a primary shard")
+            .clone();
+        Self {
+            primary,
+            stores,
+            placer,
+            shards: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The store for the primary shard
+    pub fn primary(&self) -> Arc<Store> {
+        self.primary.clone()
+    }
+
+    /// Return the name of the shard that stores the data for `id`
+    pub fn shard(&self, id: &SubgraphDeploymentId) -> Result<String, StoreError> {
+        if id.is_meta() {
+            return Ok(PRIMARY_SHARD.to_string());
+        }
+        if let Some(shard) = self.shards.lock().unwrap().get(id) {
+            return Ok(shard.clone());
+        }
+        let shard = self
+            .primary
+            .find_shard(id)?
+            .ok_or_else(|| StoreError::DeploymentNotFound(id.to_string()))?;
+        self.shards
+            .lock()
+            .unwrap()
+            .insert(id.clone(), shard.clone());
+        Ok(shard)
+    }
+
+    fn store_for_shard(&self, shard: &str) -> Result<&Arc<Store>, StoreError> {
+        self.stores
+            .get(shard)
+            .ok_or_else(|| StoreError::ConstraintViolation(format!("unknown shard {}", shard)))
+    }
+
+    /// Return the store that holds the data for `id`
+    fn store(&self, id: &SubgraphDeploymentId) -> Result<&Arc<Store>, StoreError> {
+        let shard = self.shard(id)?;
+        self.store_for_shard(&shard)
+    }
+
+    /// Return the store in which to look up the entity `key`. Metadata
+    /// for a deployment is kept with the deployment; the only metadata we
+    /// can route by key is the `SubgraphDeployment` itself. All other
+    /// metadata lookups by key go to the primary
+    fn store_for_key(&self, key: &EntityKey) -> Result<&Arc<Store>, StoreError> {
+        if key.subgraph_id.is_meta() {
+            if key.entity_type == MetadataType::SubgraphDeployment.as_str() {
+                match SubgraphDeploymentId::new(key.entity_id.clone()) {
+                    Ok(id) => match self.shard(&id) {
+                        Ok(shard) => self.store_for_shard(&shard),
+                        Err(StoreError::DeploymentNotFound(_)) => Ok(&self.primary),
+                        Err(e) => Err(e),
+                    },
+                    Err(_) => Ok(&self.primary),
+                }
+            } else {
+                Ok(&self.primary)
+            }
+        } else {
+            self.store(&key.subgraph_id)
+        }
+    }
+
+    /// Decide in which shard a new deployment should be created. A
+    /// deployment that already exists stays where it is, and a grafted
+    /// deployment has to be in the same shard as its graft base
+    fn place(
+        &self,
+        name: &SubgraphName,
+        id: &SubgraphDeploymentId,
+        deployment: &SubgraphDeploymentEntity,
+    ) -> Result<String, StoreError> {
+        if let Some(shard) = self.primary.find_shard(id)? {
+            return Ok(shard);
+        }
+        if let Some(base) = deployment.graft_base() {
+            return self.shard(base);
+        }
+        let network = deployment.network().unwrap_or("");
+        let shard = self
+            .placer
+            .place(name.as_str(), network)
+            .map_err(|msg| StoreError::ConstraintViolation(msg))?
+            .map(|(shard, _)| shard)
+            .unwrap_or_else(|| PRIMARY_SHARD.to_string());
+        // Make sure the shard exists
+        self.store_for_shard(&shard)?;
+        Ok(shard)
+    }
+
+    fn create_deployment_internal(
+        &self,
+        name: SubgraphName,
+        schema: &Schema,
+        deployment: SubgraphDeploymentEntity,
+        node_id: NodeId,
+        mode: SubgraphVersionSwitchingMode,
+        // replace == true is only used in tests; for non-test code, it must
+        // be 'false'
+        replace: bool,
+    ) -> Result<(), StoreError> {
+        let shard = self.place(&name, &schema.id, &deployment)?;
+        let store = self.store_for_shard(&shard)?;
+
+        store.create_deployment(schema, deployment, replace)?;
+        if shard != PRIMARY_SHARD {
+            self.primary.record_shard(&schema.id, &shard)?;
+        }
+        self.shards.lock().unwrap().insert(schema.id.clone(), shard);
+
+        self.primary
+            .create_subgraph_version(name, &schema.id, node_id, mode, |id| {
+                let id = SubgraphDeploymentId::new(id).map_err(|id| {
+                    StoreError::ConstraintViolation(format!("illegal deployment id {}", id))
+                })?;
+                match self.store(&id) {
+                    Ok(store) => store.exists_and_synced(id.as_str()),
+                    // A missing deployment is treated like one that
+                    // is not synced
+                    Err(StoreError::DeploymentNotFound(_)) => Ok(false),
+                    Err(e) => Err(e),
+                }
+            })
+    }
+
+    /// Remove the assignment of the deployment `id` so that no node will
+    /// index it
+    pub fn unassign_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        self.primary.unassign_subgraph(id)
+    }
+
+    /// Return the node to which the deployment `id` is assigned
+    pub fn assigned_node(&self, id: &SubgraphDeploymentId) -> Result<Option<NodeId>, StoreError> {
+        self.primary.assigned_node(id)
+    }
+
+    /// Return all deployments that are neither the current nor the pending
+    /// version of any subgraph
+    pub fn unused_deployments(&self) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+        self.primary.unused_deployments()
+    }
+
+    /// Delete the deployment `id` with all its data and metadata. The
+    /// caller must make sure that the deployment is not used by any
+    /// subgraph, e.g., by checking `unused_deployments`
+    ///
+    /// The deployment is unassigned first, then dropped from its shard, and
+    /// only then does the primary forget about it. If any of these steps
+    /// fails, the primary still knows where the deployment is stored, and
+    /// the removal can simply be run again
+    pub fn remove_deployment(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let shard = self.shard(id)?;
+        let store = self.store_for_shard(&shard)?;
+
+        self.primary.unassign_subgraph(id)?;
+        store.drop_deployment(id)?;
+        if shard != PRIMARY_SHARD {
+            self.primary.forget_shard(id)?;
+        }
+        self.shards.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    /// Set how many blocks of history the deployment `id` keeps. With
+    /// `None`, the deployment keeps all of its history. Versions older
+    /// than that are removed the next time deployments are pruned
+    pub fn set_history_blocks(
+        &self,
+        id: &SubgraphDeploymentId,
+        history_blocks: Option<BlockNumber>,
+    ) -> Result<(), StoreError> {
+        let store = self.store(id)?;
+        store.set_history_blocks(id, history_blocks)
+    }
+
+    /// Prune the history of all deployments in all shards that limit how
+    /// much history they keep
+    pub fn prune(&self, logger: &Logger) -> Result<(), StoreError> {
+        for store in self.stores.values() {
+            store.prune(logger)?;
+        }
+        Ok(())
+    }
+
+    /// Start a background task that prunes the history of deployments
+    /// every `GRAPH_STORE_PRUNE_INTERVAL` seconds. Does nothing if that
+    /// is not set, so that only the nodes that are configured for it prune
+    pub fn start_pruning(self: Arc<Self>, logger: &Logger) {
+        let interval = *PRUNE_INTERVAL;
+        if interval == Duration::from_secs(0) {
+            return;
+        }
+
+        let logger = logger.new(o!("component" => "HistoryPruner"));
+        graph::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                let store = self.clone();
+                let logger = logger.clone();
+                // Pruning can take a long time and only uses blocking
+                // database calls
+                let _ = graph::spawn_blocking_allow_panic(move || {
+                    if let Err(e) = store.prune(&logger) {
+                        error!(logger, "Failed to prune deployment history";
+                               "error" => e.to_string());
+                    }
+                })
+                .await;
+            }
+        });
+    }
+
+    /// Copy the deployment `id` into a new database schema in `shard` and
+    /// make the copy the storage for the deployment. The shard can be the
+    /// one the deployment is in already. The deployment keeps indexing
+    /// while most of the data is copied, and is only paused for the final
+    /// switch over to the copy. Other processes learn about the switch
+    /// through a store event and forget where they thought the deployment
+    /// was stored. An earlier copy of the deployment that was interrupted
+    /// is finished or undone first
+    pub async fn copy_deployment(
+        &self,
+        logger: &Logger,
+        id: &SubgraphDeploymentId,
+        shard: &str,
+    ) -> Result<(), StoreError> {
+        let logger = logger.new(o!("component" => "DeploymentCopy"));
+        self.recover_copy(&logger, id)?;
+
+        let src_shard = self.shard(id)?;
+        let src = self.store_for_shard(&src_shard)?.clone();
+        let dst = self.store_for_shard(shard)?.clone();
+
+        let copy = {
+            let logger = logger.clone();
+            let id = id.clone();
+            let primary = self.primary.clone();
+            let dst_shard = shard.to_owned();
+            blocking(move || {
+                let mut copy =
+                    DeploymentCopy::new(&logger, &id, primary, &src_shard, src, &dst_shard, dst)?;
+                if let Err(e) = copy.copy_data().and_then(|_| copy.catch_up()) {
+                    copy.abandon()?;
+                    return Err(e);
+                }
+                Ok(copy)
+            })
+            .await?
+        };
+
+        // Keep the deployment from fighting with us over locks while we
+        // switch to the copy
+        let pause = self.primary.assigned_node(id)?.is_some() && !self.primary.is_paused(id)?;
+        if pause {
+            self.primary.pause_subgraph(id)?;
+        }
+        let res = match copy.wait_for_stop().await {
+            Ok(block) => blocking(move || copy.finish(block)).await,
+            Err(e) => copy.abandon().and(Err(e)),
+        };
+        self.forget_location(id);
+        if pause {
+            self.primary.resume_subgraph(id)?;
+        }
+        res
+    }
+
+    /// Finish or undo a copy of the deployment `id` that was interrupted,
+    /// for example because the process doing the copy was killed. Does
+    /// nothing if no copy of `id` is in progress
+    fn recover_copy(&self, logger: &Logger, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let state = match self.primary.find_copy(id)? {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        let src = self.store_for_shard(&state.src_shard)?;
+        let dst = self.store_for_shard(&state.dst_shard)?;
+        copy::recover(logger, &self.primary, src, dst, id, &state)?;
+        self.forget_location(id);
+        Ok(())
+    }
+
+    /// Remove everything we have cached about where and how the data for
+    /// the deployment `id` is stored
+    fn forget_location(&self, id: &SubgraphDeploymentId) {
+        forget_location(&self.shards, &self.stores, id);
+    }
+
+    /// Start a background task that makes this store forget the location
+    /// of deployments whenever any process announces that it switched a
+    /// deployment to a copy. Without it, this store keeps using the old
+    /// location of a deployment that another process copied
+    pub fn start_forgetting_copied_deployments(&self) {
+        let shards = self.shards.clone();
+        let stores = self.stores.clone();
+        let events = self.subscribe(vec![(
+            SUBGRAPHS_ID.clone(),
+            e::DEPLOYMENT_SCHEMA.to_owned(),
+        )]);
+        graph::spawn(
+            events
+                .for_each(move |event| {
+                    for change in &event.changes {
+                        if let Ok(id) = SubgraphDeploymentId::new(change.entity_id.clone()) {
+                            forget_location(&shards, &stores, &id);
+                        }
+                    }
+                    Ok(())
+                })
+                .compat(),
+        );
+    }
+
+    /// Export the entities of the deployment `id` as of `block`, or as of
+    /// its latest block, to CSV files in the directory `dir` and return
+    /// the files that were written
+    pub fn export(
+        &self,
+        logger: &Logger,
+        id: &SubgraphDeploymentId,
+        block: Option<BlockNumber>,
+        dir: &Path,
+    ) -> Result<Vec<PathBuf>, StoreError> {
+        let store = self.store(id)?;
+        store.export(logger, id, block, dir)
+    }
+
+    /// Return the relational layout of the deployment `id`
+    pub fn layout(&self, id: &SubgraphDeploymentId) -> Result<Arc<Layout>, StoreError> {
+        let store = self.store(id)?;
+        store.layout(id)
+    }
+
+    // Only for tests to simplify their handling of test fixtures, so that
+    // tests can reset the block pointer of a subgraph by recreating it
+    #[cfg(debug_assertions)]
+    pub fn create_deployment_replace(
+        &self,
+        name: SubgraphName,
+        schema: &Schema,
+        deployment: SubgraphDeploymentEntity,
+        node_id: NodeId,
+        mode: SubgraphVersionSwitchingMode,
+    ) -> Result<(), StoreError> {
+        self.create_deployment_internal(name, schema, deployment, node_id, mode, true)
+    }
+
+    // Only needed for tests
+    #[cfg(debug_assertions)]
+    pub(crate) fn clear_storage_cache(&self) {
+        for store in self.stores.values() {
+            store.storage_cache.lock().unwrap().clear();
+        }
+        self.shards.lock().unwrap().clear();
+    }
+}
+
+impl StoreTrait for ShardedStore {
+    fn block_ptr(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+    ) -> Result<Option<EthereumBlockPointer>, Error> {
+        let store = self.store(&subgraph_id)?;
+        store.block_ptr(subgraph_id)
+    }
+
+    fn supports_proof_of_indexing<'a>(
+        &'a self,
+        subgraph_id: &'a SubgraphDeploymentId,
+    ) -> DynTryFuture<'a, bool> {
+        let store = match self.store(subgraph_id) {
+            Ok(store) => store,
+            Err(e) => return async move { Err(e.into()) }.boxed(),
+        };
+        store.supports_proof_of_indexing(subgraph_id)
+    }
+
+    fn get_proof_of_indexing<'a>(
+        &'a self,
+        subgraph_id: &'a SubgraphDeploymentId,
+        indexer: &'a Option<Address>,
+        block_hash: H256,
+    ) -> DynTryFuture<'a, Option<[u8; 32]>> {
+        let store = match self.store(subgraph_id) {
+            Ok(store) => store,
+            Err(e) => return async move { Err(e.into()) }.boxed(),
+        };
+        store.get_proof_of_indexing(subgraph_id, indexer, block_hash)
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        let store = self.store_for_key(&key)?;
+        store.get(key)
+    }
+
+    fn get_many(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        ids_for_type: BTreeMap<&str, Vec<&str>>,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        let store = self.store(subgraph_id)?;
+        store.get_many(subgraph_id, ids_for_type)
+    }
+
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        let store = self.store(&query.subgraph_id)?;
+        store.find(query)
+    }
+
+    fn find_one(&self, query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+        let store = self.store(&query.subgraph_id)?;
+        store.find_one(query)
+    }
+
+    fn find_ens_name(&self, hash: &str) -> Result<Option<String>, QueryExecutionError> {
+        self.primary.find_ens_name(hash)
+    }
+
+    fn transact_block_operations(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptr_to: EthereumBlockPointer,
+        mods: Vec<EntityModification>,
+        stopwatch: StopwatchMetrics,
+        deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<bool, StoreError> {
+        let store = self.store(&subgraph_id)?;
+        store.transact_block_operations(
+            subgraph_id,
+            block_ptr_to,
+            mods,
+            stopwatch,
+            deterministic_errors,
+        )
+    }
+
+    fn apply_metadata_operations(
+        &self,
+        target_deployment: &SubgraphDeploymentId,
+        operations: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        let store = self.store(target_deployment)?;
+        store.apply_metadata_operations(target_deployment, operations)
+    }
+
+    fn revert_block_operations(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptr_from: EthereumBlockPointer,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        let store = self.store(&subgraph_id)?;
+        store.revert_block_operations(subgraph_id, block_ptr_from, block_ptr_to)
+    }
+
+    fn rewind(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        // Make sure that nothing tries to index the deployment while we
+        // rewind it
+        if let Some(node) = self.primary.assigned_node(&subgraph_id)? {
+            if !self.primary.is_paused(&subgraph_id)? {
+                return Err(StoreError::ConstraintViolation(format!(
+                    "deployment {} is assigned to node {} and must be paused \
+                     or unassigned before it can be rewound",
+                    subgraph_id, node
+                )));
+            }
+        }
+        let store = self.store(&subgraph_id)?;
+        store.rewind(subgraph_id, block_ptr_to)
+    }
+
+    fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+        // Each shard only sends notifications about changes in its own
+        // database; merge the events from all of them
+        let mut streams = self
+            .stores
+            .values()
+            .map(|store| store.subscribe(entities.clone()));
+        let first: EventStream = Box::new(streams.next().expect("we always have a primary shard"));
+        let merged = streams.fold(first, |merged, stream| -> EventStream {
+            Box::new(merged.select(stream))
+        });
+        StoreEventStream::new(merged)
+    }
+
+    fn deployment_state_from_name(
+        &self,
+        name: SubgraphName,
+    ) -> Result<DeploymentState, StoreError> {
+        let id = self.primary.current_deployment_for_subgraph(name)?;
+        self.deployment_state_from_id(id)
+    }
+
+    fn deployment_state_from_id(
+        &self,
+        id: SubgraphDeploymentId,
+    ) -> Result<DeploymentState, StoreError> {
+        let store = self.store(&id)?;
+        store.deployment_state_from_id(id)
+    }
+
+    fn create_subgraph_deployment(
+        &self,
+        name: SubgraphName,
+        schema: &Schema,
+        deployment: SubgraphDeploymentEntity,
+        node_id: NodeId,
+        mode: SubgraphVersionSwitchingMode,
+    ) -> Result<(), StoreError> {
+        self.create_deployment_internal(name, schema, deployment, node_id, mode, false)
+    }
+
+    fn create_subgraph(&self, name: SubgraphName) -> Result<String, StoreError> {
+        self.primary.create_subgraph(name)
+    }
+
+    fn remove_subgraph(&self, name: SubgraphName) -> Result<(), StoreError> {
+        self.primary.remove_subgraph(name)
+    }
+
+    fn reassign_subgraph(
+        &self,
+        id: &SubgraphDeploymentId,
+        node: &NodeId,
+    ) -> Result<(), StoreError> {
+        self.primary.reassign_subgraph(id, node)
+    }
+
+    fn pause_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        self.primary.pause_subgraph(id)
+    }
+
+    fn resume_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        self.primary.resume_subgraph(id)
+    }
+
+    fn set_running(&self, id: &SubgraphDeploymentId, running: bool) -> Result<(), StoreError> {
+        self.primary.set_running(id, running)
+    }
+
+    fn start_subgraph_deployment(
+        &self,
+        logger: &Logger,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<(), StoreError> {
+        self.forget_location(subgraph_id);
+        let store = self.store(subgraph_id)?;
+        store.start_subgraph_deployment(logger, subgraph_id)
+    }
+
+    fn unfail(&self, subgraph_id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let store = self.store(subgraph_id)?;
+        store.unfail(subgraph_id)
+    }
+
+    fn migrate_subgraph_deployment(
+        &self,
+        logger: &Logger,
+        subgraph_id: &SubgraphDeploymentId,
+        block_ptr: &EthereumBlockPointer,
+    ) {
+        match self.store(subgraph_id) {
+            Ok(store) => store.migrate_subgraph_deployment(logger, subgraph_id, block_ptr),
+            Err(e) => warn!(logger, "failed to find shard to start migrating";
+                            "subgraph" => subgraph_id.to_string(),
+                            "error" => e.to_string(),
+            ),
+        }
+    }
+
+    fn block_number(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_hash: H256,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        let store = self.store(subgraph_id)?;
+        let network = store.network_name(subgraph_id)?;
+        self.primary
+            .chain_block_number(subgraph_id, network, block_hash)
+    }
+
+    fn query_store(
+        self: Arc<Self>,
+        id: &SubgraphDeploymentId,
+        for_subscription: bool,
+    ) -> Result<Arc<dyn QueryStoreTrait + Send + Sync>, StoreError> {
+        let store = self.store(id)?.clone();
+        store.query_store(id, for_subscription)
+    }
+
+    fn deployment_synced(&self, id: &SubgraphDeploymentId) -> Result<(), Error> {
+        let store = self.store(id)?;
+        self.primary.promote_pending_version(id)?;
+        store.set_synced(id)?;
+        Ok(())
+    }
+
+    fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError> {
+        let deployments = match self.primary.deployments_for_filter(filter)? {
+            Some(deployments) => deployments,
+            None => return Ok(vec![]),
+        };
+
+        if deployments.is_empty() {
+            // Ask every shard for the status of all its deployments
+            let mut infos = vec![];
+            for (shard, store) in &self.stores {
+                let mut shard_infos = store.deployment_statuses(vec![])?;
+                if shard != PRIMARY_SHARD {
+                    self.primary.fill_status_from_primary(&mut shard_infos)?;
+                }
+                infos.extend(shard_infos);
+            }
+            return Ok(infos);
+        }
+
+        // Group the deployments by the shard they live in. Deployments
+        // that we do not know about are simply left out
+        let mut by_shard: HashMap<String, Vec<String>> = HashMap::new();
+        for deployment in &deployments {
+            let id = match SubgraphDeploymentId::new(deployment.clone()) {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            match self.shard(&id) {
+                Ok(shard) => by_shard.entry(shard).or_default().push(deployment.clone()),
+                Err(StoreError::DeploymentNotFound(_)) => { /* skip */ }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut infos = vec![];
+        for (shard, ids) in by_shard {
+            let mut shard_infos = self.store_for_shard(&shard)?.deployment_statuses(ids)?;
+            if shard != PRIMARY_SHARD {
+                self.primary.fill_status_from_primary(&mut shard_infos)?;
+            }
+            infos.extend(shard_infos);
+        }
+
+        // Return statuses in the order in which the deployments were given
+        infos.sort_by_key(|info| {
+            deployments
+                .iter()
+                .position(|deployment| deployment == &info.subgraph)
+        });
+        Ok(infos)
+    }
+}
+
+impl SubgraphDeploymentStore for ShardedStore {
+    fn input_schema(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error> {
+        let store = self.store(subgraph_id)?;
+        store.input_schema(subgraph_id)
+    }
+
+    fn api_schema(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Arc<ApiSchema>, Error> {
+        let store = self.store(subgraph_id)?;
+        store.api_schema(subgraph_id)
+    }
+
+    fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error> {
+        let store = self.store(subgraph_id)?;
+        store.network_name(subgraph_id)
+    }
+}
+
+impl EthereumCallCache for ShardedStore {
+    fn get_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.primary.get_call(contract_address, encoded_call, block)
+    }
+
+    fn set_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+        return_value: &[u8],
+    ) -> Result<(), Error> {
+        self.primary
+            .set_call(contract_address, encoded_call, block, return_value)
+    }
+}
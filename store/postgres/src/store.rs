@@ -16,14 +16,16 @@ use std::collections::{BTreeMap, HashMap};
 use std::convert::{TryFrom, TryInto};
 use std::iter::FromIterator;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
 use std::time::Instant;
 use tokio::sync::Semaphore;
 
+use graph::components::ethereum::REORG_THRESHOLD;
 use graph::components::store::{EntityCollection, QueryStore, Store as StoreTrait};
 use graph::components::subgraph::ProofOfIndexingFinisher;
 use graph::data::subgraph::schema::{
-    SubgraphDeploymentEntity, TypedEntity as _, POI_OBJECT, SUBGRAPHS_ID,
+    SubgraphDeploymentEntity, SubgraphError, TypedEntity as _, POI_OBJECT, SUBGRAPHS_ID,
 };
 use graph::prelude::{
     debug, ethabi, format_err, futures03, info, o, tiny_keccak, tokio, trace, warn, web3,
@@ -38,11 +40,12 @@ use graph::prelude::{
 use graph_graphql::prelude::api_schema;
 use web3::types::{Address, H256};
 
+use crate::block_range::block_number;
 use crate::metadata;
 use crate::relational::Layout;
 use crate::relational_queries::FromEntityData;
 use crate::store_events::SubscriptionManager;
-use crate::{connection_pool::ConnectionPool, detail, entities as e};
+use crate::{connection_pool::ConnectionPool, detail, entities as e, export};
 
 lazy_static! {
     static ref CONNECTION_LIMITER: Semaphore = {
@@ -160,7 +163,11 @@ pub struct StoreInner {
     logger: Logger,
     subscriptions: Arc<SubscriptionManager>,
 
+    /// The name of the shard this store manages
+    pub(crate) shard: String,
     conn: ConnectionPool,
+    /// The pool for the database that holds chain data, i.e., the primary
+    chain_conn: ConnectionPool,
     read_only_pools: Vec<ConnectionPool>,
     replica_order: Vec<ReplicaId>,
     conn_round_robin_counter: AtomicUsize,
@@ -190,16 +197,21 @@ impl Deref for Store {
 }
 
 impl Store {
+    /// Create a store for the shard `shard` whose data lives in `pool`.
+    /// Chain data, like the `ethereum_blocks` table, is read from
+    /// `chain_pool`, which must be the pool for the primary
     pub fn new(
         logger: &Logger,
+        shard: String,
         subscriptions: Arc<SubscriptionManager>,
         pool: ConnectionPool,
+        chain_pool: ConnectionPool,
         read_only_pools: Vec<ConnectionPool>,
         mut pool_weights: Vec<usize>,
         registry: Arc<dyn MetricsRegistry>,
     ) -> Self {
         // Create a store-specific logger
-        let logger = logger.new(o!("component" => "Store"));
+        let logger = logger.new(o!("component" => "Store", "shard" => shard.clone()));
 
         // Create the entities table (if necessary)
         initiate_schema(&logger, &pool.get().unwrap(), &pool.get().unwrap());
@@ -229,7 +241,9 @@ impl Store {
         let store = StoreInner {
             logger: logger.clone(),
             subscriptions,
+            shard,
             conn: pool,
+            chain_conn: chain_pool,
             read_only_pools,
             replica_order,
             conn_round_robin_counter: AtomicUsize::new(0),
@@ -615,7 +629,9 @@ impl Store {
     }
 
     /// Deprecated. Use `with_conn` instead.
-    fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<PgConnection>>, Error> {
+    pub(crate) fn get_conn(
+        &self,
+    ) -> Result<PooledConnection<ConnectionManager<PgConnection>>, Error> {
         self.conn.get().map_err(Error::from)
     }
 
@@ -704,8 +720,21 @@ impl Store {
             metadata::deployment_graft(&conn, &subgraph_id)?.map(|(_, ptr)| ptr.number as i32);
 
         // Generate an API schema for the subgraph and make sure all types in the
-        // API schema have a @subgraphId directive as well
+        // API schema have a @subgraphId directive as well. Types imported
+        // from other deployments become part of the API schema, too
         let mut schema = input_schema.clone();
+        match metadata::imported_schemas(&conn, &input_schema)
+            .map_err(Error::from)
+            .and_then(|schemas| schema.add_imported_types(&schemas).map_err(Error::from))
+        {
+            Ok(()) => (),
+            Err(e) => warn!(
+                self.logger,
+                "Imported types can not be queried";
+                "subgraph" => subgraph_id.as_str(),
+                "error" => e.to_string()
+            ),
+        }
         schema.document = api_schema(&schema.document)?;
         schema.add_subgraph_id_directives(subgraph_id.clone());
 
@@ -764,13 +793,12 @@ impl Store {
         }
     }
 
-    fn create_deployment_internal(
+    /// Create the metadata and the database schema for the deployment
+    /// `schema.id` in this store unless they already exist
+    pub(crate) fn create_deployment(
         &self,
-        name: SubgraphName,
         schema: &Schema,
         deployment: SubgraphDeploymentEntity,
-        node_id: NodeId,
-        mode: SubgraphVersionSwitchingMode,
         // replace == true is only used in tests; for non-test code, it must
         // be 'false'
         replace: bool,
@@ -781,7 +809,7 @@ impl Store {
         let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
         econn.transaction(|| -> Result<(), StoreError> {
             let exists = metadata::deployment_exists(&econn.conn, &schema.id)?;
-            let mut event = if replace || !exists {
+            let event = if replace || !exists {
                 let ops = deployment.create_operations(&schema.id);
                 self.apply_metadata_operations_with_conn(&econn, ops)?
             } else {
@@ -789,58 +817,354 @@ impl Store {
             };
 
             if !exists {
-                econn.create_schema(schema)?;
+                econn.create_schema(schema, &self.shard)?;
             }
 
-            // Create subgraph, subgraph version, and assignment
-            let changes =
-                metadata::create_subgraph_version(&econn.conn, name, &schema.id, node_id, mode)?;
-            event.changes.extend(changes);
-
             econn.send_store_event(&event)
         })
     }
 
-    // Only for tests to simplify their handling of test fixtures, so that
-    // tests can reset the block pointer of a subgraph by recreating it
-    #[cfg(debug_assertions)]
-    pub fn create_deployment_replace(
+    /// Create a subgraph, subgraph version, and assignment for the
+    /// deployment `id`. Must only be called on the primary. Since the
+    /// deployments of the subgraph can live in other shards,
+    /// `exists_and_synced` is used to check whether a deployment is synced
+    pub(crate) fn create_subgraph_version<F>(
         &self,
         name: SubgraphName,
-        schema: &Schema,
-        deployment: SubgraphDeploymentEntity,
+        id: &SubgraphDeploymentId,
         node_id: NodeId,
         mode: SubgraphVersionSwitchingMode,
+        exists_and_synced: F,
+    ) -> Result<(), StoreError>
+    where
+        F: Fn(&str) -> Result<bool, StoreError>,
+    {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        econn.transaction(|| -> Result<(), StoreError> {
+            let changes = metadata::create_subgraph_version(
+                &econn.conn,
+                name,
+                id,
+                node_id,
+                mode,
+                exists_and_synced,
+            )?;
+            econn.send_store_event(&StoreEvent::new(changes))
+        })
+    }
+
+    /// Return `true` if the deployment `id` exists in this store and is
+    /// synced
+    pub(crate) fn exists_and_synced(&self, id: &str) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::exists_and_synced(&conn, id)
+    }
+
+    /// Return the name of the shard in which the deployment `id` is
+    /// stored, according to this store's `deployment_schemas` table
+    pub(crate) fn find_shard(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Option<String>, StoreError> {
+        let conn = self.get_conn()?;
+        e::find_shard(&conn, id)
+    }
+
+    /// Remember that the deployment `id` is stored in `shard`
+    pub(crate) fn record_shard(
+        &self,
+        id: &SubgraphDeploymentId,
+        shard: &str,
     ) -> Result<(), StoreError> {
-        self.create_deployment_internal(name, schema, deployment, node_id, mode, true)
+        let conn = self.get_conn()?;
+        e::record_shard(&conn, id, shard)
     }
 
-    pub(crate) fn status_internal(
+    /// Forget that the deployment `id` is stored in another shard. Must
+    /// only be called on the primary, and only after the deployment has
+    /// been dropped from that shard
+    pub(crate) fn forget_shard(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        e::forget_shard(&conn, id)
+    }
+
+    /// Remove the assignment for the deployment `id`. Must only be called
+    /// on the primary
+    pub(crate) fn unassign_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        econn.transaction(|| -> Result<(), StoreError> {
+            let changes = metadata::unassign_subgraph(&econn.conn, id)?;
+            let event = StoreEvent::new(changes);
+            econn.send_store_event(&event)
+        })
+    }
+
+    /// Pause or resume the deployment `id`. Must only be called on the
+    /// primary
+    fn set_paused(&self, id: &SubgraphDeploymentId, paused: bool) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        econn.transaction(|| -> Result<(), StoreError> {
+            let changes = metadata::set_paused(&econn.conn, id, paused)?;
+            let event = StoreEvent::new(changes);
+            econn.send_store_event(&event)
+        })
+    }
+
+    /// Return `true` if the deployment `id` is paused. Must only be called
+    /// on the primary
+    pub(crate) fn is_paused(&self, id: &SubgraphDeploymentId) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::is_paused(&conn, id)
+    }
+
+    /// Return the copy of the deployment `id` that is in progress, if
+    /// there is one. Must only be called on the primary
+    pub(crate) fn find_copy(
         &self,
-        filter: status::Filter,
-    ) -> Result<Vec<status::Info>, StoreError> {
+        id: &SubgraphDeploymentId,
+    ) -> Result<Option<e::CopyState>, StoreError> {
         let conn = self.get_conn()?;
-        conn.transaction(|| -> Result<Vec<status::Info>, StoreError> {
-            match filter {
-                status::Filter::SubgraphName(name) => {
-                    let deployments = detail::deployments_for_subgraph(&conn, name)?;
-                    if deployments.is_empty() {
-                        Ok(Vec::new())
-                    } else {
-                        detail::deployment_statuses(&conn, deployments)
-                    }
+        e::find_copy(&conn, id.as_str())
+    }
+
+    /// Return `true` if an indexer for the deployment `id` is still
+    /// running. Must only be called on the primary
+    pub(crate) fn is_running(&self, id: &SubgraphDeploymentId) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        e::is_running(&conn, id)
+    }
+
+    /// Return the node that the deployment `id` is assigned to. Must only
+    /// be called on the primary
+    pub(crate) fn assigned_node(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Option<NodeId>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::assigned_node(&conn, id)
+    }
+
+    /// Return the deployments that are not used by any subgraph. Must only
+    /// be called on the primary
+    pub(crate) fn unused_deployments(&self) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::unused_deployments(&conn)
+    }
+
+    /// Delete the data and all metadata for the deployment `id` from this
+    /// store. This can not be undone
+    pub(crate) fn drop_deployment(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        econn.transaction(|| -> Result<(), StoreError> {
+            let event = econn.remove_metadata(id)?;
+            metadata::remove_subgraph_errors(&econn.conn, id)?;
+            e::drop_schema(&econn.conn, id)?;
+            econn.send_store_event(&event)
+        })?;
+        self.storage_cache.lock().unwrap().remove(id);
+        self.subgraph_cache.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    /// Set how many blocks of history the deployment `id` keeps; with
+    /// `None`, it keeps all of its history. Must be called on the store
+    /// for the shard that holds the deployment
+    pub(crate) fn set_history_blocks(
+        &self,
+        id: &SubgraphDeploymentId,
+        history_blocks: Option<BlockNumber>,
+    ) -> Result<(), StoreError> {
+        if let Some(history_blocks) = history_blocks {
+            if history_blocks <= *REORG_THRESHOLD {
+                return Err(StoreError::Unknown(format_err!(
+                    "deployments must keep more than {} blocks of history so that \
+                     reorgs can be reverted, but {} blocks were requested",
+                    *REORG_THRESHOLD,
+                    history_blocks
+                )));
+            }
+        }
+        let conn = self.get_conn()?;
+        metadata::set_history_blocks(&conn, id, history_blocks)
+    }
+
+    /// Prune the history of all deployments in this store that limit how
+    /// much history they keep and have advanced far enough since they were
+    /// last pruned. Each deployment is pruned in its own transaction
+    pub(crate) fn prune(&self, logger: &Logger) -> Result<(), StoreError> {
+        let deployments = {
+            let conn = self.get_conn()?;
+            metadata::deployments_to_prune(&conn)?
+        };
+        for (id, horizon) in deployments {
+            let start = Instant::now();
+            let econn = self.get_entity_conn(&id, ReplicaId::Main)?;
+            let count = econn.transaction(|| econn.prune(horizon))?;
+            info!(logger, "Pruned deployment history";
+                  "subgraph" => id.as_str(),
+                  "horizon" => horizon,
+                  "versions" => count,
+                  "time_ms" => start.elapsed().as_millis());
+        }
+        Ok(())
+    }
+
+    /// Export the entities of the deployment `id` that are visible at
+    /// `block` to CSV files in the directory `dir`. Without a `block`,
+    /// export the entities at the latest block of the deployment
+    pub(crate) fn export(
+        &self,
+        logger: &Logger,
+        id: &SubgraphDeploymentId,
+        block: Option<BlockNumber>,
+        dir: &Path,
+    ) -> Result<Vec<PathBuf>, StoreError> {
+        let layout = self.layout(id)?;
+        let conn = self.get_conn()?;
+        // Make sure the history we export is not pruned or reverted
+        // while we read it
+        conn.build_transaction()
+            .read_only()
+            .repeatable_read()
+            .run(|| {
+                let latest = metadata::latest_block_number(&conn, id)?;
+                let horizon = metadata::history_horizon(&conn, id)?;
+                let block = block.unwrap_or(latest);
+                if block < horizon || block > latest {
+                    return Err(StoreError::Unknown(format_err!(
+                        "deployment {} can only be exported at blocks {} to {}, \
+                         but block {} was requested",
+                        id,
+                        horizon,
+                        latest,
+                        block
+                    )));
                 }
-                status::Filter::SubgraphVersion(name, use_current) => {
-                    match detail::subgraph_version(&conn, name, use_current)? {
-                        Some(deployment) => detail::deployment_statuses(&conn, vec![deployment]),
-                        None => Ok(vec![]),
-                    }
+                export::export(logger, &conn, &layout, block, dir)
+            })
+    }
+
+    /// Return the relational layout of the deployment `id`
+    pub(crate) fn layout(&self, id: &SubgraphDeploymentId) -> Result<Arc<Layout>, StoreError> {
+        let conn = self.get_conn()?;
+        self.storage(&conn, id)
+    }
+
+    pub(crate) fn current_deployment_for_subgraph(
+        &self,
+        name: SubgraphName,
+    ) -> Result<SubgraphDeploymentId, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::current_deployment_for_subgraph(&conn, name)
+    }
+
+    pub(crate) fn set_synced(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        metadata::set_synced(&conn, id)
+    }
+
+    pub(crate) fn promote_pending_version(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        econn.transaction(|| {
+            let changes = metadata::promote_pending_version(&econn.conn, id)?;
+            econn.send_store_event(&StoreEvent::new(changes))
+        })
+    }
+
+    /// Look up the block number for the block with `hash`, and check that
+    /// the block belongs to `subgraph_network`
+    pub(crate) fn chain_block_number(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        subgraph_network: Option<String>,
+        hash: H256,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        use crate::db_schema::ethereum_blocks::dsl;
+
+        // We should also really check that the block with the given hash is
+        // on the chain starting at the subgraph's current head. That check is
+        // very expensive though with the data structures we have currently
+        // available. Ideally, we'd have the last REORG_THRESHOLD blocks in
+        // memory so that we can check against them, and then mark in the
+        // database the blocks on the main chain that we consider final
+        let block: Option<(i64, String)> = dsl::ethereum_blocks
+            .select((dsl::number, dsl::network_name))
+            .filter(dsl::hash.eq(format!("{:x}", hash)))
+            .first(&*self.chain_conn.get().map_err(Error::from)?)
+            .optional()?;
+        block
+            .map(|(number, network_name)| {
+                if subgraph_network.is_none() || Some(&network_name) == subgraph_network.as_ref() {
+                    BlockNumber::try_from(number)
+                        .map_err(|e| StoreError::QueryExecutionError(e.to_string()))
+                } else {
+                    Err(StoreError::QueryExecutionError(format!(
+                        "subgraph {} belongs to network {} but block {:x} belongs to network {}",
+                        subgraph_id,
+                        subgraph_network.unwrap_or("(none)".to_owned()),
+                        hash,
+                        network_name
+                    )))
                 }
-                status::Filter::Deployments(deployments) => {
-                    detail::deployment_statuses(&conn, deployments)
+            })
+            .transpose()
+    }
+
+    /// Resolve `filter` to the deployments it refers to. An empty list
+    /// means 'all deployments'; `None` means that `filter` does not match
+    /// any deployment. Must only be called on the primary
+    pub(crate) fn deployments_for_filter(
+        &self,
+        filter: status::Filter,
+    ) -> Result<Option<Vec<String>>, StoreError> {
+        let conn = self.get_conn()?;
+        match filter {
+            status::Filter::SubgraphName(name) => {
+                let deployments = detail::deployments_for_subgraph(&conn, name)?;
+                if deployments.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(deployments))
                 }
             }
-        })
+            status::Filter::SubgraphVersion(name, use_current) => {
+                Ok(detail::subgraph_version(&conn, name, use_current)?.map(|d| vec![d]))
+            }
+            status::Filter::Deployments(deployments) => Ok(Some(deployments)),
+        }
+    }
+
+    /// Return the status of the `deployments` that are stored in this
+    /// store. An empty list of `deployments` means 'all deployments'
+    pub(crate) fn deployment_statuses(
+        &self,
+        deployments: Vec<String>,
+    ) -> Result<Vec<status::Info>, StoreError> {
+        let conn = self.get_conn()?;
+        detail::deployment_statuses(&conn, deployments)
+    }
+
+    /// Fill in assignments and chain heads for `infos`. Must only be called
+    /// on the primary
+    pub(crate) fn fill_status_from_primary(
+        &self,
+        infos: &mut [status::Info],
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        detail::fill_from_primary(&conn, infos)
+    }
+
+    pub(crate) fn status_internal(
+        &self,
+        filter: status::Filter,
+    ) -> Result<Vec<status::Info>, StoreError> {
+        match self.deployments_for_filter(filter)? {
+            Some(deployments) => self.deployment_statuses(deployments),
+            None => Ok(vec![]),
+        }
     }
 }
 
@@ -934,6 +1258,8 @@ impl StoreTrait for Store {
                                 EntityRange {
                                     first: None,
                                     skip: 0,
+                                    after: None,
+                                    before: None,
                                 },
                                 block_number.try_into().unwrap(),
                                 None,
@@ -1049,6 +1375,7 @@ impl StoreTrait for Store {
         block_ptr_to: EthereumBlockPointer,
         mods: Vec<EntityModification>,
         stopwatch: StopwatchMetrics,
+        deterministic_errors: Vec<SubgraphError>,
     ) -> Result<bool, StoreError> {
         // All operations should apply only to entities in this subgraph or
         // the subgraph of subgraphs
@@ -1079,6 +1406,12 @@ impl StoreTrait for Store {
 
                 let should_migrate = econn.should_migrate(&subgraph_id, &block_ptr_to)?;
 
+                // Update rollups alongside the entities they summarize so
+                // that reverting the block also reverts the rollups
+                let mut mods = mods;
+                let rollup_mods = econn.rollup_modifications(&mods)?;
+                mods.extend(rollup_mods);
+
                 // Emit a store event for the changes we are about to make. We
                 // wait with sending it until we have done all our other work
                 // so that we do not hold a lock on the notification queue
@@ -1090,6 +1423,13 @@ impl StoreTrait for Store {
                 self.apply_entity_modifications(&econn, mods, Some(&block_ptr_to), stopwatch)?;
                 section.end();
 
+                metadata::insert_subgraph_errors(
+                    &econn.conn,
+                    &subgraph_id,
+                    deterministic_errors,
+                    block_number(&block_ptr_to),
+                )?;
+
                 let metadata_event =
                     metadata::forward_block_ptr(&econn.conn, &subgraph_id, block_ptr_to)?;
                 Ok((event, metadata_event, should_migrate))
@@ -1166,6 +1506,70 @@ impl StoreTrait for Store {
         })
     }
 
+    fn rewind(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        // Don't rewind past a graft point
+        let info = self.subgraph_info(&subgraph_id)?;
+        if let Some(graft_block) = info.graft_block {
+            if graft_block as u64 > block_ptr_to.number {
+                return Err(format_err!(
+                    "Can not rewind subgraph `{}` to block {} as it was \
+                    grafted at block {} and reverting past a graft point \
+                    is not possible",
+                    subgraph_id,
+                    block_ptr_to.number,
+                    graft_block
+                )
+                .into());
+            }
+        }
+
+        let block = BlockNumber::try_from(block_ptr_to.number + 1)
+            .map_err(|e| StoreError::QueryExecutionError(e.to_string()))?;
+
+        let econn = self.get_entity_conn(&subgraph_id, ReplicaId::Main)?;
+        let (event, metadata_event) = econn.transaction(|| -> Result<_, StoreError> {
+            match Self::block_ptr_with_conn(&subgraph_id, &econn)? {
+                Some(current) if current.number > block_ptr_to.number => { /* ok */ }
+                current => {
+                    return Err(StoreError::ConstraintViolation(format!(
+                        "can not rewind subgraph `{}` to block {} since it is at block {}",
+                        subgraph_id,
+                        block_ptr_to.number,
+                        current
+                            .map(|ptr| ptr.number.to_string())
+                            .unwrap_or("(none)".to_owned())
+                    )))
+                }
+            }
+            let horizon = metadata::history_horizon(&econn.conn, &subgraph_id)?;
+            if (block_ptr_to.number as BlockNumber) < horizon {
+                return Err(StoreError::Unknown(format_err!(
+                    "Can not rewind subgraph `{}` to block {} as its history \
+                     before block {} has been pruned",
+                    subgraph_id,
+                    block_ptr_to.number,
+                    horizon
+                )));
+            }
+            let metadata_event =
+                metadata::rewind_block_ptr(&econn.conn, &subgraph_id, block_ptr_to)?;
+
+            let (event, count) = econn.revert_from(block)?;
+            econn.update_entity_count(count)?;
+            Ok((event, metadata_event))
+        })?;
+
+        // Send the events separately, because NOTIFY uses a global DB lock.
+        econn.transaction(|| {
+            econn.send_store_event(&metadata_event)?;
+            econn.send_store_event(&event)
+        })
+    }
+
     fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
         self.subscriptions.subscribe(entities)
     }
@@ -1174,8 +1578,8 @@ impl StoreTrait for Store {
         &self,
         name: SubgraphName,
     ) -> Result<DeploymentState, StoreError> {
-        let conn = self.get_conn()?;
-        metadata::deployment_state_from_name(&conn, name)
+        let id = self.current_deployment_for_subgraph(name)?;
+        self.deployment_state_from_id(id)
     }
 
     fn deployment_state_from_id(
@@ -1198,7 +1602,10 @@ impl StoreTrait for Store {
         node_id: NodeId,
         mode: SubgraphVersionSwitchingMode,
     ) -> Result<(), StoreError> {
-        self.create_deployment_internal(name, schema, deployment, node_id, mode, false)
+        self.create_deployment(schema, deployment, false)?;
+        self.create_subgraph_version(name, &schema.id, node_id, mode, |id| {
+            self.exists_and_synced(id)
+        })
     }
 
     fn create_subgraph(&self, name: SubgraphName) -> Result<String, StoreError> {
@@ -1228,6 +1635,19 @@ impl StoreTrait for Store {
         })
     }
 
+    fn pause_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        self.set_paused(id, true)
+    }
+
+    fn resume_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        self.set_paused(id, false)
+    }
+
+    fn set_running(&self, id: &SubgraphDeploymentId, running: bool) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        e::set_running(&conn, id, running)
+    }
+
     fn start_subgraph_deployment(
         &self,
         logger: &Logger,
@@ -1241,6 +1661,11 @@ impl StoreTrait for Store {
         })
     }
 
+    fn unfail(&self, subgraph_id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(subgraph_id, ReplicaId::Main)?;
+        econn.transaction(|| metadata::unfail_deployment(&econn.conn, subgraph_id))
+    }
+
     fn migrate_subgraph_deployment(
         &self,
         logger: &Logger,
@@ -1273,42 +1698,15 @@ impl StoreTrait for Store {
         subgraph_id: &SubgraphDeploymentId,
         hash: H256,
     ) -> Result<Option<BlockNumber>, StoreError> {
-        use crate::db_schema::ethereum_blocks::dsl;
-
-        // We should also really check that the block with the given hash is
-        // on the chain starting at the subgraph's current head. That check is
-        // very expensive though with the data structures we have currently
-        // available. Ideally, we'd have the last REORG_THRESHOLD blocks in
-        // memory so that we can check against them, and then mark in the
-        // database the blocks on the main chain that we consider final
-        let block: Option<(i64, String)> = dsl::ethereum_blocks
-            .select((dsl::number, dsl::network_name))
-            .filter(dsl::hash.eq(format!("{:x}", hash)))
-            .first(&*self.get_conn()?)
-            .optional()?;
         let subgraph_network = self.network_name(subgraph_id)?;
-        block
-            .map(|(number, network_name)| {
-                if subgraph_network.is_none() || Some(&network_name) == subgraph_network.as_ref() {
-                    BlockNumber::try_from(number)
-                        .map_err(|e| StoreError::QueryExecutionError(e.to_string()))
-                } else {
-                    Err(StoreError::QueryExecutionError(format!(
-                        "subgraph {} belongs to network {} but block {:x} belongs to network {}",
-                        subgraph_id,
-                        subgraph_network.unwrap_or("(none)".to_owned()),
-                        hash,
-                        network_name
-                    )))
-                }
-            })
-            .transpose()
+        self.chain_block_number(subgraph_id, subgraph_network, hash)
     }
 
     fn query_store(
         self: Arc<Self>,
+        _: &SubgraphDeploymentId,
         for_subscription: bool,
-    ) -> Arc<(dyn QueryStore + Send + Sync + 'static)> {
+    ) -> Result<Arc<dyn QueryStore + Send + Sync>, StoreError> {
         use std::sync::atomic::Ordering;
 
         let replica_id = match for_subscription {
@@ -1324,20 +1722,17 @@ impl StoreTrait for Store {
             true => ReplicaId::Main,
         };
 
-        Arc::new(crate::query_store::QueryStore::new(
+        Ok(Arc::new(crate::query_store::QueryStore::new(
             self,
             for_subscription,
             replica_id,
-        ))
+        )))
     }
 
     fn deployment_synced(&self, id: &SubgraphDeploymentId) -> Result<(), Error> {
-        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
-        econn.transaction(|| {
-            let changes = metadata::deployment_synced(&econn.conn, id)?;
-            econn.send_store_event(&StoreEvent::new(changes))?;
-            Ok(())
-        })
+        self.promote_pending_version(id)?;
+        self.set_synced(id)?;
+        Ok(())
     }
 
     fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError> {
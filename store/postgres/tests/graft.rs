@@ -2,6 +2,7 @@ use diesel::pg::PgConnection;
 use diesel::*;
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::BTreeSet;
 use std::str::FromStr;
 use test_store::*;
 
@@ -128,6 +129,7 @@ fn insert_test_data(store: Arc<DieselStore>) {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        features: BTreeSet::new(),
     };
 
     // Create SubgraphDeploymentEntity
@@ -5,16 +5,21 @@ use diesel::prelude::*;
 use futures::future::IntoFuture;
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::str::FromStr;
+use std::sync::Arc;
 
+use graph::components::store::{Aggregate, Child, ChildOrder};
 use graph::data::store::scalar::{BigDecimal, BigInt, Bytes};
 use graph::prelude::{
-    web3::types::H256, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityQuery,
-    EntityRange, Future01CompatExt, Schema, SubgraphDeploymentId, Value, ValueType,
-    BLOCK_NUMBER_MAX,
+    web3::types::H256, Entity, EntityCollection, EntityCursor, EntityFilter, EntityKey,
+    EntityOrder, EntityQuery, EntityRange, Future01CompatExt, Schema, SubgraphDeploymentId, Value,
+    ValueType, BLOCK_NUMBER_MAX,
+};
+use graph_store_postgres::layout_for_tests::{
+    Catalog, ColumnType, Layout, SqlName, STRING_PREFIX_SIZE,
 };
-use graph_store_postgres::layout_for_tests::{Layout, STRING_PREFIX_SIZE};
 
 use test_store::*;
 
@@ -334,6 +339,17 @@ fn insert_pets(conn: &PgConnection, layout: &Layout) {
     insert_pet(conn, layout, "Cat", "garfield", "Garfield");
 }
 
+/// Insert a chain of things `a -> b -> c -> d` where each thing's
+/// `bigThing` is the next one and `d` refers to itself
+fn insert_things(conn: &PgConnection, layout: &Layout) {
+    for (id, big_thing) in vec![("a", "b"), ("b", "c"), ("c", "d"), ("d", "d")] {
+        let mut thing = Entity::new();
+        thing.set("id", id);
+        thing.set("bigThing", big_thing);
+        insert_entity(conn, layout, "Thing", thing);
+    }
+}
+
 fn insert_test_data(conn: &PgConnection) -> Layout {
     let schema = Schema::parse(THINGS_GQL, THINGS_SUBGRAPH_ID.clone()).unwrap();
 
@@ -547,6 +563,8 @@ fn count_scalar_entities(conn: &PgConnection, layout: &Layout) -> usize {
             EntityRange {
                 first: None,
                 skip: 0,
+                after: None,
+                before: None,
             },
             BLOCK_NUMBER_MAX,
             None,
@@ -636,6 +654,7 @@ fn test_find(expected_entity_ids: Vec<&str>, query: EntityQuery) {
             None,
         );
         insert_pets(conn, layout);
+        insert_things(conn, layout);
 
         let unordered = matches!(query.order, EntityOrder::Unordered);
         let entities = layout
@@ -846,6 +865,95 @@ fn find_string_less_than_range() {
     )
 }
 
+#[test]
+fn find_after_cursor() {
+    let cursor = |order_by: &str, value: Value, id: &str| EntityCursor::new(order_by, value, id);
+    let red = || Value::from("red");
+
+    // Users without a favorite color sort last in both directions
+    test_find(
+        vec!["1", "3"],
+        user_query()
+            .asc("favorite_color")
+            .after(cursor("favorite_color", red(), "2")),
+    );
+    test_find(
+        vec!["2", "3"],
+        user_query().desc("favorite_color").after(cursor(
+            "favorite_color",
+            Value::from("yellow"),
+            "1",
+        )),
+    );
+    test_find(
+        vec![],
+        user_query()
+            .asc("favorite_color")
+            .after(cursor("favorite_color", Value::Null, "3")),
+    );
+    test_find(
+        vec!["3", "2"],
+        user_query()
+            .asc("coffee")
+            .after(cursor("coffee", Value::Bool(false), "1")),
+    );
+    let cindini = Bytes::from_str(&hex::encode("Cindini")).unwrap();
+    test_find(
+        vec!["1", "3"],
+        user_query()
+            .asc("bin_name")
+            .after(cursor("bin_name", Value::Bytes(cindini), "2")),
+    );
+    test_find(
+        vec!["2"],
+        user_query()
+            .desc("name")
+            .first(1)
+            .after(cursor("name", Value::from("Johnton"), "1")),
+    );
+    test_find(
+        vec!["3"],
+        user_query().after(cursor("id", Value::from("2"), "2")),
+    );
+}
+
+#[test]
+fn find_before_cursor() {
+    let cursor = |order_by: &str, value: Value, id: &str| EntityCursor::new(order_by, value, id);
+
+    test_find(
+        vec!["2", "1"],
+        user_query()
+            .asc("favorite_color")
+            .before(cursor("favorite_color", Value::Null, "3")),
+    );
+    test_find(
+        vec!["2"],
+        user_query().asc("favorite_color").before(cursor(
+            "favorite_color",
+            Value::from("yellow"),
+            "1",
+        )),
+    );
+    test_find(
+        vec!["1"],
+        user_query()
+            .asc("favorite_color")
+            .after(cursor("favorite_color", Value::from("red"), "2"))
+            .before(cursor("favorite_color", Value::Null, "3")),
+    );
+    test_find(
+        vec!["3", "1"],
+        user_query()
+            .desc("name")
+            .before(cursor("name", Value::from("Cindini"), "2")),
+    );
+    test_find(
+        vec!["1"],
+        user_query().before(cursor("id", Value::from("2"), "2")),
+    );
+}
+
 #[test]
 fn find_string_multiple_and() {
     test_find(
@@ -1295,6 +1403,63 @@ fn find_where_nested_and_or() {
     )
 }
 
+#[test]
+fn find_child_filter() {
+    fn big_thing(filter: EntityFilter, derived: bool) -> EntityFilter {
+        EntityFilter::Child(Child {
+            attr: "bigThing".to_owned(),
+            entity_type: "Thing".to_owned(),
+            filter: Box::new(filter),
+            derived,
+        })
+    }
+    let id_is = |id: &str| EntityFilter::Equal("id".to_owned(), Value::from(id));
+    let thing_query = || query(vec!["Thing"]);
+
+    // Things whose bigThing is `c`
+    test_find(
+        vec!["b"],
+        thing_query().filter(big_thing(id_is("c"), false)),
+    );
+    // Things that are the bigThing of `a`
+    test_find(vec!["b"], thing_query().filter(big_thing(id_is("a"), true)));
+    // Things whose bigThing's bigThing is `c`
+    test_find(
+        vec!["a"],
+        thing_query().filter(big_thing(big_thing(id_is("c"), false), false)),
+    );
+    // Things whose bigThing is the bigThing of `b`
+    test_find(
+        vec!["b"],
+        thing_query().filter(big_thing(big_thing(id_is("b"), true), false)),
+    );
+}
+
+#[test]
+fn find_order_by_child() {
+    let by_big_thing = |ascending| {
+        let child = ChildOrder {
+            attr: "bigThing".to_owned(),
+            entity_type: "Thing".to_owned(),
+            child_attr: "id".to_owned(),
+        };
+        let order = if ascending {
+            EntityOrder::ChildAscending(child, ValueType::String)
+        } else {
+            EntityOrder::ChildDescending(child, ValueType::String)
+        };
+        query(vec!["Thing"]).order(order)
+    };
+
+    test_find(vec!["a", "b", "c", "d"], by_big_thing(true));
+    // `c` and `d` both refer to `d` and are ordered by their own id
+    test_find(vec!["c", "d", "b", "a"], by_big_thing(false));
+    let cursor = |value: &str, id: &str| EntityCursor::new("bigThing__id", Value::from(value), id);
+    test_find(vec!["b", "a"], by_big_thing(false).after(cursor("d", "d")));
+    test_find(vec!["d"], by_big_thing(true).after(cursor("d", "c")));
+    test_find(vec!["a"], by_big_thing(true).before(cursor("c", "b")));
+}
+
 #[test]
 fn find_enum_equal() {
     test_find(
@@ -1535,3 +1700,137 @@ fn find_empty_and_or() {
         user_query().filter(EntityFilter::Or(vec![EntityFilter::And(vec![])])),
     )
 }
+
+#[test]
+fn aggregate_users() {
+    run_test(|conn, layout| -> Result<(), ()> {
+        insert_users(conn, layout);
+
+        let aggregates = vec![
+            Aggregate::Count,
+            Aggregate::Sum("age".to_owned()),
+            Aggregate::Min("age".to_owned()),
+            Aggregate::Max("seconds_age".to_owned()),
+            Aggregate::Avg("age".to_owned()),
+        ];
+        let values = layout
+            .aggregate(conn, "User", None, &aggregates, BLOCK_NUMBER_MAX)
+            .expect("aggregating users works");
+        assert_eq!(
+            vec![
+                Value::BigInt(BigInt::from(3)),
+                Value::BigInt(BigInt::from(138)),
+                Value::Int(28),
+                Value::BigInt(BigInt::from(67 * 31557600)),
+                Value::BigDecimal(BigDecimal::from(46)),
+            ],
+            values
+        );
+
+        // Only users who don't drink coffee
+        let filter = EntityFilter::Equal("coffee".to_owned(), Value::Bool(false));
+        let values = layout
+            .aggregate(
+                conn,
+                "User",
+                Some(filter),
+                &aggregates[..2],
+                BLOCK_NUMBER_MAX,
+            )
+            .expect("aggregating with a filter works");
+        assert_eq!(
+            vec![
+                Value::BigInt(BigInt::from(2)),
+                Value::BigInt(BigInt::from(95))
+            ],
+            values
+        );
+
+        // Nothing matches; everything but the count is null
+        let filter = EntityFilter::Equal("name".to_owned(), Value::from("Nobody"));
+        let values = layout
+            .aggregate(
+                conn,
+                "User",
+                Some(filter),
+                &aggregates[..2],
+                BLOCK_NUMBER_MAX,
+            )
+            .expect("aggregating nothing works");
+        assert_eq!(vec![Value::BigInt(BigInt::from(0)), Value::Null], values);
+
+        // Only numbers can be aggregated
+        let sum_names = vec![Aggregate::Sum("name".to_owned())];
+        assert!(layout
+            .aggregate(conn, "User", None, &sum_names, BLOCK_NUMBER_MAX)
+            .is_err());
+        Ok(())
+    });
+}
+
+#[test]
+fn references_keep_their_column_type() {
+    const IMPORTER_GQL: &str = "
+        type _Schema_ @import(types: [\"Band\"], from: { id: \"missingBands\" })
+
+        type Fan @entity {
+            id: ID!
+            favorite: Band
+        }";
+    const BANDS_GQL: &str = "
+        type Band @entity {
+            id: Bytes!
+            name: String!
+        }";
+    const IMPORTER_SCHEMA: &str = "importer";
+
+    run_test(|conn, _| -> Result<(), ()> {
+        conn.batch_execute(&format!(
+            "drop schema if exists {schema} cascade; create schema {schema}",
+            schema = IMPORTER_SCHEMA
+        ))
+        .unwrap();
+
+        let schema = Schema::parse(IMPORTER_GQL, SubgraphDeploymentId::new("importer").unwrap())
+            .expect("importer schema is valid");
+        let favorite_type = |layout: &Layout| {
+            layout
+                .table_for_entity("Fan")
+                .unwrap()
+                .column(&SqlName::from("favorite"))
+                .unwrap()
+                .column_type
+                .clone()
+        };
+
+        // The imported deployment does not exist when the tables are
+        // created, and references to it are stored as strings
+        let layout =
+            Layout::create_relational_schema(conn, &schema, IMPORTER_SCHEMA.to_owned()).unwrap();
+        assert_eq!(ColumnType::String, favorite_type(&layout));
+
+        // Once the imported schema is known, new tables would store the
+        // references as bytes, but the existing column keeps its type
+        let bands = Schema::parse(
+            BANDS_GQL,
+            SubgraphDeploymentId::new("missingBands").unwrap(),
+        )
+        .expect("bands schema is valid");
+        let imports = schema
+            .imported_schemas()
+            .into_iter()
+            .map(|schema_ref| (schema_ref, Arc::new(bands.clone())))
+            .collect::<HashMap<_, _>>();
+        let catalog = Catalog::make_empty(IMPORTER_SCHEMA.to_owned()).unwrap();
+        let layout = Layout::new(&schema, catalog, &imports, true).unwrap();
+        assert_eq!(ColumnType::BytesId, favorite_type(&layout));
+
+        let catalog = Catalog::new(conn, IMPORTER_SCHEMA.to_owned()).unwrap();
+        let layout = Layout::new(&schema, catalog, &imports, true).unwrap();
+        assert_eq!(ColumnType::String, favorite_type(&layout));
+
+        conn.batch_execute(&format!("drop schema {} cascade", IMPORTER_SCHEMA))
+            .unwrap();
+        Ok(())
+    });
+}
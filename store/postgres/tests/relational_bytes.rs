@@ -8,6 +8,7 @@ use lazy_static::lazy_static;
 use std::collections::BTreeMap;
 use std::fmt::Debug;
 
+use graph::components::store::EntityDeletion;
 use graph::data::store::scalar::{BigDecimal, BigInt};
 use graph::prelude::{
     web3::types::H256, ChildMultiplicity, Entity, EntityCollection, EntityKey, EntityLink,
@@ -326,6 +327,65 @@ fn delete() {
     });
 }
 
+#[test]
+fn deletions() {
+    run_test(|conn, layout| -> Result<(), ()> {
+        insert_entity(&conn, &layout, "Thing", BEEF_ENTITY.clone());
+
+        // Update the entity in block 1 and delete it in block 2
+        let mut entity = BEEF_ENTITY.clone();
+        entity.set("name", "Moo");
+        let key = EntityKey {
+            subgraph_id: THINGS_SUBGRAPH_ID.clone(),
+            entity_type: "Thing".to_owned(),
+            entity_id: entity.id().unwrap().clone(),
+        };
+        layout
+            .update(&conn, &key, entity, 1)
+            .expect("Failed to update");
+        layout.delete(&conn, &key, 2).expect("Failed to delete");
+
+        // Only the deletion is reported, not the version the update replaced
+        let deletions = layout
+            .deletions(&conn, 0, BLOCK_NUMBER_MAX, 100, None)
+            .expect("Failed to list deletions");
+        let beef = EntityDeletion {
+            entity_type: "Thing".to_owned(),
+            entity_id: "0xdeadbeef".to_owned(),
+            block: 2,
+        };
+        assert_eq!(vec![beef.clone()], deletions);
+
+        // Deletions in the same block are ordered by id, and `first` and
+        // `after` page through them
+        insert_thing(&conn, &layout, "cafe", "Cafe");
+        let key = EntityKey {
+            subgraph_id: THINGS_SUBGRAPH_ID.clone(),
+            entity_type: "Thing".to_owned(),
+            entity_id: "cafe".to_owned(),
+        };
+        layout.delete(&conn, &key, 2).expect("Failed to delete");
+        let cafe = EntityDeletion {
+            entity_type: "Thing".to_owned(),
+            entity_id: "0xcafe".to_owned(),
+            block: 2,
+        };
+        let deletions = layout
+            .deletions(&conn, 0, BLOCK_NUMBER_MAX, 1, None)
+            .expect("Failed to list deletions");
+        assert_eq!(vec![cafe.clone()], deletions);
+        let deletions = layout
+            .deletions(&conn, 0, BLOCK_NUMBER_MAX, 100, Some(&cafe))
+            .expect("Failed to list deletions");
+        assert_eq!(vec![beef.clone()], deletions);
+        let deletions = layout
+            .deletions(&conn, 0, BLOCK_NUMBER_MAX, 100, Some(&beef))
+            .expect("Failed to list deletions");
+        assert!(deletions.is_empty());
+        Ok(())
+    });
+}
+
 //
 // Test Layout::query to check that query generation is syntactically sound
 //
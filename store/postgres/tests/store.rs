@@ -4,7 +4,7 @@ use graph_mock::MockMetricsRegistry;
 use graphql_parser::schema as s;
 use hex_literal::hex;
 use lazy_static::lazy_static;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::str::FromStr;
 use std::time::Duration;
 use test_store::*;
@@ -48,6 +48,22 @@ const USER_GQL: &str = "
         id: ID!,
         text: String
     }
+
+    type Trade @entity {
+        id: ID!,
+        timestamp: Int!,
+        trader: String!,
+        amount: BigInt!
+    }
+
+    type TraderHourData @entity @rollup(source: \"Trade\", interval: \"hour\", timestamp: \"timestamp\", dimensions: [\"trader\"]) {
+        id: ID!,
+        timestamp: Int!,
+        trader: String!,
+        trades: Int! @count,
+        volume: BigInt! @sum(field: \"amount\"),
+        lastAmount: BigInt @last(field: \"amount\")
+    }
 ";
 
 const USER: &str = "User";
@@ -161,6 +177,7 @@ fn insert_test_data(store: Arc<DieselStore>) {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        features: BTreeSet::new(),
     };
 
     // Create SubgraphDeploymentEntity
@@ -1416,6 +1433,45 @@ fn revert_block_with_partial_update() {
     })
 }
 
+#[test]
+fn rewind_to_earlier_block() {
+    run_test(|store| -> Result<(), ()> {
+        let user_key = |id: &str| EntityKey {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_type: USER.to_owned(),
+            entity_id: id.to_owned(),
+        };
+
+        // Rewinding a deployment that is being indexed is not allowed
+        store
+            .rewind(TEST_SUBGRAPH_ID.clone(), *GENESIS_PTR)
+            .expect_err("rewinding an active deployment fails");
+
+        store
+            .pause_subgraph(&TEST_SUBGRAPH_ID)
+            .expect("can pause deployment");
+        store
+            .rewind(TEST_SUBGRAPH_ID.clone(), *GENESIS_PTR)
+            .expect("rewinding to the genesis block works");
+
+        // Only the entity from the genesis block is left
+        assert!(store.get(user_key("1")).unwrap().is_some());
+        assert!(store.get(user_key("2")).unwrap().is_none());
+        assert!(store.get(user_key("3")).unwrap().is_none());
+        assert_eq!(1, get_entity_count(store.clone(), &TEST_SUBGRAPH_ID));
+        assert_eq!(
+            Some(*GENESIS_PTR),
+            store.block_ptr(TEST_SUBGRAPH_ID.clone()).unwrap()
+        );
+
+        // We can only rewind to blocks before the current block
+        store
+            .rewind(TEST_SUBGRAPH_ID.clone(), *TEST_BLOCK_1_PTR)
+            .expect_err("rewinding forward fails");
+        Ok(())
+    })
+}
+
 fn mock_data_source() -> DataSource {
     DataSource {
         kind: String::from("ethereum/contract"),
@@ -1619,6 +1675,7 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            features: BTreeSet::new(),
         };
 
         // Create SubgraphDeploymentEntity
@@ -1770,7 +1827,7 @@ fn throttle_subscription_delivers() {
             subscribe_and_consume(store.clone(), &SUBGRAPHS_ID, "SubgraphDeployment")
                 .throttle_while_syncing(
                     &*LOGGER,
-                    store.clone().query_store(true),
+                    store.clone().query_store(&SUBGRAPHS_ID, true).unwrap(),
                     SUBGRAPHS_ID.clone(),
                     Duration::from_millis(500),
                 );
@@ -1778,7 +1835,7 @@ fn throttle_subscription_delivers() {
         let subscription = subscribe_and_consume(store.clone(), &TEST_SUBGRAPH_ID, USER)
             .throttle_while_syncing(
                 &*LOGGER,
-                store.clone().query_store(true),
+                store.clone().query_store(&TEST_SUBGRAPH_ID, true).unwrap(),
                 TEST_SUBGRAPH_ID.clone(),
                 Duration::from_millis(500),
             );
@@ -1829,7 +1886,7 @@ fn throttle_subscription_throttles() {
             let subscription = subscribe_and_consume(store.clone(), &TEST_SUBGRAPH_ID, USER)
                 .throttle_while_syncing(
                     &*LOGGER,
-                    store.clone().query_store(true),
+                    store.clone().query_store(&TEST_SUBGRAPH_ID, true).unwrap(),
                     TEST_SUBGRAPH_ID.clone(),
                     Duration::from_secs(30),
                 );
@@ -1956,6 +2013,7 @@ fn handle_large_string_with_index() {
                     make_insert_op(TWO, &other_text),
                 ],
                 stopwatch_metrics,
+                vec![],
             )
             .expect("Failed to insert large text");
 
@@ -2368,3 +2426,128 @@ fn reorg_tracking() {
         Ok(())
     })
 }
+
+#[test]
+fn rollups_are_maintained_and_reverted() {
+    fn trade(id: &str, trader: &str, timestamp: i32, amount: i32) -> EntityOperation {
+        let mut data = Entity::new();
+        data.set("id", id);
+        data.set("timestamp", timestamp);
+        data.set("trader", trader);
+        data.set("amount", BigInt::from(amount));
+        EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: TEST_SUBGRAPH_ID.clone(),
+                entity_type: "Trade".to_owned(),
+                entity_id: id.to_owned(),
+            },
+            data,
+        }
+    }
+
+    fn bucket_key(id: &str) -> EntityKey {
+        EntityKey {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_type: "TraderHourData".to_owned(),
+            entity_id: id.to_owned(),
+        }
+    }
+
+    fn check_bucket(store: &Arc<DieselStore>, id: &str, trades: i32, volume: i32, last: i32) {
+        let bucket = store
+            .get(bucket_key(id))
+            .unwrap()
+            .expect("the rollup bucket exists");
+        assert_eq!(Some(&Value::Int(trades)), bucket.get("trades"));
+        assert_eq!(
+            Some(&Value::BigInt(BigInt::from(volume))),
+            bucket.get("volume")
+        );
+        assert_eq!(
+            Some(&Value::BigInt(BigInt::from(last))),
+            bucket.get("lastAmount")
+        );
+    }
+
+    run_test(|store| -> Result<(), ()> {
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_3_PTR,
+            vec![
+                trade("t1", "alice", 18010, 10),
+                trade("t2", "alice", 18020, 5),
+                trade("t3", "alice", 21600, 1),
+            ],
+        )
+        .unwrap();
+        check_bucket(&store, "alice-18000", 2, 15, 5);
+        check_bucket(&store, "alice-21600", 1, 1, 1);
+
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_4_PTR,
+            vec![trade("t4", "alice", 21599, 7)],
+        )
+        .unwrap();
+        check_bucket(&store, "alice-18000", 3, 22, 7);
+
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_4_PTR,
+                *TEST_BLOCK_3_PTR,
+            )
+            .unwrap();
+        check_bucket(&store, "alice-18000", 2, 15, 5);
+
+        // Dashes in dimensions are escaped so that they can not be confused
+        // with the separator between the dimensions and the bucket
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_4_PTR,
+            vec![trade("t5", "alice-18000", 18030, 3)],
+        )
+        .unwrap();
+        check_bucket(&store, "alice-18000", 2, 15, 5);
+        check_bucket(&store, "alice\\-18000-18000", 1, 3, 3);
+
+        // The last value comes from the source entity that was written
+        // last, even though `tx-10` sorts before `tx-9`
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_5_PTR,
+            vec![
+                trade("tx-9", "bob", 36010, 2),
+                trade("tx-10", "bob", 36020, 4),
+            ],
+        )
+        .unwrap();
+        check_bucket(&store, "bob-36000", 2, 6, 4);
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_5_PTR,
+                *TEST_BLOCK_4_PTR,
+            )
+            .unwrap();
+
+        // Mappings can not write rollups
+        let mut data = Entity::new();
+        data.set("id", "alice-0");
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_5_PTR,
+            vec![EntityOperation::Set {
+                key: bucket_key("alice-0"),
+                data,
+            }],
+        )
+        .expect_err("writing a rollup directly fails");
+        Ok(())
+    })
+}
@@ -2,10 +2,20 @@ use graph::{
     data::subgraph::schema::MetadataType,
     data::subgraph::schema::SubgraphDeploymentAssignmentEntity,
     data::subgraph::schema::SubgraphEntity,
+    data::subgraph::schema::SubgraphError,
+    data::subgraph::schema::SubgraphHealth,
     data::subgraph::schema::SUBGRAPHS_ID,
+    data::subgraph::status,
+    prelude::entity,
+    prelude::tokio,
+    prelude::web3::types::H256,
     prelude::EntityChange,
     prelude::EntityChangeOperation,
+    prelude::EntityCollection,
     prelude::EntityKey,
+    prelude::EntityOperation,
+    prelude::EntityQuery,
+    prelude::EthereumBlockPointer,
     prelude::Schema,
     prelude::StoreEvent,
     prelude::SubgraphDeploymentEntity,
@@ -15,8 +25,10 @@ use graph::{
     prelude::TypedEntity,
     prelude::{NodeId, Store as _, SubgraphDeploymentId},
 };
-use graph_store_postgres::NetworkStore;
-use std::collections::HashSet;
+use graph_store_postgres::{NetworkStore, PRIMARY_SHARD};
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
 use test_store::*;
 
 const SUBGRAPH_GQL: &str = "
@@ -44,6 +56,22 @@ fn removed(typ: MetadataType, id: &str) -> EntityChange {
     }
 }
 
+/// Remove all subgraphs and create a deployment `id` with `SUBGRAPH_GQL`
+fn create_user_subgraph(id: &str) -> SubgraphDeploymentId {
+    let id = SubgraphDeploymentId::new(id).unwrap();
+    remove_subgraphs();
+    create_test_subgraph(&id, SUBGRAPH_GQL);
+    id
+}
+
+fn status(store: &NetworkStore, id: &SubgraphDeploymentId) -> status::Info {
+    store
+        .status(status::Filter::Deployments(vec![id.to_string()]))
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
 #[test]
 fn reassign_subgraph() {
     fn setup() -> SubgraphDeploymentId {
@@ -83,6 +111,306 @@ fn reassign_subgraph() {
     })
 }
 
+#[test]
+fn pause_and_resume_subgraph() {
+    fn setup() -> SubgraphDeploymentId {
+        create_user_subgraph("pauseSubgraph")
+    }
+
+    fn is_paused(store: &NetworkStore, id: &SubgraphDeploymentId) -> bool {
+        let infos = store
+            .status(status::Filter::Deployments(vec![id.to_string()]))
+            .unwrap();
+        assert_eq!(1, infos.len());
+        infos[0].paused
+    }
+
+    run_test_sequentially(setup, |store, id| async move {
+        assert!(!is_paused(store.as_ref(), &id));
+
+        let expected = vec![StoreEvent::new(vec![set(
+            MetadataType::SubgraphDeploymentAssignment,
+            id.as_str(),
+        )])];
+
+        let events = tap_store_events(|| store.pause_subgraph(&id).unwrap());
+        assert!(is_paused(store.as_ref(), &id));
+        assert_eq!(expected, events);
+
+        // Pausing does not change the assignment
+        let node = store
+            .get(SubgraphDeploymentAssignmentEntity::key(id.clone()))
+            .unwrap()
+            .and_then(|entity| entity.get("nodeId").cloned())
+            .and_then(|value| value.as_string());
+        assert_eq!(Some("test"), node.as_deref());
+
+        let events = tap_store_events(|| store.resume_subgraph(&id).unwrap());
+        assert!(!is_paused(store.as_ref(), &id));
+        assert_eq!(expected, events);
+
+        // Only assigned deployments can be paused
+        let unknown = SubgraphDeploymentId::new("notAssigned").unwrap();
+        store
+            .pause_subgraph(&unknown)
+            .expect_err("pausing an unassigned deployment fails");
+    })
+}
+
+#[test]
+fn retry_and_unfail_subgraph() {
+    fn setup() -> SubgraphDeploymentId {
+        create_user_subgraph("retrySubgraph")
+    }
+
+    run_test_sequentially(setup, |store, id| async move {
+        let error = SubgraphError {
+            subgraph_id: id.clone(),
+            message: "ethereum node timed out".to_string(),
+            block_ptr: None,
+            handler: None,
+            deterministic: false,
+        };
+        let mut ops = SubgraphDeploymentEntity::fail_operations(&id, error);
+        ops.extend(SubgraphDeploymentEntity::retry_operations(
+            &id, 2, 1600000000,
+        ));
+        store.apply_metadata_operations(&id, ops).unwrap();
+
+        let info = status(store.as_ref(), &id);
+        assert_eq!(SubgraphHealth::Failed, info.health);
+        assert_eq!(2, info.retry_count);
+        assert_eq!(Some(1600000000), info.next_retry_at);
+
+        store.unfail(&id).unwrap();
+
+        let info = status(store.as_ref(), &id);
+        assert_eq!(SubgraphHealth::Healthy, info.health);
+        assert!(info.fatal_error.is_none());
+        assert_eq!(0, info.retry_count);
+        assert_eq!(None, info.next_retry_at);
+    })
+}
+
+#[test]
+fn non_fatal_errors() {
+    fn setup() -> SubgraphDeploymentId {
+        create_user_subgraph("nonFatalErrors")
+    }
+
+    run_test_sequentially(setup, |store, id| async move {
+        let error = SubgraphError {
+            subgraph_id: id.clone(),
+            message: "handler failed".to_string(),
+            block_ptr: Some(*BLOCK_ONE),
+            handler: Some("handleTransfer".to_string()),
+            deterministic: true,
+        };
+        // The same failure twice in one block is only recorded once
+        let same_error = SubgraphError {
+            subgraph_id: id.clone(),
+            message: "handler failed".to_string(),
+            block_ptr: Some(*BLOCK_ONE),
+            handler: Some("handleTransfer".to_string()),
+            deterministic: true,
+        };
+        transact_errors(&store, id.clone(), *BLOCK_ONE, vec![error, same_error]).unwrap();
+
+        let info = status(store.as_ref(), &id);
+        assert_eq!(SubgraphHealth::Unhealthy, info.health);
+        assert!(info.fatal_error.is_none());
+        assert_eq!(1, info.non_fatal_errors.len());
+        assert_eq!(
+            Some("handleTransfer"),
+            info.non_fatal_errors[0].handler.as_deref()
+        );
+        assert!(
+            store
+                .deployment_state_from_id(id.clone())
+                .unwrap()
+                .has_non_fatal_errors
+        );
+
+        // Reverting the block removes the errors
+        store
+            .revert_block_operations(id.clone(), *BLOCK_ONE, *GENESIS_PTR)
+            .unwrap();
+
+        let info = status(store.as_ref(), &id);
+        assert_eq!(SubgraphHealth::Healthy, info.health);
+        assert!(info.non_fatal_errors.is_empty());
+        assert!(
+            !store
+                .deployment_state_from_id(id.clone())
+                .unwrap()
+                .has_non_fatal_errors
+        );
+    })
+}
+
+/// A block pointer for a fake block with the given number
+fn block(number: u64) -> EthereumBlockPointer {
+    (H256::from_low_u64_be(number), number).into()
+}
+
+/// Set the name of the `User` with id `1` in block `number`
+fn set_name(store: &Arc<NetworkStore>, id: &SubgraphDeploymentId, number: u64, name: &str) {
+    let key = EntityKey {
+        subgraph_id: id.clone(),
+        entity_type: "User".to_owned(),
+        entity_id: "1".to_owned(),
+    };
+    let data = entity! { id: "1", name: name };
+    transact_entity_operations(
+        store,
+        id.clone(),
+        block(number),
+        vec![EntityOperation::Set { key, data }],
+    )
+    .unwrap();
+}
+
+/// The names of all `User`s at block `number`
+fn names_at(store: &NetworkStore, id: &SubgraphDeploymentId, number: i32) -> Vec<String> {
+    let query = EntityQuery::new(
+        id.clone(),
+        number,
+        EntityCollection::All(vec!["User".to_owned()]),
+    );
+    store
+        .find(query)
+        .unwrap()
+        .into_iter()
+        .map(|user| user.get("name").unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn prune_history() {
+    fn setup() -> SubgraphDeploymentId {
+        create_user_subgraph("pruneHistory")
+    }
+
+    run_test_sequentially(setup, |store, id| async move {
+        set_name(&store, &id, 1, "one");
+        set_name(&store, &id, 100, "hundred");
+        set_name(&store, &id, 200, "two hundred");
+
+        // Deployments must keep enough history to revert reorgs
+        assert!(store.store().set_history_blocks(&id, Some(10)).is_err());
+
+        store.store().set_history_blocks(&id, Some(60)).unwrap();
+        store.store().prune(&*LOGGER).unwrap();
+
+        let info = status(store.as_ref(), &id);
+        assert_eq!(Some(60), info.history_blocks);
+        assert_eq!(141, info.history_horizon);
+        assert_eq!(1, info.entity_count);
+
+        // The version that was only visible before block 100 is gone, but
+        // the one that was visible at the horizon is kept
+        assert!(names_at(store.as_ref(), &id, 50).is_empty());
+        assert_eq!(vec!["hundred"], names_at(store.as_ref(), &id, 150));
+        assert_eq!(vec!["two hundred"], names_at(store.as_ref(), &id, 200));
+
+        // Rewinding past the horizon is not possible
+        assert!(store.rewind(id.clone(), block(100)).is_err());
+
+        // Pruning again without new blocks does nothing
+        store.store().prune(&*LOGGER).unwrap();
+        assert_eq!(141, status(store.as_ref(), &id).history_horizon);
+    })
+}
+
+#[test]
+fn copy_deployment() {
+    fn setup() -> SubgraphDeploymentId {
+        create_user_subgraph("copyDeployment")
+    }
+
+    run_test_sequentially(setup, |store, id| async move {
+        set_name(&store, &id, 1, "one");
+        set_name(&store, &id, 100, "hundred");
+
+        // The copy is only switched over once the indexer of the
+        // deployment records that it stopped
+        store.set_running(&id, true).unwrap();
+        let namespace = store.store().layout(&id).unwrap().catalog.schema.clone();
+        let stop = async {
+            tokio::time::delay_for(Duration::from_secs(2)).await;
+            assert_eq!(
+                namespace,
+                store.store().layout(&id).unwrap().catalog.schema.clone()
+            );
+            store.set_running(&id, false).unwrap();
+        };
+        let (res, ()) = tokio::join!(
+            store.store().copy_deployment(&*LOGGER, &id, PRIMARY_SHARD),
+            stop
+        );
+        res.unwrap();
+        let copied = store.store().layout(&id).unwrap().catalog.schema.clone();
+        assert_ne!(namespace, copied);
+
+        // The copy has the same history as the original
+        assert_eq!(vec!["one"], names_at(store.as_ref(), &id, 50));
+        assert_eq!(vec!["hundred"], names_at(store.as_ref(), &id, 100));
+        assert_eq!(1, status(store.as_ref(), &id).entity_count);
+
+        // The deployment keeps indexing into the copy
+        set_name(&store, &id, 200, "two hundred");
+        assert_eq!(vec!["hundred"], names_at(store.as_ref(), &id, 150));
+        assert_eq!(vec!["two hundred"], names_at(store.as_ref(), &id, 200));
+        assert_eq!(block(200), store.block_ptr(id.clone()).unwrap().unwrap());
+
+        // Nothing of the first copy is left behind that would keep us
+        // from copying the deployment again
+        store
+            .store()
+            .copy_deployment(&*LOGGER, &id, PRIMARY_SHARD)
+            .await
+            .unwrap();
+        let recopied = store.store().layout(&id).unwrap().catalog.schema.clone();
+        assert_ne!(copied, recopied);
+        assert_eq!(vec!["two hundred"], names_at(store.as_ref(), &id, 200));
+    })
+}
+
+#[test]
+fn export_deployment() {
+    fn setup() -> SubgraphDeploymentId {
+        create_user_subgraph("exportDeployment")
+    }
+
+    fn export(store: &NetworkStore, id: &SubgraphDeploymentId, block: Option<i32>) -> String {
+        let dir = std::env::temp_dir().join("graph-node-export-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = store.store().export(&*LOGGER, id, block, &dir).unwrap();
+        assert_eq!(vec![dir.join("User.csv"), dir.join("schema.json")], files);
+        std::fs::read_to_string(dir.join("User.csv")).unwrap()
+    }
+
+    run_test_sequentially(setup, |store, id| async move {
+        set_name(&store, &id, 1, "one");
+        set_name(&store, &id, 100, "hundred, and \"quoted\"");
+
+        assert_eq!(
+            "id,name\r\n1,one\r\n",
+            export(store.as_ref(), &id, Some(50))
+        );
+        assert_eq!(
+            "id,name\r\n1,\"hundred, and \"\"quoted\"\"\"\r\n",
+            export(store.as_ref(), &id, None)
+        );
+
+        // Blocks the deployment has not reached yet can not be exported
+        assert!(store
+            .store()
+            .export(&*LOGGER, &id, Some(200), &std::env::temp_dir())
+            .is_err());
+    })
+}
+
 #[test]
 fn create_subgraph() {
     const SUBGRAPH_NAME: &str = "create/subgraph";
@@ -152,6 +480,7 @@ fn create_subgraph() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            features: BTreeSet::new(),
         };
         let deployment = SubgraphDeploymentEntity::new(&manifest, false, None);
         let node_id = NodeId::new("left").unwrap();
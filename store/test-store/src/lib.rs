@@ -3,6 +3,7 @@ extern crate diesel;
 
 use crate::tokio::runtime::{Builder, Runtime};
 use graph::data::graphql::effort::LoadManager;
+use graph::data::subgraph::schema::SubgraphError;
 use graph::log;
 use graph::prelude::{Store as _, *};
 use graph_graphql::prelude::{
@@ -11,11 +12,14 @@ use graph_graphql::prelude::{
 use graph_mock::MockMetricsRegistry;
 use graph_store_postgres::connection_pool::ConnectionPool;
 use graph_store_postgres::{
-    ChainHeadUpdateListener, ChainStore, NetworkStore, Store, SubscriptionManager,
+    ChainHeadUpdateListener, ChainStore, NetworkStore, ShardedStore, Store, SubscriptionManager,
+    PRIMARY_SHARD,
 };
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
+use std::iter::FromIterator;
 use std::sync::Mutex;
 use std::time::Instant;
 use web3::types::H256;
@@ -32,6 +36,15 @@ pub const NETWORK_VERSION: &str = "graph test suite";
 
 const CONN_POOL_SIZE: usize = 20;
 
+/// Tests only use one database, and all deployments go into the primary
+struct PrimaryOnly;
+
+impl DeploymentPlacer for PrimaryOnly {
+    fn place(&self, _: &str, _: &str) -> Result<Option<(String, Vec<NodeId>)>, String> {
+        Ok(None)
+    }
+}
+
 lazy_static! {
     pub static ref LOGGER:Logger = match env::var_os("GRAPH_LOG") {
         Some(_) => log::logger(false),
@@ -77,12 +90,16 @@ lazy_static! {
                 ));
                 let store = Arc::new(Store::new(
                     &logger,
+                    PRIMARY_SHARD.to_string(),
                     subscriptions,
                     postgres_conn_pool.clone(),
+                    postgres_conn_pool.clone(),
                     Vec::new(),
                     Vec::new(),
                     registry.clone(),
                 ));
+                let stores = HashMap::from_iter(vec![(PRIMARY_SHARD.to_string(), store)]);
+                let store = Arc::new(ShardedStore::new(stores, Arc::new(PrimaryOnly)));
                 let chain_store = ChainStore::new(NETWORK_NAME.to_owned(), net_identifiers, chain_head_update_listener, postgres_conn_pool);
                 Arc::new(NetworkStore::new(store, chain_store))
             })
@@ -154,6 +171,7 @@ fn create_subgraph(
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        features: BTreeSet::new(),
     };
 
     let deployment = SubgraphDeploymentEntity::new(&manifest, false, None).graft(base);
@@ -208,7 +226,24 @@ pub fn transact_entity_operations(
         subgraph_id.clone(),
         metrics_registry.clone(),
     );
-    store.transact_block_operations(subgraph_id, block_ptr_to, mods, stopwatch_metrics)
+    store.transact_block_operations(subgraph_id, block_ptr_to, mods, stopwatch_metrics, vec![])
+}
+
+/// Convenience to move `subgraph_id` to `block_ptr_to` without any entity
+/// changes, recording `errors` as non-fatal errors
+pub fn transact_errors(
+    store: &Arc<NetworkStore>,
+    subgraph_id: SubgraphDeploymentId,
+    block_ptr_to: EthereumBlockPointer,
+    errors: Vec<SubgraphError>,
+) -> Result<bool, StoreError> {
+    let metrics_registry = Arc::new(MockMetricsRegistry::new());
+    let stopwatch_metrics = StopwatchMetrics::new(
+        Logger::root(slog::Discard, o!()),
+        subgraph_id.clone(),
+        metrics_registry.clone(),
+    );
+    store.transact_block_operations(subgraph_id, block_ptr_to, vec![], stopwatch_metrics, errors)
 }
 
 pub fn insert_ens_name(hash: &str, name: &str) {
@@ -455,7 +490,10 @@ fn execute_subgraph_query_internal(
         let logger = logger.clone();
         let resolver = return_err!(rt.block_on(StoreResolver::at_block(
             &logger,
-            STORE.clone().query_store(false),
+            return_err!(STORE
+                .clone()
+                .query_store(query.schema.id(), false)
+                .map_err(QueryExecutionError::from)),
             bc,
             query.schema.id().clone()
         )));